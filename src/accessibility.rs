@@ -0,0 +1,57 @@
+use accesskit::{Live, Role};
+use egui::{Id, Response};
+
+/// Name a widget for screen readers that has no visible label, e.g. the prompt field or an icon
+/// button
+pub fn set_name(response: &Response, name: &str) {
+    response.ctx.accesskit_node_builder(response.id, |builder| {
+        builder.set_name(name);
+    });
+}
+
+/// Group the widgets added by `add_contents` under a named document. Its value is the whole text,
+/// so a screen reader can read it in one piece even if only the visible parts are laid out.
+pub fn document(
+    ui: &mut egui::Ui,
+    name: &str,
+    text: &str,
+    add_contents: impl FnOnce(&mut egui::Ui),
+) {
+    let id = ui.id().with(("document", name));
+    ui.ctx().accesskit_node_builder(id, |builder| {
+        builder.set_role(Role::Document);
+        builder.set_name(name);
+        builder.set_value(text);
+    });
+
+    let ctx = ui.ctx().clone();
+    ctx.with_accessibility_parent(id, || add_contents(ui));
+}
+
+/// Messages for screen readers that are not shown in the popup, e.g. that a streamed response is
+/// complete
+#[derive(Default)]
+pub struct Announcer {
+    message: String,
+}
+
+impl Announcer {
+    pub fn announce(&mut self, message: impl Into<String>) {
+        self.message = message.into();
+    }
+
+    /// Forget the last message, so that the same message is read out again the next time
+    pub fn clear(&mut self) {
+        self.message.clear();
+    }
+
+    /// Add the message to the tree as a polite live region. Screen readers read it out when it
+    /// changes, after what they are reading at the moment.
+    pub fn show(&self, ctx: &egui::Context) {
+        ctx.accesskit_node_builder(Id::new("announcement"), |builder| {
+            builder.set_role(Role::Status);
+            builder.set_live(Live::Polite);
+            builder.set_name(self.message.as_str());
+        });
+    }
+}
@@ -0,0 +1,394 @@
+use std::sync::atomic::Ordering;
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    chatgpt::{ChatGPT, StreamSink},
+    fetch::fetch_page,
+    model::{CompletionResponse, FunctionCall, Message, Tool, ToolCall},
+    search::{format_results, SearchEngine},
+};
+
+/// How many times the model may call tools before the agent gives up on a final answer
+pub const MAX_TURNS: usize = 8;
+
+/// The longest text a tool returns to the model, longer results are cut off
+const MAX_RESULT_CHARS: usize = 20_000;
+
+/// The tool result that tells the model the user didn't allow the call
+const DENIED: &str = "The user did not allow this tool call.";
+
+/// What the tools need from the application, e.g. the clipboard
+pub trait Host {
+    fn clipboard_text(&self) -> Result<String>;
+    /// The local date and time with the time zone, e.g. `2024-05-01 14:30 +02:00`
+    fn local_time(&self) -> String;
+    /// The web search the model may use, none if it is not configured
+    fn search_engine(&self) -> Option<&SearchEngine> {
+        None
+    }
+}
+
+/// The built-in tools the agent offers to the model, with the web search if it is configured.
+/// None of them can change anything on the computer.
+pub fn tools(search: bool) -> Vec<Tool> {
+    let no_parameters = json!({ "type": "object", "properties": {} });
+
+    let mut tools = vec![
+        Tool::function(
+            "read_clipboard",
+            "Read the text that is currently in the clipboard of the user",
+            no_parameters.clone(),
+        ),
+        Tool::function(
+            "fetch_url",
+            "Fetch a web page or text file and return its text",
+            json!({
+                "type": "object",
+                "properties": {
+                    "url": { "type": "string", "description": "The http or https URL" }
+                },
+                "required": ["url"]
+            }),
+        ),
+        Tool::function(
+            "current_time",
+            "Get the current local date and time of the user",
+            no_parameters,
+        ),
+        Tool::function(
+            "calculate",
+            "Evaluate an arithmetic expression with + - * / % ^ and parentheses",
+            json!({
+                "type": "object",
+                "properties": {
+                    "expression": { "type": "string", "description": "e.g. (1.5 + 2) * 3^2" }
+                },
+                "required": ["expression"]
+            }),
+        ),
+    ];
+
+    if search {
+        tools.push(Tool::function(
+            "web_search",
+            "Search the web, e.g. for current events. Cite the URLs of the results you use as \
+            Markdown links.",
+            json!({
+                "type": "object",
+                "properties": {
+                    "query": { "type": "string" }
+                },
+                "required": ["query"]
+            }),
+        ));
+    }
+    tools
+}
+
+/// Answer the question, calling tools until the model gives a final answer. Every call is passed
+/// to `confirm` first and only run if it returns true. Partial responses of all turns are sent to
+/// the sender.
+pub fn run(
+    chatgpt: &mut ChatGPT,
+    question: Message,
+    sender: impl StreamSink,
+    host: &dyn Host,
+    mut confirm: impl FnMut(&ToolCall) -> bool,
+) -> Result<CompletionResponse> {
+    chatgpt.set_tools(tools(host.search_engine().is_some()));
+    let cancel = chatgpt.cancel_handle();
+
+    let resp = (|| {
+        let mut resp = chatgpt.ask_stream_message(question, &sender)?;
+
+        for _ in 0..MAX_TURNS {
+            let Some(calls) = tool_calls(&resp) else {
+                return Ok(resp);
+            };
+
+            for call in calls {
+                let result = match confirm(&call) {
+                    true => {
+                        execute(&call.function, host).unwrap_or_else(|e| format!("Error: {e:#}"))
+                    }
+                    false => DENIED.to_string(),
+                };
+                tracing::debug!(tool = %call.function.name, "tool call answered");
+                chatgpt.push_tool_result(&call.id, truncate(result));
+            }
+
+            // Stopped while a call waited for confirmation
+            if cancel.load(Ordering::Relaxed) {
+                return Ok(resp);
+            }
+            resp = chatgpt.continue_stream(&sender)?;
+        }
+
+        bail!("The model did not answer after {MAX_TURNS} rounds of tool calls")
+    })();
+
+    chatgpt.set_tools(Vec::new());
+    resp
+}
+
+fn tool_calls(resp: &CompletionResponse) -> Option<Vec<ToolCall>> {
+    resp.choices
+        .first()
+        .and_then(|choice| choice.message.as_ref())
+        .and_then(|message| message.tool_calls.clone())
+        .filter(|calls| !calls.is_empty())
+}
+
+fn truncate(mut text: String) -> String {
+    if let Some((end, _)) = text.char_indices().nth(MAX_RESULT_CHARS) {
+        text.truncate(end);
+        text.push_str("\n[cut off]");
+    }
+    text
+}
+
+/// Run a tool call of the model
+pub fn execute(call: &FunctionCall, host: &dyn Host) -> Result<String> {
+    #[derive(Deserialize)]
+    struct Url {
+        url: String,
+    }
+    #[derive(Deserialize)]
+    struct Expression {
+        expression: String,
+    }
+    #[derive(Deserialize)]
+    struct Query {
+        query: String,
+    }
+
+    // Tools without parameters may be called without any arguments
+    let arguments = match call.arguments.trim() {
+        "" => "{}",
+        arguments => arguments,
+    };
+
+    match call.name.as_str() {
+        "read_clipboard" => host.clipboard_text(),
+        "fetch_url" => Ok(fetch_page(&serde_json::from_str::<Url>(arguments)?.url)?.text),
+        "current_time" => Ok(host.local_time()),
+        "calculate" => {
+            let Expression { expression } = serde_json::from_str(arguments)?;
+            calculate(&expression).map(format_number)
+        }
+        "web_search" => {
+            let Some(engine) = host.search_engine() else {
+                bail!("The web search is not configured");
+            };
+            let Query { query } = serde_json::from_str(arguments)?;
+            engine
+                .search(&query)
+                .map(|results| format_results(&results))
+        }
+        name => bail!("There is no tool named {name:?}"),
+    }
+}
+
+/// The longest expression the calculator evaluates
+const MAX_EXPRESSION_CHARS: usize = 1000;
+
+/// How deep parentheses, signs and powers may nest. The parser recurses for each level.
+const MAX_NESTING: usize = 64;
+
+/// Evaluate an arithmetic expression
+pub fn calculate(expression: &str) -> Result<f64> {
+    if expression.chars().count() > MAX_EXPRESSION_CHARS {
+        bail!("The expression is longer than {MAX_EXPRESSION_CHARS} characters");
+    }
+    let mut parser = Parser {
+        chars: expression.chars().filter(|c| !c.is_whitespace()).collect(),
+        pos: 0,
+        depth: 0,
+    };
+    let value = parser.sum()?;
+    if let Some(c) = parser.peek() {
+        bail!("Unexpected {c:?} in the expression");
+    }
+    if !value.is_finite() {
+        bail!("The result is not a finite number");
+    }
+    Ok(value)
+}
+
+/// Integers without a fraction, everything else as it is
+fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        value.to_string()
+    }
+}
+
+/// A recursive descent parser that evaluates while it parses
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// The current nesting level, see [`MAX_NESTING`]
+    depth: usize,
+}
+
+impl Parser {
+    /// Parse a nested part of the expression one level deeper
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> Result<f64>) -> Result<f64> {
+        if self.depth >= MAX_NESTING {
+            bail!("The expression is nested deeper than {MAX_NESTING} levels");
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn sum(&mut self) -> Result<f64> {
+        let mut value = self.product()?;
+        loop {
+            if self.eat('+') {
+                value += self.product()?;
+            } else if self.eat('-') {
+                value -= self.product()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<f64> {
+        let mut value = self.power()?;
+        loop {
+            if self.eat('*') {
+                value *= self.power()?;
+            } else if self.eat('/') {
+                value /= self.power()?;
+            } else if self.eat('%') {
+                value %= self.power()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// `^` binds right to left and stronger than a sign, so `-2^2` is -4
+    fn power(&mut self) -> Result<f64> {
+        if self.eat('-') {
+            return Ok(-self.nested(Self::power)?);
+        }
+        if self.eat('+') {
+            return self.nested(Self::power);
+        }
+
+        let base = self.atom()?;
+        match self.eat('^') {
+            true => Ok(base.powf(self.nested(Self::power)?)),
+            false => Ok(base),
+        }
+    }
+
+    fn atom(&mut self) -> Result<f64> {
+        if self.eat('(') {
+            let value = self.nested(Self::sum)?;
+            if !self.eat(')') {
+                bail!("A closing parenthesis is missing");
+            }
+            return Ok(value);
+        }
+
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_digit() || c == '.') {
+            self.pos += 1;
+        }
+        let number: String = self.chars[start..self.pos].iter().collect();
+        if number.is_empty() {
+            return Err(match self.peek() {
+                Some(c) => anyhow!("Unexpected {c:?} in the expression"),
+                None => anyhow!("The expression ends too early"),
+            });
+        }
+        number
+            .parse()
+            .with_context(|| format!("{number:?} is not a number"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calculator() {
+        assert_eq!(calculate("1 + 2 * 3").unwrap(), 7.0);
+        assert_eq!(calculate("(1.5 + 2) * 2").unwrap(), 7.0);
+        assert_eq!(calculate("2^3^2").unwrap(), 512.0);
+        assert_eq!(calculate("-2^2").unwrap(), -4.0);
+        assert_eq!(calculate("10 % 4 - -1").unwrap(), 3.0);
+        assert_eq!(format_number(calculate("7 / 2").unwrap()), "3.5");
+        assert_eq!(format_number(calculate("2^40").unwrap()), "1099511627776");
+
+        assert!(calculate("1 / 0").is_err());
+        assert!(calculate("(1 + 2").is_err());
+        assert!(calculate("1 +").is_err());
+        assert!(calculate("2 x 3").is_err());
+    }
+
+    #[test]
+    fn calculator_limits_nesting_and_length() {
+        assert_eq!(calculate(&format!("{}1", "-".repeat(64))).unwrap(), 1.0);
+        assert!(calculate(&format!("{}1", "-".repeat(65))).is_err());
+        assert!(calculate(&format!("{}1{}", "(".repeat(65), ")".repeat(65))).is_err());
+        assert!(calculate(&["2"; 66].join("^")).is_err());
+        assert!(calculate(&format!("{}1", "-".repeat(100_000))).is_err());
+        assert!(calculate(&"1+".repeat(1000)).is_err());
+    }
+
+    #[test]
+    fn unknown_tools_and_bad_arguments_fail() {
+        struct NoHost;
+        impl Host for NoHost {
+            fn clipboard_text(&self) -> Result<String> {
+                Ok("clipboard".to_string())
+            }
+            fn local_time(&self) -> String {
+                "now".to_string()
+            }
+        }
+
+        let call = |name: &str, arguments: &str| FunctionCall {
+            name: name.to_string(),
+            arguments: arguments.to_string(),
+        };
+        assert_eq!(
+            execute(&call("read_clipboard", ""), &NoHost).unwrap(),
+            "clipboard"
+        );
+        assert_eq!(
+            execute(&call("calculate", r#"{"expression": "6*7"}"#), &NoHost).unwrap(),
+            "42"
+        );
+        assert!(execute(&call("calculate", "{}"), &NoHost).is_err());
+        assert!(execute(&call("delete_files", "{}"), &NoHost).is_err());
+        assert!(execute(
+            &call("fetch_url", r#"{"url": "file:///etc/passwd"}"#),
+            &NoHost
+        )
+        .is_err());
+    }
+}
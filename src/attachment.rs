@@ -0,0 +1,243 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::{
+    document::{Document, MAX_DOCUMENT_SIZE},
+    markdown::fence_for,
+    model::Message,
+};
+
+/// Larger files are most likely not meant to be sent as text
+pub const MAX_ATTACHMENT_SIZE: u64 = 1024 * 1024;
+
+/// The estimated tokens of each part a large attachment is split into, so that parts that aren't
+/// needed can be removed
+pub const CHUNK_TOKENS: usize = 8_000;
+
+/// A text file whose content is sent together with the prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    /// The file name without the directory
+    pub name: String,
+    pub content: String,
+}
+
+impl Attachment {
+    /// Read a text file, or the text of a PDF or DOCX document. Binary files and files that are
+    /// too large are rejected.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let document = path
+            .extension()
+            .and_then(|ext| Document::from_extension(&ext.to_string_lossy()));
+
+        let size = std::fs::metadata(path)
+            .with_context(|| format!("Could not read {}", path.display()))?
+            .len();
+        let max_size = match document {
+            Some(_) => MAX_DOCUMENT_SIZE,
+            None => MAX_ATTACHMENT_SIZE,
+        };
+        if size > max_size {
+            bail!("{name} is too large to attach");
+        }
+
+        let bytes =
+            std::fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+        if let Some(document) = document {
+            let content = document
+                .extract_text(&bytes)
+                .with_context(|| format!("Could not read the text of {name}"))?;
+            return Ok(Self { name, content });
+        }
+        let Ok(content) = String::from_utf8(bytes) else {
+            bail!("{name} is not a text file");
+        };
+        if content.contains('\0') {
+            bail!("{name} is not a text file");
+        }
+
+        Ok(Self { name, content })
+    }
+
+    /// The file extension, used as the language of the code block. Documents are plain text.
+    fn extension(&self) -> &str {
+        let extension = Path::new(&self.name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        match Document::from_extension(extension) {
+            Some(_) => "",
+            None => extension,
+        }
+    }
+
+    /// Split the attachment at paragraphs into parts of about `max_tokens` each, named like
+    /// `report (2 of 3).pdf`. Attachments that fit stay as they are.
+    pub fn into_chunks(self, max_tokens: usize) -> Vec<Self> {
+        if self.estimated_tokens() <= max_tokens {
+            return vec![self];
+        }
+
+        let mut chunks = vec![String::new()];
+        for paragraph in self.content.split_inclusive("\n\n") {
+            let chunk = chunks.last_mut().unwrap();
+            let tokens = Message::user(format!("{chunk}{paragraph}")).estimated_tokens();
+            if tokens > max_tokens && !chunk.is_empty() {
+                chunks.push(String::new());
+            }
+            chunks.last_mut().unwrap().push_str(paragraph);
+        }
+
+        let path = Path::new(&self.name);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let extension = path
+            .extension()
+            .map(|ext| format!(".{}", ext.to_string_lossy()))
+            .unwrap_or_default();
+        let count = chunks.len();
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, content)| Self {
+                name: format!("{stem} ({} of {count}){extension}", i + 1),
+                content,
+            })
+            .collect()
+    }
+
+    /// A rough estimate of the tokens the attachment adds to the prompt
+    pub fn estimated_tokens(&self) -> usize {
+        Message::user(&self.content).estimated_tokens()
+    }
+}
+
+/// An image sent with the prompt to a multimodal model
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageAttachment {
+    pub name: String,
+    /// The media type, e.g. `image/png`
+    pub mime: &'static str,
+    pub data: Vec<u8>,
+}
+
+impl ImageAttachment {
+    /// The media type of an image file by its extension, `None` for other files
+    pub fn mime_type(path: &Path) -> Option<&'static str> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "png" => Some("image/png"),
+            "jpg" | "jpeg" => Some("image/jpeg"),
+            "gif" => Some("image/gif"),
+            "webp" => Some("image/webp"),
+            _ => None,
+        }
+    }
+
+    /// Read an image file in one of the formats the API accepts
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let Some(mime) = Self::mime_type(path) else {
+            bail!("{name} is not a supported image");
+        };
+
+        let data =
+            std::fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+
+        Ok(Self { name, mime, data })
+    }
+
+    /// The image as a data URL, the way it is sent to the API
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime, STANDARD.encode(&self.data))
+    }
+}
+
+/// Append the attachments to the prompt, each as a code block headed by its file name
+pub fn with_attachments(prompt: &str, attachments: &[Attachment]) -> String {
+    let mut text = prompt.to_string();
+
+    for attachment in attachments {
+        let fence = fence_for(&attachment.content);
+        let content = attachment.content.trim_end_matches('\n');
+        text.push_str(&format!(
+            "\n\n{}:\n{fence}{}\n{content}\n{fence}",
+            attachment.name,
+            attachment.extension()
+        ));
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::code_blocks;
+
+    #[test]
+    fn attachments_are_code_blocks() {
+        let attachments = [
+            Attachment {
+                name: "main.rs".into(),
+                content: "fn main() {}\n".into(),
+            },
+            Attachment {
+                name: "README.md".into(),
+                content: "```sh\ncargo run\n```".into(),
+            },
+        ];
+
+        let prompt = with_attachments("Explain", &attachments);
+        assert!(prompt.starts_with("Explain\n\nmain.rs:\n```rs\n"));
+
+        let blocks = code_blocks(&prompt);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].lang, Some("rs"));
+        assert_eq!(blocks[0].code, "fn main() {}\n");
+        assert_eq!(blocks[1].code, "```sh\ncargo run\n```\n");
+    }
+
+    #[test]
+    fn large_attachments_are_split_at_paragraphs() {
+        let paragraph = "a".repeat(40);
+        let attachment = Attachment {
+            name: "report.pdf".into(),
+            content: [paragraph.as_str(); 5].join("\n\n"),
+        };
+
+        let chunks = attachment.clone().into_chunks(30);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].name, "report (1 of 3).pdf");
+        assert_eq!(chunks[0].content, format!("{paragraph}\n\n{paragraph}\n\n"));
+        let joined: String = chunks.iter().map(|chunk| chunk.content.as_str()).collect();
+        assert_eq!(joined, attachment.content);
+        assert_eq!(chunks[2].extension(), "");
+
+        assert_eq!(attachment.clone().into_chunks(1000), [attachment]);
+    }
+
+    #[test]
+    fn image_data_url() {
+        assert_eq!(
+            ImageAttachment::mime_type(Path::new("Screenshot.PNG")),
+            Some("image/png")
+        );
+        assert_eq!(ImageAttachment::mime_type(Path::new("notes.txt")), None);
+
+        let image = ImageAttachment {
+            name: "pixel.gif".into(),
+            mime: "image/gif",
+            data: b"GIF89a".to_vec(),
+        };
+        assert_eq!(image.data_url(), "data:image/gif;base64,R0lGODlh");
+    }
+}
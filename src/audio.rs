@@ -0,0 +1,17 @@
+use anyhow::{bail, Result};
+use winapi::um::playsoundapi::{PlaySoundW, SND_MEMORY, SND_NODEFAULT, SND_SYNC};
+
+/// Play a WAV file from memory and wait until it is done
+pub fn play_wav(wav: &[u8]) -> Result<()> {
+    let played = unsafe {
+        PlaySoundW(
+            wav.as_ptr() as _,
+            std::ptr::null_mut(),
+            SND_MEMORY | SND_SYNC | SND_NODEFAULT,
+        )
+    };
+    if played == 0 {
+        bail!("Could not play the spoken answer");
+    }
+    Ok(())
+}
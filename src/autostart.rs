@@ -0,0 +1,54 @@
+use anyhow::{bail, Context, Result};
+use winapi::{
+    shared::winerror::{ERROR_FILE_NOT_FOUND, ERROR_SUCCESS},
+    um::{
+        winnt::REG_SZ,
+        winreg::{RegDeleteKeyValueW, RegSetKeyValueW, HKEY_CURRENT_USER},
+    },
+};
+
+use crate::settings::Settings;
+
+/// The registry key of the programs that Windows starts when the user logs in
+const RUN_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Run";
+const VALUE_NAME: &str = "popup-gpt";
+
+fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain([0]).collect()
+}
+
+/// Start the popup hidden when the user logs in if the settings enable it, with the same config
+/// file. Otherwise it is removed from the startup programs again.
+pub fn apply(settings: &Settings) -> Result<()> {
+    let key = wide(RUN_KEY);
+    let name = wide(VALUE_NAME);
+
+    if !settings.start_at_login.unwrap_or(false) {
+        let status = unsafe { RegDeleteKeyValueW(HKEY_CURRENT_USER, key.as_ptr(), name.as_ptr()) };
+        if status != ERROR_SUCCESS as i32 && status != ERROR_FILE_NOT_FOUND as i32 {
+            bail!("Could not remove popup-gpt from the startup programs ({status})");
+        }
+        return Ok(());
+    }
+
+    let exe = std::env::current_exe().context("Could not locate the executable")?;
+    let command = wide(&format!(
+        "\"{}\" --hidden --config \"{}\"",
+        exe.display(),
+        settings.file_location.display()
+    ));
+    let status = unsafe {
+        RegSetKeyValueW(
+            HKEY_CURRENT_USER,
+            key.as_ptr(),
+            name.as_ptr(),
+            REG_SZ,
+            command.as_ptr() as _,
+            (command.len() * std::mem::size_of::<u16>()) as u32,
+        )
+    };
+    if status != ERROR_SUCCESS as i32 {
+        bail!("Could not add popup-gpt to the startup programs ({status})");
+    }
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use std::ffi::c_void;
+
+use winapi::{
+    shared::{
+        minwindef::{BOOL, FARPROC},
+        windef::HWND,
+    },
+    um::{
+        dwmapi::{DwmExtendFrameIntoClientArea, DwmSetWindowAttribute},
+        libloaderapi::{GetModuleHandleA, GetProcAddress},
+        uxtheme::MARGINS,
+    },
+};
+
+use crate::settings::Backdrop;
+
+/// `DWMWA_SYSTEMBACKDROP_TYPE`, supported from Windows 11 22H2
+const DWMWA_SYSTEMBACKDROP_TYPE: u32 = 38;
+/// `DWMWA_WINDOW_CORNER_PREFERENCE`, supported from Windows 11
+const DWMWA_WINDOW_CORNER_PREFERENCE: u32 = 33;
+const DWMSBT_NONE: u32 = 1;
+const DWMSBT_MAINWINDOW: u32 = 2;
+const DWMSBT_TRANSIENTWINDOW: u32 = 3;
+const DWMWCP_ROUND: u32 = 2;
+
+/// `WCA_ACCENT_POLICY` of the undocumented `SetWindowCompositionAttribute`, the way to blur the
+/// background on Windows 10
+const WCA_ACCENT_POLICY: u32 = 19;
+const ACCENT_DISABLED: u32 = 0;
+const ACCENT_ENABLE_BLURBEHIND: u32 = 3;
+const ACCENT_ENABLE_ACRYLICBLURBEHIND: u32 = 4;
+
+#[repr(C)]
+struct AccentPolicy {
+    accent_state: u32,
+    accent_flags: u32,
+    /// The tint of the acrylic as `0xAABBGGRR`
+    gradient_color: u32,
+    animation_id: u32,
+}
+
+#[repr(C)]
+struct WindowCompositionAttributeData {
+    attribute: u32,
+    data: *mut c_void,
+    size: usize,
+}
+
+type SetWindowCompositionAttribute =
+    unsafe extern "system" fn(HWND, *mut WindowCompositionAttributeData) -> BOOL;
+
+/// Blur what is behind the window with the given material. Returns false if the system doesn't
+/// support it, the window then keeps its flat background.
+pub fn apply(window: u64, backdrop: Backdrop) -> bool {
+    let hwnd = window as HWND;
+
+    if set_system_backdrop(hwnd, backdrop) {
+        return true;
+    }
+
+    // Windows 10 has no Mica, acrylic is the closest
+    let accent_state = match backdrop {
+        Backdrop::None => ACCENT_DISABLED,
+        Backdrop::Blur => ACCENT_ENABLE_BLURBEHIND,
+        Backdrop::Acrylic | Backdrop::Mica => ACCENT_ENABLE_ACRYLICBLURBEHIND,
+    };
+    set_accent(hwnd, accent_state)
+}
+
+/// The backdrop materials of Windows 11. The frame is extended over the whole window, so the
+/// material shows through the transparent parts of the client area.
+fn set_system_backdrop(hwnd: HWND, backdrop: Backdrop) -> bool {
+    let backdrop_type = match backdrop {
+        Backdrop::None => DWMSBT_NONE,
+        Backdrop::Mica => DWMSBT_MAINWINDOW,
+        Backdrop::Acrylic => DWMSBT_TRANSIENTWINDOW,
+        // A plain blur only exists as the Windows 10 accent
+        Backdrop::Blur => return false,
+    };
+
+    let margin = if backdrop == Backdrop::None { 0 } else { -1 };
+    let margins = MARGINS {
+        cxLeftWidth: margin,
+        cxRightWidth: margin,
+        cyTopHeight: margin,
+        cyBottomHeight: margin,
+    };
+
+    unsafe {
+        let result = DwmSetWindowAttribute(
+            hwnd,
+            DWMWA_SYSTEMBACKDROP_TYPE,
+            &backdrop_type as *const u32 as _,
+            std::mem::size_of::<u32>() as u32,
+        );
+        if result < 0 {
+            return false;
+        }
+
+        DwmExtendFrameIntoClientArea(hwnd, &margins);
+        DwmSetWindowAttribute(
+            hwnd,
+            DWMWA_WINDOW_CORNER_PREFERENCE,
+            &DWMWCP_ROUND as *const u32 as _,
+            std::mem::size_of::<u32>() as u32,
+        );
+    }
+
+    true
+}
+
+fn set_accent(hwnd: HWND, accent_state: u32) -> bool {
+    let set_attribute = unsafe {
+        let user32 = GetModuleHandleA(c"user32.dll".as_ptr());
+        if user32.is_null() {
+            return false;
+        }
+        let proc = GetProcAddress(user32, c"SetWindowCompositionAttribute".as_ptr());
+        if proc.is_null() {
+            return false;
+        }
+        std::mem::transmute::<FARPROC, SetWindowCompositionAttribute>(proc)
+    };
+
+    let mut policy = AccentPolicy {
+        accent_state,
+        accent_flags: 0,
+        // A light tint of the frame color, the frame itself adds the rest
+        gradient_color: 0x103e_3632,
+        animation_id: 0,
+    };
+    let mut data = WindowCompositionAttributeData {
+        attribute: WCA_ACCENT_POLICY,
+        data: &mut policy as *mut AccentPolicy as _,
+        size: std::mem::size_of::<AccentPolicy>(),
+    };
+
+    unsafe { set_attribute(hwnd, &mut data) != 0 }
+}
@@ -0,0 +1,32 @@
+use winapi::um::wincrypt::{
+    CertCloseStore, CertEnumCertificatesInStore, CertOpenSystemStoreW, PCCERT_CONTEXT,
+};
+
+/// The root certificates of the Windows certificate store in DER, which include those that
+/// companies distribute to their computers
+pub fn root_certificates() -> Vec<Vec<u8>> {
+    let name: Vec<u16> = "ROOT".encode_utf16().chain([0]).collect();
+    let store = unsafe { CertOpenSystemStoreW(0, name.as_ptr()) };
+    if store.is_null() {
+        tracing::warn!("could not open the Windows certificate store");
+        return Vec::new();
+    }
+
+    let mut certificates = Vec::new();
+    let mut context: PCCERT_CONTEXT = std::ptr::null();
+    loop {
+        // Frees the previous context
+        context = unsafe { CertEnumCertificatesInStore(store, context) };
+        if context.is_null() {
+            break;
+        }
+        let der = unsafe {
+            let context = &*context;
+            std::slice::from_raw_parts(context.pbCertEncoded, context.cbCertEncoded as usize)
+        };
+        certificates.push(der.to_vec());
+    }
+
+    unsafe { CertCloseStore(store, 0) };
+    certificates
+}
@@ -1,46 +1,185 @@
-use std::sync::mpsc::Sender;
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc,
+    },
+    time::Duration,
+};
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    misc::SSEStream,
-    model::{CompletionRequest, CompletionResponse, Message, DEFAULT_MODEL},
+    http,
+    misc::{PollingReader, SSEStream, SseError},
+    model::{
+        ApiErrorResponse, AudioOutput, CompletionRequest, CompletionResponse, InputAudio, Message,
+        Role, StreamOptions, Tool, DEFAULT_MODEL,
+    },
 };
 
+pub const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
 pub const CHATGPT_ENDPOINT: &str = "https://api.openai.com/v1/chat/completions";
 
+pub const DEFAULT_SYSTEM_MESSAGE: &str = "You are a helpful AI assistant.";
+
+/// The API version used for Azure OpenAI deployments
+pub const AZURE_API_VERSION: &str = "2024-02-01";
+
+/// The kind of API the client talks to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Provider {
+    /// OpenAI or any OpenAI compatible API, authenticated with a bearer token
+    #[default]
+    OpenAi,
+    /// An Azure OpenAI deployment. The base URL is the deployment URL, e.g.
+    /// `https://{resource}.openai.azure.com/openai/deployments/{deployment}`, and the key is sent
+    /// in the `api-key` header.
+    Azure,
+}
+
+/// How the API answered a test request with the configured key, see [`ChatGPT::check_key`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyCheck {
+    /// The API accepted the key
+    Valid,
+    /// The API rejected the key, with the reason it gave
+    InvalidKey(String),
+    /// The API could not be reached, e.g. because of a wrong URL or no network
+    Unreachable(String),
+    /// The API accepted the key but failed the request for another reason, e.g. an unknown model
+    Failed(String),
+}
+
+/// The reply language that makes the model answer in the language of the prompt
+pub const REPLY_LANGUAGE_AUTO: &str = "auto";
+
+/// Where the partial responses of a stream go, e.g. a channel or straight to the UI
+pub trait StreamSink {
+    fn send_partial(&self, partial: CompletionResponse);
+}
+
+impl StreamSink for Sender<CompletionResponse> {
+    fn send_partial(&self, partial: CompletionResponse) {
+        // The receiver only displays the progress, the response is still complete without it
+        let _ = self.send(partial);
+    }
+}
+
+impl<T: StreamSink + ?Sized> StreamSink for &T {
+    fn send_partial(&self, partial: CompletionResponse) {
+        (**self).send_partial(partial);
+    }
+}
+
+/// The instruction for generating conversation titles
+const TITLE_PROMPT: &str = "Write a title of at most five words for the conversation above. \
+    Reply with the title only, without quotes or punctuation at the end.";
+
+/// A stream that didn't receive any data for this long is considered stalled and aborted
+pub const STREAM_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long [`ChatGPT::is_reachable`] waits for an answer
+const REACHABLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a blocked stream checks for cancellation
+const STREAM_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 #[derive(Debug, Clone, Default)]
 pub struct ChatGPT {
     endpoint: String,
+    provider: Provider,
     token: String,
     assistant: Assistant,
+    cancel: Arc<AtomicBool>,
+    /// The last streamed request if it failed on the way to the API, see
+    /// [`ChatGPT::retry_stream`]
+    failed: Option<CompletionRequest>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Assistant {
+    model: String,
     system_msg: String,
+    /// The system message the current conversation was started with. Changes to `system_msg`
+    /// only apply to new conversations.
+    conversation_system_msg: Option<String>,
     conversation: Vec<Message>,
+    /// Number of most likely alternatives to request log probabilities for. `None` disables
+    /// logprobs entirely.
+    top_logprobs: Option<u8>,
+    /// Sampling temperature, the API default if not set
+    temperature: Option<f32>,
+    /// The estimated number of tokens the sent conversation may use. Older messages that are not
+    /// pinned are left out to stay below it.
+    context_limit: Option<usize>,
+    /// The language every response is written in, see [`reply_language_instruction`]
+    reply_language: Option<String>,
+    /// The current conversation doesn't get the reply language instruction
+    skip_reply_language: bool,
+    /// The functions the model may call, none if empty
+    tools: Vec<Tool>,
+    /// The messages of the conversation that were dropped to save memory, without tool calls and
+    /// their results. Each entry is the index of the message they were before and their number.
+    spilled: Vec<(usize, usize)>,
 }
 
 impl Default for Assistant {
     fn default() -> Self {
         Self {
-            system_msg: "You are a helpful AI assistant.".to_string(),
+            model: DEFAULT_MODEL.to_string(),
+            system_msg: DEFAULT_SYSTEM_MESSAGE.to_string(),
+            conversation_system_msg: None,
             conversation: Vec::new(),
+            top_logprobs: None,
+            temperature: None,
+            context_limit: None,
+            reply_language: None,
+            skip_reply_language: false,
+            tools: Vec::new(),
+            spilled: Vec::new(),
         }
     }
 }
 
 impl Assistant {
-    fn generate_request(&self) -> CompletionRequest {
-        let mut messages = vec![Message::system(self.system_msg.clone())];
-        messages.extend(self.conversation.iter().cloned());
+    fn push_question(&mut self, question: Message) {
+        if self.conversation_system_msg.is_none() {
+            self.conversation_system_msg = Some(self.system_msg.clone());
+        }
+        self.conversation.push(question);
+    }
 
-        CompletionRequest {
-            model: DEFAULT_MODEL.to_string(),
-            messages,
-            ..Default::default()
+    fn generate_request(&self) -> Result<CompletionRequest> {
+        let mut messages = trim_context(&self.conversation, self.context_limit, &self.model);
+        if let Some(language) = self.reply_language.as_deref() {
+            if !self.skip_reply_language {
+                append_reply_language(&mut messages, language);
+            }
+        }
+
+        let mut builder = CompletionRequest::builder(&self.model)
+            .message(Message::system(
+                self.conversation_system_msg
+                    .as_ref()
+                    .unwrap_or(&self.system_msg),
+            ))
+            .messages(messages);
+
+        if let Some(temperature) = self.temperature {
+            builder = builder.temperature(temperature);
+        }
+        if let Some(top_logprobs) = self.top_logprobs {
+            builder = builder.logprobs(true).top_logprobs(top_logprobs);
         }
+        if !self.tools.is_empty() {
+            builder = builder.tools(self.tools.iter().cloned());
+        }
+
+        builder.build()
     }
 }
 
@@ -51,85 +190,903 @@ impl ChatGPT {
 
         Self {
             endpoint,
+            provider: Provider::OpenAi,
             token,
             assistant,
+            cancel: Arc::default(),
+            failed: None,
+        }
+    }
+
+    pub fn set_token(&mut self, token: String) {
+        self.token = token;
+    }
+
+    /// Use the given provider at the given base URL, e.g. `https://api.openai.com/v1` for OpenAI
+    pub fn set_provider(&mut self, provider: Provider, base_url: impl AsRef<str>) {
+        let base_url = base_url.as_ref().trim_end_matches('/');
+
+        self.provider = provider;
+        self.endpoint = match provider {
+            Provider::OpenAi => format!("{base_url}/chat/completions"),
+            Provider::Azure => {
+                format!("{base_url}/chat/completions?api-version={AZURE_API_VERSION}")
+            }
+        };
+    }
+
+    /// Use an OpenAI compatible API at the given base URL, e.g. `https://api.openai.com/v1`
+    pub fn set_base_url(&mut self, base_url: impl AsRef<str>) {
+        self.set_provider(Provider::OpenAi, base_url);
+    }
+
+    pub fn set_model(&mut self, model: impl AsRef<str>) {
+        self.assistant.model = model.as_ref().to_string();
+    }
+
+    /// Set the system message that is sent at the start of every request. A conversation that is
+    /// already running keeps the system message it was started with.
+    pub fn set_system_message(&mut self, system_msg: impl AsRef<str>) {
+        self.assistant.system_msg = system_msg.as_ref().to_string();
+    }
+
+    /// Replace the system message of the current conversation only, e.g. when a prompt template
+    /// is used. It is reset with the next `clear_conversation`.
+    pub fn set_conversation_system_message(&mut self, system_msg: impl AsRef<str>) {
+        self.assistant.conversation_system_msg = Some(system_msg.as_ref().to_string());
+    }
+
+    /// Open the connection to the API in advance, see [`http::warm_up`]
+    pub fn warm_up(&self) {
+        http::warm_up(&self.endpoint);
+    }
+
+    /// Whether the API answers at all, e.g. to find out when the network is back. Any status
+    /// counts, the key isn't checked.
+    pub fn is_reachable(&self) -> bool {
+        http::is_reachable(&self.endpoint, REACHABLE_TIMEOUT)
+    }
+
+    /// Add the token to a request in the way the provider expects it
+    fn authorize(&self, req_builder: ureq::Request) -> ureq::Request {
+        match self.provider {
+            Provider::OpenAi => req_builder.set("Authorization", &format!("Bearer {}", self.token)),
+            Provider::Azure => req_builder.set("api-key", &self.token),
         }
     }
 
     fn send_request(&self, req: CompletionRequest) -> Result<ureq::Response> {
-        let authorization = format!("Bearer {}", self.token);
+        tracing::debug!(endpoint = %self.endpoint, messages = req.messages.len(), "sending request");
 
-        let resp = ureq::post(&self.endpoint)
-            .set("Authorization", &authorization)
-            .send_json(req)?;
+        let request = || self.authorize(http::post(&self.endpoint));
 
-        Ok(resp)
+        // Requests reuse pooled connections, which the server may have closed in the meantime
+        let resp = match request().send_json(&req) {
+            Err(e) if http::connection_closed(&e) => {
+                tracing::debug!(error = %e, "retrying on a new connection");
+                request().send_json(&req)
+            }
+            resp => resp,
+        };
+
+        match resp {
+            Ok(resp) => Ok(resp),
+            Err(ureq::Error::Status(status, resp)) => {
+                let body = resp.into_string()?;
+                tracing::warn!(status, %body, "request failed");
+                Err(parse_error(&body)
+                    .unwrap_or_else(|| anyhow!("Request failed with status {status}: {body}")))
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "request failed");
+                Err(e.into())
+            }
+        }
     }
 
+    #[tracing::instrument(skip_all, fields(model = %req.model))]
     fn request(&self, req: CompletionRequest) -> Result<CompletionResponse> {
         let resp = self.send_request(req)?.into_string()?;
 
-        println!("{}", resp);
-
-        let resp: CompletionResponse = serde_json::from_str(&resp)?;
+        tracing::trace!(body = %resp, "received response");
 
-        Ok(resp)
+        parse_response(&resp)
     }
 
+    #[tracing::instrument(skip_all, fields(model = %req.model))]
     fn request_stream(
         &self,
         req: CompletionRequest,
-        sender: Sender<CompletionResponse>,
+        sender: impl StreamSink,
     ) -> Result<CompletionResponse> {
         let resp = self.send_request(req)?;
 
-        let stream = resp.into_reader();
-        let stream = SSEStream::new(stream);
+        let stream = PollingReader::new(resp.into_reader(), STREAM_POLL_INTERVAL);
+        let stream = SSEStream::new(stream)
+            .cancel_flag(Arc::clone(&self.cancel))
+            .idle_timeout(STREAM_IDLE_TIMEOUT);
 
         let mut response = CompletionResponse::default();
 
         for event in stream {
-            let partial_response: CompletionResponse = serde_json::from_str(&event)?;
+            let event = match event {
+                Ok(event) => event,
+                // Keep what was generated so far when the user stops the response
+                Err(SseError::Cancelled) => {
+                    tracing::debug!("stream cancelled");
+                    return Ok(response);
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "stream failed");
+                    return Err(e.into());
+                }
+            };
+            tracing::trace!(data = %event.data, "received event");
+
+            if event.data == "[DONE]" {
+                tracing::debug!(finish_reason = ?response.finish_reason(), "stream finished");
+                return Ok(response);
+            }
+
+            let partial_response = parse_response(&event.data)?;
 
             response.merge_delta(partial_response.clone());
-            sender.send(partial_response).unwrap();
+            sender.send_partial(partial_response);
+        }
+
+        tracing::warn!("stream ended before [DONE]");
+        bail!("The response stream ended before the response was complete")
+    }
+
+    /// Request log probabilities with the given number of alternatives per token, or disable
+    /// them with `None`
+    pub fn set_logprobs(&mut self, top_logprobs: Option<u8>) {
+        self.assistant.top_logprobs = top_logprobs;
+    }
+
+    pub fn model(&self) -> &str {
+        &self.assistant.model
+    }
+
+    /// The system message of the current conversation, or the one a new conversation will use
+    pub fn conversation_system_message(&self) -> &str {
+        self.assistant
+            .conversation_system_msg
+            .as_ref()
+            .unwrap_or(&self.assistant.system_msg)
+    }
+
+    /// Set the sampling temperature, or use the API default with `None`
+    pub fn set_temperature(&mut self, temperature: Option<f32>) {
+        self.assistant.temperature = temperature;
+    }
+
+    /// A flag that stops a running `ask_stream` when set. The handle can be obtained up front, so
+    /// that a stream can be cancelled while another thread holds the client.
+    pub fn cancel_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.cancel)
+    }
+
+    /// The questions and responses of the current conversation, without the system message
+    pub fn conversation(&self) -> &[Message] {
+        &self.assistant.conversation
+    }
+
+    /// The tokens the next request uses if the prompt is sent, including the system message and
+    /// the context that fits into the limit. Estimated for models without a known tokenizer.
+    pub fn estimated_request_tokens(&self, prompt: &str) -> usize {
+        let model = &self.assistant.model;
+        let mut messages = self.assistant.conversation.clone();
+        messages.push(Message::user(prompt));
+
+        let context: usize = trim_context(&messages, self.assistant.context_limit, model)
+            .iter()
+            .map(|message| message.tokens(model))
+            .sum();
+        context + Message::system(self.conversation_system_message()).tokens(model)
+    }
+
+    /// Drop all messages after the first `len` ones, e.g. to branch off at an earlier message
+    pub fn truncate_conversation(&mut self, len: usize) {
+        self.failed = None;
+        self.assistant.conversation.truncate(len);
+        self.assistant.spilled.retain(|&(before, _)| before <= len);
+    }
+
+    /// The number of the messages that are no longer in [`Self::conversation`], without tool
+    /// calls and their results, which are not saved
+    pub fn spilled(&self) -> usize {
+        self.assistant.spilled.iter().map(|&(_, count)| count).sum()
+    }
+
+    /// The index of a message of [`Self::conversation`] among the saved messages, which are the
+    /// questions and answers without the tool calls and their results. For a tool call or result
+    /// it is the index of the next saved message.
+    pub fn saved_index(&self, index: usize) -> usize {
+        let conversation = &self.assistant.conversation;
+        let saved = conversation[..index.min(conversation.len())]
+            .iter()
+            .filter(|message| !message.is_tool_exchange())
+            .count();
+        let spilled: usize = self
+            .assistant
+            .spilled
+            .iter()
+            .filter(|&&(before, _)| before <= index)
+            .map(|&(_, count)| count)
+            .sum();
+        spilled + saved
+    }
+
+    /// Drop the oldest messages until at most `max` are left, e.g. once they are saved in the
+    /// history. Pinned messages and the last message are kept in place, and tool results are
+    /// dropped together with their call.
+    pub fn spill_conversation(&mut self, max: usize) {
+        let conversation = &self.assistant.conversation;
+        let mut excess = conversation.len().saturating_sub(max);
+        let mut drop = vec![false; conversation.len()];
+        for group in tool_groups(conversation) {
+            if excess == 0 {
+                break;
+            }
+            let pinned = conversation[group.clone()]
+                .iter()
+                .any(|message| message.pinned);
+            if group.end != conversation.len() && !pinned {
+                excess = excess.saturating_sub(group.len());
+                drop[group].fill(true);
+            }
+        }
+
+        // Note where the dropped messages were, among the earlier dropped ones
+        let mut spilled = Vec::new();
+        let mut earlier = std::mem::take(&mut self.assistant.spilled)
+            .into_iter()
+            .peekable();
+        let mut pending = 0;
+        let mut kept = Vec::new();
+        let old = std::mem::take(&mut self.assistant.conversation);
+        let len = old.len();
+        for (i, (message, drop)) in old.into_iter().zip(drop).enumerate() {
+            while let Some((_, count)) = earlier.next_if(|&(before, _)| before <= i) {
+                pending += count;
+            }
+            if drop {
+                pending += usize::from(!message.is_tool_exchange());
+                continue;
+            }
+            if pending > 0 {
+                spilled.push((kept.len(), std::mem::take(&mut pending)));
+            }
+            kept.push(message);
+        }
+        pending += earlier
+            .filter(|&(before, _)| before <= len)
+            .map(|(_, count)| count)
+            .sum::<usize>();
+        if pending > 0 {
+            spilled.push((kept.len(), pending));
+        }
+
+        self.assistant.conversation = kept;
+        self.assistant.spilled = spilled;
+    }
+
+    /// Limit the estimated tokens of the sent conversation, or send all of it with `None`
+    pub fn set_context_limit(&mut self, context_limit: Option<usize>) {
+        self.assistant.context_limit = context_limit;
+    }
+
+    /// Make every response use the language, whatever the system prompt says.
+    /// [`REPLY_LANGUAGE_AUTO`] answers in the language of the prompt.
+    pub fn set_reply_language(&mut self, language: Option<String>) {
+        self.assistant.reply_language = language;
+    }
+
+    /// Leave out the reply language in the current conversation, e.g. for translations. It is
+    /// reset with the next `clear_conversation`.
+    pub fn skip_reply_language(&mut self) {
+        self.assistant.skip_reply_language = true;
+    }
+
+    /// Offer the functions to the model, or none with an empty list
+    pub fn set_tools(&mut self, tools: Vec<Tool>) {
+        self.assistant.tools = tools;
+    }
+
+    /// Answer a tool call of the last response, the next `continue_stream` sends the result
+    pub fn push_tool_result(&mut self, tool_call_id: impl AsRef<str>, result: impl AsRef<str>) {
+        self.assistant
+            .conversation
+            .push(Message::tool(tool_call_id, result));
+    }
+
+    /// Keep a message in the context when it is trimmed
+    pub fn set_message_pinned(&mut self, index: usize, pinned: bool) {
+        if let Some(message) = self.assistant.conversation.get_mut(index) {
+            message.pinned = pinned;
+        }
+    }
+
+    /// Exclude a message from the requests without removing it from the conversation. A tool call
+    /// and its results are muted together.
+    pub fn set_message_muted(&mut self, index: usize, muted: bool) {
+        let group = self.tool_group(index);
+        for message in &mut self.assistant.conversation[group] {
+            message.muted = muted;
+        }
+    }
+
+    /// The messages that belong together with the message at the index, see [`tool_groups`]
+    fn tool_group(&self, index: usize) -> Range<usize> {
+        tool_groups(&self.assistant.conversation)
+            .into_iter()
+            .find(|group| group.contains(&index))
+            .unwrap_or_default()
+    }
+
+    pub fn set_message_content(&mut self, index: usize, content: impl AsRef<str>) {
+        if let Some(message) = self.assistant.conversation.get_mut(index) {
+            message.content = content.as_ref().to_string();
         }
+    }
 
-        Ok(response)
+    /// Remove a message from the conversation, a tool call together with its results
+    pub fn remove_message(&mut self, index: usize) {
+        let group = self.tool_group(index);
+        if !group.is_empty() {
+            self.failed = None;
+            self.assistant.conversation.drain(group.clone());
+            for (before, _) in &mut self.assistant.spilled {
+                if *before > group.start {
+                    *before = group.start.max(*before - group.len());
+                }
+            }
+        }
     }
 
     pub fn clear_conversation(&mut self) {
+        self.failed = None;
         self.assistant.conversation.clear();
+        self.assistant.spilled.clear();
+        self.assistant.conversation_system_msg = None;
+        self.assistant.skip_reply_language = false;
+    }
+
+    /// Replace the current conversation, e.g. to continue a saved one
+    pub fn set_conversation(&mut self, system_msg: impl AsRef<str>, conversation: Vec<Message>) {
+        self.failed = None;
+        self.assistant.conversation_system_msg = Some(system_msg.as_ref().to_string());
+        self.assistant.conversation = conversation;
+        self.assistant.spilled.clear();
+        self.assistant.skip_reply_language = false;
     }
 
     pub fn ask(&mut self, question: impl AsRef<str>) -> Result<CompletionResponse> {
-        self.assistant.conversation.push(Message::user(question));
+        self.assistant.push_question(Message::user(question));
 
-        let req = self.assistant.generate_request();
-        let resp = self.request(req)?;
+        let resp = match self
+            .assistant
+            .generate_request()
+            .and_then(|req| self.request(req))
+        {
+            Ok(resp) => resp,
+            Err(e) => {
+                self.drop_unanswered_question();
+                return Err(e);
+            }
+        };
 
-        self.assistant
-            .conversation
-            .push(resp.choices[0].message.as_ref().unwrap().clone());
+        let Some(message) = resp
+            .choices
+            .first()
+            .and_then(|choice| choice.message.clone())
+        else {
+            self.assistant.conversation.pop();
+            bail!("The response contains no message");
+        };
+        self.assistant.conversation.push(message);
 
         Ok(resp)
     }
 
+    /// Ask for a short title for an exchange. This is a separate request that doesn't change the
+    /// conversation.
+    pub fn suggest_title(&self, question: &str, answer: &str) -> Result<String> {
+        let req = CompletionRequest::builder(&self.assistant.model)
+            .message(Message::user(question))
+            .message(Message::assistant(answer))
+            .message(Message::user(TITLE_PROMPT))
+            .max_tokens(20)
+            .build()?;
+
+        let resp = self.request(req)?;
+        let title = resp
+            .primary_response()
+            .unwrap_or_default()
+            .trim()
+            .trim_matches(|c: char| c == '"' || c == '\'' || c == '.')
+            .to_string();
+
+        Ok(title)
+    }
+
+    /// Test the token and endpoint with a request for a single token, without changing the
+    /// conversation
+    pub fn check_key(&self) -> KeyCheck {
+        let req = CompletionRequest::builder(&self.assistant.model)
+            .message(Message::user("Hi"))
+            .max_tokens(1)
+            .build();
+        let req = match req {
+            Ok(req) => req,
+            Err(e) => return KeyCheck::Failed(format!("{e:#}")),
+        };
+
+        match self.authorize(http::post(&self.endpoint)).send_json(&req) {
+            Ok(_) => KeyCheck::Valid,
+            Err(ureq::Error::Status(status, resp)) => {
+                let body = resp.into_string().unwrap_or_default();
+                tracing::debug!(status, %body, "key check failed");
+                let reason = parse_error(&body)
+                    .map(|e| e.to_string())
+                    .unwrap_or_else(|| format!("Request failed with status {status}"));
+                match status {
+                    401 | 403 => KeyCheck::InvalidKey(reason),
+                    _ => KeyCheck::Failed(reason),
+                }
+            }
+            Err(e) => KeyCheck::Unreachable(e.to_string()),
+        }
+    }
+
     pub fn ask_stream(
         &mut self,
         question: impl AsRef<str>,
-        sender: Sender<CompletionResponse>,
+        sender: impl StreamSink,
+    ) -> Result<CompletionResponse> {
+        self.ask_stream_message(Message::user(question), sender)
+    }
+
+    /// Ask with a recording, e.g. a WAV file, and get a spoken answer in the voice. Needs an audio
+    /// model like `gpt-4o-audio-preview`. The transcript of the answer is its content, the WAV is
+    /// in its `audio`.
+    pub fn ask_audio(
+        &mut self,
+        question: impl AsRef<str>,
+        wav: &[u8],
+        voice: &str,
     ) -> Result<CompletionResponse> {
-        self.assistant.conversation.push(Message::user(question));
+        self.assistant.push_question(Message {
+            input_audio: Some(InputAudio::wav(wav)),
+            ..Message::user(question)
+        });
 
-        let mut req = self.assistant.generate_request();
+        let mut req = self.assistant.generate_request()?;
+        req.modalities = Some(vec!["text".to_string(), "audio".to_string()]);
+        req.audio = Some(AudioOutput {
+            voice: voice.to_string(),
+            format: "wav".to_string(),
+        });
+        let mut resp = match self.request(req) {
+            Ok(resp) => resp,
+            Err(e) => {
+                self.assistant.conversation.pop();
+                return Err(e);
+            }
+        };
+
+        let Some(message) = resp
+            .choices
+            .first_mut()
+            .and_then(|choice| choice.message.as_mut())
+        else {
+            self.assistant.conversation.pop();
+            bail!("The response contains no message");
+        };
+        // Spoken answers have no content, the transcript stands in for it
+        if let Some(audio) = message
+            .audio
+            .as_ref()
+            .filter(|_| message.content.is_empty())
+        {
+            message.content = audio.transcript.clone();
+        }
+        self.assistant.conversation.push(message.clone());
+
+        Ok(resp)
+    }
+
+    /// Like `ask_stream`, but with a prepared user message, e.g. one with images
+    pub fn ask_stream_message(
+        &mut self,
+        question: Message,
+        sender: impl StreamSink,
+    ) -> Result<CompletionResponse> {
+        self.assistant.push_question(question);
+
+        let len = self.assistant.conversation.len();
+        let resp = match self.continue_stream(sender) {
+            Ok(resp) => resp,
+            Err(e) => {
+                if !self.can_retry() {
+                    self.drop_unanswered_question();
+                }
+                return Err(e);
+            }
+        };
+        // The stream was cancelled before anything was generated, so drop the question again
+        if self.assistant.conversation.len() == len {
+            self.assistant.conversation.pop();
+        }
+
+        Ok(resp)
+    }
+
+    /// Request a response to the conversation as it is, e.g. after the results of tool calls were
+    /// pushed
+    pub fn continue_stream(&mut self, sender: impl StreamSink) -> Result<CompletionResponse> {
+        let mut req = self.assistant.generate_request()?;
         req.stream = Some(true);
-        let resp = self.request_stream(req, sender)?;
+        req.stream_options = Some(StreamOptions {
+            include_usage: true,
+        });
+        self.send_stream(req, sender)
+    }
 
-        self.assistant
-            .conversation
-            .push(resp.choices[0].message.as_ref().unwrap().clone());
+    /// Whether the last streamed request failed on the way to the API and can be sent again with
+    /// [`ChatGPT::retry_stream`]
+    pub fn can_retry(&self) -> bool {
+        self.failed.is_some()
+    }
+
+    /// Send the last streamed request again, e.g. once the network is back. The question is part
+    /// of the conversation since the first attempt, so it isn't added again.
+    pub fn retry_stream(&mut self, sender: impl StreamSink) -> Result<CompletionResponse> {
+        let Some(req) = self.failed.take() else {
+            bail!("There is no failed request to send again");
+        };
+        let resp = self.send_stream(req, sender);
+        if resp.is_err() && !self.can_retry() {
+            self.drop_unanswered_question();
+        }
+        resp
+    }
+
+    /// Remove the question of a request that failed for good. It would be sent again, without an
+    /// answer, with every following request.
+    fn drop_unanswered_question(&mut self) {
+        let conversation = &mut self.assistant.conversation;
+        if conversation
+            .last()
+            .is_some_and(|message| matches!(message.role, Role::User))
+        {
+            conversation.pop();
+        }
+    }
+
+    /// Stream the response to the request and add it to the conversation. The request is kept
+    /// for a retry if it fails on the way to the API.
+    fn send_stream(
+        &mut self,
+        req: CompletionRequest,
+        sender: impl StreamSink,
+    ) -> Result<CompletionResponse> {
+        self.cancel.store(false, Ordering::Relaxed);
+        self.failed = None;
+        let resp = match self.request_stream(req.clone(), sender) {
+            Ok(resp) => resp,
+            Err(e) => {
+                if http::is_network_error(&e) {
+                    self.failed = Some(req);
+                }
+                return Err(e);
+            }
+        };
+
+        if let Some(message) = resp
+            .choices
+            .first()
+            .and_then(|choice| choice.message.clone())
+        {
+            self.assistant.conversation.push(message);
+        }
 
         Ok(resp)
     }
 }
+
+/// The messages of a conversation that are sent: all messages that aren't muted, without the
+/// oldest ones that don't fit into the limit. Pinned messages and the last message are always
+/// kept. A tool call and its results are kept or left out together, the API rejects one without
+/// the other. The tokens are counted with the tokenizer of the model.
+fn trim_context(conversation: &[Message], limit: Option<usize>, model: &str) -> Vec<Message> {
+    let mut messages: Vec<_> = conversation
+        .iter()
+        .filter(|message| !message.muted)
+        .cloned()
+        .collect();
+    let calls: Vec<_> = messages
+        .iter()
+        .flat_map(|message| message.tool_calls.iter().flatten())
+        .map(|call| call.id.clone())
+        .collect();
+    messages.retain(|message| {
+        !matches!(message.role, Role::Tool)
+            || message
+                .tool_call_id
+                .as_ref()
+                .is_some_and(|id| calls.contains(id))
+    });
+
+    let Some(limit) = limit else {
+        return messages;
+    };
+
+    let tokens_of = |message: &Message| message.tokens(model);
+    let mut tokens: usize = messages.iter().map(tokens_of).sum();
+    let mut keep = vec![true; messages.len()];
+
+    for group in tool_groups(&messages) {
+        if tokens <= limit {
+            break;
+        }
+        let group_messages = &messages[group.clone()];
+        if group.end != messages.len() && !group_messages.iter().any(|message| message.pinned) {
+            keep[group].fill(false);
+            tokens -= group_messages.iter().map(tokens_of).sum::<usize>();
+        }
+    }
+
+    let mut keep = keep.into_iter();
+    messages.retain(|_| keep.next().unwrap_or(true));
+    messages
+}
+
+/// The ranges of messages that are sent or left out together: a message with tool calls and the
+/// results that follow it, every other message on its own
+fn tool_groups(messages: &[Message]) -> Vec<Range<usize>> {
+    let mut groups = Vec::new();
+    let mut start = 0;
+    while start < messages.len() {
+        let mut end = start + 1;
+        if let Some(calls) = &messages[start].tool_calls {
+            while messages.get(end).is_some_and(|message| {
+                matches!(message.role, Role::Tool)
+                    && calls
+                        .iter()
+                        .any(|call| message.tool_call_id.as_ref() == Some(&call.id))
+            }) {
+                end += 1;
+            }
+        }
+        groups.push(start..end);
+        start = end;
+    }
+    groups
+}
+
+/// The instruction that makes the model answer in the language
+fn reply_language_instruction(language: &str) -> String {
+    match language.trim() {
+        REPLY_LANGUAGE_AUTO => "Answer in the language of my message above.".to_string(),
+        language => format!("Answer in {language}."),
+    }
+}
+
+/// Append the reply language instruction to the last user message of the sent messages. It is
+/// only part of the request, the conversation keeps the message as it was typed.
+fn append_reply_language(messages: &mut [Message], language: &str) {
+    let Some(question) = messages
+        .iter_mut()
+        .rev()
+        .find(|message| matches!(message.role, Role::User))
+    else {
+        return;
+    };
+    question.content = format!(
+        "{}\n\n{}",
+        question.content,
+        reply_language_instruction(language)
+    );
+}
+
+/// Parse a CompletionResponse, falling back to the API error payload when the body is not a valid
+/// response so that the actual cause of the failure is reported
+fn parse_response(body: &str) -> Result<CompletionResponse> {
+    serde_json::from_str(body).map_err(|e| parse_error(body).unwrap_or_else(|| e.into()))
+}
+
+fn parse_error(body: &str) -> Option<anyhow::Error> {
+    serde_json::from_str::<ApiErrorResponse>(body)
+        .ok()
+        .map(|resp| resp.error.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::ToolCall;
+
+    fn message(content: &str, pinned: bool) -> Message {
+        Message {
+            pinned,
+            ..Message::user(content)
+        }
+    }
+
+    /// An assistant message that only calls the tools with the IDs
+    fn tool_calls(ids: &[&str]) -> Message {
+        let calls = ids
+            .iter()
+            .map(|id| ToolCall {
+                id: id.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        Message {
+            tool_calls: Some(calls),
+            ..Message::assistant("")
+        }
+    }
+
+    #[test]
+    fn trim_keeps_pinned_and_last_messages() {
+        // Every message is estimated at 4 + 4 tokens
+        let conversation = [
+            message("aaaaaaaaaaaaaaaa", true),
+            message("bbbbbbbbbbbbbbbb", false),
+            message("cccccccccccccccc", false),
+            message("dddddddddddddddd", false),
+        ];
+
+        let contents = |limit| -> Vec<String> {
+            trim_context(&conversation, limit, "")
+                .into_iter()
+                .map(|message| message.content[..1].to_string())
+                .collect()
+        };
+
+        assert_eq!(contents(None), ["a", "b", "c", "d"]);
+        assert_eq!(contents(Some(24)), ["a", "c", "d"]);
+        assert_eq!(contents(Some(0)), ["a", "d"]);
+    }
+
+    #[test]
+    fn trim_keeps_tool_calls_with_their_results() {
+        let conversation = [
+            Message::user("aaaaaaaaaaaaaaaa"),
+            tool_calls(&["1", "2"]),
+            Message::tool("1", "bbbbbbbbbbbbbbbb"),
+            Message::tool("2", "cccccccccccccccc"),
+            Message::assistant("dddddddddddddddd"),
+            Message::tool("3", "without a call"),
+            Message::user("eeeeeeeeeeeeeeee"),
+        ];
+
+        let roles = |limit| -> Vec<&str> {
+            trim_context(&conversation, limit, "")
+                .iter()
+                .map(|message| message.role.as_str())
+                .collect()
+        };
+
+        assert_eq!(
+            roles(None),
+            ["user", "assistant", "tool", "tool", "assistant", "user"]
+        );
+        // Dropping the first question isn't enough, the call goes with both results
+        assert_eq!(roles(Some(30)), ["assistant", "user"]);
+
+        let mut pinned = conversation.clone();
+        pinned[3].pinned = true;
+        let kept = trim_context(&pinned, Some(0), "");
+        let roles: Vec<_> = kept.iter().map(|message| message.role.as_str()).collect();
+        assert_eq!(roles, ["assistant", "tool", "tool", "user"]);
+    }
+
+    #[test]
+    fn tool_calls_are_muted_and_removed_with_their_results() {
+        let mut chatgpt = ChatGPT::default();
+        chatgpt.set_conversation(
+            "system",
+            vec![
+                Message::user("a"),
+                tool_calls(&["1"]),
+                Message::tool("1", "result"),
+                Message::assistant("answer"),
+            ],
+        );
+
+        chatgpt.set_message_muted(2, true);
+        let muted: Vec<_> = chatgpt.conversation().iter().map(|m| m.muted).collect();
+        assert_eq!(muted, [false, true, true, false]);
+
+        chatgpt.remove_message(1);
+        let contents: Vec<_> = chatgpt.conversation().iter().map(|m| &m.content).collect();
+        assert_eq!(contents, ["a", "answer"]);
+        chatgpt.remove_message(5);
+        assert_eq!(chatgpt.conversation().len(), 2);
+    }
+
+    #[test]
+    fn reply_language_is_appended_to_the_last_question() {
+        let mut messages = vec![
+            Message::user("Hallo"),
+            Message::assistant("Hi"),
+            Message::user("Wie geht's?"),
+            Message::assistant("Gut"),
+        ];
+
+        append_reply_language(&mut messages, "English");
+        assert_eq!(messages[2].content, "Wie geht's?\n\nAnswer in English.");
+        assert_eq!(messages[0].content, "Hallo");
+
+        append_reply_language(&mut messages[..1], REPLY_LANGUAGE_AUTO);
+        assert!(messages[0]
+            .content
+            .ends_with("the language of my message above."));
+    }
+
+    #[test]
+    fn spilling_keeps_pinned_messages_and_tool_results() {
+        let mut chatgpt = ChatGPT::default();
+        let conversation = ["a", "b", "c", "d", "e"].map(Message::user).to_vec();
+        chatgpt.set_conversation("system", conversation);
+
+        chatgpt.spill_conversation(3);
+        assert_eq!(chatgpt.spilled(), 2);
+        assert_eq!(chatgpt.conversation()[0].content, "c");
+
+        chatgpt.set_message_pinned(1, true);
+        chatgpt.spill_conversation(1);
+        assert_eq!(chatgpt.spilled(), 3);
+        assert_eq!(chatgpt.conversation()[0].content, "d");
+
+        chatgpt.set_conversation(
+            "system",
+            vec![
+                Message::user("a"),
+                tool_calls(&["1"]),
+                Message::tool("1", "result"),
+                Message::assistant("answer"),
+                Message::user("b"),
+            ],
+        );
+        assert_eq!(chatgpt.saved_index(3), 1);
+        chatgpt.spill_conversation(3);
+        // The tool call and its result are not saved, so they don't count
+        assert_eq!(chatgpt.spilled(), 1);
+        assert_eq!(chatgpt.conversation()[0].content, "answer");
+        assert_eq!(chatgpt.saved_index(1), 2);
+    }
+
+    #[test]
+    fn spilling_keeps_pinned_messages_in_place() {
+        let mut chatgpt = ChatGPT::default();
+        let conversation = ["a", "b", "c", "d", "e"].map(Message::user).to_vec();
+        chatgpt.set_conversation("system", conversation);
+        chatgpt.set_message_pinned(0, true);
+
+        chatgpt.spill_conversation(2);
+        let contents: Vec<_> = chatgpt.conversation().iter().map(|m| &m.content).collect();
+        assert_eq!(contents, ["a", "e"]);
+        assert_eq!(chatgpt.spilled(), 3);
+        assert_eq!(chatgpt.saved_index(0), 0);
+        assert_eq!(chatgpt.saved_index(1), 4);
+
+        // The pinned message is before the dropped ones, so removing it moves them up
+        chatgpt.remove_message(0);
+        assert_eq!(chatgpt.saved_index(0), 3);
+        chatgpt.truncate_conversation(0);
+        assert_eq!(chatgpt.spilled(), 3);
+    }
+
+    #[test]
+    fn trim_skips_muted_messages() {
+        let mut conversation = vec![Message::user("a"), Message::user("b")];
+        conversation[0].muted = true;
+
+        assert_eq!(trim_context(&conversation, None, "").len(), 1);
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    io::{IsTerminal, Read, Write},
+    path::PathBuf,
+    sync::mpsc::channel,
+};
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use popup_gpt::{
+    chatgpt::ChatGPT,
+    history::History,
+    model::{CompletionResponse, DEFAULT_AUDIO_MODEL, DEFAULT_VOICE},
+};
+
+use crate::{audio, settings::Settings};
+
+/// A small always-on-top ChatGPT popup, summoned with Ctrl+Alt+K
+#[derive(Debug, Parser)]
+#[command(version)]
+pub struct Cli {
+    /// Use the given config file instead of the one in the user config directory
+    #[arg(long, value_name = "PATH", conflicts_with = "portable")]
+    pub config: Option<PathBuf>,
+
+    /// Keep the config file next to the executable, e.g. to run from a USB stick
+    #[arg(long)]
+    pub portable: bool,
+
+    /// Start with the given settings profile instead of the last active one
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Start hidden in the background and accept requests from other programs on the
+    /// `popup-gpt` local socket
+    #[arg(long)]
+    pub daemon: bool,
+
+    /// Start hidden in the background and wait for the hotkey, e.g. when started at login
+    #[arg(long)]
+    pub hidden: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Ask a question and print the answer to the terminal instead of opening the popup. Text
+    /// piped into stdin is appended to the question.
+    Ask {
+        /// The question, the words don't need to be quoted
+        question: Vec<String>,
+
+        /// Don't save the question and answer in the history
+        #[arg(long)]
+        no_history: bool,
+
+        /// Ask with a recorded WAV file and play the spoken answer. The question words are sent
+        /// along with it.
+        #[arg(long, value_name = "WAV")]
+        audio: Option<PathBuf>,
+
+        /// Save the spoken answer to a WAV file instead of playing it
+        #[arg(long, value_name = "PATH", requires = "audio")]
+        save_audio: Option<PathBuf>,
+    },
+}
+
+impl Cli {
+    /// The config file to use according to the command line flags
+    pub fn settings_path(&self) -> Result<PathBuf> {
+        if let Some(config) = &self.config {
+            return Ok(config.clone());
+        }
+
+        if self.portable {
+            let exe = std::env::current_exe().context("Could not locate the executable")?;
+            let dir = exe
+                .parent()
+                .context("The executable has no parent directory")?;
+
+            return Ok(dir.join("popup-gpt.json"));
+        }
+
+        Settings::default_path()
+    }
+}
+
+/// Answer a question from the command line, streaming the answer to stdout
+pub fn ask(settings: &Settings, question: Vec<String>, no_history: bool) -> Result<()> {
+    let mut question = question.join(" ");
+
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        let mut input = String::new();
+        stdin
+            .lock()
+            .read_to_string(&mut input)
+            .context("Could not read stdin")?;
+
+        if !input.trim().is_empty() {
+            if !question.is_empty() {
+                question.push_str("\n\n");
+            }
+            question.push_str(input.trim_end());
+        }
+    }
+
+    if question.trim().is_empty() {
+        bail!("There is no question to ask, pass it as arguments or pipe it into stdin");
+    }
+
+    let mut chatgpt = ChatGPT::new(String::new());
+    settings.configure(&mut chatgpt, None)?;
+
+    let (tx, rx) = channel::<CompletionResponse>();
+    let printer = std::thread::spawn(move || {
+        let mut stdout = std::io::stdout().lock();
+        for resp in rx {
+            let delta = resp
+                .choices
+                .first()
+                .and_then(|choice| choice.delta.as_ref())
+                .and_then(|delta| delta.content.as_deref());
+            if let Some(delta) = delta {
+                let _ = stdout.write_all(delta.as_bytes());
+                let _ = stdout.flush();
+            }
+        }
+        let _ = writeln!(stdout);
+    });
+
+    let resp = chatgpt.ask_stream(&question, tx);
+    let _ = printer.join();
+    let resp = resp?;
+
+    if !no_history {
+        let history = History::open(&settings.history_path())?;
+        if let Some(saved) = crate::save_exchange(&history, None, &chatgpt, &question, &resp) {
+            saved?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Ask with a recorded WAV file, print the transcript of the spoken answer and play it or save it
+pub fn ask_audio(
+    settings: &Settings,
+    question: Vec<String>,
+    wav: PathBuf,
+    save_audio: Option<PathBuf>,
+    no_history: bool,
+) -> Result<()> {
+    let question = question.join(" ");
+    let recording =
+        std::fs::read(&wav).with_context(|| format!("Could not read {}", wav.display()))?;
+
+    let mut chatgpt = ChatGPT::new(String::new());
+    settings.configure(&mut chatgpt, None)?;
+    chatgpt.set_model(
+        settings
+            .audio_model
+            .as_deref()
+            .unwrap_or(DEFAULT_AUDIO_MODEL),
+    );
+    let voice = settings.voice.as_deref().unwrap_or(DEFAULT_VOICE);
+
+    let resp = chatgpt.ask_audio(&question, &recording, voice)?;
+    let answer = resp
+        .choices
+        .first()
+        .and_then(|choice| choice.message.as_ref())
+        .context("The response contains no message")?;
+    println!("{}", answer.content);
+
+    match answer
+        .audio
+        .as_ref()
+        .map(|audio| audio.decode())
+        .transpose()?
+    {
+        Some(speech) => match &save_audio {
+            Some(path) => std::fs::write(path, speech)
+                .with_context(|| format!("Could not write {}", path.display()))?,
+            None => audio::play_wav(&speech)?,
+        },
+        None => tracing::warn!("the response has no audio, is the model an audio model?"),
+    }
+
+    if !no_history {
+        // The history only keeps text, so the recording is named instead
+        let question = match question.is_empty() {
+            true => format!("[{}]", wav.display()),
+            false => format!("{question} [{}]", wav.display()),
+        };
+        let history = History::open(&settings.history_path())?;
+        if let Some(saved) = crate::save_exchange(&history, None, &chatgpt, &question, &resp) {
+            saved?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Connect stdout and stderr to the console the program was started from. The popup is built as a
+/// GUI application, which doesn't get a console by default.
+pub fn attach_console() {
+    use winapi::um::wincon::{AttachConsole, ATTACH_PARENT_PROCESS};
+
+    // Fails if stdout is redirected or there is no parent console, which is fine in both cases
+    unsafe { AttachConsole(ATTACH_PARENT_PROCESS) };
+}
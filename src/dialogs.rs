@@ -0,0 +1,221 @@
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use anyhow::Context;
+use eframe::NativeOptions;
+use egui::{Color32, RichText, Vec2};
+use popup_gpt::{
+    export::{export, ExportFormat},
+    history::SavedConversation,
+    hotkey::Hotkey,
+};
+
+use crate::i18n::tr;
+
+/// Open a file or directory with the application that is associated with it
+pub fn open_path(path: &Path) {
+    // Explorer returns a non-zero exit code even on success, so the result is not checked
+    let _ = std::process::Command::new("explorer").arg(path).spawn();
+}
+
+/// Ask for a file and export the conversation to it. The format is chosen by the file extension.
+pub fn export_conversation(conversation: &SavedConversation, title: &str) -> anyhow::Result<()> {
+    let mut dialog = rfd::FileDialog::new().set_file_name(&format!("{}.md", file_name(title)));
+    for format in ExportFormat::ALL {
+        dialog = dialog.add_filter(format.name(), &[format.extension()]);
+    }
+    let Some(path) = dialog.save_file() else {
+        return Ok(());
+    };
+
+    let format = path
+        .extension()
+        .and_then(|extension| ExportFormat::from_extension(&extension.to_string_lossy()))
+        .unwrap_or(ExportFormat::Markdown);
+
+    std::fs::write(&path, export(conversation, title, format)?)
+        .with_context(|| format!("Could not write {}", path.display()))
+}
+
+/// Ask for a file and save a code block from the response to it
+pub fn save_code_block(code: &str, extension: &str) -> anyhow::Result<()> {
+    let Some(path) = rfd::FileDialog::new()
+        .set_file_name(&format!("code.{extension}"))
+        .add_filter(extension, &[extension])
+        .save_file()
+    else {
+        return Ok(());
+    };
+
+    std::fs::write(&path, code).with_context(|| format!("Could not write {}", path.display()))
+}
+
+/// Turn a title into a file name by dropping the characters Windows doesn't allow
+fn file_name(title: &str) -> String {
+    let name: String = title
+        .chars()
+        .filter(|c| {
+            !matches!(
+                c,
+                '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' | '…'
+            )
+        })
+        .filter(|c| !c.is_control())
+        .collect();
+
+    match name.trim() {
+        "" => "conversation".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// A small standalone window that is shown instead of the popup when Popup-GPT can't start
+struct ErrorDialog {
+    heading: String,
+    error: String,
+    hint: String,
+    /// The config file, if the error is caused by the config
+    path: Option<PathBuf>,
+}
+
+impl eframe::App for ErrorDialog {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading(&self.heading);
+            ui.add_space(8.0);
+            ui.label(RichText::new(&self.error).color(Color32::from_rgb(230, 90, 90)));
+            ui.add_space(8.0);
+            ui.label(&self.hint);
+            ui.add_space(8.0);
+
+            ui.horizontal(|ui| {
+                if let Some(path) = &self.path {
+                    if ui.button(tr("Open config")).clicked() {
+                        open_path(path);
+                    }
+                }
+                if ui.button(tr("Quit")).clicked() {
+                    frame.close();
+                }
+            });
+        });
+    }
+}
+
+fn show_error_dialog(dialog: ErrorDialog) {
+    let opts = NativeOptions {
+        always_on_top: true,
+        centered: true,
+        initial_window_size: Some(Vec2::new(500.0, 200.0)),
+        ..Default::default()
+    };
+
+    let _ = eframe::run_native("Popup-GPT", opts, Box::new(|_cc| Box::new(dialog)));
+}
+
+/// Show the config error dialog and block until it is closed
+pub fn show_config_error(path: PathBuf, error: &anyhow::Error) {
+    show_error_dialog(ErrorDialog {
+        heading: tr("Popup-GPT could not load its configuration").to_string(),
+        error: format!("{error:#}"),
+        hint: tr("Fix the config file and start Popup-GPT again.").to_string(),
+        path: Some(path),
+    });
+}
+
+/// Show that there is no place for the config file, e.g. without a config directory
+pub fn show_config_path_error(error: &anyhow::Error) {
+    show_error_dialog(ErrorDialog {
+        heading: tr("Popup-GPT could not load its configuration").to_string(),
+        error: format!("{error:#}"),
+        hint: tr("Choose the config file with --config and start Popup-GPT again.").to_string(),
+        path: None,
+    });
+}
+
+/// Asks for another combination when the popup hotkey is taken
+struct HotkeyDialog {
+    error: String,
+    hotkey: String,
+    chosen: Rc<RefCell<Option<String>>>,
+}
+
+impl eframe::App for HotkeyDialog {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading(tr("Popup-GPT could not register its hotkey"));
+            ui.add_space(8.0);
+            ui.label(RichText::new(&self.error).color(Color32::from_rgb(230, 90, 90)));
+            ui.add_space(8.0);
+
+            ui.horizontal(|ui| {
+                ui.label(tr("Hotkey"));
+                ui.text_edit_singleline(&mut self.hotkey);
+            });
+            // Validated right here, so a typo doesn't need another round through the dialog
+            let parsed = self.hotkey.parse::<Hotkey>();
+            let hint = match &parsed {
+                Ok(hotkey) => hotkey.to_string(),
+                Err(e) => format!("{e:#}"),
+            };
+            ui.label(RichText::new(hint).small().color(Color32::GRAY));
+            ui.add_space(8.0);
+
+            ui.horizontal(|ui| {
+                if ui
+                    .add_enabled(parsed.is_ok(), egui::Button::new(tr("Use this hotkey")))
+                    .clicked()
+                {
+                    if let Ok(hotkey) = parsed {
+                        *self.chosen.borrow_mut() = Some(hotkey.to_string());
+                    }
+                    frame.close();
+                }
+                if ui.button(tr("Quit")).clicked() {
+                    frame.close();
+                }
+            });
+        });
+    }
+}
+
+/// Show why the popup hotkey could not be registered and ask for another combination. Blocks
+/// until the dialog is closed and returns the combination, `None` if the user quit.
+pub fn pick_hotkey(error: &str, current: &str) -> Option<String> {
+    let chosen = Rc::default();
+    let dialog = HotkeyDialog {
+        error: error.to_string(),
+        hotkey: current.to_string(),
+        chosen: Rc::clone(&chosen),
+    };
+
+    let opts = NativeOptions {
+        always_on_top: true,
+        centered: true,
+        initial_window_size: Some(Vec2::new(500.0, 200.0)),
+        ..Default::default()
+    };
+    let _ = eframe::run_native("Popup-GPT", opts, Box::new(|_cc| Box::new(dialog)));
+
+    chosen.take()
+}
+
+/// Show invalid command line arguments or the help text, since there is no console to print them to
+pub fn show_usage_error(error: &clap::Error) {
+    use clap::error::ErrorKind;
+
+    let heading = match error.kind() {
+        ErrorKind::DisplayHelp | ErrorKind::DisplayVersion => "Popup-GPT",
+        _ => tr("Invalid command line arguments"),
+    };
+
+    show_error_dialog(ErrorDialog {
+        heading: heading.to_string(),
+        error: error.render().to_string(),
+        hint: String::new(),
+        path: None,
+    });
+}
@@ -0,0 +1,462 @@
+use std::io::Read;
+
+use anyhow::{bail, Context, Result};
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+
+use crate::html::decode_entities;
+
+/// Documents are larger than text files, mostly because of their images and fonts
+pub const MAX_DOCUMENT_SIZE: u64 = 50 * 1024 * 1024;
+
+/// The document formats whose text can be extracted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Document {
+    Pdf,
+    Docx,
+}
+
+impl Document {
+    /// The format of a file by its extension, `None` for other files
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_lowercase().as_str() {
+            "pdf" => Some(Self::Pdf),
+            "docx" => Some(Self::Docx),
+            _ => None,
+        }
+    }
+
+    /// The text of the document, paragraphs are separated by blank lines
+    pub fn extract_text(self, data: &[u8]) -> Result<String> {
+        let text = match self {
+            Self::Pdf => pdf_text(data)?,
+            Self::Docx => docx_text(data)?,
+        };
+
+        if text.trim().is_empty() {
+            bail!("The document contains no text that can be extracted, it may be scanned");
+        }
+        Ok(text)
+    }
+}
+
+/// The text of the main document of a DOCX file. Headings and list items are kept as Markdown.
+fn docx_text(data: &[u8]) -> Result<String> {
+    let xml = zip_entry(data, "word/document.xml").context("This is not a valid DOCX file")?;
+    let xml = String::from_utf8(xml).context("The document is not valid UTF-8")?;
+
+    let mut text = String::new();
+    let mut paragraph = String::new();
+    let mut prefix = String::new();
+    let mut rest = xml.as_str();
+
+    while let Some(open) = rest.find('<') {
+        let Some(close) = rest[open..].find('>') else {
+            break;
+        };
+        let tag = &rest[open + 1..open + close];
+        let after = &rest[open + close + 1..];
+        let name = tag
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+
+        match name {
+            "w:t" if !tag.ends_with('/') => {
+                let end = after.find("</w:t>").unwrap_or(after.len());
+                paragraph.push_str(&decode_entities(&after[..end]));
+                rest = &after[end..];
+                continue;
+            }
+            "w:tab" => paragraph.push('\t'),
+            "w:br" | "w:cr" => paragraph.push('\n'),
+            "w:pStyle" => {
+                let level = xml_attribute(tag, "w:val")
+                    .and_then(|style| style.strip_prefix("Heading")?.parse::<usize>().ok());
+                if let Some(level) = level {
+                    prefix = format!("{} ", "#".repeat(level.clamp(1, 6)));
+                }
+            }
+            "w:numPr" if prefix.is_empty() => prefix = "- ".to_string(),
+            "" if tag == "/w:p" => {
+                if !paragraph.trim().is_empty() {
+                    text.push_str(&prefix);
+                    text.push_str(paragraph.trim_end());
+                    text.push_str("\n\n");
+                }
+                paragraph.clear();
+                prefix.clear();
+            }
+            _ => (),
+        }
+        rest = after;
+    }
+
+    Ok(text.trim_end().to_string())
+}
+
+fn xml_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!("{name}=\""))? + name.len() + 2;
+    let end = tag[start..].find('"')?;
+    Some(&tag[start..start + end])
+}
+
+fn read_u16(data: &[u8], at: usize) -> Option<usize> {
+    let bytes = data.get(at..at + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<usize> {
+    let bytes = data.get(at..at + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+}
+
+/// The content of a file in a ZIP archive, found through the central directory at its end
+fn zip_entry(data: &[u8], wanted: &str) -> Result<Vec<u8>> {
+    const END_OF_DIRECTORY: &[u8] = b"PK\x05\x06";
+    const DIRECTORY_ENTRY: &[u8] = b"PK\x01\x02";
+    const LOCAL_HEADER: &[u8] = b"PK\x03\x04";
+    let invalid = || anyhow::anyhow!("The ZIP archive is damaged");
+
+    let end = data
+        .windows(4)
+        .rposition(|window| window == END_OF_DIRECTORY)
+        .ok_or_else(invalid)?;
+    let entries = read_u16(data, end + 10).ok_or_else(invalid)?;
+    let mut at = read_u32(data, end + 16).ok_or_else(invalid)?;
+
+    for _ in 0..entries {
+        if data.get(at..at + 4) != Some(DIRECTORY_ENTRY) {
+            return Err(invalid());
+        }
+        let method = read_u16(data, at + 10).ok_or_else(invalid)?;
+        let size = read_u32(data, at + 20).ok_or_else(invalid)?;
+        let name_len = read_u16(data, at + 28).ok_or_else(invalid)?;
+        let extra_len = read_u16(data, at + 30).ok_or_else(invalid)?;
+        let comment_len = read_u16(data, at + 32).ok_or_else(invalid)?;
+        let offset = read_u32(data, at + 42).ok_or_else(invalid)?;
+        let name = data.get(at + 46..at + 46 + name_len).ok_or_else(invalid)?;
+        at += 46 + name_len + extra_len + comment_len;
+
+        if name != wanted.as_bytes() {
+            continue;
+        }
+
+        if data.get(offset..offset + 4) != Some(LOCAL_HEADER) {
+            return Err(invalid());
+        }
+        let start = offset
+            + 30
+            + read_u16(data, offset + 26).ok_or_else(invalid)?
+            + read_u16(data, offset + 28).ok_or_else(invalid)?;
+        let compressed = data.get(start..start + size).ok_or_else(invalid)?;
+
+        return match method {
+            0 => Ok(compressed.to_vec()),
+            8 => {
+                let mut content = Vec::new();
+                DeflateDecoder::new(compressed).read_to_end(&mut content)?;
+                Ok(content)
+            }
+            _ => bail!("{wanted} uses an unsupported compression"),
+        };
+    }
+
+    bail!("{wanted} is missing in the archive")
+}
+
+/// The text shown by the content streams of a PDF. This covers PDFs with simple fonts, text in
+/// fonts without a Unicode mapping comes out garbled or is left out.
+fn pdf_text(data: &[u8]) -> Result<String> {
+    if !data.starts_with(b"%PDF") {
+        bail!("This is not a valid PDF file");
+    }
+
+    let mut text = String::new();
+    let mut rest = data;
+    while let Some(start) = find(rest, b"stream") {
+        let dictionary = &rest[..start];
+        let dictionary = &dictionary[rfind(dictionary, b"<<").unwrap_or(0)..];
+        let mut body = &rest[start + b"stream".len()..];
+        body = body.strip_prefix(b"\r").unwrap_or(body);
+        body = body.strip_prefix(b"\n").unwrap_or(body);
+        let Some(end) = find(body, b"endstream") else {
+            break;
+        };
+        rest = &body[end + b"endstream".len()..];
+
+        if !is_content_stream(dictionary) {
+            continue;
+        }
+        let content = match find(dictionary, b"/FlateDecode") {
+            Some(_) => {
+                let mut content = Vec::new();
+                // A damaged stream may still have decoded text before the damage
+                let _ = ZlibDecoder::new(&body[..end]).read_to_end(&mut content);
+                content
+            }
+            None if find(dictionary, b"/Filter").is_some() => continue,
+            None => body[..end].to_vec(),
+        };
+
+        let page = content_text(&content);
+        if !page.trim().is_empty() {
+            text.push_str(page.trim());
+            text.push_str("\n\n");
+        }
+    }
+
+    Ok(text.trim_end().to_string())
+}
+
+/// Streams of images, fonts and other objects are not drawn as text
+fn is_content_stream(dictionary: &[u8]) -> bool {
+    const OTHER_STREAMS: &[&[u8]] = &[
+        b"/Subtype",
+        b"/Length1",
+        b"/Length2",
+        b"/Length3",
+        b"/ObjStm",
+        b"/XRef",
+        b"/Metadata",
+        b"/EmbeddedFile",
+    ];
+    !OTHER_STREAMS
+        .iter()
+        .any(|key| find(dictionary, key).is_some())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .rposition(|window| window == needle)
+}
+
+/// The strings of the text operators in a content stream, with line breaks where the text moves
+/// to a new line
+fn content_text(content: &[u8]) -> String {
+    let mut text = String::new();
+    // The operands of the next operator
+    let mut strings: Vec<Vec<u8>> = Vec::new();
+    let mut numbers: Vec<f32> = Vec::new();
+    let mut i = 0;
+
+    while i < content.len() {
+        match content[i] {
+            b'(' => {
+                let (string, end) = literal_string(content, i + 1);
+                strings.push(string);
+                i = end;
+            }
+            b'<' if content.get(i + 1) != Some(&b'<') => {
+                let end = content[i..]
+                    .iter()
+                    .position(|&c| c == b'>')
+                    .map_or(content.len(), |end| i + end);
+                strings.push(hex_string(&content[i + 1..end]));
+                i = end + 1;
+            }
+            b'%' => {
+                while i < content.len() && content[i] != b'\n' && content[i] != b'\r' {
+                    i += 1;
+                }
+            }
+            c if c.is_ascii_digit() || c == b'-' || c == b'.' || c == b'+' => {
+                let start = i;
+                i += 1;
+                while i < content.len() && (content[i].is_ascii_digit() || content[i] == b'.') {
+                    i += 1;
+                }
+                let number = std::str::from_utf8(&content[start..i]).unwrap_or_default();
+                // Large gaps between the strings of a `TJ` array are spaces between words
+                if number.parse::<f32>().is_ok_and(|n| n < -200.0) && !strings.is_empty() {
+                    strings.push(b" ".to_vec());
+                }
+                numbers.push(number.parse().unwrap_or_default());
+            }
+            c if c.is_ascii_alphabetic() || c == b'\'' || c == b'"' => {
+                let start = i;
+                i += 1;
+                while i < content.len()
+                    && (content[i].is_ascii_alphanumeric() || content[i] == b'*')
+                {
+                    i += 1;
+                }
+                let newline = |text: &mut String| {
+                    if !text.is_empty() && !text.ends_with('\n') {
+                        text.push('\n');
+                    }
+                };
+                match &content[start..i] {
+                    b"Tj" | b"TJ" => {}
+                    b"'" | b"\"" | b"T*" | b"Tm" | b"ET" => newline(&mut text),
+                    // Only a vertical move starts a new line
+                    b"Td" | b"TD" if numbers.last().is_some_and(|y| *y != 0.0) => {
+                        newline(&mut text)
+                    }
+                    b"Td" | b"TD" => {}
+                    _ => {
+                        strings.clear();
+                        numbers.clear();
+                        continue;
+                    }
+                }
+                for string in strings.drain(..) {
+                    text.push_str(&decode_pdf_string(&string));
+                }
+                numbers.clear();
+            }
+            _ => i += 1,
+        }
+    }
+
+    text
+}
+
+/// A `(...)` string with its escapes resolved, and the position after it
+fn literal_string(content: &[u8], mut i: usize) -> (Vec<u8>, usize) {
+    let mut string = Vec::new();
+    let mut depth = 0;
+
+    while let Some(&c) = content.get(i) {
+        i += 1;
+        match c {
+            b'(' => {
+                depth += 1;
+                string.push(c);
+            }
+            b')' if depth == 0 => break,
+            b')' => {
+                depth -= 1;
+                string.push(c);
+            }
+            b'\\' => {
+                let Some(&escaped) = content.get(i) else {
+                    break;
+                };
+                i += 1;
+                match escaped {
+                    b'n' => string.push(b'\n'),
+                    b'r' => string.push(b'\r'),
+                    b't' => string.push(b'\t'),
+                    b'b' | b'f' => {}
+                    b'0'..=b'7' => {
+                        let mut value = (escaped - b'0') as u32;
+                        for _ in 0..2 {
+                            match content.get(i) {
+                                Some(&digit @ b'0'..=b'7') => {
+                                    value = value * 8 + (digit - b'0') as u32;
+                                    i += 1;
+                                }
+                                _ => break,
+                            }
+                        }
+                        string.push(value as u8);
+                    }
+                    // A line break after a backslash continues the string
+                    b'\r' | b'\n' => {}
+                    other => string.push(other),
+                }
+            }
+            _ => string.push(c),
+        }
+    }
+
+    (string, i)
+}
+
+fn hex_string(hex: &[u8]) -> Vec<u8> {
+    let digits: Vec<u8> = hex
+        .iter()
+        .filter_map(|&c| (c as char).to_digit(16).map(|d| d as u8))
+        .collect();
+    digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0))
+        .collect()
+}
+
+/// UTF-16 strings start with a byte order mark, all others are read as Latin-1, which matches
+/// the standard encodings for letters
+fn decode_pdf_string(string: &[u8]) -> String {
+    if let Some(utf16) = string.strip_prefix(&[0xFE, 0xFF]) {
+        let units: Vec<u16> = utf16
+            .chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]))
+            .collect();
+        return String::from_utf16_lossy(&units);
+    }
+
+    string
+        .iter()
+        .map(|&c| c as char)
+        .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pdf_text_operators() {
+        let content = br"BT /F1 12 Tf 72 712 Td (Hello \(PDF\) world) Tj 0 -14 Td
+            [(Spa) -20 (ced) -300 (out)] TJ T* <48692021> Tj ET";
+        assert_eq!(
+            content_text(content),
+            "Hello (PDF) world\nSpaced out\nHi !\n"
+        );
+
+        let pdf = b"%PDF-1.4\n1 0 obj << /Length 44 >>\nstream\nBT (First page) Tj ET\nendstream\n\
+            2 0 obj << /Subtype /Image /Length 3 >>\nstream\n(x) Tj\nendstream\n\
+            3 0 obj << /Length 20 >>\nstream\nBT (\\376\\377\\000A) Tj ET\nendstream";
+        assert_eq!(pdf_text(pdf).unwrap(), "First page\n\nA");
+    }
+
+    #[test]
+    fn docx_paragraphs() {
+        let xml = r#"<w:document><w:body>
+            <w:p><w:pPr><w:pStyle w:val="Heading2"/></w:pPr><w:r><w:t>Results</w:t></w:r></w:p>
+            <w:p><w:r><w:t xml:space="preserve">Sales &amp; </w:t></w:r><w:r><w:t>costs</w:t></w:r></w:p>
+            <w:p><w:pPr><w:numPr><w:ilvl w:val="0"/></w:numPr></w:pPr><w:r><w:t>Item</w:t></w:r></w:p>
+            <w:p/></w:body></w:document>"#;
+
+        // A ZIP archive with the document stored uncompressed
+        let name = b"word/document.xml";
+        let mut zip = Vec::new();
+        zip.extend_from_slice(b"PK\x03\x04");
+        zip.extend_from_slice(&[0; 22]);
+        zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&0u16.to_le_bytes());
+        zip.extend_from_slice(name);
+        zip.extend_from_slice(xml.as_bytes());
+
+        let directory = zip.len();
+        zip.extend_from_slice(b"PK\x01\x02");
+        zip.extend_from_slice(&[0; 16]);
+        zip.extend_from_slice(&(xml.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&(xml.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&[0; 12]);
+        zip.extend_from_slice(&0u32.to_le_bytes());
+        zip.extend_from_slice(name);
+
+        zip.extend_from_slice(b"PK\x05\x06");
+        zip.extend_from_slice(&[0; 6]);
+        zip.extend_from_slice(&1u16.to_le_bytes());
+        zip.extend_from_slice(&[0; 4]);
+        zip.extend_from_slice(&(directory as u32).to_le_bytes());
+        zip.extend_from_slice(&[0; 2]);
+
+        assert_eq!(
+            Document::Docx.extract_text(&zip).unwrap(),
+            "## Results\n\nSales & costs\n\n- Item"
+        );
+        assert!(Document::Docx.extract_text(b"not a zip").is_err());
+    }
+}
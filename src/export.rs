@@ -0,0 +1,366 @@
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+use crate::{
+    markdown::{quote, segments, Segment},
+    model::{Message, Role, SavedConversation},
+};
+
+/// The file formats a conversation can be exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    /// The message list as sent to the API, including the system message
+    Json,
+    /// A standalone HTML page
+    Html,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 3] = [Self::Markdown, Self::Json, Self::Html];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json => "json",
+            ExportFormat::Html => "html",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "Markdown",
+            ExportFormat::Json => "JSON",
+            ExportFormat::Html => "HTML",
+        }
+    }
+
+    /// The format belonging to a file extension
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.extension().eq_ignore_ascii_case(extension))
+    }
+}
+
+/// Render a conversation in the given format
+pub fn export(
+    conversation: &SavedConversation,
+    title: &str,
+    format: ExportFormat,
+) -> Result<String> {
+    match format {
+        ExportFormat::Markdown => Ok(to_markdown(conversation, title)),
+        ExportFormat::Json => to_json(conversation),
+        ExportFormat::Html => Ok(to_html(conversation, title)),
+    }
+}
+
+/// A question and its answer as a Markdown section for a notes file, headed by the time. The
+/// question is quoted to set it apart from the answer.
+pub fn note_entry(time: &str, question: &str, answer: &str) -> String {
+    format!("## {time}\n\n{}\n\n{}\n", quote(question), answer.trim())
+}
+
+/// Read a conversation exported as JSON or Markdown. Besides the own formats, JSON exports of the
+/// OpenAI playground are accepted, which wrap the messages in an object with the request
+/// parameters and may split the message content into parts.
+pub fn import(text: &str) -> Result<SavedConversation> {
+    let text = text.trim_start_matches('\u{feff}').trim();
+
+    let (model, messages) = if text.starts_with('[') || text.starts_with('{') {
+        from_json(text)?
+    } else {
+        (String::new(), from_markdown(text)?)
+    };
+
+    let mut messages = messages.into_iter().peekable();
+    let system_prompt = match messages.peek() {
+        Some(message) if matches!(message.role, Role::System) => {
+            messages.next().map(|message| message.content)
+        }
+        _ => None,
+    };
+
+    Ok(SavedConversation {
+        system_prompt: system_prompt.unwrap_or_default(),
+        model,
+        messages: messages.collect(),
+    })
+}
+
+fn from_json(text: &str) -> Result<(String, Vec<Message>)> {
+    let value: Value = serde_json::from_str(text).context("The file is not valid JSON")?;
+
+    let (model, messages) = match &value {
+        Value::Array(messages) => (None, messages),
+        Value::Object(request) => match request.get("messages") {
+            Some(Value::Array(messages)) => (request.get("model"), messages),
+            _ => bail!("The JSON object doesn't contain a message list"),
+        },
+        _ => bail!("The JSON doesn't contain a message list"),
+    };
+
+    let messages = messages
+        .iter()
+        .map(|message| {
+            let role = message
+                .get("role")
+                .and_then(Value::as_str)
+                .context("A message has no role")?;
+            Ok(Message::new(
+                role.parse()?,
+                json_content(message.get("content")),
+            ))
+        })
+        .collect::<Result<_>>()?;
+
+    let model = model.and_then(Value::as_str).unwrap_or_default();
+    Ok((model.to_string(), messages))
+}
+
+/// The text of a message content, which is either a string or a list of content parts
+fn json_content(content: Option<&Value>) -> String {
+    match content {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        _ => String::new(),
+    }
+}
+
+/// Parse the Markdown export, where every message starts with a `## Role` header
+fn from_markdown(text: &str) -> Result<Vec<Message>> {
+    let mut messages: Vec<Message> = Vec::new();
+    let mut in_code = false;
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+        }
+
+        let role = match line.strip_prefix("## ") {
+            Some(header) if !in_code => header.trim().to_lowercase().parse::<Role>().ok(),
+            _ => None,
+        };
+
+        match (role, messages.last_mut()) {
+            (Some(role), _) => messages.push(Message::new(role, "")),
+            (None, Some(message)) => {
+                message.content.push_str(line);
+                message.content.push('\n');
+            }
+            // The title and model before the first message
+            (None, None) => (),
+        }
+    }
+
+    if messages.is_empty() {
+        bail!(
+            "The file doesn't contain any messages with a \"## User\" or \"## Assistant\" header"
+        );
+    }
+
+    for message in &mut messages {
+        message.content = message.content.trim().to_string();
+    }
+
+    Ok(messages)
+}
+
+fn role_header(role: &Role) -> &'static str {
+    match role {
+        Role::System => "System",
+        Role::Assistant => "Assistant",
+        Role::User => "User",
+        Role::Tool => "Tool",
+    }
+}
+
+fn to_markdown(conversation: &SavedConversation, title: &str) -> String {
+    let mut out = format!("# {title}\n\n");
+    if !conversation.model.is_empty() {
+        out.push_str(&format!("*Model: {}*\n\n", conversation.model));
+    }
+
+    for message in all_messages(conversation) {
+        out.push_str(&format!("## {}\n\n", role_header(&message.role)));
+        out.push_str(message.content.trim_end());
+        out.push_str("\n\n");
+    }
+
+    out
+}
+
+fn to_json(conversation: &SavedConversation) -> Result<String> {
+    let messages: Vec<_> = all_messages(conversation).collect();
+    Ok(serde_json::to_string_pretty(&messages)?)
+}
+
+fn to_html(conversation: &SavedConversation, title: &str) -> String {
+    let mut body = String::new();
+
+    for message in all_messages(conversation) {
+        body.push_str(&format!(
+            "<section class=\"{}\">\n<h2>{}</h2>\n",
+            message.role.as_str(),
+            role_header(&message.role)
+        ));
+
+        for segment in segments(&message.content) {
+            match segment {
+                Segment::Text(text) => {
+                    for paragraph in text.split("\n\n").filter(|p| !p.trim().is_empty()) {
+                        let paragraph = escape_html(paragraph.trim()).replace('\n', "<br>\n");
+                        body.push_str(&format!("<p>{paragraph}</p>\n"));
+                    }
+                }
+                Segment::Code(block) => {
+                    let class = block
+                        .lang
+                        .map(|lang| format!(" class=\"language-{}\"", escape_html(lang)))
+                        .unwrap_or_default();
+                    body.push_str(&format!(
+                        "<pre><code{class}>{}</code></pre>\n",
+                        escape_html(block.code)
+                    ));
+                }
+            }
+        }
+
+        body.push_str("</section>\n");
+    }
+
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 50em; margin: 2em auto; color: #222; }}
+section {{ margin-bottom: 1.5em; }}
+h2 {{ font-size: 0.9em; color: #888; text-transform: uppercase; }}
+pre {{ background: #f4f4f4; padding: 0.8em; overflow-x: auto; }}
+.system {{ color: #666; font-style: italic; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+{body}</body>
+</html>
+",
+        title = escape_html(title)
+    )
+}
+
+/// The system message followed by the conversation
+fn all_messages(conversation: &SavedConversation) -> impl Iterator<Item = Message> + '_ {
+    let system = (!conversation.system_prompt.is_empty())
+        .then(|| Message::system(&conversation.system_prompt));
+
+    system
+        .into_iter()
+        .chain(conversation.messages.iter().cloned())
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation() -> SavedConversation {
+        SavedConversation {
+            system_prompt: "Be brief".to_string(),
+            model: "gpt-4o".to_string(),
+            messages: vec![
+                Message::user("How do I list files?"),
+                Message::assistant("Use:\n```sh\nls <dir>\n```"),
+            ],
+        }
+    }
+
+    #[test]
+    fn markdown_has_role_headers() {
+        let md = export(&conversation(), "Files", ExportFormat::Markdown).unwrap();
+
+        assert!(md.starts_with("# Files\n"));
+        assert!(md.contains("## System\n\nBe brief\n"));
+        assert!(md.contains("## User\n\nHow do I list files?\n"));
+        assert!(md.contains("## Assistant\n\nUse:\n```sh\nls <dir>\n```\n"));
+    }
+
+    #[test]
+    fn json_is_the_message_list() {
+        let json = export(&conversation(), "Files", ExportFormat::Json).unwrap();
+        let messages: Vec<Message> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].content, "Be brief");
+    }
+
+    #[test]
+    fn import_own_exports() {
+        for format in [ExportFormat::Markdown, ExportFormat::Json] {
+            let exported = export(&conversation(), "Files", format).unwrap();
+            let imported = import(&exported).unwrap();
+
+            assert_eq!(imported.system_prompt, "Be brief");
+            let contents: Vec<_> = imported
+                .messages
+                .iter()
+                .map(|m| m.content.as_str())
+                .collect();
+            assert_eq!(
+                contents,
+                ["How do I list files?", "Use:\n```sh\nls <dir>\n```"]
+            );
+        }
+    }
+
+    #[test]
+    fn import_playground_export() {
+        let json = r#"{
+            "model": "gpt-4o",
+            "messages": [
+                {"role": "system", "content": [{"type": "text", "text": "Be brief"}]},
+                {"role": "user", "content": [{"type": "text", "text": "Hi"}]},
+                {"role": "assistant", "content": "Hello"}
+            ],
+            "temperature": 1
+        }"#;
+
+        let imported = import(json).unwrap();
+        assert_eq!(imported.model, "gpt-4o");
+        assert_eq!(imported.system_prompt, "Be brief");
+        assert_eq!(imported.messages.len(), 2);
+        assert_eq!(imported.messages[0].content, "Hi");
+    }
+
+    #[test]
+    fn html_escapes_code() {
+        let html = export(&conversation(), "<Files>", ExportFormat::Html).unwrap();
+
+        assert!(html.contains("<title>&lt;Files&gt;</title>"));
+        assert!(html.contains("<pre><code class=\"language-sh\">ls &lt;dir&gt;\n</code></pre>"));
+    }
+
+    #[test]
+    fn note_entries_quote_the_question() {
+        let entry = note_entry("14:05", "What is this?\n\nfn main() {}", "A function.\n");
+        assert_eq!(
+            entry,
+            "## 14:05\n\n> What is this?\n>\n> fn main() {}\n\nA function.\n"
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::{Duration, Instant};
+
+use winapi::{
+    shared::windef::{HWND, RECT},
+    um::winuser::{
+        GetWindowLongW, GetWindowRect, SetLayeredWindowAttributes, SetWindowLongW, SetWindowPos,
+        ShowWindow, GWL_EXSTYLE, LWA_ALPHA, SWP_NOACTIVATE, SWP_NOSIZE, SWP_NOZORDER, SW_HIDE,
+        WS_EX_LAYERED,
+    },
+};
+
+/// How far the window moves while it slides in or out, in pixels
+pub const SLIDE_DISTANCE: f32 = 24.0;
+
+/// The time between the steps of the fade out
+const FRAME_TIME: Duration = Duration::from_millis(16);
+
+/// Set the opacity of the whole window, from 0 to 1. The window is made a layered window first.
+pub fn set_opacity(window: u64, opacity: f32) {
+    let hwnd = window as HWND;
+    unsafe {
+        let style = GetWindowLongW(hwnd, GWL_EXSTYLE);
+        if style & WS_EX_LAYERED as i32 == 0 {
+            SetWindowLongW(hwnd, GWL_EXSTYLE, style | WS_EX_LAYERED as i32);
+        }
+        SetLayeredWindowAttributes(hwnd, 0, (opacity.clamp(0.0, 1.0) * 255.0) as u8, LWA_ALPHA);
+    }
+}
+
+/// Start fast and slow down towards the end, which looks less mechanical than a linear fade
+pub fn ease(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0);
+    1.0 - (1.0 - t).powi(3)
+}
+
+/// Fade the window out and hide it. This blocks for the duration, which is fine since the popup
+/// waits for the hotkey right after. The window is moved back to where it was once it is hidden.
+pub fn fade_out(window: u64, duration: Duration, slide: bool) {
+    let hwnd = window as HWND;
+    let mut rect = RECT {
+        left: 0,
+        top: 0,
+        right: 0,
+        bottom: 0,
+    };
+    let slide = slide && unsafe { GetWindowRect(hwnd, &mut rect) } != 0;
+
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        let t = ease(start.elapsed().as_secs_f32() / duration.as_secs_f32());
+        set_opacity(window, 1.0 - t);
+        if slide {
+            move_window(hwnd, rect.left, rect.top + (SLIDE_DISTANCE * t) as i32);
+        }
+        std::thread::sleep(FRAME_TIME);
+    }
+
+    unsafe { ShowWindow(hwnd, SW_HIDE) };
+    set_opacity(window, 0.0);
+    if slide {
+        move_window(hwnd, rect.left, rect.top);
+    }
+}
+
+fn move_window(hwnd: HWND, x: i32, y: i32) {
+    unsafe {
+        SetWindowPos(
+            hwnd,
+            std::ptr::null_mut(),
+            x,
+            y,
+            0,
+            0,
+            SWP_NOSIZE | SWP_NOZORDER | SWP_NOACTIVATE,
+        )
+    };
+}
@@ -0,0 +1,135 @@
+use std::{io::Read, time::Duration};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{attachment::Attachment, html, http, model::Message};
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The most that is downloaded of a page, HTML is a lot longer than its text
+const MAX_FETCH_BYTES: u64 = 2 * 1024 * 1024;
+
+/// The longest text that is kept of a page, about 10k tokens. Longer pages are cut off.
+pub const MAX_PAGE_CHARS: usize = 40_000;
+
+/// The readable text of a fetched web page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    pub url: String,
+    /// The page as Markdown, or the text of other text resources
+    pub text: String,
+    /// The text was cut off at `MAX_PAGE_CHARS`
+    pub truncated: bool,
+}
+
+impl Page {
+    /// The page as an attachment of the prompt, named after its host
+    pub fn attachment(&self) -> Attachment {
+        Attachment {
+            name: format!("{}.md", host(&self.url)),
+            content: self.text.clone(),
+        }
+    }
+
+    /// A rough estimate of the tokens the page adds to the prompt
+    pub fn estimated_tokens(&self) -> usize {
+        Message::user(&self.text).estimated_tokens()
+    }
+}
+
+/// Download a web page and convert it to Markdown without its boilerplate. Other text resources
+/// are kept as they are.
+pub fn fetch_page(url: &str) -> Result<Page> {
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        bail!("Only http and https URLs can be fetched");
+    }
+
+    let resp = http::get(url)
+        .timeout(FETCH_TIMEOUT)
+        .call()
+        .with_context(|| format!("Could not fetch {url}"))?;
+    let is_html = resp.content_type().contains("html");
+    let charset = resp.charset().to_string();
+
+    let mut bytes = Vec::new();
+    resp.into_reader()
+        .take(MAX_FETCH_BYTES)
+        .read_to_end(&mut bytes)
+        .with_context(|| format!("Could not fetch {url}"))?;
+    let body = decode(&bytes, &charset);
+
+    let mut text = match is_html {
+        true => html::page_to_markdown(&body),
+        false => body,
+    };
+    let truncated = match text.char_indices().nth(MAX_PAGE_CHARS) {
+        Some((end, _)) => {
+            text.truncate(end);
+            true
+        }
+        None => false,
+    };
+
+    Ok(Page {
+        url: url.to_string(),
+        text,
+        truncated,
+    })
+}
+
+/// The text of a body in its charset. Single-byte Western charsets are decoded as Latin-1, all
+/// others as UTF-8 with invalid bytes replaced, e.g. a character that was cut off at the end.
+fn decode(bytes: &[u8], charset: &str) -> String {
+    match charset.to_ascii_lowercase().as_str() {
+        "iso-8859-1" | "iso-8859-15" | "latin1" | "windows-1252" => {
+            bytes.iter().map(|&byte| char::from(byte)).collect()
+        }
+        _ => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// The URL the prompt starts with, if any
+pub fn leading_url(prompt: &str) -> Option<&str> {
+    let first = prompt.split_whitespace().next()?;
+    let rest = first
+        .strip_prefix("https://")
+        .or_else(|| first.strip_prefix("http://"))?;
+    (!rest.is_empty()).then_some(first)
+}
+
+/// The host of a URL without the `www.`, e.g. `example.com`
+pub fn host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    let host = host.rsplit_once('@').map_or(host, |(_, host)| host);
+    host.strip_prefix("www.").unwrap_or(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bodies_are_decoded() {
+        assert_eq!(decode(b"caf\xe9", "ISO-8859-1"), "café");
+        assert_eq!(decode("café".as_bytes(), "utf-8"), "café");
+        assert_eq!(decode(&"über".as_bytes()[..1], "utf-8"), "\u{fffd}");
+    }
+
+    #[test]
+    fn urls_at_the_start_of_prompts() {
+        assert_eq!(
+            leading_url("https://example.com/a?b \nWhat is this about?"),
+            Some("https://example.com/a?b")
+        );
+        assert_eq!(
+            leading_url("  http://example.com"),
+            Some("http://example.com")
+        );
+        assert_eq!(leading_url("Summarize https://example.com"), None);
+        assert_eq!(leading_url("https://"), None);
+
+        assert_eq!(host("https://www.example.com/a/b"), "example.com");
+        assert_eq!(host("http://user@docs.rs:8080?q"), "docs.rs:8080");
+    }
+}
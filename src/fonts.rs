@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+
+use egui::{FontData, FontDefinitions, FontFamily};
+
+/// Windows fonts that cover what the built-in fonts lack, in the order they are tried. The index
+/// selects the font in a collection.
+const FALLBACK_FONTS: &[(&str, u32)] = &[
+    // Emoji as monochrome outlines, egui can't render the color layers
+    ("seguiemj.ttf", 0),
+    // Symbols, arrows and box-drawing characters
+    ("seguisym.ttf", 0),
+    // Math operators and letters of the rendered TeX, Cambria Math is the second font
+    ("cambria.ttc", 1),
+    // Chinese, Japanese and Korean
+    ("msyh.ttc", 0),
+    ("YuGothM.ttc", 0),
+    ("malgun.ttf", 0),
+];
+
+/// Add the installed system fonts as fallbacks after the built-in ones, so characters that the
+/// built-in fonts are missing don't render as boxes. Missing fonts are skipped.
+pub fn install_fallbacks(ctx: &egui::Context) {
+    let fonts_dir = std::env::var_os("WINDIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(r"C:\Windows"))
+        .join("Fonts");
+
+    let mut fonts = FontDefinitions::default();
+    for (file, index) in FALLBACK_FONTS {
+        let data = match std::fs::read(fonts_dir.join(file)) {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::debug!(font = file, error = %e, "fallback font not loaded");
+                continue;
+            }
+        };
+
+        let name = file.to_string();
+        fonts.font_data.insert(
+            name.clone(),
+            FontData {
+                index: *index,
+                ..FontData::from_owned(data)
+            },
+        );
+        for family in [FontFamily::Monospace, FontFamily::Proportional] {
+            fonts.families.entry(family).or_default().push(name.clone());
+        }
+    }
+
+    ctx.set_fonts(fonts);
+}
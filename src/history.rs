@@ -0,0 +1,591 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+
+pub use crate::model::SavedConversation;
+use crate::model::{self, Message, Role, Usage};
+
+/// The schema migrations, applied in order. The number of applied migrations is stored in the
+/// `user_version` of the database.
+const MIGRATIONS: &[&str] = &["
+    CREATE TABLE conversations (
+        id INTEGER PRIMARY KEY,
+        title TEXT,
+        system_prompt TEXT NOT NULL,
+        model TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE TABLE messages (
+        id INTEGER PRIMARY KEY,
+        conversation_id INTEGER NOT NULL REFERENCES conversations(id) ON DELETE CASCADE,
+        role TEXT NOT NULL,
+        content TEXT NOT NULL,
+        model TEXT NOT NULL,
+        tokens INTEGER,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX messages_conversation ON messages(conversation_id);
+", "
+    CREATE VIRTUAL TABLE messages_fts USING fts5(
+        content, content='messages', content_rowid='id'
+    );
+    INSERT INTO messages_fts(messages_fts) VALUES ('rebuild');
+    CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+        INSERT INTO messages_fts(rowid, content) VALUES (new.id, new.content);
+    END;
+    CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+        INSERT INTO messages_fts(messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+    END;
+    CREATE TRIGGER messages_fts_update AFTER UPDATE ON messages BEGIN
+        INSERT INTO messages_fts(messages_fts, rowid, content) VALUES ('delete', old.id, old.content);
+        INSERT INTO messages_fts(rowid, content) VALUES (new.id, new.content);
+    END;
+", "
+    ALTER TABLE conversations
+        ADD COLUMN parent_id INTEGER REFERENCES conversations(id) ON DELETE SET NULL;
+    ALTER TABLE conversations ADD COLUMN branched_after INTEGER;
+", "
+    ALTER TABLE messages ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;
+", "
+    ALTER TABLE messages ADD COLUMN prompt_tokens INTEGER;
+"];
+
+/// The title of conversation `c`, falling back to the start of its first question
+const TITLE_SQL: &str = "COALESCE(c.title, (
+    SELECT substr(m.content, 1, 100) FROM messages m
+    WHERE m.conversation_id = c.id AND m.role = 'user'
+    ORDER BY m.id LIMIT 1
+), '')";
+
+/// Marks the start of a match in a search snippet
+pub const MATCH_START: char = '\u{1}';
+/// Marks the end of a match in a search snippet
+pub const MATCH_END: char = '\u{2}';
+
+/// The maximum number of search results
+const SEARCH_LIMIT: usize = 50;
+
+/// An entry in the list of saved conversations
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversationSummary {
+    pub id: i64,
+    /// The title of the conversation, or the start of the first question if it has none
+    pub title: String,
+    pub model: String,
+    /// Seconds since the Unix epoch
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub usage: ConversationUsage,
+}
+
+/// The tokens of all responses of a conversation that reported their usage
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConversationUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// What the tokens cost in US dollars, none if a model has no known price
+    pub cost: Option<f64>,
+}
+
+impl Default for ConversationUsage {
+    fn default() -> Self {
+        Self {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            cost: Some(0.0),
+        }
+    }
+}
+
+impl ConversationUsage {
+    pub fn total_tokens(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// A message that matches a search query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub conversation_id: i64,
+    pub title: String,
+    /// The part of the message around the matches. The matches are enclosed in `MATCH_START` and
+    /// `MATCH_END`.
+    pub snippet: String,
+    pub created_at: i64,
+}
+
+/// Persistent storage of past conversations in an SQLite database
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    /// Open the database at the given path, creating it if it doesn't exist yet
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)
+            .with_context(|| format!("Could not open the history at {}", path.display()))?;
+        Self::init(conn)
+    }
+
+    /// A history that is not persisted, e.g. for tests
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+
+        let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            conn.execute_batch(migration)
+                .with_context(|| format!("Could not migrate the history to version {}", i + 1))?;
+            conn.pragma_update(None, "user_version", i + 1)?;
+        }
+
+        Ok(Self { conn })
+    }
+
+    /// Create a new, empty conversation and return its ID
+    pub fn start_conversation(&self, system_prompt: &str, model: &str) -> Result<i64> {
+        let now = now();
+        self.conn.execute(
+            "INSERT INTO conversations (system_prompt, model, created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?3)",
+            params![system_prompt, model, now],
+        )?;
+
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Append a message to a conversation. `usage` is the usage of the request that generated
+    /// the message, if the API reported it.
+    pub fn add_message(
+        &self,
+        conversation: i64,
+        message: &Message,
+        model: &str,
+        usage: Option<&Usage>,
+    ) -> Result<i64> {
+        let now = now();
+        self.conn.execute(
+            "INSERT INTO messages
+                (conversation_id, role, content, model, tokens, prompt_tokens, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                conversation,
+                message.role.as_str(),
+                message.content,
+                model,
+                usage.map(|usage| usage.completion_tokens),
+                usage.map(|usage| usage.prompt_tokens),
+                now
+            ],
+        )?;
+        let id = self.conn.last_insert_rowid();
+
+        self.conn.execute(
+            "UPDATE conversations SET updated_at = ?2 WHERE id = ?1",
+            params![conversation, now],
+        )?;
+
+        Ok(id)
+    }
+
+    /// All saved conversations, the most recently updated one first
+    pub fn conversations(&self) -> Result<Vec<ConversationSummary>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT c.id, {TITLE_SQL}, c.model, c.created_at, c.updated_at
+             FROM conversations c
+             ORDER BY c.updated_at DESC, c.id DESC"
+        ))?;
+
+        let usages = self.usages()?;
+        let conversations = stmt
+            .query_map([], |row| {
+                let id = row.get(0)?;
+                Ok(ConversationSummary {
+                    id,
+                    title: row.get(1)?,
+                    model: row.get(2)?,
+                    created_at: row.get(3)?,
+                    updated_at: row.get(4)?,
+                    usage: usages.get(&id).copied().unwrap_or_default(),
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(conversations)
+    }
+
+    /// The usage of every conversation with recorded usage. The cost is summed up per model.
+    /// Branches don't count the usage of the messages they copied.
+    fn usages(&self) -> Result<HashMap<i64, ConversationUsage>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT conversation_id, model, SUM(prompt_tokens), SUM(tokens) FROM messages
+             WHERE prompt_tokens IS NOT NULL
+             GROUP BY conversation_id, model",
+        )?;
+        let mut rows = stmt.query([])?;
+
+        let mut usages = HashMap::<i64, ConversationUsage>::new();
+        while let Some(row) = rows.next()? {
+            let model: String = row.get(1)?;
+            let prompt_tokens: u64 = row.get(2)?;
+            let completion_tokens: u64 = row.get::<_, Option<u64>>(3)?.unwrap_or_default();
+
+            let usage = usages.entry(row.get(0)?).or_default();
+            usage.prompt_tokens += prompt_tokens;
+            usage.completion_tokens += completion_tokens;
+            usage.cost = usage
+                .cost
+                .zip(model::cost(&model, prompt_tokens, completion_tokens))
+                .map(|(sum, cost)| sum + cost);
+        }
+
+        Ok(usages)
+    }
+
+    /// Find the messages containing all words of the query, the best matches first. The last word
+    /// also matches as a prefix, so results show up while typing.
+    pub fn search(&self, query: &str) -> Result<Vec<SearchResult>> {
+        let Some(query) = fts_query(query) else {
+            return Ok(Vec::new());
+        };
+
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT c.id, {TITLE_SQL},
+                snippet(messages_fts, 0, char(1), char(2), '…', 16), m.created_at
+             FROM messages_fts
+             JOIN messages m ON m.id = messages_fts.rowid
+             JOIN conversations c ON c.id = m.conversation_id
+             WHERE messages_fts MATCH ?1
+             ORDER BY rank
+             LIMIT ?2"
+        ))?;
+
+        let results = stmt
+            .query_map(params![query, SEARCH_LIMIT], |row| {
+                Ok(SearchResult {
+                    conversation_id: row.get(0)?,
+                    title: row.get(1)?,
+                    snippet: row.get(2)?,
+                    created_at: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(results)
+    }
+
+    /// Load a conversation with all its messages
+    pub fn conversation(&self, id: i64) -> Result<SavedConversation> {
+        let (system_prompt, model) = self
+            .conn
+            .query_row(
+                "SELECT system_prompt, model FROM conversations WHERE id = ?1",
+                [id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .with_context(|| format!("There is no saved conversation with the ID {id}"))?;
+
+        let mut stmt = self.conn.prepare(
+            "SELECT role, content, pinned FROM messages WHERE conversation_id = ?1 ORDER BY id",
+        )?;
+        let messages = stmt
+            .query_map([id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?))
+            })?
+            .map(|row| {
+                let (role, content, pinned): (String, String, bool) = row?;
+                Ok(Message {
+                    pinned,
+                    ..Message::new(role.parse::<Role>()?, content)
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(SavedConversation {
+            system_prompt,
+            model,
+            messages,
+        })
+    }
+
+    /// Create a branch of a conversation that contains its first `message_count` messages. The
+    /// branch records the conversation and message it was forked from.
+    pub fn branch_conversation(&self, parent: i64, message_count: usize) -> Result<i64> {
+        let tx = self.conn.unchecked_transaction()?;
+        let now = now();
+
+        let branched_after: Option<i64> = tx
+            .query_row(
+                "SELECT id FROM messages WHERE conversation_id = ?1 ORDER BY id LIMIT 1 OFFSET ?2",
+                params![parent, message_count.saturating_sub(1)],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        tx.execute(
+            "INSERT INTO conversations
+                (system_prompt, model, created_at, updated_at, parent_id, branched_after)
+             SELECT system_prompt, model, ?2, ?2, id, ?3 FROM conversations WHERE id = ?1",
+            params![parent, now, branched_after.filter(|_| message_count > 0)],
+        )?;
+        if tx.changes() == 0 {
+            bail!("There is no saved conversation with the ID {parent}");
+        }
+        let id = tx.last_insert_rowid();
+
+        tx.execute(
+            "INSERT INTO messages (conversation_id, role, content, model, tokens, created_at, pinned)
+             SELECT ?2, role, content, model, tokens, created_at, pinned FROM messages
+             WHERE conversation_id = ?1 ORDER BY id LIMIT ?3",
+            params![parent, id, message_count],
+        )?;
+
+        tx.commit()?;
+        Ok(id)
+    }
+
+    /// The conversation the given one was branched from, if any
+    pub fn parent(&self, id: i64) -> Result<Option<i64>> {
+        let parent = self.conn.query_row(
+            "SELECT parent_id FROM conversations WHERE id = ?1",
+            [id],
+            |row| row.get(0),
+        )?;
+        Ok(parent)
+    }
+
+    /// Pin or unpin the message at the given position in a conversation
+    pub fn set_pinned(&self, conversation: i64, index: usize, pinned: bool) -> Result<()> {
+        self.conn.execute(
+            "UPDATE messages SET pinned = ?3 WHERE id = (
+                SELECT id FROM messages WHERE conversation_id = ?1 ORDER BY id LIMIT 1 OFFSET ?2
+            )",
+            params![conversation, index, pinned],
+        )?;
+        Ok(())
+    }
+
+    /// Delete the message at the given position in a conversation
+    pub fn delete_message(&self, conversation: i64, index: usize) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM messages WHERE id = (
+                SELECT id FROM messages WHERE conversation_id = ?1 ORDER BY id LIMIT 1 OFFSET ?2
+            )",
+            params![conversation, index],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_title(&self, id: i64, title: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE conversations SET title = ?2 WHERE id = ?1",
+            params![id, title],
+        )?;
+        Ok(())
+    }
+
+    /// Delete a conversation with all its messages
+    pub fn delete_conversation(&self, id: i64) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM conversations WHERE id = ?1", [id])?;
+        Ok(())
+    }
+}
+
+/// Turn user input into an FTS5 query that matches all words literally, so that characters like
+/// `"` or `*` in the input can't cause syntax errors
+fn fts_query(input: &str) -> Option<String> {
+    let words: Vec<_> = input
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect();
+
+    if words.is_empty() {
+        return None;
+    }
+
+    Some(format!("{}*", words.join(" ")))
+}
+
+/// The current time in seconds since the Unix epoch
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_conversation() {
+        let history = History::open_in_memory().unwrap();
+        let id = history.start_conversation("Be brief", "gpt-4o").unwrap();
+        history
+            .add_message(id, &Message::user("Hi"), "gpt-4o", None)
+            .unwrap();
+        let usage = Usage {
+            prompt_tokens: 30,
+            completion_tokens: 12,
+            total_tokens: 42,
+        };
+        history
+            .add_message(id, &Message::assistant("Hello"), "gpt-4o", Some(&usage))
+            .unwrap();
+
+        let rows: Vec<(String, String, Option<u32>)> = history
+            .conn
+            .prepare("SELECT role, content, tokens FROM messages WHERE conversation_id = ?1")
+            .unwrap()
+            .query_map([id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            [
+                ("user".to_string(), "Hi".to_string(), None),
+                ("assistant".to_string(), "Hello".to_string(), Some(12)),
+            ]
+        );
+
+        let usage = history.conversations().unwrap()[0].usage;
+        assert_eq!(usage.total_tokens(), 42);
+        assert_eq!(usage.cost, Some((30.0 * 2.5 + 12.0 * 10.0) / 1_000_000.0));
+    }
+
+    #[test]
+    fn search_messages() {
+        let history = History::open_in_memory().unwrap();
+        let id = history.start_conversation("", "gpt-4o").unwrap();
+        history
+            .add_message(
+                id,
+                &Message::user("Give me a regex for dates"),
+                "gpt-4o",
+                None,
+            )
+            .unwrap();
+        history
+            .add_message(id, &Message::assistant("Try \\d{4}-\\d{2}"), "gpt-4o", None)
+            .unwrap();
+
+        let results = history.search("regex dat").unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].conversation_id, id);
+        assert_eq!(results[0].title, "Give me a regex for dates");
+        assert!(results[0].snippet.contains("\u{1}regex\u{2}"));
+
+        assert!(history.search("\"*(").unwrap().is_empty());
+        assert!(history.search("  ").unwrap().is_empty());
+
+        history.delete_conversation(id).unwrap();
+        assert!(history.search("regex").unwrap().is_empty());
+    }
+
+    #[test]
+    fn branch_conversation() {
+        let history = History::open_in_memory().unwrap();
+        let id = history.start_conversation("Be brief", "gpt-4o").unwrap();
+        for message in [
+            Message::user("Q1"),
+            Message::assistant("A1"),
+            Message::user("Q2"),
+            Message::assistant("A2"),
+        ] {
+            history.add_message(id, &message, "gpt-4o", None).unwrap();
+        }
+
+        history.set_pinned(id, 0, true).unwrap();
+        let branch = history.branch_conversation(id, 2).unwrap();
+        let saved = history.conversation(branch).unwrap();
+        let contents: Vec<_> = saved.messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["Q1", "A1"]);
+        assert!(saved.messages[0].pinned && !saved.messages[1].pinned);
+        assert_eq!(saved.system_prompt, "Be brief");
+        assert_eq!(history.parent(branch).unwrap(), Some(id));
+
+        // The original is unchanged and deleting it keeps the branch
+        assert_eq!(history.conversation(id).unwrap().messages.len(), 4);
+        history.delete_conversation(id).unwrap();
+        assert_eq!(history.parent(branch).unwrap(), None);
+        assert_eq!(history.conversation(branch).unwrap().messages.len(), 2);
+    }
+
+    #[test]
+    fn list_load_and_delete() {
+        let history = History::open_in_memory().unwrap();
+        let first = history.start_conversation("", "gpt-4o").unwrap();
+        history
+            .add_message(first, &Message::user("First question"), "gpt-4o", None)
+            .unwrap();
+        let second = history.start_conversation("Be brief", "gpt-4o").unwrap();
+        history
+            .add_message(second, &Message::user("Second question"), "gpt-4o", None)
+            .unwrap();
+        history
+            .add_message(second, &Message::assistant("Answer"), "gpt-4o", None)
+            .unwrap();
+
+        let titles: Vec<_> = history
+            .conversations()
+            .unwrap()
+            .into_iter()
+            .map(|conversation| conversation.title)
+            .collect();
+        assert_eq!(titles, ["Second question", "First question"]);
+
+        history.set_title(first, "Greeting").unwrap();
+        assert_eq!(history.conversations().unwrap()[1].title, "Greeting");
+
+        let saved = history.conversation(second).unwrap();
+        assert_eq!(saved.system_prompt, "Be brief");
+        assert_eq!(saved.messages.len(), 2);
+        assert_eq!(saved.messages[1].content, "Answer");
+
+        history.delete_conversation(second).unwrap();
+        assert_eq!(history.conversations().unwrap().len(), 1);
+        assert!(history.conversation(second).is_err());
+    }
+
+    #[test]
+    fn delete_message() {
+        let history = History::open_in_memory().unwrap();
+        let id = history.start_conversation("", "gpt-4o").unwrap();
+        for message in [
+            Message::user("Q1"),
+            Message::assistant("A1"),
+            Message::user("Q2"),
+        ] {
+            history.add_message(id, &message, "gpt-4o", None).unwrap();
+        }
+        let other = history.start_conversation("", "gpt-4o").unwrap();
+        history
+            .add_message(other, &Message::user("Other"), "gpt-4o", None)
+            .unwrap();
+
+        history.delete_message(id, 1).unwrap();
+        let contents = |id| -> Vec<String> {
+            let saved = history.conversation(id).unwrap();
+            saved.messages.into_iter().map(|m| m.content).collect()
+        };
+        assert_eq!(contents(id), ["Q1", "Q2"]);
+
+        // An index past the end deletes nothing, not even in other conversations
+        history.delete_message(id, 2).unwrap();
+        assert_eq!(contents(id), ["Q1", "Q2"]);
+        assert_eq!(contents(other), ["Other"]);
+    }
+}
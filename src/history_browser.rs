@@ -0,0 +1,239 @@
+use chrono::{Local, TimeZone};
+use egui::{text::LayoutJob, Color32, FontId, RichText, ScrollArea, TextEdit, TextFormat};
+use popup_gpt::{
+    history::{
+        ConversationSummary, ConversationUsage, History, SavedConversation, SearchResult,
+        MATCH_END, MATCH_START,
+    },
+    model::Role,
+};
+
+use crate::{i18n::tr, theme::palette};
+
+/// What the user chose to do in the history browser
+pub enum BrowserAction {
+    /// Continue a saved conversation in the popup
+    Continue(i64, SavedConversation),
+    Close,
+}
+
+/// A list of the saved conversations that can be opened, continued or deleted
+pub struct HistoryBrowser {
+    conversations: Vec<ConversationSummary>,
+    search: String,
+    /// The results for the current search, empty if there is no search
+    results: Vec<SearchResult>,
+    /// The conversation that is opened read-only
+    open: Option<(i64, SavedConversation)>,
+}
+
+impl HistoryBrowser {
+    pub fn new(history: &History) -> anyhow::Result<Self> {
+        Ok(Self {
+            conversations: history.conversations()?,
+            search: String::new(),
+            results: Vec::new(),
+            open: None,
+        })
+    }
+
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        history: &History,
+    ) -> anyhow::Result<Option<BrowserAction>> {
+        if let Some((id, conversation)) = &self.open {
+            let mut back = false;
+            let mut resume = false;
+            let mut export = false;
+            ui.horizontal(|ui| {
+                back = ui.button(tr("← Back")).clicked();
+                resume = ui.button(tr("Continue")).clicked();
+                export = ui.button(tr("Export")).clicked();
+            });
+
+            let usage = self
+                .conversations
+                .iter()
+                .find(|summary| summary.id == *id)
+                .map(|summary| summary.usage)
+                .filter(|usage| usage.total_tokens() > 0);
+            if let Some(usage) = usage {
+                let mut text = tr("{prompt} prompt and {completion} completion tokens")
+                    .replace("{prompt}", &usage.prompt_tokens.to_string())
+                    .replace("{completion}", &usage.completion_tokens.to_string());
+                if let Some(cost) = usage.cost {
+                    text.push_str(&format!(" · {}", format_cost(cost)));
+                }
+                ui.label(RichText::new(text).small().color(Color32::GRAY));
+            }
+
+            ScrollArea::vertical()
+                .auto_shrink([false, false])
+                .show(ui, |ui| {
+                    for message in &conversation.messages {
+                        let palette = palette(ui.visuals());
+                        let color = match message.role {
+                            Role::User => palette.input,
+                            _ => palette.text,
+                        };
+                        ui.label(
+                            RichText::new(message.role.as_str())
+                                .small()
+                                .color(Color32::GRAY),
+                        );
+                        ui.label(RichText::new(&message.content).color(color));
+                        ui.add_space(6.0);
+                    }
+                });
+
+            if export {
+                crate::dialogs::export_conversation(conversation, &conversation.fallback_title())?;
+            }
+            if resume {
+                return Ok(Some(BrowserAction::Continue(*id, conversation.clone())));
+            }
+            if back {
+                self.open = None;
+            }
+            return Ok(None);
+        }
+
+        let mut open = None;
+        let mut delete = None;
+        let mut close = false;
+
+        ui.horizontal(|ui| {
+            ui.label(RichText::new(tr("History")).strong());
+            if ui.small_button(tr("Close")).clicked() {
+                close = true;
+            }
+        });
+
+        let search = ui.add(
+            TextEdit::singleline(&mut self.search)
+                .hint_text(tr("Search"))
+                .desired_width(f32::INFINITY),
+        );
+        if search.changed() {
+            self.results = history.search(&self.search)?;
+        }
+
+        if self.conversations.is_empty() {
+            ui.label(RichText::new(tr("No saved conversations yet")).color(Color32::GRAY));
+        } else if !self.search.trim().is_empty() && self.results.is_empty() {
+            ui.label(RichText::new(tr("Nothing found")).color(Color32::GRAY));
+        }
+
+        ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                if !self.search.trim().is_empty() {
+                    for result in &self.results {
+                        ui.horizontal(|ui| {
+                            ui.label(
+                                RichText::new(format_time(result.created_at))
+                                    .monospace()
+                                    .color(Color32::GRAY),
+                            );
+                            if ui.link(&result.title).clicked() {
+                                open = Some(result.conversation_id);
+                            }
+                        });
+                        ui.label(highlight_snippet(
+                            &result.snippet,
+                            palette(ui.visuals()).text,
+                        ));
+                        ui.add_space(4.0);
+                    }
+                    return;
+                }
+
+                for conversation in &self.conversations {
+                    ui.horizontal(|ui| {
+                        ui.label(
+                            RichText::new(format_time(conversation.updated_at))
+                                .monospace()
+                                .color(Color32::GRAY),
+                        );
+                        if ui.link(&conversation.title).clicked() {
+                            open = Some(conversation.id);
+                        }
+                        if conversation.usage.total_tokens() > 0 {
+                            ui.label(
+                                RichText::new(format_usage(&conversation.usage))
+                                    .small()
+                                    .color(Color32::GRAY),
+                            );
+                        }
+                        if ui.small_button("🗑").on_hover_text(tr("Delete")).clicked() {
+                            delete = Some(conversation.id);
+                        }
+                    });
+                }
+            });
+
+        if let Some(id) = open {
+            self.open = Some((id, history.conversation(id)?));
+        }
+        if let Some(id) = delete {
+            history.delete_conversation(id)?;
+            self.conversations
+                .retain(|conversation| conversation.id != id);
+        }
+
+        Ok(close.then_some(BrowserAction::Close))
+    }
+}
+
+/// Format a Unix timestamp as local date and time
+fn format_time(timestamp: i64) -> String {
+    match Local.timestamp_opt(timestamp, 0).single() {
+        Some(time) => time.format("%Y-%m-%d %H:%M").to_string(),
+        None => String::new(),
+    }
+}
+
+/// The tokens and the cost of a conversation in short, e.g. `12.3k tokens · $0.04`
+fn format_usage(usage: &ConversationUsage) -> String {
+    let tokens = match usage.total_tokens() {
+        tokens @ 0..=999 => tokens.to_string(),
+        tokens => format!("{:.1}k", tokens as f64 / 1000.0),
+    };
+    let mut text = tr("{tokens} tokens").replace("{tokens}", &tokens);
+    if let Some(cost) = usage.cost {
+        text.push_str(&format!(" · {}", format_cost(cost)));
+    }
+    text
+}
+
+/// US dollars with more decimals for small amounts, e.g. `$0.0042`
+fn format_cost(cost: f64) -> String {
+    match cost {
+        _ if cost < 0.01 => format!("${cost:.4}"),
+        _ => format!("${cost:.2}"),
+    }
+}
+
+/// Lay out a search snippet with the matches highlighted
+fn highlight_snippet(snippet: &str, color: Color32) -> LayoutJob {
+    let normal = TextFormat {
+        font_id: FontId::proportional(13.0),
+        color,
+        ..Default::default()
+    };
+    let highlighted = TextFormat {
+        color: Color32::BLACK,
+        background: Color32::from_rgb(230, 200, 90),
+        ..normal.clone()
+    };
+
+    let mut job = LayoutJob::default();
+    for (i, part) in snippet.split([MATCH_START, MATCH_END]).enumerate() {
+        // The parts alternate between text outside and inside of matches
+        let format = if i % 2 == 0 { &normal } else { &highlighted };
+        job.append(part, 0.0, format.clone());
+    }
+
+    job
+}
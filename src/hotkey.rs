@@ -0,0 +1,230 @@
+use anyhow::{bail, Result};
+
+/// The named keys with their Windows virtual key codes. The first name of a code is the one it is
+/// displayed with, the others are accepted as well.
+const KEY_NAMES: &[(&str, u32)] = &[
+    ("Backspace", 0x08),
+    ("Back", 0x08),
+    ("Tab", 0x09),
+    ("Enter", 0x0D),
+    ("Return", 0x0D),
+    ("Pause", 0x13),
+    ("Esc", 0x1B),
+    ("Escape", 0x1B),
+    ("Space", 0x20),
+    ("PageUp", 0x21),
+    ("PgUp", 0x21),
+    ("PageDown", 0x22),
+    ("PgDn", 0x22),
+    ("End", 0x23),
+    ("Home", 0x24),
+    ("Left", 0x25),
+    ("Up", 0x26),
+    ("Right", 0x27),
+    ("Down", 0x28),
+    ("PrintScreen", 0x2C),
+    ("PrtSc", 0x2C),
+    ("Insert", 0x2D),
+    ("Ins", 0x2D),
+    ("Delete", 0x2E),
+    ("Del", 0x2E),
+    ("NumMultiply", 0x6A),
+    ("NumAdd", 0x6B),
+    ("NumSubtract", 0x6D),
+    ("NumDecimal", 0x6E),
+    ("NumDivide", 0x6F),
+    ("ScrollLock", 0x91),
+    ("BrowserBack", 0xA6),
+    ("BrowserForward", 0xA7),
+    ("BrowserRefresh", 0xA8),
+    ("BrowserSearch", 0xAA),
+    ("BrowserHome", 0xAC),
+    ("VolumeMute", 0xAD),
+    ("VolumeDown", 0xAE),
+    ("VolumeUp", 0xAF),
+    ("MediaNext", 0xB0),
+    ("MediaPrevious", 0xB1),
+    ("MediaPrev", 0xB1),
+    ("MediaStop", 0xB2),
+    ("MediaPlayPause", 0xB3),
+    ("MediaPlay", 0xB3),
+    ("LaunchMail", 0xB4),
+    ("LaunchApp1", 0xB6),
+    ("LaunchApp2", 0xB7),
+    (";", 0xBA),
+    ("=", 0xBB),
+    (",", 0xBC),
+    ("-", 0xBD),
+    (".", 0xBE),
+    ("/", 0xBF),
+    ("`", 0xC0),
+    ("[", 0xDB),
+    ("\\", 0xDC),
+    ("]", 0xDD),
+    ("'", 0xDE),
+];
+
+/// The modifier keys of a hotkey
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub win: bool,
+}
+
+/// A global hotkey like `Ctrl+Alt+K` or `Win+Shift+F13`, parsed from and formatted as the text
+/// used in the settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hotkey {
+    pub modifiers: Modifiers,
+    /// The Windows virtual key code of the key that is pressed with the modifiers
+    pub key: u32,
+}
+
+impl Hotkey {
+    /// A hotkey of the key with Ctrl+Alt, the way personas are configured
+    pub fn ctrl_alt(key: &str) -> Result<Self> {
+        format!("ctrl+alt+{key}").parse()
+    }
+}
+
+impl std::str::FromStr for Hotkey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut modifiers = Modifiers::default();
+        let mut key = None;
+
+        // `+` separates the keys, the plus key is `=` on most layouts and `NumAdd` on the numpad
+        for part in s.split('+').map(str::trim) {
+            match part.to_lowercase().as_str() {
+                "ctrl" | "control" => modifiers.ctrl = true,
+                "alt" => modifiers.alt = true,
+                "shift" => modifiers.shift = true,
+                "win" | "windows" | "super" => modifiers.win = true,
+                "" => bail!("{s:?} has an empty key"),
+                name => {
+                    if key.is_some() {
+                        bail!("{s:?} has more than one key besides the modifiers");
+                    }
+                    key = Some(
+                        key_code(name).ok_or_else(|| anyhow::anyhow!("Unknown key {part:?}"))?,
+                    );
+                }
+            }
+        }
+
+        let Some(key) = key else {
+            bail!("{s:?} has no key besides the modifiers");
+        };
+
+        Ok(Self { modifiers, key })
+    }
+}
+
+impl std::fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Modifiers {
+            ctrl,
+            alt,
+            shift,
+            win,
+        } = self.modifiers;
+        for (pressed, name) in [(ctrl, "Ctrl"), (alt, "Alt"), (shift, "Shift"), (win, "Win")] {
+            if pressed {
+                write!(f, "{name}+")?;
+            }
+        }
+        write!(f, "{}", key_name(self.key))
+    }
+}
+
+/// The virtual key code of a lowercase key name
+fn key_code(name: &str) -> Option<u32> {
+    if let [c @ (b'a'..=b'z' | b'0'..=b'9')] = name.as_bytes() {
+        return Some(c.to_ascii_uppercase() as u32);
+    }
+
+    let number = |prefix: &str| {
+        name.strip_prefix(prefix)
+            .and_then(|n| n.parse::<u32>().ok())
+    };
+    if let Some(n) = number("f").filter(|n| (1..=24).contains(n)) {
+        return Some(0x70 + n - 1);
+    }
+    if let Some(n) = number("numpad")
+        .or_else(|| number("num"))
+        .filter(|n| *n <= 9)
+    {
+        return Some(0x60 + n);
+    }
+
+    KEY_NAMES
+        .iter()
+        .find(|(key, _)| key.to_lowercase() == name)
+        .map(|(_, code)| *code)
+}
+
+/// The display name of a virtual key code, the hex code for keys without a name
+fn key_name(code: u32) -> String {
+    match code {
+        0x30..=0x39 | 0x41..=0x5A => char::from_u32(code).unwrap_or('?').to_string(),
+        0x60..=0x69 => format!("Num{}", code - 0x60),
+        0x70..=0x87 => format!("F{}", code - 0x70 + 1),
+        _ => KEY_NAMES
+            .iter()
+            .find(|(_, key)| *key == code)
+            .map(|(name, _)| name.to_string())
+            .unwrap_or_else(|| format!("0x{code:02X}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_format_round_trip() {
+        let hotkey: Hotkey = "win+shift+f13".parse().unwrap();
+        assert_eq!(
+            hotkey.modifiers,
+            Modifiers {
+                shift: true,
+                win: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(hotkey.key, 0x7C);
+        assert_eq!(hotkey.to_string(), "Shift+Win+F13");
+
+        for text in [
+            "Ctrl+Alt+K",
+            "Ctrl+Alt+Num5",
+            "Alt+MediaPlayPause",
+            "Ctrl+Shift+;",
+        ] {
+            let hotkey: Hotkey = text.parse().unwrap();
+            assert_eq!(hotkey.to_string(), text);
+            assert_eq!(hotkey.to_string().parse::<Hotkey>().unwrap(), hotkey);
+        }
+
+        assert_eq!(
+            "control + ALT + numpad5".parse::<Hotkey>().unwrap(),
+            Hotkey::ctrl_alt("num5").unwrap()
+        );
+        assert_eq!(
+            "ctrl+alt+del".parse::<Hotkey>().unwrap().to_string(),
+            "Ctrl+Alt+Delete"
+        );
+    }
+
+    #[test]
+    fn invalid_hotkeys() {
+        assert!("ctrl+alt".parse::<Hotkey>().is_err());
+        assert!("ctrl+alt+k+j".parse::<Hotkey>().is_err());
+        assert!("ctrl+alt+f25".parse::<Hotkey>().is_err());
+        assert!("ctrl+hyper+k".parse::<Hotkey>().is_err());
+        assert!("ctrl++".parse::<Hotkey>().is_err());
+    }
+}
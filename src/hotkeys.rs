@@ -0,0 +1,94 @@
+use popup_gpt::hotkey::Hotkey;
+use windows_hotkeys::{
+    keys::{ModKey, VKey},
+    HotkeyManager,
+};
+
+use crate::{i18n::tr, settings::Settings};
+
+/// The hotkey that shows the popup, unless the settings choose another one
+pub const DEFAULT_HOTKEY: &str = "Ctrl+Alt+K";
+
+/// The global hotkeys and what they summon
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HotkeyAction {
+    /// Show the popup with an empty prompt
+    Popup,
+    /// Show the quick action palette for the clipboard content
+    QuickActions,
+    /// Start a conversation with the persona of the given name
+    Persona(String),
+}
+
+/// The registered global hotkeys
+pub struct Hotkeys {
+    pub manager: HotkeyManager<HotkeyAction>,
+    /// The optional hotkeys that could not be registered, the popup still works without them
+    pub errors: Vec<String>,
+}
+
+/// Register a hotkey with the callback that produces its action
+fn register_hotkey(
+    manager: &mut HotkeyManager<HotkeyAction>,
+    hotkey: Hotkey,
+    action: impl Fn() -> HotkeyAction + 'static,
+) -> bool {
+    let modifiers = [
+        (hotkey.modifiers.ctrl, ModKey::Ctrl),
+        (hotkey.modifiers.alt, ModKey::Alt),
+        (hotkey.modifiers.shift, ModKey::Shift),
+        (hotkey.modifiers.win, ModKey::Win),
+    ]
+    .into_iter()
+    .filter_map(|(pressed, modifier)| pressed.then_some(modifier))
+    .collect::<Vec<_>>();
+
+    manager
+        .register(VKey::CustomKeyCode(hotkey.key as i32), &modifiers, action)
+        .is_ok()
+}
+
+/// Register the global hotkeys. Fails with a message naming the combination if the popup hotkey
+/// can't be registered, usually because another application owns it.
+pub fn register(settings: &Settings) -> Result<Hotkeys, String> {
+    let mut manager = HotkeyManager::new();
+    let errors = register_all(&mut manager, settings)?;
+    Ok(Hotkeys { manager, errors })
+}
+
+/// Register the hotkeys with an existing manager, e.g. after they were suspended. Returns the
+/// optional hotkeys that could not be registered.
+pub fn register_all(
+    manager: &mut HotkeyManager<HotkeyAction>,
+    settings: &Settings,
+) -> Result<Vec<String>, String> {
+    let text = settings.hotkey.as_deref().unwrap_or(DEFAULT_HOTKEY);
+    let hotkey: Hotkey = text.parse().map_err(|e| format!("{e:#}"))?;
+    if !register_hotkey(manager, hotkey, || HotkeyAction::Popup) {
+        tracing::warn!(%hotkey, "hotkey is taken");
+        return Err(tr("{combination} is already used by another application")
+            .replace("{combination}", &hotkey.to_string()));
+    }
+
+    let mut errors = Vec::new();
+    let quick_actions = Hotkey::ctrl_alt("A").expect("A is a key");
+    if !register_hotkey(manager, quick_actions, || HotkeyAction::QuickActions) {
+        errors.push(tr("Could not register Ctrl+Alt+A for quick actions").to_string());
+    }
+
+    for persona in &settings.personas {
+        let name = persona.name.clone();
+        let registered = Hotkey::ctrl_alt(&persona.key).is_ok_and(|hotkey| {
+            register_hotkey(manager, hotkey, move || HotkeyAction::Persona(name.clone()))
+        });
+        if !registered {
+            errors.push(
+                tr("Could not register Ctrl+Alt+{key} for the persona {name}")
+                    .replace("{key}", &persona.key)
+                    .replace("{name}", &persona.name),
+            );
+        }
+    }
+
+    Ok(errors)
+}
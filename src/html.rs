@@ -0,0 +1,553 @@
+use crate::markdown::{fence_for, quote};
+
+/// Tags that carry structure worth keeping. HTML without any of them, e.g. the styled spans that
+/// code editors copy, is better pasted as the plain text.
+const STRUCTURE_TAGS: &[&str] = &[
+    "a",
+    "b",
+    "blockquote",
+    "code",
+    "em",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "i",
+    "li",
+    "p",
+    "pre",
+    "strong",
+    "table",
+];
+
+/// Tags whose content is not text
+const SKIPPED_TAGS: &[&str] = &["head", "script", "style", "title"];
+
+/// The parts of a web page around its content, e.g. menus and forms
+const BOILERPLATE_TAGS: &[&str] = &[
+    "aside", "button", "footer", "form", "header", "iframe", "nav", "noscript", "select", "svg",
+];
+
+/// Whether the HTML has structure that gets lost when it is pasted as plain text
+pub fn has_structure(html: &str) -> bool {
+    tokens(html).any(|token| match token {
+        Token::Open(tag) => STRUCTURE_TAGS.contains(&tag_name(tag).as_str()),
+        _ => false,
+    })
+}
+
+/// Convert HTML, e.g. copied from a browser or Word, to Markdown. Headings, lists, links,
+/// emphasis, code, quotes and tables are kept, everything else becomes plain text.
+pub fn to_markdown(html: &str) -> String {
+    convert(html, Converter::default())
+}
+
+/// Convert a web page to Markdown without its menus, headers, footers and forms. If the page
+/// marks its content with `main` or `article`, only that is kept.
+pub fn page_to_markdown(html: &str) -> String {
+    let converter = Converter {
+        boilerplate: BOILERPLATE_TAGS,
+        ..Default::default()
+    };
+    convert(content_of_page(html), converter)
+}
+
+/// The `main` element of the page, or the first to the last `article`, including the tags. The
+/// whole page if it has neither.
+fn content_of_page(html: &str) -> &str {
+    // ASCII lowercase keeps the byte positions
+    let lower = html.to_ascii_lowercase();
+
+    for tag in ["main", "article"] {
+        let start = lower.match_indices(&format!("<{tag}")).find(|(i, open)| {
+            lower[i + open.len()..].starts_with(|c: char| c == '>' || c.is_ascii_whitespace())
+        });
+        let end = lower.rfind(&format!("</{tag}>"));
+        if let (Some((start, _)), Some(end)) = (start, end) {
+            if start < end {
+                return &html[start..end + tag.len() + 3];
+            }
+        }
+    }
+
+    html
+}
+
+fn convert(html: &str, mut converter: Converter) -> String {
+    for token in tokens(html) {
+        match token {
+            Token::Text(text) => converter.text(&decode_entities(text)),
+            Token::Open(tag) => converter.open(tag),
+            Token::Close(name) => converter.close(&name.trim().to_ascii_lowercase()),
+        }
+    }
+
+    converter.out.trim().to_string()
+}
+
+enum Token<'a> {
+    Text(&'a str),
+    /// The content of an opening tag without the angle brackets
+    Open(&'a str),
+    /// The name of a closing tag
+    Close(&'a str),
+}
+
+/// Split the HTML into text and tags. Comments and declarations like `<!DOCTYPE>` are dropped.
+fn tokens(html: &str) -> impl Iterator<Item = Token<'_>> {
+    let mut rest = html;
+
+    std::iter::from_fn(move || loop {
+        if rest.is_empty() {
+            return None;
+        }
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+
+        let is_tag = rest.starts_with('<')
+            && rest[1..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '/' || c == '!');
+        if !is_tag {
+            let first = rest.chars().next().map_or(1, char::len_utf8);
+            let end = rest[first..]
+                .find('<')
+                .map_or(rest.len(), |end| end + first);
+            let (text, tail) = rest.split_at(end);
+            rest = tail;
+            return Some(Token::Text(text));
+        }
+
+        let end = rest.find('>').unwrap_or(rest.len());
+        let tag = &rest[1..end];
+        rest = rest.get(end + 1..).unwrap_or_default();
+
+        if let Some(name) = tag.strip_prefix('/') {
+            return Some(Token::Close(name));
+        } else if !tag.starts_with('!') {
+            return Some(Token::Open(tag.trim_end_matches('/')));
+        }
+    })
+}
+
+/// The lowercase name of a tag, e.g. `a` for `a href="..."`
+fn tag_name(tag: &str) -> String {
+    tag.split(|c: char| c.is_whitespace())
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+/// The value of an attribute of a tag, with the entities decoded
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let mut rest = tag.trim_start_matches(|c: char| !c.is_whitespace());
+
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+
+        let key_end = rest
+            .find(|c: char| c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        rest = rest[key_end..].trim_start();
+
+        let value = match rest.strip_prefix('=') {
+            Some(value) => {
+                let value = value.trim_start();
+                let (value, tail) = match value.chars().next() {
+                    Some(quote @ ('"' | '\'')) => {
+                        let end = value[1..].find(quote).map_or(value.len(), |end| end + 1);
+                        (&value[1..end], value.get(end + 1..).unwrap_or_default())
+                    }
+                    _ => value.split_at(value.find(char::is_whitespace).unwrap_or(value.len())),
+                };
+                rest = tail;
+                value
+            }
+            None => "",
+        };
+
+        if key.eq_ignore_ascii_case(name) {
+            return Some(decode_entities(value));
+        }
+    }
+}
+
+/// Replace the named entities common in copied text and all numeric ones
+pub(crate) fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let entity = &rest[1..end];
+            let c = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ => match entity.strip_prefix('#') {
+                    Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16)
+                        .ok()
+                        .and_then(char::from_u32),
+                    Some(decimal) => decimal.parse().ok().and_then(char::from_u32),
+                    None => None,
+                },
+            };
+            c.map(|c| (c, end + 1))
+        });
+
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// An element whose content is rewritten once it is closed
+struct Capture {
+    tag: String,
+    /// The position in the output where the content starts
+    start: usize,
+    /// The link target of `a` or the code language of `pre`
+    attr: Option<String>,
+}
+
+#[derive(Default)]
+struct Converter {
+    out: String,
+    captures: Vec<Capture>,
+    /// The open lists, `None` for bullet lists and the next number for numbered ones
+    lists: Vec<Option<u32>>,
+    /// The number of rows so far in each open table
+    tables: Vec<usize>,
+    /// The number of open `pre` elements, whitespace is kept in them
+    pre: usize,
+    /// The number of open elements whose content is dropped
+    skip: usize,
+    /// Tags whose content is dropped as well, besides the `SKIPPED_TAGS`
+    boilerplate: &'static [&'static str],
+}
+
+impl Converter {
+    fn text(&mut self, text: &str) {
+        if self.skip > 0 {
+            return;
+        }
+        if self.pre > 0 {
+            self.out.push_str(text);
+            return;
+        }
+
+        let mut collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if text.starts_with(char::is_whitespace) && !collapsed.is_empty() {
+            collapsed.insert(0, ' ');
+        }
+        if text.ends_with(char::is_whitespace) {
+            collapsed.push(' ');
+        }
+
+        let at_word_start = self.out.is_empty() || self.out.ends_with([' ', '\n']);
+        let collapsed = match at_word_start {
+            true => collapsed.trim_start(),
+            false => &collapsed,
+        };
+        self.out.push_str(collapsed);
+    }
+
+    fn open(&mut self, tag: &str) {
+        let name = tag_name(tag);
+        if self.is_skipped(&name) {
+            self.skip += 1;
+        }
+        if self.skip > 0 {
+            return;
+        }
+
+        match name.as_str() {
+            "p" | "table" | "hr" => self.blank_line(),
+            "div" | "tr" | "br" => self.newline(),
+            _ => (),
+        }
+
+        match name.as_str() {
+            "br" if self.pre > 0 => self.out.push('\n'),
+            "hr" => {
+                self.out.push_str("---");
+                self.blank_line();
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.blank_line();
+                let level = name[1..].parse().unwrap_or(1);
+                self.out.push_str(&format!("{} ", "#".repeat(level)));
+            }
+            "ul" | "ol" => {
+                if self.lists.is_empty() {
+                    self.blank_line();
+                } else {
+                    self.newline();
+                }
+                let start = attribute(tag, "start").and_then(|start| start.parse().ok());
+                self.lists
+                    .push((name == "ol").then_some(start.unwrap_or(1)));
+            }
+            "li" => {
+                self.newline();
+                let indent = "    ".repeat(self.lists.len().saturating_sub(1));
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "- ".to_string(),
+                };
+                self.out.push_str(&format!("{indent}{marker}"));
+            }
+            "strong" | "b" => self.out.push_str("**"),
+            "em" | "i" => self.out.push('*'),
+            "code" if self.pre > 0 => {
+                let lang = attribute(tag, "class").and_then(|class| {
+                    class
+                        .split_whitespace()
+                        .find_map(|class| class.strip_prefix("language-").map(str::to_string))
+                });
+                if let Some(capture) = self.captures.iter_mut().rev().find(|c| c.tag == "pre") {
+                    capture.attr = capture.attr.take().or(lang);
+                }
+            }
+            "code" => self.out.push('`'),
+            "img" => {
+                let alt = attribute(tag, "alt").unwrap_or_default();
+                if let Some(src) = attribute(tag, "src").filter(|src| !src.starts_with("data:")) {
+                    self.out.push_str(&format!("![{alt}]({src})"));
+                }
+            }
+            "td" | "th" => {
+                let row_start = self.out.is_empty() || self.out.ends_with('\n');
+                self.out.push_str(if row_start { "| " } else { " | " });
+            }
+            "pre" => {
+                self.blank_line();
+                self.pre += 1;
+                self.capture(name, None);
+            }
+            "blockquote" => {
+                self.blank_line();
+                self.capture(name, None);
+            }
+            "a" => {
+                let href = attribute(tag, "href");
+                self.capture(name, href);
+            }
+            "table" => self.tables.push(0),
+            _ => (),
+        }
+    }
+
+    fn close(&mut self, name: &str) {
+        if self.is_skipped(name) {
+            self.skip = self.skip.saturating_sub(1);
+            return;
+        }
+        if self.skip > 0 {
+            return;
+        }
+
+        match name {
+            "p" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => self.blank_line(),
+            "div" | "li" => self.newline(),
+            "ul" | "ol" => {
+                self.lists.pop();
+                match self.lists.is_empty() {
+                    true => self.blank_line(),
+                    false => self.newline(),
+                }
+            }
+            "strong" | "b" => self.out.push_str("**"),
+            "em" | "i" => self.out.push('*'),
+            "code" if self.pre == 0 => self.out.push('`'),
+            "tr" => {
+                let cells = self
+                    .out
+                    .lines()
+                    .last()
+                    .unwrap_or_default()
+                    .matches(" | ")
+                    .count()
+                    + 1;
+                self.out.push_str(" |");
+                if let Some(rows) = self.tables.last_mut() {
+                    *rows += 1;
+                    // The first row is the header, Markdown tables need one
+                    if *rows == 1 {
+                        self.out.push_str(&format!("\n|{}", " --- |".repeat(cells)));
+                    }
+                }
+                self.newline();
+            }
+            "table" => {
+                self.tables.pop();
+                self.blank_line();
+            }
+            "pre" => {
+                self.pre = self.pre.saturating_sub(1);
+                if let Some((code, lang)) = self.end_capture(name) {
+                    let fence = fence_for(&code);
+                    let code = code.trim_end_matches('\n');
+                    let lang = lang.unwrap_or_default();
+                    self.out
+                        .push_str(&format!("{fence}{lang}\n{code}\n{fence}"));
+                }
+                self.blank_line();
+            }
+            "blockquote" => {
+                if let Some((content, _)) = self.end_capture(name) {
+                    self.out.push_str(&quote(&content));
+                }
+                self.blank_line();
+            }
+            "a" => {
+                if let Some((text, href)) = self.end_capture(name) {
+                    let text = text.trim();
+                    match href.filter(|href| !href.is_empty() && !href.starts_with('#')) {
+                        Some(href) if !href.starts_with("javascript:") => {
+                            self.out.push_str(&format!("[{text}]({href})"));
+                        }
+                        _ => self.out.push_str(text),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn is_skipped(&self, name: &str) -> bool {
+        SKIPPED_TAGS.contains(&name) || self.boilerplate.contains(&name)
+    }
+
+    fn capture(&mut self, tag: String, attr: Option<String>) {
+        self.captures.push(Capture {
+            tag,
+            start: self.out.len(),
+            attr,
+        });
+    }
+
+    /// Remove the content of the innermost open element with the name from the output and return
+    /// it with the attribute of the element
+    fn end_capture(&mut self, name: &str) -> Option<(String, Option<String>)> {
+        let index = self.captures.iter().rposition(|c| c.tag == name)?;
+        let capture = self.captures.remove(index);
+        self.captures.truncate(index);
+
+        Some((self.out.split_off(capture.start), capture.attr))
+    }
+
+    fn newline(&mut self) {
+        if self.pre == 0 {
+            self.out.truncate(self.out.trim_end_matches(' ').len());
+        }
+        if !self.out.is_empty() && !self.out.ends_with('\n') {
+            self.out.push('\n');
+        }
+    }
+
+    /// End the block, lists stay together to not turn them into loose lists
+    fn blank_line(&mut self) {
+        self.newline();
+        if self.lists.is_empty() && !self.out.is_empty() && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn structure_is_kept() {
+        let html = r#"<html><head><style>p { color: red }</style></head><body>
+            <h2>Install</h2>
+            <p>Get it from <a href="https://example.com/?a=1&amp;b=2">the <b>site</b></a>, then:</p>
+            <ol><li>Unpack it</li><li>Run <code>setup</code>
+                <ul><li>as admin</li></ul></li></ol>
+            <pre><code class="language-sh">cd app
+./setup --all</code></pre>
+            <blockquote><p>It just works</p></blockquote>
+            </body></html>"#;
+
+        assert_eq!(
+            to_markdown(html),
+            "## Install\n\n\
+            Get it from [the **site**](https://example.com/?a=1&b=2), then:\n\n\
+            1. Unpack it\n\
+            2. Run `setup`\n    \
+            - as admin\n\n\
+            ```sh\ncd app\n./setup --all\n```\n\n\
+            > It just works"
+        );
+    }
+
+    #[test]
+    fn tables_get_a_header_row() {
+        let html = "<table><tr><th>Name</th><th>Size</th></tr><tr><td>a.txt</td><td>1&nbsp;KB</td></tr></table>";
+
+        assert_eq!(
+            to_markdown(html),
+            "| Name | Size |\n| --- | --- |\n| a.txt | 1 KB |"
+        );
+    }
+
+    #[test]
+    fn pages_lose_their_boilerplate() {
+        let page = r#"<body><nav><a href="/">Home</a></nav>
+            <MAIN class="post"><h1>Title</h1><p>Text</p><form><button>Subscribe</button></form></MAIN>
+            <footer>Imprint</footer></body>"#;
+        assert_eq!(page_to_markdown(page), "# Title\n\nText");
+
+        let page = "<header>Menu</header><p>Text</p><aside>Ads</aside><footer>Imprint</footer>";
+        assert_eq!(page_to_markdown(page), "Text");
+
+        // `main-menu` is not a `main` element
+        let page = "<main-menu>Menu</main-menu><article>A</article><article>B</article>";
+        assert_eq!(page_to_markdown(page), "AB");
+    }
+
+    #[test]
+    fn styled_spans_have_no_structure() {
+        let html = r#"<div style="white-space: pre"><div><span style="color: blue">fn</span> main() {}</div></div>"#;
+
+        assert!(!has_structure(html));
+        assert!(has_structure("<p>Text</p>"));
+    }
+
+    #[test]
+    fn text_may_start_with_any_character() {
+        assert_eq!(to_markdown("über"), "über");
+        assert_eq!(to_markdown("<p>x</p>é"), "x\n\né");
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    io::BufReader,
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use popup_gpt::{
+    chatgpt::ChatGPT,
+    history::History,
+    local_api::{self, ApiError, EventStream, Route, DEFAULT_PORT},
+};
+use serde_json::json;
+
+use crate::{
+    save_exchange,
+    settings::{HttpApi, Settings},
+};
+
+/// How long a client may take to send its request, so it can't block the following ones
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serve the local HTTP API on a background thread. `show` brings up the popup and `hide` hides
+/// it. Answers run on threads of their own, so the popup can be shown and hidden meanwhile.
+pub fn serve(
+    config: &HttpApi,
+    settings_path: PathBuf,
+    history: Option<Arc<Mutex<History>>>,
+    show: impl Fn() + Send + 'static,
+    hide: impl Fn() + Send + 'static,
+) -> Result<()> {
+    if config.token.trim().is_empty() {
+        bail!("The HTTP API needs a token in the settings");
+    }
+    let port = config.port.unwrap_or(DEFAULT_PORT);
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .with_context(|| format!("Could not start the HTTP API on port {port}"))?;
+    tracing::info!(port, "HTTP API listening");
+
+    let token = config.token.clone();
+    std::thread::spawn(move || {
+        for conn in listener.incoming() {
+            let conn = match conn {
+                Ok(conn) => conn,
+                Err(e) => {
+                    tracing::warn!(error = %e, "could not accept an HTTP API connection");
+                    continue;
+                }
+            };
+            if let Err(e) = handle_connection(conn, &token, &settings_path, &history, &show, &hide)
+            {
+                tracing::warn!(error = %e, "HTTP API connection failed");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_connection(
+    mut conn: TcpStream,
+    token: &str,
+    settings_path: &Path,
+    history: &Option<Arc<Mutex<History>>>,
+    show: &impl Fn(),
+    hide: &impl Fn(),
+) -> Result<()> {
+    conn.set_read_timeout(Some(READ_TIMEOUT))?;
+    let request = local_api::read_request(&mut BufReader::new(&conn))?;
+
+    let route = match local_api::route(&request, token) {
+        Ok(route) => route,
+        Err(e) => {
+            tracing::info!(status = e.status, path = %request.path, "HTTP API request refused");
+            local_api::write_error(&mut conn, &e)?;
+            return Ok(());
+        }
+    };
+    tracing::info!(?route, "HTTP API request");
+
+    match route {
+        Route::Show => {
+            show();
+            local_api::write_no_content(&mut conn)?;
+        }
+        Route::Hide => {
+            hide();
+            local_api::write_no_content(&mut conn)?;
+        }
+        Route::Ask { prompt, stream } => {
+            let settings_path = settings_path.to_path_buf();
+            let history = history.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = ask(conn, &prompt, stream, &settings_path, history) {
+                    tracing::warn!(error = %e, "could not answer an HTTP API request");
+                }
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Answer the prompt in a conversation of its own with the active profile and save it in the
+/// history
+fn ask(
+    mut conn: TcpStream,
+    prompt: &str,
+    stream: bool,
+    settings_path: &Path,
+    history: Option<Arc<Mutex<History>>>,
+) -> Result<()> {
+    // The settings file is the shared state with the popup, which may have switched the profile
+    let mut chatgpt = ChatGPT::new(String::new());
+    let configured =
+        Settings::load(settings_path).and_then(|settings| settings.configure(&mut chatgpt, None));
+    if let Err(e) = configured {
+        local_api::write_error(&mut conn, &ApiError::new(500, format!("{e:#}")))?;
+        return Ok(());
+    }
+
+    let resp = match stream {
+        true => {
+            let events = EventStream::start(&mut conn)?;
+            let resp = chatgpt.ask_stream(prompt, &events);
+            let error = resp.as_ref().err().map(|e| format!("{e:#}"));
+            events.finish(error.as_deref());
+            resp
+        }
+        false => {
+            let resp = chatgpt.ask(prompt);
+            match &resp {
+                Ok(resp) => {
+                    let answer = resp.primary_response().unwrap_or_default();
+                    local_api::write_json(&mut conn, 200, &json!({ "answer": answer }))?;
+                }
+                Err(e) => {
+                    local_api::write_error(&mut conn, &ApiError::new(502, format!("{e:#}")))?;
+                }
+            }
+            resp
+        }
+    };
+
+    if let (Ok(resp), Some(history)) = (resp, history) {
+        let saved = save_exchange(&history.lock().unwrap(), None, &chatgpt, prompt, &resp);
+        if let Some(Err(e)) = saved {
+            tracing::warn!(error = %e, "could not save the HTTP API exchange");
+        }
+    }
+
+    Ok(())
+}
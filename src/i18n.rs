@@ -0,0 +1,417 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+/// The languages of the user interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Language {
+    #[serde(rename = "en")]
+    English,
+    #[serde(rename = "de")]
+    German,
+}
+
+/// The current language as the index of the enum variant
+static LANGUAGE: AtomicU8 = AtomicU8::new(Language::English as u8);
+
+impl Language {
+    /// The language of the Windows user, English if it isn't supported
+    pub fn system() -> Self {
+        use winapi::um::winnls::GetUserDefaultLocaleName;
+        use winapi::um::winnt::LOCALE_NAME_MAX_LENGTH;
+
+        let mut buf = [0u16; LOCALE_NAME_MAX_LENGTH];
+        let len = unsafe { GetUserDefaultLocaleName(buf.as_mut_ptr(), buf.len() as i32) };
+        // The length includes the terminating null
+        let locale = String::from_utf16_lossy(&buf[..(len.max(1) as usize - 1)]);
+
+        match locale.split('-').next() {
+            Some("de") => Language::German,
+            _ => Language::English,
+        }
+    }
+
+    fn translations(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Language::English => &[],
+            Language::German => GERMAN,
+        }
+    }
+}
+
+/// Switch the language of all strings translated from now on
+pub fn set_language(language: Language) {
+    LANGUAGE.store(language as u8, Ordering::Relaxed);
+}
+
+fn language() -> Language {
+    match LANGUAGE.load(Ordering::Relaxed) {
+        x if x == Language::German as u8 => Language::German,
+        _ => Language::English,
+    }
+}
+
+/// Translate a string of the user interface. The English text is the key, so strings without a
+/// translation stay English. Placeholders like `{name}` are kept for the caller to replace.
+pub fn tr(text: &'static str) -> &'static str {
+    language()
+        .translations()
+        .iter()
+        .find(|(english, _)| *english == text)
+        .map(|(_, translated)| *translated)
+        .unwrap_or(text)
+}
+
+const GERMAN: &[(&str, &str)] = &[
+    // Status bar
+    ("Profile", "Profil"),
+    ("Insert", "Einfügen"),
+    (
+        "Paste the response into the previous window (Ctrl+I)",
+        "Die Antwort in das vorherige Fenster einfügen (Strg+I)",
+    ),
+    ("Insert code", "Code einfügen"),
+    (
+        "Paste the last code block into the previous window",
+        "Den letzten Codeblock in das vorherige Fenster einfügen",
+    ),
+    ("Notes", "Notizen"),
+    (
+        "Append the question and response to the notes file (Ctrl+Shift+N)",
+        "Frage und Antwort an die Notizdatei anhängen (Strg+Umschalt+N)",
+    ),
+    ("Fetching {host}…", "{host} wird abgerufen…"),
+    ("🔗 Attach the page of {host}", "🔗 Die Seite von {host} anhängen"),
+    (
+        "Fetch the page and send its text with the prompt",
+        "Die Seite abrufen und ihren Text mit dem Prompt senden",
+    ),
+    ("Fetched {tokens} tokens from {host}", "{tokens} Tokens von {host} abgerufen"),
+    ("cut off", "abgeschnitten"),
+    ("Agent", "Agent"),
+    ("Scratchpad", "Notizblock"),
+    ("Pin", "Anheften"),
+    (
+        "{prompt} prompt and {completion} completion tokens",
+        "{prompt} Prompt- und {completion} Antwort-Tokens",
+    ),
+    (
+        "The prompt has {chars} characters, {words} words and ~{tokens} tokens",
+        "Der Prompt hat {chars} Zeichen, {words} Wörter und ~{tokens} Tokens",
+    ),
+    (
+        "The paste has {chars} characters, {words} words and ~{tokens} tokens",
+        "Der eingefügte Text hat {chars} Zeichen, {words} Wörter und ~{tokens} Tokens",
+    ),
+    ("Keep the start", "Anfang behalten"),
+    ("Keep the end", "Ende behalten"),
+    ("Cut the middle", "Mitte kürzen"),
+    (
+        "Keep the response visible above the answers to the next questions",
+        "Die Antwort über den Antworten auf die nächsten Fragen sichtbar lassen",
+    ),
+    ("Pinned", "Angeheftet"),
+    (
+        "{count} older messages are only kept in the history",
+        "{count} ältere Nachrichten sind nur noch im Verlauf",
+    ),
+    ("Unpin", "Lösen"),
+    (
+        "Collect snippets of responses, right-click selected text to add it",
+        "Ausschnitte von Antworten sammeln, markierten Text per Rechtsklick hinzufügen",
+    ),
+    ("Send to scratchpad", "An den Notizblock senden"),
+    ("Add the response", "Die Antwort hinzufügen"),
+    ("Clear", "Leeren"),
+    ("Links", "Links"),
+    (
+        "Let the model read the clipboard, fetch web pages, get the time and calculate before it \
+        answers",
+        "Das Modell vor der Antwort die Zwischenablage lesen, Webseiten abrufen, die Uhrzeit \
+        abfragen und rechnen lassen",
+    ),
+    ("Allow", "Erlauben"),
+    ("Deny", "Ablehnen"),
+    ("denied", "abgelehnt"),
+    ("Translate", "Übersetzen"),
+    (
+        "Translate the prompts instead of answering them",
+        "Die Prompts übersetzen statt sie zu beantworten",
+    ),
+    ("Export", "Exportieren"),
+    ("Import", "Importieren"),
+    ("Transcript", "Verlauf"),
+    ("History", "Gespräche"),
+    ("Plugins", "Plugins"),
+    ("What's new", "Neuerungen"),
+    ("Update to {version}", "Auf {version} aktualisieren"),
+    (
+        "Download the new version, which is used from the next start",
+        "Die neue Version herunterladen, sie wird ab dem nächsten Start verwendet",
+    ),
+    ("System prompt", "Systemprompt"),
+    ("first token {time}", "erstes Token {time}"),
+    ("{speed} tokens/s", "{speed} Tokens/s"),
+    ("{tokens} tokens", "{tokens} Tokens"),
+    // Popup
+    (
+        "System prompt for new conversations",
+        "Systemprompt für neue Gespräche",
+    ),
+    (
+        "Edit the system prompt of this conversation",
+        "Den Systemprompt dieses Gesprächs bearbeiten",
+    ),
+    ("Apply", "Übernehmen"),
+    ("Detect language", "Sprache erkennen"),
+    (
+        "Swap the languages and translate the translation",
+        "Die Sprachen tauschen und die Übersetzung übersetzen",
+    ),
+    ("Translated from {language}", "Übersetzt aus: {language}"),
+    ("Save", "Speichern"),
+    ("Cancel", "Abbrechen"),
+    ("Run on clipboard", "Auf die Zwischenablage anwenden"),
+    ("Running {name}…", "{name} läuft…"),
+    ("Token probabilities", "Token-Wahrscheinlichkeiten"),
+    (
+        "Estimated tokens of the prompt and the conversation",
+        "Geschätzte Tokens des Prompts und des Gesprächs",
+    ),
+    (
+        "Scroll long lines instead of wrapping them",
+        "Lange Zeilen scrollen statt sie umzubrechen",
+    ),
+    ("Wrap long lines", "Lange Zeilen umbrechen"),
+    ("1 line", "1 Zeile"),
+    ("{count} lines", "{count} Zeilen"),
+    ("Copy the code", "Code kopieren"),
+    ("Save the code to a file", "Code in einer Datei speichern"),
+    ("Thinking…", "Denkt nach…"),
+    ("Reasoning", "Gedankengang"),
+    ("Remove the attachment", "Den Anhang entfernen"),
+    ("Remove from the queue", "Aus der Warteschlange entfernen"),
+    (
+        "The attachments are about {tokens} tokens, which may not fit into the context",
+        "Die Anhänge sind etwa {tokens} Tokens groß und passen eventuell nicht in den Kontext",
+    ),
+    (
+        "The response was cut off because it reached the token limit. Raise max_tokens or ask the model to continue.",
+        "Die Antwort wurde abgeschnitten, weil sie das Token-Limit erreicht hat. Erhöhe max_tokens oder bitte das Modell fortzufahren.",
+    ),
+    (
+        "The content filter of the API stopped the response.",
+        "Der Inhaltsfilter der API hat die Antwort gestoppt.",
+    ),
+    // Transcript
+    ("The conversation is empty", "Das Gespräch ist leer"),
+    (
+        "Never drop the message when the context is trimmed",
+        "Die Nachricht beim Kürzen des Kontexts nie weglassen",
+    ),
+    (
+        "Include the message in the context again",
+        "Die Nachricht wieder in den Kontext aufnehmen",
+    ),
+    (
+        "Exclude the message from the context",
+        "Die Nachricht aus dem Kontext ausschließen",
+    ),
+    ("Delete the message", "Die Nachricht löschen"),
+    ("Copy", "Kopieren"),
+    ("Copy as Markdown", "Als Markdown kopieren"),
+    ("Quote into prompt", "In den Prompt zitieren"),
+    ("Regenerate from here", "Ab hier neu generieren"),
+    ("✏ Edit", "✏ Bearbeiten"),
+    ("⑂ Branch", "⑂ Abzweigen"),
+    (
+        "Continue from here in a new branch",
+        "Ab hier in einem neuen Zweig weitermachen",
+    ),
+    ("Save & regenerate", "Speichern & neu generieren"),
+    // History
+    ("← Back", "← Zurück"),
+    ("Continue", "Fortsetzen"),
+    ("Close", "Schließen"),
+    ("Search", "Suchen"),
+    ("Delete", "Löschen"),
+    (
+        "No saved conversations yet",
+        "Noch keine gespeicherten Gespräche",
+    ),
+    ("Nothing found", "Nichts gefunden"),
+    ("Conversation", "Gespräch"),
+    // Errors
+    ("Retry", "Erneut versuchen"),
+    (
+        "Offline. Prompts are queued until the API can be reached again.",
+        "Offline. Prompts werden gesammelt, bis die API wieder erreichbar ist.",
+    ),
+    (
+        "The connection to the API is back",
+        "Die Verbindung zur API ist wieder da",
+    ),
+    (
+        "Send the failed request again",
+        "Die fehlgeschlagene Anfrage erneut senden",
+    ),
+    (
+        "Could not register Ctrl+Alt+A for quick actions",
+        "Strg+Alt+A konnte nicht für Schnellaktionen registriert werden",
+    ),
+    (
+        "Could not register Ctrl+Alt+{key} for the persona {name}",
+        "Strg+Alt+{key} konnte nicht für die Persona {name} registriert werden",
+    ),
+    (
+        "Conversations are not saved",
+        "Gespräche werden nicht gespeichert",
+    ),
+    (
+        "Settings are not reloaded automatically",
+        "Einstellungen werden nicht automatisch neu geladen",
+    ),
+    ("Logging is disabled", "Das Log ist deaktiviert"),
+    (
+        "Wait for the current response before exporting",
+        "Warte vor dem Exportieren auf die aktuelle Antwort",
+    ),
+    (
+        "There is nothing to export yet",
+        "Es gibt noch nichts zu exportieren",
+    ),
+    (
+        "Wait for the current response before continuing another conversation",
+        "Warte auf die aktuelle Antwort, bevor du ein anderes Gespräch fortsetzt",
+    ),
+    (
+        "The clipboard doesn't contain any text",
+        "Die Zwischenablage enthält keinen Text",
+    ),
+    ("Unknown command", "Unbekannter Befehl"),
+    (
+        "Version {version} is installed, restart Popup-GPT to use it",
+        "Version {version} ist installiert, starte Popup-GPT neu, um sie zu verwenden",
+    ),
+    (
+        "Could not save the conversation",
+        "Das Gespräch konnte nicht gespeichert werden",
+    ),
+    // Error dialogs
+    (
+        "Popup-GPT could not register its hotkey",
+        "Popup-GPT konnte sein Tastenkürzel nicht registrieren",
+    ),
+    (
+        "{combination} is already used by another application",
+        "{combination} wird bereits von einer anderen Anwendung verwendet",
+    ),
+    ("Hotkey", "Tastenkürzel"),
+    ("Use this hotkey", "Dieses Tastenkürzel verwenden"),
+    (
+        "Popup-GPT could not load its configuration",
+        "Popup-GPT konnte seine Konfiguration nicht laden",
+    ),
+    (
+        "Fix the config file and start Popup-GPT again.",
+        "Korrigiere die Konfigurationsdatei und starte Popup-GPT erneut.",
+    ),
+    (
+        "Choose the config file with --config and start Popup-GPT again.",
+        "Wähle die Konfigurationsdatei mit --config und starte Popup-GPT erneut.",
+    ),
+    (
+        "Invalid command line arguments",
+        "Ungültige Kommandozeilenargumente",
+    ),
+    ("Open config", "Konfiguration öffnen"),
+    // Shortcuts
+    ("Shortcuts", "Tastenkürzel"),
+    ("New conversation", "Neue Unterhaltung"),
+    ("Switch to the next profile", "Zum nächsten Profil wechseln"),
+    ("Open the history", "Die Gespräche öffnen"),
+    ("Show the transcript", "Den Verlauf anzeigen"),
+    (
+        "Append the response to the notes",
+        "Die Antwort an die Notizen anhängen",
+    ),
+    (
+        "Insert the response into the previous window",
+        "Die Antwort in das vorherige Fenster einfügen",
+    ),
+    ("Send the prompt", "Die Eingabe senden"),
+    (
+        "Stop the response or hide the popup",
+        "Die Antwort stoppen oder das Popup ausblenden",
+    ),
+    ("Paste", "Einfügen"),
+    ("Reset the zoom", "Den Zoom zurücksetzen"),
+    ("Scroll the transcript up", "Den Verlauf nach oben blättern"),
+    ("Scroll the transcript down", "Den Verlauf nach unten blättern"),
+    (
+        "Select the previous message of the transcript",
+        "Die vorherige Nachricht des Verlaufs auswählen",
+    ),
+    (
+        "Select the next message of the transcript",
+        "Die nächste Nachricht des Verlaufs auswählen",
+    ),
+    (
+        "Copy the selected message of the transcript",
+        "Die ausgewählte Nachricht des Verlaufs kopieren",
+    ),
+    ("Show the popup (global)", "Das Popup anzeigen (global)"),
+    ("Switch the overlay", "Das Overlay umschalten"),
+    ("Quick actions (global)", "Schnellaktionen (global)"),
+    ("Persona {name} (global)", "Persona {name} (global)"),
+    ("Reset to defaults", "Auf Standard zurücksetzen"),
+    (
+        "Click a binding and press the new key combination, Esc cancels",
+        "Klicke auf ein Kürzel und drücke die neue Tastenkombination, Esc bricht ab",
+    ),
+    (
+        "Some keys are bound more than once",
+        "Manche Tasten sind mehrfach belegt",
+    ),
+    ("Press keys…", "Tasten drücken…"),
+    // Settings
+    ("Settings", "Einstellungen"),
+    ("Export settings", "Einstellungen exportieren"),
+    (
+        "Export settings with API keys",
+        "Einstellungen mit API-Schlüsseln exportieren",
+    ),
+    (
+        "Anyone with the file can use your API keys",
+        "Jeder mit der Datei kann deine API-Schlüssel verwenden",
+    ),
+    ("Import settings", "Einstellungen importieren"),
+    ("API key", "API-Schlüssel"),
+    (
+        "API key of the profile {name}",
+        "API-Schlüssel des Profils {name}",
+    ),
+    ("Base URL", "Basis-URL"),
+    ("Test key", "Schlüssel testen"),
+    (
+        "Send a request for a single token with this key",
+        "Eine Anfrage für ein einzelnes Token mit diesem Schlüssel senden",
+    ),
+    ("The key works", "Der Schlüssel funktioniert"),
+    ("The key is invalid", "Der Schlüssel ist ungültig"),
+    ("The API can't be reached", "Die API ist nicht erreichbar"),
+    (
+        "The key was accepted, but the request failed",
+        "Der Schlüssel wurde akzeptiert, aber die Anfrage ist fehlgeschlagen",
+    ),
+    // Screen readers
+    ("Prompt", "Eingabe"),
+    ("Response", "Antwort"),
+    ("The response is complete", "Die Antwort ist vollständig"),
+    ("The request failed", "Die Anfrage ist fehlgeschlagen"),
+    // Tray
+    ("Show", "Anzeigen"),
+    ("Suspend the hotkey", "Tastenkürzel aussetzen"),
+    ("hotkey suspended", "Tastenkürzel ausgesetzt"),
+    ("Quit", "Beenden"),
+];
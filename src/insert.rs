@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use winapi::um::winuser::{
+    keybd_event, GetForegroundWindow, SetForegroundWindow, KEYEVENTF_KEYUP, VK_CONTROL,
+};
+
+/// Time for the target window to process the focus change before the paste is sent
+const FOCUS_DELAY: Duration = Duration::from_millis(100);
+
+/// The window that currently has the keyboard focus
+pub fn foreground_window() -> u64 {
+    unsafe { GetForegroundWindow() as u64 }
+}
+
+/// Focus the given window and paste the text into it. The text is left in the clipboard.
+pub fn paste_into(window: u64, text: &str) -> Result<()> {
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(text))
+        .context("Could not copy the response to the clipboard")?;
+
+    if window == 0 || unsafe { SetForegroundWindow(window as _) } == 0 {
+        anyhow::bail!("Could not focus the previous window, the response is in the clipboard");
+    }
+    std::thread::sleep(FOCUS_DELAY);
+
+    // Ctrl+V works in nearly every application, unlike typing the text key by key
+    let v = b'V';
+    unsafe {
+        keybd_event(VK_CONTROL as u8, 0, 0, 0);
+        keybd_event(v, 0, 0, 0);
+        keybd_event(v, 0, KEYEVENTF_KEYUP, 0);
+        keybd_event(VK_CONTROL as u8, 0, KEYEVENTF_KEYUP, 0);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,128 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use interprocess::local_socket::{LocalSocketListener, LocalSocketStream};
+use popup_gpt::chatgpt::ChatGPT;
+use serde::{Deserialize, Serialize};
+
+use crate::settings::Settings;
+
+/// The name of the local socket, a named pipe at `\\.\pipe\popup-gpt` on Windows
+pub const SOCKET_NAME: &str = "popup-gpt";
+
+/// A request to the daemon, sent as one line of JSON, e.g. `{"cmd":"ask","question":"Hi"}`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Request {
+    /// Answer a question in a separate conversation without showing the popup
+    Ask { question: String },
+    /// Show the popup as if the hotkey was pressed
+    Show,
+    /// Switch the active settings profile
+    SetProfile { name: String },
+}
+
+/// The reply to a request, sent as one line of JSON
+#[derive(Debug, Default, Serialize)]
+struct Response {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    answer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Serve the IPC API on a background thread. `show` is called to bring up the popup.
+pub fn serve(settings_path: PathBuf, show: impl Fn() + Send + 'static) -> Result<()> {
+    let listener = LocalSocketListener::bind(SOCKET_NAME)
+        .context("Could not start the IPC server, is another instance running?")?;
+
+    std::thread::spawn(move || {
+        for conn in listener.incoming() {
+            match conn {
+                Ok(conn) => {
+                    if let Err(e) = handle_connection(conn, &settings_path, &show) {
+                        tracing::warn!(error = %e, "IPC connection failed");
+                    }
+                }
+                Err(e) => tracing::warn!(error = %e, "could not accept an IPC connection"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Answer requests until the client closes the connection
+fn handle_connection(
+    conn: LocalSocketStream,
+    settings_path: &Path,
+    show: &impl Fn(),
+) -> Result<()> {
+    let mut reader = BufReader::new(conn);
+    let mut line = String::new();
+
+    while reader.read_line(&mut line)? > 0 {
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                tracing::info!(?request, "IPC request");
+                handle_request(request, settings_path, show)
+            }
+            Err(e) => Err(anyhow::anyhow!("Invalid request: {e}")),
+        };
+
+        let response = match response {
+            Ok(answer) => Response {
+                ok: true,
+                answer,
+                ..Default::default()
+            },
+            Err(e) => Response {
+                error: Some(format!("{e:#}")),
+                ..Default::default()
+            },
+        };
+
+        let conn = reader.get_mut();
+        serde_json::to_writer(&mut *conn, &response)?;
+        conn.write_all(b"\n")?;
+        conn.flush()?;
+        line.clear();
+    }
+
+    Ok(())
+}
+
+fn handle_request(
+    request: Request,
+    settings_path: &Path,
+    show: &impl Fn(),
+) -> Result<Option<String>> {
+    // The settings file is the shared state with the popup, which picks up changes to it
+    let mut settings = Settings::load(settings_path)?;
+
+    match request {
+        Request::Ask { question } => {
+            let mut chatgpt = ChatGPT::new(String::new());
+            settings.configure(&mut chatgpt, None)?;
+
+            let resp = chatgpt.ask(question)?;
+            Ok(resp.primary_response().map(str::to_string))
+        }
+        Request::Show => {
+            show();
+            Ok(None)
+        }
+        Request::SetProfile { name } => {
+            if !settings.profile_names().contains(&name) {
+                anyhow::bail!("There is no profile named {name:?}");
+            }
+            settings.active_profile = Some(name);
+            settings.save()?;
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use egui::{Color32, RichText, TextEdit};
+use popup_gpt::{
+    chatgpt::{ChatGPT, KeyCheck, Provider, OPENAI_BASE_URL},
+    model::DEFAULT_MODEL,
+};
+
+use crate::{i18n::tr, settings::Settings};
+
+/// What the user chose to do in the API key editor
+pub enum KeyEditorAction {
+    /// Send a test request with the client, the result is passed to [`KeyEditor::checked`]
+    Test(ChatGPT),
+    /// Store the key and the base URL in the active profile
+    Save {
+        token: String,
+        base_url: Option<String>,
+    },
+    Close,
+}
+
+/// The state of the test of the entered key
+enum Check {
+    Untested,
+    Running,
+    Done(KeyCheck),
+}
+
+/// An editor for the API key and base URL of the active profile, which can be tested before
+/// they are saved
+pub struct KeyEditor {
+    profile: String,
+    provider: Provider,
+    model: Option<String>,
+    token: String,
+    base_url: String,
+    /// The default profile takes its base URL from the environment, only other profiles can
+    /// change it
+    base_url_editable: bool,
+    check: Check,
+}
+
+impl KeyEditor {
+    pub fn new(settings: &Settings) -> Self {
+        let profile = settings.active_profile();
+        let base_url_editable = settings.profiles.iter().any(|own| own.name == profile.name);
+
+        Self {
+            // A token that can't be read is entered again
+            token: settings.token().unwrap_or_default(),
+            base_url: profile.base_url.unwrap_or_default(),
+            profile: profile.name,
+            provider: profile.provider,
+            model: profile.model,
+            base_url_editable,
+            check: Check::Untested,
+        }
+    }
+
+    /// The result of the test request of [`KeyEditorAction::Test`]
+    pub fn checked(&mut self, check: KeyCheck) {
+        if matches!(self.check, Check::Running) {
+            self.check = Check::Done(check);
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) -> Option<KeyEditorAction> {
+        let mut action = None;
+
+        ui.label(
+            RichText::new(tr("API key of the profile {name}").replace("{name}", &self.profile))
+                .color(Color32::GRAY),
+        );
+
+        let mut changed = false;
+        egui::Grid::new("api_key").num_columns(2).show(ui, |ui| {
+            ui.label(tr("API key"));
+            changed |= ui
+                .add(
+                    TextEdit::singleline(&mut self.token)
+                        .password(true)
+                        .desired_width(f32::INFINITY),
+                )
+                .changed();
+            ui.end_row();
+
+            ui.label(tr("Base URL"));
+            changed |= ui
+                .add_enabled(
+                    self.base_url_editable,
+                    TextEdit::singleline(&mut self.base_url)
+                        .hint_text(OPENAI_BASE_URL)
+                        .desired_width(f32::INFINITY),
+                )
+                .changed();
+            ui.end_row();
+        });
+        // The result belongs to the key that was tested
+        if changed {
+            self.check = Check::Untested;
+        }
+
+        ui.horizontal(|ui| {
+            let testing = matches!(self.check, Check::Running);
+            if ui
+                .add_enabled(!testing, egui::Button::new(tr("Test key")))
+                .on_hover_text(tr("Send a request for a single token with this key"))
+                .clicked()
+            {
+                self.check = Check::Running;
+                action = Some(KeyEditorAction::Test(self.client()));
+            }
+            if ui.button(tr("Save")).clicked() {
+                action = Some(KeyEditorAction::Save {
+                    token: self.token.trim().to_string(),
+                    base_url: Some(self.base_url.trim().to_string())
+                        .filter(|base_url| !base_url.is_empty()),
+                });
+            }
+            if ui.button(tr("Cancel")).clicked() {
+                action = Some(KeyEditorAction::Close);
+            }
+
+            match &self.check {
+                Check::Untested => (),
+                Check::Running => {
+                    ui.spinner();
+                }
+                Check::Done(KeyCheck::Valid) => {
+                    ui.colored_label(Color32::LIGHT_GREEN, format!("✔ {}", tr("The key works")));
+                }
+                Check::Done(KeyCheck::InvalidKey(reason)) => {
+                    ui.colored_label(
+                        Color32::from_rgb(230, 90, 90),
+                        format!("✖ {}", tr("The key is invalid")),
+                    )
+                    .on_hover_text(reason);
+                }
+                Check::Done(KeyCheck::Unreachable(reason)) => {
+                    ui.colored_label(
+                        Color32::from_rgb(230, 90, 90),
+                        format!("✖ {}", tr("The API can't be reached")),
+                    )
+                    .on_hover_text(reason);
+                }
+                Check::Done(KeyCheck::Failed(reason)) => {
+                    ui.colored_label(
+                        Color32::YELLOW,
+                        format!("⚠ {}", tr("The key was accepted, but the request failed")),
+                    )
+                    .on_hover_text(reason);
+                }
+            }
+        });
+
+        action
+    }
+
+    /// A client for the entered key and base URL with the model of the profile
+    fn client(&self) -> ChatGPT {
+        let base_url = self.base_url.trim();
+        let base_url = match base_url.is_empty() {
+            true => OPENAI_BASE_URL,
+            false => base_url,
+        };
+
+        let mut chatgpt = ChatGPT::new(self.token.trim().to_string());
+        chatgpt.set_provider(self.provider, base_url);
+        chatgpt.set_model(self.model.as_deref().unwrap_or(DEFAULT_MODEL));
+        chatgpt
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::BTreeMap;
+
+use egui::{Color32, Event, InputState, Key, RichText};
+use popup_gpt::hotkey::{self, Hotkey};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    hotkeys::DEFAULT_HOTKEY,
+    i18n::tr,
+    settings::{Settings, DEFAULT_OVERLAY_HOTKEY},
+};
+
+/// The shortcuts of the popup that can be bound to other keys in the `keymap` of the settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shortcut {
+    NewConversation,
+    NextProfile,
+    History,
+    Transcript,
+    AppendToNotes,
+    InsertResponse,
+}
+
+impl Shortcut {
+    pub const ALL: [Shortcut; 6] = [
+        Shortcut::NewConversation,
+        Shortcut::NextProfile,
+        Shortcut::History,
+        Shortcut::Transcript,
+        Shortcut::AppendToNotes,
+        Shortcut::InsertResponse,
+    ];
+
+    /// The keys if the keymap doesn't bind the shortcut
+    pub fn default_keys(self) -> &'static str {
+        match self {
+            Shortcut::NewConversation => "Ctrl+N",
+            Shortcut::NextProfile => "Ctrl+P",
+            Shortcut::History => "Ctrl+H",
+            Shortcut::Transcript => "Ctrl+T",
+            Shortcut::AppendToNotes => "Ctrl+Shift+N",
+            Shortcut::InsertResponse => "Ctrl+I",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Shortcut::NewConversation => "New conversation",
+            Shortcut::NextProfile => "Switch to the next profile",
+            Shortcut::History => "Open the history",
+            Shortcut::Transcript => "Show the transcript",
+            Shortcut::AppendToNotes => "Append the response to the notes",
+            Shortcut::InsertResponse => "Insert the response into the previous window",
+        }
+    }
+}
+
+/// The keys the popup uses that can't be bound to something else
+const FIXED_KEYS: &[(&str, &str)] = &[
+    ("Enter", "Send the prompt"),
+    ("Esc", "Stop the response or hide the popup"),
+    ("Ctrl+V", "Paste"),
+    ("Ctrl+0", "Reset the zoom"),
+    ("PageUp", "Scroll the transcript up"),
+    ("PageDown", "Scroll the transcript down"),
+    ("Ctrl+Up", "Select the previous message of the transcript"),
+    ("Ctrl+Down", "Select the next message of the transcript"),
+    ("Ctrl+C", "Copy the selected message of the transcript"),
+];
+
+/// The global hotkey of the quick actions
+const QUICK_ACTIONS_KEYS: &str = "Ctrl+Alt+A";
+
+fn default_keys(text: &str) -> Hotkey {
+    text.parse().expect("the default keys are valid")
+}
+
+/// The hotkey of a pressed key with the held modifiers. Windows reserves the Win key, so it is
+/// never part of it.
+fn hotkey_of(key: Key, modifiers: egui::Modifiers) -> Option<Hotkey> {
+    let name = match key {
+        Key::Minus => "-",
+        Key::PlusEquals => "=",
+        key => key.name(),
+    };
+    let mut hotkey: Hotkey = name.parse().ok()?;
+    hotkey.modifiers = hotkey::Modifiers {
+        ctrl: modifiers.ctrl,
+        alt: modifiers.alt,
+        shift: modifiers.shift,
+        win: false,
+    };
+    Some(hotkey)
+}
+
+/// Whether the keys of the hotkey were pressed in this frame, with exactly its modifiers
+pub fn pressed(inp: &InputState, hotkey: &Hotkey) -> bool {
+    inp.events.iter().any(|event| match event {
+        Event::Key {
+            key,
+            pressed: true,
+            modifiers,
+            ..
+        } => hotkey_of(*key, *modifiers).as_ref() == Some(hotkey),
+        _ => false,
+    })
+}
+
+/// What a row of the editor binds
+#[derive(Debug, Clone, PartialEq)]
+enum Binding {
+    /// The global hotkey that shows the popup
+    Popup,
+    /// The combination that switches the overlay
+    Overlay,
+    Shortcut(Shortcut),
+    /// Keys that are listed for the conflicts but can't be changed here
+    Fixed(String),
+}
+
+struct Row {
+    binding: Binding,
+    keys: Hotkey,
+}
+
+impl Row {
+    fn description(&self) -> String {
+        match &self.binding {
+            Binding::Popup => tr("Show the popup (global)").to_string(),
+            Binding::Overlay => tr("Switch the overlay").to_string(),
+            Binding::Shortcut(shortcut) => tr(shortcut.description()).to_string(),
+            Binding::Fixed(description) => description.clone(),
+        }
+    }
+}
+
+/// The bindings the user saved in the editor
+pub struct Keymap {
+    pub hotkey: Hotkey,
+    pub overlay_hotkey: Hotkey,
+    pub shortcuts: BTreeMap<Shortcut, Hotkey>,
+}
+
+/// What the user chose to do in the keymap editor
+pub enum KeymapAction {
+    Save(Keymap),
+    Close,
+}
+
+/// A list of the global hotkeys and the shortcuts of the popup. Clicking a binding captures the
+/// next key combination for it.
+pub struct KeymapEditor {
+    rows: Vec<Row>,
+    /// The row that waits for a key combination
+    capturing: Option<usize>,
+}
+
+impl KeymapEditor {
+    pub fn new(settings: &Settings) -> Self {
+        let parse = |text: &str| text.parse::<Hotkey>().ok();
+        let hotkey = settings.hotkey.as_deref().and_then(parse);
+        let overlay_hotkey = settings.overlay_hotkey().ok();
+
+        let mut rows = vec![
+            Row {
+                binding: Binding::Popup,
+                keys: hotkey.unwrap_or_else(|| default_keys(DEFAULT_HOTKEY)),
+            },
+            Row {
+                binding: Binding::Overlay,
+                keys: overlay_hotkey.unwrap_or_else(|| default_keys(DEFAULT_OVERLAY_HOTKEY)),
+            },
+        ];
+        rows.extend(Shortcut::ALL.into_iter().map(|shortcut| Row {
+            binding: Binding::Shortcut(shortcut),
+            keys: settings.shortcut(shortcut),
+        }));
+
+        let quick_actions = (QUICK_ACTIONS_KEYS, tr("Quick actions (global)").to_string());
+        let personas = settings.personas.iter().map(|persona| {
+            let description = tr("Persona {name} (global)").replace("{name}", &persona.name);
+            (format!("Ctrl+Alt+{}", persona.key), description)
+        });
+        let fixed = FIXED_KEYS
+            .iter()
+            .map(|(keys, description)| (keys.to_string(), tr(description).to_string()))
+            .chain([(quick_actions.0.to_string(), quick_actions.1)])
+            .chain(personas);
+        rows.extend(fixed.filter_map(|(keys, description)| {
+            Some(Row {
+                binding: Binding::Fixed(description),
+                keys: parse(&keys)?,
+            })
+        }));
+
+        Self {
+            rows,
+            capturing: None,
+        }
+    }
+
+    /// The rows whose keys are bound to another row as well
+    fn conflicts(&self) -> Vec<bool> {
+        self.rows
+            .iter()
+            .enumerate()
+            .map(|(i, row)| {
+                self.rows
+                    .iter()
+                    .enumerate()
+                    .any(|(j, other)| i != j && row.keys == other.keys)
+            })
+            .collect()
+    }
+
+    /// Take the next pressed key combination for the capturing row. Esc cancels the capture. The
+    /// key events are consumed, so the popup doesn't react to them.
+    fn capture(&mut self, ui: &mut egui::Ui) {
+        let Some(row) = self.capturing else {
+            return;
+        };
+
+        let captured = ui.input_mut(|inp| {
+            let captured = inp.events.iter().find_map(|event| match event {
+                Event::Key {
+                    key,
+                    pressed: true,
+                    modifiers,
+                    ..
+                } => Some((*key, *modifiers)),
+                _ => None,
+            });
+            inp.events
+                .retain(|event| !matches!(event, Event::Key { .. } | Event::Text(_)));
+            captured
+        });
+
+        match captured {
+            Some((Key::Escape, modifiers)) if modifiers.is_none() => self.capturing = None,
+            Some((key, modifiers)) => {
+                if let Some(keys) = hotkey_of(key, modifiers) {
+                    self.rows[row].keys = keys;
+                    self.capturing = None;
+                }
+            }
+            None => (),
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) -> Option<KeymapAction> {
+        self.capture(ui);
+
+        let mut action = None;
+        let conflicts = self.conflicts();
+        let has_conflicts = conflicts.iter().any(|conflict| *conflict);
+
+        ui.horizontal(|ui| {
+            if ui.button(tr("← Back")).clicked() {
+                action = Some(KeymapAction::Close);
+            }
+            let save = ui.add_enabled(!has_conflicts, egui::Button::new(tr("Save")));
+            if save.clicked() {
+                action = Some(KeymapAction::Save(self.keymap()));
+            }
+            if ui.button(tr("Reset to defaults")).clicked() {
+                self.reset();
+            }
+        });
+        ui.label(
+            RichText::new(tr(
+                "Click a binding and press the new key combination, Esc cancels",
+            ))
+            .color(Color32::GRAY),
+        );
+        if has_conflicts {
+            ui.colored_label(
+                Color32::from_rgb(230, 90, 90),
+                format!("⚠ {}", tr("Some keys are bound more than once")),
+            );
+        }
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("keymap").striped(true).show(ui, |ui| {
+                for (i, row) in self.rows.iter().enumerate() {
+                    let mut description = RichText::new(row.description());
+                    if matches!(row.binding, Binding::Fixed(_)) {
+                        description = description.color(Color32::GRAY);
+                    }
+                    ui.label(description);
+
+                    let mut keys = match self.capturing == Some(i) {
+                        true => RichText::new(tr("Press keys…")).italics(),
+                        false => RichText::new(row.keys.to_string()).monospace(),
+                    };
+                    if conflicts[i] {
+                        keys = keys.color(Color32::from_rgb(230, 90, 90));
+                    }
+                    match row.binding {
+                        Binding::Fixed(_) => {
+                            ui.label(keys);
+                        }
+                        _ => {
+                            if ui.button(keys).clicked() {
+                                self.capturing = Some(i);
+                            }
+                        }
+                    }
+                    ui.end_row();
+                }
+            });
+        });
+
+        action
+    }
+
+    /// Bind every row to its default keys again
+    fn reset(&mut self) {
+        for row in &mut self.rows {
+            row.keys = match row.binding {
+                Binding::Popup => default_keys(DEFAULT_HOTKEY),
+                Binding::Overlay => default_keys(DEFAULT_OVERLAY_HOTKEY),
+                Binding::Shortcut(shortcut) => default_keys(shortcut.default_keys()),
+                Binding::Fixed(_) => continue,
+            };
+        }
+        self.capturing = None;
+    }
+
+    fn keymap(&self) -> Keymap {
+        let keys = |binding: Binding| {
+            self.rows
+                .iter()
+                .find(|row| row.binding == binding)
+                .map(|row| row.keys)
+                .expect("the editor has a row for each binding")
+        };
+        Keymap {
+            hotkey: keys(Binding::Popup),
+            overlay_hotkey: keys(Binding::Overlay),
+            shortcuts: Shortcut::ALL
+                .into_iter()
+                .map(|shortcut| (shortcut, keys(Binding::Shortcut(shortcut))))
+                .collect(),
+        }
+    }
+}
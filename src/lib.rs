@@ -1,3 +1,26 @@
+pub mod agent;
+pub mod attachment;
 pub mod chatgpt;
-pub mod model;
+pub mod document;
+pub mod export;
+pub mod fetch;
+#[cfg(feature = "history")]
+pub mod history;
+pub mod hotkey;
+pub mod html;
+pub mod http;
+pub mod local_api;
+pub mod markdown;
+pub mod math;
 pub mod misc;
+pub mod model;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+pub mod postprocess;
+#[cfg(feature = "plugins")]
+pub mod script;
+pub mod search;
+pub mod snippet;
+pub mod template;
+pub mod translate;
+pub mod trim;
@@ -0,0 +1,313 @@
+use std::{
+    cell::RefCell,
+    io::{self, BufRead, Write},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{chatgpt::StreamSink, model::CompletionResponse};
+
+/// The port of the local HTTP API if the settings don't choose one
+pub const DEFAULT_PORT: u16 = 8787;
+
+/// The largest request body that is read, prompts are a lot smaller
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// A request to the local HTTP API
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApiRequest {
+    pub method: String,
+    pub path: String,
+    /// The token of the `Authorization: Bearer` header
+    pub token: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// What an authorized request asks for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    /// Answer the prompt in a conversation of its own, as server-sent events if `stream` is set
+    Ask { prompt: String, stream: bool },
+    /// Show the popup as if the hotkey was pressed
+    Show,
+    /// Hide the popup, it keeps the conversation
+    Hide,
+}
+
+/// The body of `POST /v1/ask`, e.g. `{"prompt": "Explain this code", "stream": true}`
+#[derive(Debug, Deserialize)]
+struct AskBody {
+    prompt: String,
+    #[serde(default)]
+    stream: bool,
+}
+
+/// A request that is answered with an error status and a JSON body like `{"error": "..."}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+/// Read the request line, the headers and the body of a request. Only bodies with a
+/// `Content-Length` are supported.
+pub fn read_request(reader: &mut impl BufRead) -> Result<ApiRequest> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        bail!("Invalid request line: {:?}", line.trim_end());
+    };
+    let mut request = ApiRequest {
+        method: method.to_string(),
+        path: path.to_string(),
+        ..Default::default()
+    };
+
+    let mut length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            bail!("The request ended in the headers");
+        }
+        let Some((name, value)) = line.trim_end().split_once(':') else {
+            break;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => length = value.parse().context("Invalid Content-Length")?,
+            "authorization" => {
+                request.token = value
+                    .strip_prefix("Bearer ")
+                    .map(|token| token.trim().to_string());
+            }
+            _ => (),
+        }
+    }
+
+    if length > MAX_BODY_BYTES {
+        bail!("The request body is larger than {MAX_BODY_BYTES} bytes");
+    }
+    request.body = vec![0; length];
+    reader.read_exact(&mut request.body)?;
+
+    Ok(request)
+}
+
+/// The route of a request with the token. Every route needs the token, so other programs can't
+/// use the API key.
+pub fn route(request: &ApiRequest, token: &str) -> Result<Route, ApiError> {
+    let authorized = request
+        .token
+        .as_deref()
+        .is_some_and(|sent| !token.is_empty() && same_token(sent, token));
+    if !authorized {
+        return Err(ApiError::new(401, "Missing or wrong token"));
+    }
+
+    let path = request.path.split('?').next().unwrap_or_default();
+    match (request.method.as_str(), path) {
+        ("POST", "/v1/ask") => {
+            let body: AskBody = serde_json::from_slice(&request.body)
+                .map_err(|e| ApiError::new(400, format!("Invalid body: {e}")))?;
+            Ok(Route::Ask {
+                prompt: body.prompt,
+                stream: body.stream,
+            })
+        }
+        ("POST", "/v1/show") => Ok(Route::Show),
+        ("POST", "/v1/hide") => Ok(Route::Hide),
+        (_, "/v1/ask" | "/v1/show" | "/v1/hide") => {
+            Err(ApiError::new(405, "Only POST is supported"))
+        }
+        _ => Err(ApiError::new(404, format!("There is no route {path}"))),
+    }
+}
+
+/// Compare the tokens in a time that doesn't depend on where they differ
+fn same_token(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        502 => "Bad Gateway",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Answer with a JSON body and close the connection
+pub fn write_json(writer: &mut impl Write, status: u16, body: &impl Serialize) -> io::Result<()> {
+    let body = serde_json::to_vec(body)?;
+    write!(
+        writer,
+        "HTTP/1.1 {status} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+        Connection: close\r\n\r\n",
+        reason(status),
+        body.len()
+    )?;
+    writer.write_all(&body)?;
+    writer.flush()
+}
+
+pub fn write_error(writer: &mut impl Write, error: &ApiError) -> io::Result<()> {
+    write_json(writer, error.status, &json!({ "error": error.message }))
+}
+
+/// Answer without a body, e.g. to show and hide the popup
+pub fn write_no_content(writer: &mut impl Write) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n"
+    )?;
+    writer.flush()
+}
+
+/// Sends a streamed response as server-sent events with the new text of each partial response,
+/// e.g. `data: {"delta":"Hel"}`. The events end with `data: [DONE]`, after an event with the error
+/// if the response failed.
+pub struct EventStream<W: Write> {
+    writer: RefCell<W>,
+}
+
+impl<W: Write> EventStream<W> {
+    /// Start the response, the events follow until the connection is closed
+    pub fn start(mut writer: W) -> io::Result<Self> {
+        write!(
+            writer,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+            Connection: close\r\n\r\n"
+        )?;
+        writer.flush()?;
+        Ok(Self {
+            writer: RefCell::new(writer),
+        })
+    }
+
+    fn event(&self, data: &str) {
+        let mut writer = self.writer.borrow_mut();
+        // The client may be gone, the response is still complete without it
+        let _ = write!(writer, "data: {data}\n\n").and_then(|_| writer.flush());
+    }
+
+    /// End the events, with the error if the response failed
+    pub fn finish(self, error: Option<&str>) -> W {
+        if let Some(error) = error {
+            self.event(&json!({ "error": error }).to_string());
+        }
+        self.event("[DONE]");
+        self.writer.into_inner()
+    }
+}
+
+impl<W: Write> StreamSink for EventStream<W> {
+    fn send_partial(&self, partial: CompletionResponse) {
+        let delta = partial
+            .choices
+            .first()
+            .and_then(|choice| choice.delta.as_ref())
+            .and_then(|delta| delta.content.as_deref())
+            .filter(|content| !content.is_empty());
+        if let Some(delta) = delta {
+            self.event(&json!({ "delta": delta }).to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, path: &str, token: Option<&str>, body: &str) -> ApiRequest {
+        ApiRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            token: token.map(str::to_string),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn parse_requests() {
+        let raw = "POST /v1/ask HTTP/1.1\r\nHost: localhost\r\nauthorization: Bearer s3cret\r\n\
+            Content-Length: 16\r\n\r\n{\"prompt\":\"Hi\"}\n";
+        let request = read_request(&mut raw.as_bytes()).unwrap();
+        assert_eq!(
+            request,
+            ApiRequest {
+                method: "POST".into(),
+                path: "/v1/ask".into(),
+                token: Some("s3cret".into()),
+                body: b"{\"prompt\":\"Hi\"}\n".to_vec(),
+            }
+        );
+
+        assert!(read_request(&mut "POST /v1/ask HTTP/1.1\r\nHost:".as_bytes()).is_err());
+        assert!(read_request(&mut "\r\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn routes_need_the_token() {
+        let ask = request("POST", "/v1/ask", Some("s3cret"), r#"{"prompt":"Hi"}"#);
+        assert_eq!(
+            route(&ask, "s3cret"),
+            Ok(Route::Ask {
+                prompt: "Hi".into(),
+                stream: false
+            })
+        );
+        assert_eq!(route(&ask, "other").unwrap_err().status, 401);
+        assert_eq!(route(&ask, "").unwrap_err().status, 401);
+
+        let status = |method, path, token, body| {
+            route(&request(method, path, token, body), "s3cret")
+                .err()
+                .map(|e| e.status)
+        };
+        assert_eq!(status("POST", "/v1/hide", Some("s3cret"), ""), None);
+        assert_eq!(status("POST", "/v1/show", None, ""), Some(401));
+        assert_eq!(status("GET", "/v1/show", Some("s3cret"), ""), Some(405));
+        assert_eq!(status("POST", "/v1/ask", Some("s3cret"), "{}"), Some(400));
+        assert_eq!(status("POST", "/v2/ask", Some("s3cret"), ""), Some(404));
+    }
+
+    #[test]
+    fn stream_events() {
+        let partial: CompletionResponse = serde_json::from_str(
+            r#"{"id":"1","object":"chat.completion.chunk","created":0,"model":"m",
+            "choices":[{"index":0,"delta":{"content":"Hel\"lo"}}]}"#,
+        )
+        .unwrap();
+
+        let events = EventStream::start(Vec::new()).unwrap();
+        events.send_partial(partial);
+        let written = String::from_utf8(events.finish(Some("Timeout"))).unwrap();
+
+        let body = written.split("\r\n\r\n").nth(1).unwrap();
+        assert_eq!(
+            body,
+            "data: {\"delta\":\"Hel\\\"lo\"}\n\ndata: {\"error\":\"Timeout\"}\n\ndata: [DONE]\n\n"
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    backtrace::Backtrace,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use tracing_appender::{non_blocking::WorkerGuard, rolling};
+use tracing_subscriber::EnvFilter;
+
+/// The log level if the settings don't specify one
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// How many daily log files are kept
+const MAX_LOG_FILES: usize = 7;
+
+/// Write the log to a daily rolling file in the given directory. `level` is a level like `debug`
+/// or a filter like `popup_gpt=trace,ureq=info`. The returned guard flushes the log when dropped
+/// and must be kept alive until the program exits.
+pub fn init(dir: &Path, level: &str) -> Result<WorkerGuard> {
+    let filter =
+        EnvFilter::try_new(level).with_context(|| format!("{level:?} is not a valid log level"))?;
+
+    let appender = rolling::Builder::new()
+        .rotation(rolling::Rotation::DAILY)
+        .filename_prefix("popup-gpt")
+        .filename_suffix("log")
+        .max_log_files(MAX_LOG_FILES)
+        .build(dir)
+        .with_context(|| format!("Could not create the log file in {}", dir.display()))?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .with_ansi(false)
+        .try_init()
+        .map_err(|e| anyhow::anyhow!(e))?;
+
+    Ok(guard)
+}
+
+/// Replace the default panic handler, whose output is lost without a console. The panic and a
+/// backtrace are written to a crash log in the log directory, and a message box pointing to it
+/// is shown before the process exits. With a console, the crash is printed to it instead.
+pub fn install_panic_hook(dir: PathBuf, console: bool) {
+    std::panic::set_hook(Box::new(move |info| {
+        let backtrace = Backtrace::force_capture();
+        tracing::error!(panic = %info, "crashed");
+
+        let report = format!(
+            "Popup-GPT {} crashed\n\n{info}\n\nBacktrace:\n{backtrace}",
+            env!("CARGO_PKG_VERSION")
+        );
+
+        if console {
+            eprintln!("{report}");
+            std::process::exit(101);
+        }
+
+        let path = dir.join(format!(
+            "crash-{}.log",
+            chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")
+        ));
+        let written = std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&path, &report));
+
+        let text = match written {
+            Ok(()) => format!("{info}\n\nThe details were saved to {}", path.display()),
+            Err(_) => report,
+        };
+        show_crash_message(&text);
+
+        std::process::exit(101);
+    }));
+}
+
+/// Show a native message box, since the egui window may be what crashed
+fn show_crash_message(text: &str) {
+    use winapi::um::winuser::{MessageBoxW, MB_ICONERROR, MB_OK};
+
+    let wide = |s: &str| s.encode_utf16().chain([0]).collect::<Vec<u16>>();
+    let text = wide(text);
+    let caption = wide("Popup-GPT crashed");
+
+    unsafe {
+        MessageBoxW(
+            std::ptr::null_mut(),
+            text.as_ptr(),
+            caption.as_ptr(),
+            MB_OK | MB_ICONERROR,
+        )
+    };
+}
@@ -2,103 +2,2983 @@
 // implemented
 #![windows_subsystem = "windows"]
 
+mod accessibility;
+mod audio;
+mod autostart;
+mod backdrop;
+mod cert_store;
+mod cli;
+mod dialogs;
+mod fade;
+mod fonts;
+mod history_browser;
+mod hotkeys;
+mod http_api;
+mod i18n;
+mod insert;
+mod ipc;
+mod key_editor;
+mod keymap;
+mod logging;
+mod notes;
+mod overlay;
+mod response_view;
+mod settings;
+mod stats;
+mod system_proxy;
+mod theme;
+mod transcript;
+mod tray;
+mod update;
+mod worker;
+
 use std::{
-    path::PathBuf,
+    collections::{hash_map::DefaultHasher, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+    io::Cursor,
     sync::{
+        atomic::{AtomicBool, Ordering},
         mpsc::{channel, Receiver, Sender},
-        Arc, RwLock,
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use accessibility::Announcer;
+use anyhow::Context;
+use clap::Parser;
+use cli::{Cli, Command};
+use eframe::{epaint::Shadow, NativeOptions};
+use egui::{
+    text::CCursor, text_edit::CCursorRange, Color32, ColorImage, Event, FontFamily, FontId, Frame,
+    Key, Margin, Pos2, Rgba, RichText, ScrollArea, Sense, Separator, Stroke, TextEdit,
+    TextureHandle, TextureOptions, Vec2,
+};
+use history_browser::{BrowserAction, HistoryBrowser};
+use hotkeys::{HotkeyAction, Hotkeys};
+use i18n::{tr, Language};
+use image::ImageOutputFormat;
+use key_editor::{KeyEditor, KeyEditorAction};
+use keymap::{KeymapAction, KeymapEditor, Shortcut};
+use notify::RecommendedWatcher;
+use windows_hotkeys::HotkeyManager;
+
+use popup_gpt::{
+    agent,
+    attachment::{with_attachments, Attachment, ImageAttachment, CHUNK_TOKENS},
+    chatgpt::{ChatGPT, KeyCheck, DEFAULT_SYSTEM_MESSAGE},
+    export::import,
+    fetch::{self, Page},
+    history::{History, SavedConversation},
+    html, http,
+    markdown::{code_blocks, split_thinking},
+    model::{
+        context_window, CompletionResponse, FinishReason, Message, Role, TokenLogprob, ToolCall,
     },
+    plugin::Plugins,
+    postprocess::{self, Rule},
+    script::{self, Output, ScriptActions},
+    search::SearchEngine,
+    snippet::{self, snippet_query},
+    template::{parse_command, Pipeline, PromptTemplate},
+    translate,
+    trim::{trim_to_tokens, TextSize, TrimMode},
+};
+use response_view::ResponseView;
+use settings::{
+    Backdrop, CopyResponse, EscapeAction, Persona, Settings, Theme, MAX_ZOOM, MIN_ZOOM,
+};
+use stats::ExchangeStats;
+use transcript::{Transcript, TranscriptAction};
+use tray::Tray;
+use update::Release;
+use worker::{ui_channel, StreamForwarder, UiSender, Worker};
+
+const IN_FONT: FontId = FontId {
+    size: 16.0,
+    family: FontFamily::Monospace,
+};
+
+const OUT_FONT: FontId = FontId {
+    size: 16.0,
+    family: FontFamily::Monospace,
 };
 
-use eframe::{epaint::Shadow, NativeOptions};
-use egui::{
-    text::CCursor, text_edit::CCursorRange, Color32, FontFamily, FontId, Frame, Key, Margin, Pos2,
-    Rgba, ScrollArea, Separator, TextEdit, Vec2,
-};
-use serde::{Deserialize, Serialize};
-use windows_hotkeys::{
-    keys::{ModKey, VKey},
-    HotkeyManager,
-};
+/// The maximum width and height of the image thumbnails above the prompt
+const THUMBNAIL_SIZE: u32 = 64;
+
+/// Attachments above this estimated size get a warning, unless a context limit is configured
+const ATTACHMENT_WARNING_TOKENS: usize = 8_000;
+
+/// How often the overlay hotkey is checked while the popup is an overlay
+const OVERLAY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the typewriter reveals more of the response while it is behind the stream
+const TYPEWRITER_TICK: Duration = Duration::from_millis(20);
+
+/// The characters per second the typewriter reveals, more if the stream is far ahead
+const TYPEWRITER_SPEED: f32 = 120.0;
+
+/// The number of characters of the system prompt shown in the header
+const SYSTEM_PROMPT_HEADER_LEN: usize = 80;
+
+/// The page of the URL at the start of the prompt, which is attached as context
+enum PageFetch {
+    Fetching(String),
+    /// The note about the fetched page, shown until the prompt is sent
+    Fetched(String),
+}
+
+/// How long an opened connection is expected to stay open, it is not opened again before
+const WARM_UP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How often the API is checked while it can't be reached
+const OFFLINE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The pastes with more estimated tokens get a warning if the settings don't choose a limit
+const DEFAULT_PASTE_LIMIT: usize = 4_000;
+
+/// The most messages of a saved conversation kept in memory if the settings don't choose a limit
+const DEFAULT_MAX_MESSAGES: usize = 200;
+
+/// A paste into the prompt that is larger than the paste limit
+struct LargePaste {
+    text: String,
+    size: TextSize,
+}
+
+/// A tool call of the agent as shown above the response
+struct ToolCallView {
+    call: ToolCall,
+    /// Waits for the user to allow or deny the call
+    pending: Option<Sender<bool>>,
+    allowed: bool,
+}
+
+/// What of the popup is kept while it is hidden until the next hotkey
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Keep {
+    Nothing,
+    /// The conversation, but not the prompt and its attachments
+    Conversation,
+    /// Everything, the popup shows up the way it was hidden
+    Everything,
+}
+
+// Todo: Either remove the dead code or actually use the full response mode
+#[allow(dead_code)]
+enum GUIMsg {
+    CompletionResponse(Box<CompletionResponse>),
+    PartialCompletionResponse(Box<CompletionResponse>),
+    Error(String),
+    /// The request failed on the way to the API, e.g. without network. Unless the agent sent it,
+    /// it can be sent again.
+    NetworkError {
+        error: String,
+        can_retry: bool,
+    },
+    /// The API can be reached again after a request failed without network
+    Online,
+    SettingsChanged(Box<Settings>),
+    SettingsError(String),
+    /// The plugins processed the complete response, which replaces the streamed one
+    Processed(String),
+    /// A pipeline step with the given name is done and the next one starts
+    PipelineStep(String),
+    /// The last exchange was saved in the history conversation with the given ID
+    HistorySaved(Result<i64, String>),
+    /// A newer release than the running version exists
+    UpdateAvailable(Release),
+    /// The timings and token counts of the exchange that just completed
+    Stats(ExchangeStats),
+    /// Windows switched between dark and light mode, true for dark
+    SystemTheme(bool),
+    /// The update to the version was downloaded and installed
+    UpdateInstalled(Result<String, String>),
+    /// The page at the start of the prompt was fetched
+    PageFetched(Result<Page, String>),
+    /// The agent calls a tool. With a sender, it waits until the call is allowed or denied.
+    ToolCall(ToolCall, Option<Sender<bool>>),
+    /// The test request of the API key editor is done
+    KeyChecked(KeyCheck),
+    /// The HTTP API asked to hide the popup
+    Hide,
+    /// A script action is done, with what it wants done with its results
+    ScriptDone(Result<Vec<Output>, String>),
+    Flush,
+}
+unsafe impl Send for GUIMsg {}
+
+struct App {
+    settings: Settings,
+
+    // UI State
+    prompt: String,
+    response: String,
+    response_render_len: usize,
+    /// When the typewriter last revealed text, none once it caught up with the response
+    typewriter_tick: Option<Instant>,
+    /// The reasoning the API streamed separately from the response
+    reasoning: String,
+    loading: bool,
+    focus_input: bool,
+    cursor_to_end: bool,
+    truncated: bool,
+    /// The content filter categories if the content filter of the API stopped the response
+    filtered: Option<Vec<&'static str>>,
+    error: Option<String>,
+    /// The last request failed on the way to the API and can be sent again
+    can_retry: bool,
+    /// A request failed without network, prompts are queued until the API can be reached again
+    offline: bool,
+    logprobs: Vec<TokenLogprob>,
+    /// The names and responses of the finished steps of the running or last pipeline
+    pipeline_steps: Vec<(String, String)>,
+    /// The clipboard text the quick action palette operates on, if it is open
+    quick_actions: Option<String>,
+    quick_action_selected: usize,
+    /// The persona of the current conversation, if it was started with a persona hotkey
+    persona: Option<Persona>,
+    /// Files dropped on the window, sent with the next prompt
+    attachments: Vec<Attachment>,
+    /// Prompts submitted while a response was running with their attachments, sent in order once
+    /// it is complete
+    queue: VecDeque<(String, Vec<Attachment>)>,
+    /// Images dropped on the window or pasted, sent with the next prompt, with their thumbnails
+    images: Vec<(ImageAttachment, TextureHandle)>,
+    /// The system prompt being edited, if the editor is open
+    system_prompt_editor: Option<String>,
+    /// The system prompt of the current conversation while it is edited in the header
+    conversation_prompt_editor: Option<String>,
+    /// When the fade in started and where the window ends up if it slides in
+    fade_in: Option<(Instant, Option<Pos2>)>,
+    /// Whether the system blurs the background, the frame then covers the whole window
+    backdrop_active: bool,
+    backdrop_applied: bool,
+    /// The stats of the last exchange, shown under the response
+    stats: Option<ExchangeStats>,
+    response_view: ResponseView,
+    /// Tells screen readers when a response is complete or failed
+    announcer: Announcer,
+    /// The estimated tokens of the next request by a hash of the prompt, the number of
+    /// attachments, the conversation length and the model they were estimated for
+    token_estimate: Option<(u64, usize)>,
+    /// The view of all messages of the conversation, if it is open
+    transcript: Option<Transcript>,
+    /// The list of saved conversations, if it is open
+    history_browser: Option<HistoryBrowser>,
+    keymap_editor: Option<KeymapEditor>,
+    /// The editor of the API key of the active profile, if it is open
+    key_editor: Option<KeyEditor>,
+    /// Text to paste into the previous window at the end of the frame
+    pending_insert: Option<String>,
+
+    ui_sender: UiSender,
+    messages: Receiver<GUIMsg>,
+    worker: Worker,
+    /// Changed settings could not be applied yet, because a request is holding the client
+    settings_dirty: bool,
+    _settings_watcher: Option<RecommendedWatcher>,
+    hotkey_mgr: HotkeyManager<HotkeyAction>,
+    /// Set while the UI thread is blocked waiting for a hotkey
+    waiting_for_hotkey: Arc<AtomicBool>,
+    /// Windows is set to dark mode, used if the theme follows the system
+    system_dark: bool,
+    page_fetch: Option<PageFetch>,
+    /// When the connection to the API was last opened in advance
+    last_warm_up: Option<Instant>,
+    /// The last paste if it is too large, until it is trimmed or sent
+    large_paste: Option<LargePaste>,
+    /// An earlier response kept visible above the current one, until it is unpinned
+    pinned: Option<String>,
+    /// Snippets collected from responses, kept across conversations
+    scratchpad: String,
+    show_scratchpad: bool,
+    /// Prompts are answered by the agent, which may call tools
+    agent: bool,
+    /// The tool calls the agent made for the current response
+    tool_calls: Vec<ToolCallView>,
+    /// Prompts are translated instead of answered
+    translating: bool,
+    /// The language to translate from, detected by the model if not set
+    translate_source: Option<String>,
+    translate_target: String,
+    /// The popup is faded and clicks pass through it
+    overlay: bool,
+    /// The overlay hotkey was held down in the last frame
+    overlay_hotkey_down: bool,
+    /// The tray menu asks to suspend the hotkeys, applied while waiting for them
+    suspend_hotkeys: Arc<AtomicBool>,
+    /// The hotkeys are currently unregistered
+    hotkeys_suspended: bool,
+    tray: Option<Tray>,
+    /// Hide the window on the first frame, when started as a daemon
+    start_hidden: bool,
+    /// Wait for the hotkey after the hidden first frame was painted
+    wait_after_frame: bool,
+    chatgpt: Arc<RwLock<ChatGPT>>,
+    cancel: Arc<AtomicBool>,
+    /// The conversation history, `None` if it could not be opened
+    history: Option<Arc<Mutex<History>>>,
+    /// The history entry of the current conversation, once its first exchange is saved
+    history_conversation: Option<i64>,
+    plugins: Arc<Plugins>,
+    /// The scripts that are quick actions
+    scripts: Arc<ScriptActions>,
+    /// The description of the script action that is running
+    script_running: Option<String>,
+    /// The plugins that are turned off for the current conversation
+    disabled_plugins: HashSet<String>,
+    /// A newer release, if the update check found one
+    update: Option<Release>,
+    /// The update is being downloaded
+    updating: bool,
+
+    window_handle: u64,
+    /// The window that had the focus before the popup was summoned
+    previous_window: u64,
+
+    // Window moving / scaling helpers
+    window_scale_direction: Vec2,
+    window_pointer_offset: Vec2,
+}
+
+impl App {
+    fn new(
+        settings: Settings,
+        hotkeys: Hotkeys,
+        daemon: bool,
+        hidden: bool,
+        ctx: &egui::Context,
+    ) -> Self {
+        let Hotkeys {
+            manager: hkm,
+            errors: hotkey_errors,
+        } = hotkeys;
+
+        let chatgpt = ChatGPT::new(String::new());
+        let cancel = chatgpt.cancel_handle();
+        let chatgpt = Arc::new(RwLock::new(chatgpt));
+
+        let (ui_sender, messages) = ui_channel(ctx);
+
+        let sender = ui_sender.clone();
+        let settings_watcher = Settings::watch(&settings.file_location, move |settings| {
+            let msg = match settings {
+                Ok(settings) => GUIMsg::SettingsChanged(Box::new(settings)),
+                Err(e) => GUIMsg::SettingsError(format!("{e:#}")),
+            };
+            let _ = sender.send(msg);
+        });
+
+        let sender = ui_sender.clone();
+        theme::watch_system(move |dark| {
+            let _ = sender.send(GUIMsg::SystemTheme(dark));
+        });
+
+        let waiting_for_hotkey = Arc::new(AtomicBool::new(false));
+        let wake = || {
+            let interrupt = hkm.interrupt_handle();
+            let waiting = waiting_for_hotkey.clone();
+            // Interrupting while the popup is shown would leave the interrupt queued and show
+            // the popup again right after it is hidden
+            move || {
+                if waiting.load(Ordering::SeqCst) {
+                    interrupt.interrupt();
+                }
+            }
+        };
+        let ipc_server = if daemon {
+            Some(ipc::serve(settings.file_location.clone(), wake()))
+        } else {
+            None
+        };
+
+        let show_for_api = wake();
+
+        let suspend_hotkeys = Arc::new(AtomicBool::new(false));
+        let tray = Tray::spawn(suspend_hotkeys.clone(), wake());
+
+        let mut app = Self {
+            settings,
+            chatgpt,
+            cancel,
+            history: None,
+            history_conversation: None,
+            plugins: Arc::default(),
+            scripts: Arc::default(),
+            script_running: None,
+            disabled_plugins: HashSet::new(),
+            update: None,
+            updating: false,
+            hotkey_mgr: hkm,
+            waiting_for_hotkey,
+            suspend_hotkeys,
+            hotkeys_suspended: false,
+            system_dark: theme::system_is_dark(),
+            overlay: false,
+            overlay_hotkey_down: false,
+            page_fetch: None,
+            last_warm_up: None,
+            large_paste: None,
+            pinned: None,
+            scratchpad: String::new(),
+            show_scratchpad: false,
+            agent: false,
+            tool_calls: Vec::new(),
+            translating: false,
+            translate_source: None,
+            translate_target: String::new(),
+            tray: None,
+            start_hidden: daemon || hidden,
+            wait_after_frame: false,
+            ui_sender,
+            messages,
+            worker: Worker::spawn(),
+            settings_dirty: true,
+            _settings_watcher: None,
+            focus_input: true,
+            cursor_to_end: false,
+            loading: false,
+            truncated: false,
+            filtered: None,
+            error: None,
+            can_retry: false,
+            offline: false,
+            logprobs: Vec::new(),
+            pipeline_steps: Vec::new(),
+            quick_actions: None,
+            quick_action_selected: 0,
+            persona: None,
+            attachments: Vec::new(),
+            queue: VecDeque::new(),
+            images: Vec::new(),
+            system_prompt_editor: None,
+            conversation_prompt_editor: None,
+            fade_in: None,
+            backdrop_active: false,
+            backdrop_applied: false,
+            stats: None,
+            response_view: ResponseView::default(),
+            announcer: Announcer::default(),
+            token_estimate: None,
+            transcript: None,
+            history_browser: None,
+            keymap_editor: None,
+            key_editor: None,
+            pending_insert: None,
+            prompt: String::new(),
+            response: String::new(),
+            response_render_len: 0,
+            typewriter_tick: None,
+            reasoning: String::new(),
+            window_handle: 0,
+            previous_window: 0,
+            window_scale_direction: Vec2::ZERO,
+            window_pointer_offset: Vec2::ZERO,
+        };
+
+        if !hotkey_errors.is_empty() {
+            app.error = Some(hotkey_errors.join("\n"));
+        }
+
+        app.translate_target = app
+            .settings
+            .translate_target
+            .clone()
+            .unwrap_or_else(|| translate::DEFAULT_TARGET.to_string());
+
+        match tray {
+            Ok(tray) => app.tray = Some(tray),
+            Err(e) => tracing::warn!(error = %e, "no tray icon"),
+        }
+
+        if let Some(Err(e)) = ipc_server {
+            tracing::error!(error = %e, "IPC server failed");
+            app.error = Some(format!("{e:#}"));
+        }
+
+        match History::open(&app.settings.history_path()) {
+            Ok(history) => app.history = Some(Arc::new(Mutex::new(history))),
+            Err(e) => app.error = Some(format!("{}: {e:#}", tr("Conversations are not saved"))),
+        }
+
+        if let Some(config) = app.settings.http_api.clone() {
+            let sender = app.ui_sender.clone();
+            let hidden = Arc::clone(&app.waiting_for_hotkey);
+            // A hidden popup handles the message only once it is shown, and would hide right away
+            let hide = move || {
+                if !hidden.load(Ordering::SeqCst) {
+                    let _ = sender.send(GUIMsg::Hide);
+                }
+            };
+            let served = http_api::serve(
+                &config,
+                app.settings.file_location.clone(),
+                app.history.clone(),
+                show_for_api,
+                hide,
+            );
+            if let Err(e) = served {
+                tracing::error!(error = %e, "HTTP API failed");
+                app.error = Some(format!("{e:#}"));
+            }
+        }
+
+        match settings_watcher {
+            Ok(watcher) => app._settings_watcher = Some(watcher),
+            Err(e) => {
+                app.error = Some(format!(
+                    "{}: {e:#}",
+                    tr("Settings are not reloaded automatically")
+                ))
+            }
+        }
+
+        app.apply_settings();
+        app.load_plugins();
+
+        if app.settings.check_for_updates != Some(false) {
+            app.check_for_updates();
+        }
+
+        app
+    }
+
+    /// Look for a new release in the background
+    fn check_for_updates(&self) {
+        let sender = self.ui_sender.clone();
+
+        std::thread::spawn(move || match update::check() {
+            Ok(Some(release)) => {
+                tracing::info!(version = release.version(), "update available");
+                let _ = sender.send(GUIMsg::UpdateAvailable(release));
+            }
+            Ok(None) => (),
+            // Being offline is no reason to bother the user
+            Err(e) => tracing::warn!(error = %e, "update check failed"),
+        });
+    }
+
+    /// Download and install the available update in the background
+    fn install_update(&mut self) {
+        let Some(release) = self.update.clone() else {
+            return;
+        };
+        self.updating = true;
+
+        let sender = self.ui_sender.clone();
+
+        std::thread::spawn(move || {
+            let installed = update::install(&release)
+                .map(|_| release.version().to_string())
+                .map_err(|e| format!("{e:#}"));
+            let _ = sender.send(GUIMsg::UpdateInstalled(installed));
+        });
+    }
+
+    /// Load the plugins and the script actions from their directories, which picks up changes to
+    /// the scripts
+    fn load_plugins(&mut self) {
+        match Plugins::load_dir(&self.settings.plugin_dir()) {
+            Ok(plugins) => self.plugins = Arc::new(plugins),
+            Err(e) => {
+                tracing::warn!(error = %e, "could not load the plugins");
+                self.error = Some(format!("{e:#}"));
+            }
+        }
+        match ScriptActions::load_dir(&self.settings.action_dir()) {
+            Ok(scripts) => self.scripts = Arc::new(scripts),
+            Err(e) => {
+                tracing::warn!(error = %e, "could not load the script actions");
+                self.error = Some(format!("{e:#}"));
+            }
+        }
+    }
+
+    /// Configure the client according to the current settings. If the client is busy with a
+    /// request, the settings are applied once it is done.
+    fn apply_settings(&mut self) {
+        let Ok(mut chatgpt) = self.chatgpt.try_write() else {
+            self.settings_dirty = true;
+            return;
+        };
+        self.settings_dirty = false;
+
+        if let Err(e) = self.settings.configure(&mut chatgpt, self.persona.as_ref()) {
+            self.error = Some(format!("{e:#}"));
+        }
+        if let Err(e) = self.settings.overlay_hotkey() {
+            self.error = Some(format!("{e:#}"));
+        }
+        for shortcut in Shortcut::ALL {
+            if let Err(e) = self.settings.shortcut_keys(shortcut) {
+                self.error = Some(format!("{e:#}"));
+            }
+        }
+    }
+
+    /// Make the profile with the given name the active one and persist the choice
+    fn switch_profile(&mut self, name: String) {
+        self.settings.active_profile = Some(name);
+        self.apply_settings();
+
+        if let Err(e) = self.settings.save() {
+            self.error = Some(format!("{e:#}"));
+        }
+    }
+
+    /// Switch to the profile after the active one, wrapping around at the end
+    fn next_profile(&mut self) {
+        let names = self.settings.profile_names();
+        let active = self.settings.active_profile().name;
+        let pos = names.iter().position(|name| *name == active).unwrap_or(0);
+
+        self.switch_profile(names[(pos + 1) % names.len()].clone());
+    }
+
+    fn show_status_bar(&mut self, ui: &mut egui::Ui) {
+        let active = self.settings.active_profile().name;
+        let mut selected = active.clone();
+
+        ui.horizontal(|ui| {
+            if let Some(persona) = &self.persona {
+                ui.label(RichText::new(&persona.name).color(Color32::LIGHT_BLUE));
+            }
+
+            ui.label(RichText::new(tr("Profile")).color(Color32::GRAY));
+            egui::ComboBox::from_id_source("profile")
+                .selected_text(&selected)
+                .show_ui(ui, |ui| {
+                    for name in self.settings.profile_names() {
+                        ui.selectable_value(&mut selected, name.clone(), name);
+                    }
+                });
+
+            if !self.loading && !self.response.is_empty() {
+                if ui
+                    .small_button(tr("Insert"))
+                    .on_hover_text(tr("Paste the response into the previous window (Ctrl+I)"))
+                    .clicked()
+                {
+                    self.pending_insert = Some(self.answer().to_string());
+                }
+
+                if ui
+                    .small_button(tr("Pin"))
+                    .on_hover_text(tr(
+                        "Keep the response visible above the answers to the next questions",
+                    ))
+                    .clicked()
+                {
+                    self.pinned = Some(self.answer().to_string());
+                }
+
+                if let Some(block) = code_blocks(self.answer()).last() {
+                    if ui
+                        .small_button(tr("Insert code"))
+                        .on_hover_text(tr("Paste the last code block into the previous window"))
+                        .clicked()
+                    {
+                        self.pending_insert = Some(block.code.to_string());
+                    }
+                }
+            }
+
+            if self.settings.notes_file.is_some()
+                && !self.loading
+                && !self.response.is_empty()
+                && ui
+                    .small_button(tr("Notes"))
+                    .on_hover_text(tr(
+                        "Append the question and response to the notes file (Ctrl+Shift+N)",
+                    ))
+                    .clicked()
+            {
+                self.append_to_notes();
+            }
+
+            if ui.small_button(tr("Export")).clicked() {
+                self.export_conversation();
+            }
+            if ui.small_button(tr("Import")).clicked() {
+                self.import_conversation();
+            }
+
+            if ui
+                .selectable_label(self.agent, tr("Agent"))
+                .on_hover_text(tr(
+                    "Let the model read the clipboard, fetch web pages, get the time and calculate \
+                    before it answers",
+                ))
+                .clicked()
+            {
+                self.agent = !self.agent;
+            }
+
+            if ui
+                .selectable_label(self.translating, tr("Translate"))
+                .on_hover_text(tr("Translate the prompts instead of answering them"))
+                .clicked()
+            {
+                self.translating = !self.translating;
+            }
+
+            if ui
+                .selectable_label(self.show_scratchpad, tr("Scratchpad"))
+                .on_hover_text(tr(
+                    "Collect snippets of responses, right-click selected text to add it",
+                ))
+                .clicked()
+            {
+                self.show_scratchpad = !self.show_scratchpad;
+            }
+
+            if ui.small_button(tr("Transcript")).clicked() {
+                self.toggle_transcript();
+            }
+
+            if self.history.is_some() && ui.small_button(tr("History")).clicked() {
+                self.toggle_history_browser();
+            }
+
+            if !self.plugins.is_empty() {
+                ui.menu_button(tr("Plugins"), |ui| {
+                    for name in self.plugins.names() {
+                        let mut enabled = !self.disabled_plugins.contains(name);
+                        if ui.checkbox(&mut enabled, name).changed() {
+                            match enabled {
+                                true => self.disabled_plugins.remove(name),
+                                false => self.disabled_plugins.insert(name.to_string()),
+                            };
+                        }
+                    }
+                });
+            }
+
+            if let Some(release) = &self.update {
+                let version = release.version().to_string();
+                ui.hyperlink_to(tr("What's new"), &release.html_url);
+                if self.updating {
+                    ui.spinner();
+                } else if ui
+                    .small_button(tr("Update to {version}").replace("{version}", &version))
+                    .on_hover_text(tr(
+                        "Download the new version, which is used from the next start",
+                    ))
+                    .clicked()
+                {
+                    self.install_update();
+                }
+            }
+
+            ui.menu_button(tr("Settings"), |ui| {
+                if ui.button(tr("Export settings")).clicked() {
+                    self.export_settings(false);
+                    ui.close_menu();
+                }
+                if ui
+                    .button(tr("Export settings with API keys"))
+                    .on_hover_text(tr("Anyone with the file can use your API keys"))
+                    .clicked()
+                {
+                    self.export_settings(true);
+                    ui.close_menu();
+                }
+                if ui.button(tr("Import settings")).clicked() {
+                    self.import_settings();
+                    ui.close_menu();
+                }
+                if ui.button(tr("API key")).clicked() {
+                    self.key_editor = Some(KeyEditor::new(&self.settings));
+                    ui.close_menu();
+                }
+            });
+
+            if ui.small_button(tr("Shortcuts")).clicked() {
+                self.keymap_editor = match self.keymap_editor {
+                    Some(_) => None,
+                    None => Some(KeymapEditor::new(&self.settings)),
+                };
+            }
+
+            if ui.small_button(tr("System prompt")).clicked() {
+                self.system_prompt_editor = match self.system_prompt_editor {
+                    Some(_) => None,
+                    None => Some(
+                        self.settings
+                            .active_profile()
+                            .system_prompt
+                            .unwrap_or_else(|| DEFAULT_SYSTEM_MESSAGE.to_string()),
+                    ),
+                };
+            }
+        });
+
+        if selected != active {
+            self.switch_profile(selected);
+        }
+    }
+
+    fn toggle_transcript(&mut self) {
+        self.transcript = match self.transcript {
+            Some(_) => None,
+            None => Some(Transcript::default()),
+        };
+    }
+
+    fn show_transcript(&mut self, ui: &mut egui::Ui) {
+        let Some(transcript) = &mut self.transcript else {
+            return;
+        };
+        // The conversation is locked while a response is generated
+        let Ok(chatgpt) = self.chatgpt.try_read() else {
+            ui.spinner();
+            return;
+        };
+
+        if chatgpt.spilled() > 0 {
+            ui.label(
+                RichText::new(
+                    tr("{count} older messages are only kept in the history")
+                        .replace("{count}", &chatgpt.spilled().to_string()),
+                )
+                .small()
+                .color(Color32::GRAY),
+            );
+        }
+        let action = transcript.show(ui, chatgpt.conversation());
+        drop(chatgpt);
+
+        match action {
+            Some(TranscriptAction::Branch(len)) => self.branch_conversation(len),
+            Some(TranscriptAction::Mute(i, muted)) => {
+                if let Ok(mut chatgpt) = self.chatgpt.try_write() {
+                    chatgpt.set_message_muted(i, muted);
+                }
+            }
+            Some(TranscriptAction::Delete(i)) => self.delete_message(i),
+            Some(TranscriptAction::Pin(i, pinned)) => self.pin_message(i, pinned),
+            Some(TranscriptAction::Quote(text)) => {
+                self.transcript = None;
+                self.prompt.push_str(&text);
+                self.focus_input = true;
+                self.cursor_to_end = true;
+            }
+            Some(TranscriptAction::Edit(i, text)) => {
+                // Like a branch that ends before the edited message, which is then sent again
+                self.branch_conversation(i);
+                self.transcript = None;
+                self.send_prompt(text, None);
+            }
+            None => (),
+        }
+    }
+
+    fn max_messages(&self) -> usize {
+        self.settings.max_messages.unwrap_or(DEFAULT_MAX_MESSAGES)
+    }
+
+    fn pin_message(&mut self, index: usize, pinned: bool) {
+        let Ok(mut chatgpt) = self.chatgpt.try_write() else {
+            return;
+        };
+        chatgpt.set_message_pinned(index, pinned);
+
+        let saved = chatgpt
+            .conversation()
+            .get(index)
+            .is_some_and(|message| !message.is_tool_exchange());
+        if let (Some(history), Some(id), true) = (&self.history, self.history_conversation, saved) {
+            let index = chatgpt.saved_index(index);
+            if let Err(e) = history.lock().unwrap().set_pinned(id, index, pinned) {
+                self.error = Some(format!("{e:#}"));
+            }
+        }
+    }
+
+    /// Remove a message from the conversation and its saved copy
+    fn delete_message(&mut self, index: usize) {
+        let Ok(mut chatgpt) = self.chatgpt.try_write() else {
+            return;
+        };
+        let saved = chatgpt
+            .conversation()
+            .get(index)
+            .is_some_and(|message| !message.is_tool_exchange());
+        let saved_index = chatgpt.saved_index(index);
+        chatgpt.remove_message(index);
+
+        if let (Some(history), Some(id), true) = (&self.history, self.history_conversation, saved) {
+            if let Err(e) = history.lock().unwrap().delete_message(id, saved_index) {
+                self.error = Some(format!("{e:#}"));
+            }
+        }
+    }
+
+    /// Continue the conversation from an earlier message, keeping the first `len` messages. The
+    /// saved conversation stays as it is and the branch is saved as a new one.
+    fn branch_conversation(&mut self, len: usize) {
+        let Ok(mut chatgpt) = self.chatgpt.try_write() else {
+            return;
+        };
+        let saved_len = chatgpt.saved_index(len);
+        chatgpt.truncate_conversation(len);
+
+        if let (Some(history), Some(id)) = (&self.history, self.history_conversation) {
+            match history.lock().unwrap().branch_conversation(id, saved_len) {
+                Ok(branch) => self.history_conversation = Some(branch),
+                Err(e) => {
+                    self.error = Some(format!("{e:#}"));
+                    self.history_conversation = None;
+                }
+            }
+        }
+
+        self.response = chatgpt
+            .conversation()
+            .last()
+            .filter(|message| matches!(message.role, Role::Assistant))
+            .map(|message| message.content.clone())
+            .unwrap_or_default();
+        self.response_render_len = self.response.len();
+        self.reasoning.clear();
+        self.logprobs.clear();
+        self.pipeline_steps.clear();
+        self.truncated = false;
+        self.filtered = None;
+        self.prompt.clear();
+        self.focus_input = true;
+    }
+
+    fn toggle_history_browser(&mut self) {
+        if self.history_browser.take().is_some() {
+            return;
+        }
+        let Some(history) = &self.history else {
+            return;
+        };
+
+        match HistoryBrowser::new(&history.lock().unwrap()) {
+            Ok(browser) => self.history_browser = Some(browser),
+            Err(e) => self.error = Some(format!("{e:#}")),
+        }
+    }
+
+    fn show_history_browser(&mut self, ui: &mut egui::Ui) {
+        let (Some(browser), Some(history)) = (&mut self.history_browser, &self.history) else {
+            return;
+        };
+
+        let action = browser.show(ui, &history.lock().unwrap());
+        match action {
+            Ok(Some(BrowserAction::Continue(id, conversation))) => {
+                self.load_conversation(Some(id), conversation)
+            }
+            Ok(Some(BrowserAction::Close)) => self.history_browser = None,
+            Ok(None) => (),
+            Err(e) => self.error = Some(format!("{e:#}")),
+        }
+    }
+
+    fn show_keymap_editor(&mut self, ui: &mut egui::Ui) {
+        let Some(editor) = &mut self.keymap_editor else {
+            return;
+        };
+
+        match editor.show(ui) {
+            Some(KeymapAction::Save(keymap)) => {
+                self.keymap_editor = None;
+                self.save_keymap(keymap);
+            }
+            Some(KeymapAction::Close) => self.keymap_editor = None,
+            None => (),
+        }
+    }
+
+    fn show_key_editor(&mut self, ui: &mut egui::Ui) {
+        let Some(editor) = &mut self.key_editor else {
+            return;
+        };
+
+        match editor.show(ui) {
+            Some(KeyEditorAction::Test(chatgpt)) => {
+                let sender = self.ui_sender.clone();
+                std::thread::spawn(move || {
+                    let _ = sender.send(GUIMsg::KeyChecked(chatgpt.check_key()));
+                });
+            }
+            Some(KeyEditorAction::Save { token, base_url }) => {
+                self.key_editor = None;
+                self.settings.set_base_url(base_url);
+                let saved = self
+                    .settings
+                    .set_token(token)
+                    .and_then(|()| self.settings.save());
+                if let Err(e) = saved {
+                    self.error = Some(format!("{e:#}"));
+                }
+                self.apply_settings();
+            }
+            Some(KeyEditorAction::Close) => self.key_editor = None,
+            None => (),
+        }
+
+        ui.add(Separator::default());
+    }
+
+    /// Persist the bindings of the keymap editor and register the global hotkeys with the new
+    /// keys. The old popup hotkey is kept if the new one is taken.
+    fn save_keymap(&mut self, keymap: keymap::Keymap) {
+        let old_hotkey = self.settings.hotkey.clone();
+        self.settings.hotkey = Some(keymap.hotkey.to_string());
+        self.settings.overlay_hotkey = Some(keymap.overlay_hotkey.to_string());
+        self.settings.keymap = keymap
+            .shortcuts
+            .into_iter()
+            .filter(|(shortcut, keys)| keys.to_string() != shortcut.default_keys())
+            .map(|(shortcut, keys)| (shortcut, keys.to_string()))
+            .collect();
+
+        if let Err(e) = self.register_hotkeys_again() {
+            self.settings.hotkey = old_hotkey;
+            self.error = Some(e);
+            if let Err(e) = self.register_hotkeys_again() {
+                tracing::warn!(error = %e, "could not register the old hotkey again");
+            }
+        }
+
+        if let Err(e) = self.settings.save() {
+            self.error = Some(format!("{e:#}"));
+        }
+    }
+
+    /// Register the global hotkeys with the keys of the current settings, unless they are
+    /// suspended. Fails if the popup hotkey can't be registered.
+    fn register_hotkeys_again(&mut self) -> Result<(), String> {
+        if self.hotkeys_suspended {
+            return Ok(());
+        }
+
+        if let Err(e) = self.hotkey_mgr.unregister_all() {
+            tracing::warn!(error = %e, "could not unregister the hotkeys");
+        }
+        let errors = hotkeys::register_all(&mut self.hotkey_mgr, &self.settings)?;
+        if !errors.is_empty() {
+            self.error = Some(errors.join("\n"));
+        }
+        Ok(())
+    }
+
+    /// Ask for a file and export the settings to it, with or without the API tokens
+    fn export_settings(&mut self, include_tokens: bool) {
+        let Some(path) = rfd::FileDialog::new()
+            .set_file_name("popup-gpt-settings.json")
+            .add_filter(tr("Settings"), &["json"])
+            .save_file()
+        else {
+            return;
+        };
+
+        let exported = self.settings.export(include_tokens).and_then(|bundle| {
+            std::fs::write(&path, bundle)
+                .with_context(|| format!("Could not write {}", path.display()))
+        });
+        if let Err(e) = exported {
+            self.error = Some(format!("{e:#}"));
+        }
+    }
+
+    /// Ask for exported settings and replace the current settings with them
+    fn import_settings(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter(tr("Settings"), &["json"])
+            .pick_file()
+        else {
+            return;
+        };
+
+        let imported = std::fs::read_to_string(&path)
+            .with_context(|| format!("Could not read {}", path.display()))
+            .and_then(|bundle| self.settings.import(&bundle))
+            .and_then(|()| Settings::load(&self.settings.file_location));
+
+        match imported {
+            Ok(settings) => {
+                tracing::info!(path = %path.display(), "settings imported");
+                self.handle_message(GUIMsg::SettingsChanged(Box::new(settings)));
+                if let Err(e) = self.register_hotkeys_again() {
+                    self.error = Some(e);
+                }
+            }
+            Err(e) => self.error = Some(format!("{e:#}")),
+        }
+    }
+
+    /// Export the current conversation to a file
+    fn export_conversation(&mut self) {
+        let conversation = {
+            let Ok(chatgpt) = self.chatgpt.try_read() else {
+                self.error = Some(tr("Wait for the current response before exporting").to_string());
+                return;
+            };
+            SavedConversation {
+                system_prompt: chatgpt.conversation_system_message().to_string(),
+                model: chatgpt.model().to_string(),
+                messages: chatgpt.conversation().to_vec(),
+            }
+        };
+
+        if conversation.messages.is_empty() {
+            self.error = Some(tr("There is nothing to export yet").to_string());
+            return;
+        }
+
+        let title = conversation.fallback_title();
+        if let Err(e) = dialogs::export_conversation(&conversation, &title) {
+            self.error = Some(format!("{e:#}"));
+        }
+    }
+
+    /// Ask for an exported conversation and load it as a new conversation
+    fn import_conversation(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter(tr("Conversation"), &["json", "md"])
+            .pick_file()
+        else {
+            return;
+        };
+
+        let conversation = std::fs::read_to_string(&path)
+            .with_context(|| format!("Could not read {}", path.display()))
+            .and_then(|text| import(&text));
+
+        match conversation {
+            Ok(conversation) => self.load_conversation(None, conversation),
+            Err(e) => self.error = Some(format!("{e:#}")),
+        }
+    }
+
+    /// Load a saved conversation into the client, so that new questions continue it. With a
+    /// history ID, new messages are appended to that history entry.
+    fn load_conversation(&mut self, history_id: Option<i64>, conversation: SavedConversation) {
+        let Ok(mut chatgpt) = self.chatgpt.try_write() else {
+            self.error = Some(
+                tr("Wait for the current response before continuing another conversation")
+                    .to_string(),
+            );
+            return;
+        };
+
+        self.response = conversation
+            .messages
+            .iter()
+            .rev()
+            .find(|message| matches!(message.role, Role::Assistant))
+            .map(|message| message.content.clone())
+            .unwrap_or_default();
+        self.response_render_len = 0;
+        self.reasoning.clear();
+        self.logprobs.clear();
+        self.pipeline_steps.clear();
+        self.truncated = false;
+        self.filtered = None;
+
+        chatgpt.set_conversation(conversation.system_prompt, conversation.messages);
+        if history_id.is_some() {
+            chatgpt.spill_conversation(self.max_messages());
+        }
+        self.history_conversation = history_id;
+        self.history_browser = None;
+        self.prompt.clear();
+        self.focus_input = true;
+    }
+
+    /// Autocomplete popup below the prompt while a slash command is typed. Tab completes the first
+    /// suggestion.
+    fn show_command_popup(&mut self, ui: &mut egui::Ui, prompt_input: &egui::Response) {
+        let popup_id = ui.make_persistent_id("command_popup");
+        let suggestions = self.command_suggestions();
+
+        if suggestions.is_empty() || !prompt_input.has_focus() {
+            if ui.memory(|mem| mem.is_popup_open(popup_id)) {
+                ui.memory_mut(|mem| mem.close_popup());
+            }
+            return;
+        }
+        ui.memory_mut(|mem| mem.open_popup(popup_id));
+
+        let mut completion = None;
+        if ui.input(|inp| inp.key_pressed(Key::Tab)) {
+            completion = suggestions.first().map(|cmd| cmd.name.clone());
+        }
+
+        egui::popup::popup_below_widget(ui, popup_id, prompt_input, |ui| {
+            ui.set_min_width(300.0);
+            for cmd in &suggestions {
+                let text = format!("/{}  {}", cmd.name, cmd.description);
+                if ui.selectable_label(false, text).clicked() {
+                    completion = Some(cmd.name.clone());
+                }
+            }
+        });
+
+        if let Some(name) = completion {
+            self.prompt = format!("/{name} ");
+            self.cursor_to_end = true;
+            prompt_input.request_focus();
+        }
+    }
+
+    /// Offer the snippets matching the name typed after `;;` at the end of the prompt. Tab or a
+    /// click replaces the trigger and the name with the text of the best or the chosen snippet.
+    fn show_snippet_popup(&mut self, ui: &mut egui::Ui, prompt_input: &egui::Response) {
+        let popup_id = ui.make_persistent_id("snippet_popup");
+        let (start, suggestions) = match snippet_query(&self.prompt) {
+            Some((start, query)) => (start, snippet::suggest(&self.settings.snippets, query)),
+            None => (0, Vec::new()),
+        };
+
+        if suggestions.is_empty() || !prompt_input.has_focus() {
+            if ui.memory(|mem| mem.is_popup_open(popup_id)) {
+                ui.memory_mut(|mem| mem.close_popup());
+            }
+            return;
+        }
+        ui.memory_mut(|mem| mem.open_popup(popup_id));
+
+        let mut completion = None;
+        if ui.input(|inp| inp.key_pressed(Key::Tab)) {
+            completion = suggestions.first().map(|snippet| snippet.text.clone());
+        }
+
+        egui::popup::popup_below_widget(ui, popup_id, prompt_input, |ui| {
+            ui.set_min_width(300.0);
+            for snippet in &suggestions {
+                let preview = snippet.text.lines().next().unwrap_or_default();
+                let text = format!(";;{}  {preview}", snippet.name);
+                if ui.selectable_label(false, text).clicked() {
+                    completion = Some(snippet.text.clone());
+                }
+            }
+        });
+
+        if let Some(text) = completion {
+            self.prompt.truncate(start);
+            self.prompt.push_str(&text);
+            self.cursor_to_end = true;
+            prompt_input.request_focus();
+        }
+    }
+
+    /// Open the quick action palette for the current clipboard text
+    fn open_quick_actions(&mut self) {
+        let text = arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text());
+
+        match text {
+            Ok(text) if !text.trim().is_empty() => {
+                self.quick_actions = Some(text);
+                self.quick_action_selected = 0;
+            }
+            _ => self.error = Some(tr("The clipboard doesn't contain any text").to_string()),
+        }
+    }
+
+    /// List of presets and script actions that can be run on the clipboard text with a single key
+    /// press. The arrow keys and Enter or the number keys select an action.
+    fn show_quick_actions(&mut self, ui: &mut egui::Ui) {
+        let Some(text) = &self.quick_actions else {
+            return;
+        };
+
+        let actions = self.settings.commands();
+        let scripts = Arc::clone(&self.scripts);
+        let count = actions.len() + scripts.actions().len();
+        let preview: String = text.chars().take(80).collect();
+        ui.label(
+            RichText::new(format!(
+                "{}: {}…",
+                tr("Run on clipboard"),
+                preview.replace('\n', " ")
+            ))
+            .color(Color32::GRAY),
+        );
+
+        let mut chosen = None;
+        for (i, action) in actions.iter().enumerate() {
+            let label = format!("{}  {}", i + 1, action.description);
+            if ui
+                .selectable_label(i == self.quick_action_selected, label)
+                .clicked()
+            {
+                chosen = Some(i);
+            }
+        }
+        for (i, script) in scripts.actions().iter().enumerate() {
+            let i = actions.len() + i;
+            let label = format!("{}  📜 {}", i + 1, script.description);
+            if ui
+                .selectable_label(i == self.quick_action_selected, label)
+                .on_hover_text(&script.name)
+                .clicked()
+            {
+                chosen = Some(i);
+            }
+        }
+
+        ui.input(|inp| {
+            if inp.key_pressed(Key::ArrowDown) {
+                self.quick_action_selected = (self.quick_action_selected + 1) % count;
+            }
+            if inp.key_pressed(Key::ArrowUp) {
+                self.quick_action_selected = (self.quick_action_selected + count - 1) % count;
+            }
+            if inp.key_pressed(Key::Enter) {
+                chosen = Some(self.quick_action_selected);
+            }
+
+            let number_keys = [
+                Key::Num1,
+                Key::Num2,
+                Key::Num3,
+                Key::Num4,
+                Key::Num5,
+                Key::Num6,
+                Key::Num7,
+                Key::Num8,
+                Key::Num9,
+            ];
+            for (i, key) in number_keys.iter().enumerate().take(count) {
+                if inp.key_pressed(*key) {
+                    chosen = Some(i);
+                }
+            }
+        });
+
+        if let Some(action) = chosen.and_then(|i| actions.get(i)) {
+            let text = self.quick_actions.take().unwrap_or_default();
+            self.prompt = format!("/{}", action.name);
+            self.send_prompt(action.render(text), action.system_prompt.clone());
+        } else if let Some(script) = chosen
+            .and_then(|i| i.checked_sub(actions.len()))
+            .and_then(|i| scripts.actions().get(i))
+        {
+            let text = self.quick_actions.take().unwrap_or_default();
+            self.run_script(&script.name, &script.description, text);
+        }
+
+        ui.add(Separator::default());
+    }
+
+    /// Run a script action on the text in the background, its outputs are applied once it is done
+    fn run_script(&mut self, name: &str, description: &str, text: String) {
+        tracing::info!(name, "running a script action");
+        self.script_running = Some(description.to_string());
+        self.error = None;
+
+        let scripts = Arc::clone(&self.scripts);
+        let name = name.to_string();
+        let host = Arc::new(ScriptHost {
+            settings: self.settings.clone(),
+        });
+        let sender = self.ui_sender.clone();
+        std::thread::spawn(move || {
+            let outputs = scripts.run(&name, text, host).map_err(|e| format!("{e:#}"));
+            let _ = sender.send(GUIMsg::ScriptDone(outputs));
+        });
+    }
+
+    /// Copy, show or insert the results of a script action. Inserting hides the popup, so it
+    /// happens last and only the last inserted text is pasted.
+    fn apply_script_outputs(&mut self, outputs: Vec<Output>) {
+        let mut insert = None;
+        for output in outputs {
+            match output {
+                Output::Copy(text) => {
+                    if let Err(e) =
+                        arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(text))
+                    {
+                        self.error = Some(format!("{e:#}"));
+                    }
+                }
+                Output::Show(text) => {
+                    self.reasoning.clear();
+                    self.stats = None;
+                    self.response_view.reset();
+                    self.response_render_len = text.len();
+                    self.response = text;
+                }
+                Output::Insert(text) => insert = Some(text),
+            }
+        }
+
+        if let Some(text) = insert {
+            self.insert(text);
+        }
+    }
+
+    /// Attach the dropped files to the next prompt
+    fn attach_files(&mut self, ctx: &egui::Context, files: Vec<egui::DroppedFile>) {
+        for path in files.into_iter().filter_map(|file| file.path) {
+            let attached = match ImageAttachment::mime_type(&path) {
+                Some(_) => ImageAttachment::from_file(&path)
+                    .and_then(|image| self.attach_image(ctx, image)),
+                None => Attachment::from_file(&path).map(|attachment| {
+                    self.attachments
+                        .extend(attachment.into_chunks(CHUNK_TOKENS))
+                }),
+            };
+            if let Err(e) = attached {
+                self.error = Some(format!("{e:#}"));
+            }
+        }
+    }
+
+    /// Attach an image to the next prompt and create its thumbnail
+    fn attach_image(&mut self, ctx: &egui::Context, image: ImageAttachment) -> anyhow::Result<()> {
+        let thumbnail = image::load_from_memory(&image.data)
+            .with_context(|| format!("{} is not a valid image", image.name))?
+            .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+            .to_rgba8();
+        let size = [thumbnail.width() as usize, thumbnail.height() as usize];
+        let texture = ctx.load_texture(
+            &image.name,
+            ColorImage::from_rgba_unmultiplied(size, thumbnail.as_raw()),
+            TextureOptions::default(),
+        );
+
+        self.images.push((image, texture));
+        Ok(())
+    }
+
+    /// Paste rich text from a browser or Word as Markdown instead of the plain text, so lists,
+    /// links and code keep their structure. Ctrl+Shift+V pastes the plain text.
+    fn convert_html_paste(&self, ctx: &egui::Context) {
+        let pasting = ctx.input(|inp| {
+            !inp.modifiers.shift && inp.events.iter().any(|e| matches!(e, Event::Paste(_)))
+        });
+        if !pasting {
+            return;
+        }
+
+        let Ok(html) = arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get().html())
+        else {
+            return;
+        };
+        if !html::has_structure(&html) {
+            return;
+        }
+        let markdown = html::to_markdown(&html);
+        if markdown.is_empty() {
+            return;
+        }
+
+        ctx.input_mut(|inp| {
+            for event in &mut inp.events {
+                if let Event::Paste(text) = event {
+                    *text = markdown.clone();
+                }
+            }
+        });
+    }
+
+    /// Attach the image in the clipboard, if there is one. Text is pasted by the prompt itself.
+    fn paste_image(&mut self, ctx: &egui::Context) {
+        let Ok(clipboard_image) =
+            arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_image())
+        else {
+            return;
+        };
+
+        let mut png = Vec::new();
+        let encoded = image::RgbaImage::from_raw(
+            clipboard_image.width as u32,
+            clipboard_image.height as u32,
+            clipboard_image.bytes.into_owned(),
+        )
+        .context("The clipboard image is invalid")
+        .and_then(|rgba| {
+            rgba.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+                .context("Could not encode the clipboard image")
+        });
+
+        let attached = encoded.and_then(|_| {
+            let image = ImageAttachment {
+                name: "clipboard.png".to_string(),
+                mime: "image/png",
+                data: png,
+            };
+            self.attach_image(ctx, image)
+        });
+        if let Err(e) = attached {
+            self.error = Some(format!("{e:#}"));
+        }
+    }
+
+    /// The attached files as chips above the prompt, with a warning if they are large
+    fn show_attachments(&mut self, ui: &mut egui::Ui) {
+        if self.attachments.is_empty() && self.images.is_empty() {
+            return;
+        }
+
+        let mut removed_image = None;
+        let mut removed = None;
+        ui.horizontal_wrapped(|ui| {
+            for (i, (_, thumbnail)) in self.images.iter().enumerate() {
+                if ui
+                    .add(egui::ImageButton::new(thumbnail, thumbnail.size_vec2()))
+                    .on_hover_text(tr("Remove the attachment"))
+                    .clicked()
+                {
+                    removed_image = Some(i);
+                }
+            }
+
+            for (i, attachment) in self.attachments.iter().enumerate() {
+                let chip = format!(
+                    "📄 {} (~{})  ✖",
+                    attachment.name,
+                    attachment.estimated_tokens()
+                );
+                if ui
+                    .small_button(chip)
+                    .on_hover_text(tr("Remove the attachment"))
+                    .clicked()
+                {
+                    removed = Some(i);
+                }
+            }
+        });
+        if let Some(i) = removed {
+            self.attachments.remove(i);
+        }
+        if let Some(i) = removed_image {
+            // Dropping the handle frees the thumbnail texture
+            drop(self.images.remove(i));
+        }
+
+        let tokens: usize = self
+            .attachments
+            .iter()
+            .map(Attachment::estimated_tokens)
+            .sum();
+        let limit = self
+            .settings
+            .context_limit
+            .unwrap_or(ATTACHMENT_WARNING_TOKENS);
+        if tokens > limit {
+            ui.colored_label(
+                Color32::from_rgb(230, 160, 60),
+                format!(
+                    "⚠ {}",
+                    tr("The attachments are about {tokens} tokens, which may not fit into the context")
+                        .replace("{tokens}", &tokens.to_string())
+                ),
+            );
+        }
+    }
+
+    /// Offer to attach the page of the URL the prompt starts with, and the note about the fetched
+    /// page
+    fn show_page_fetch(&mut self, ui: &mut egui::Ui) {
+        let url = fetch::leading_url(&self.prompt);
+
+        match (&self.page_fetch, url) {
+            (Some(PageFetch::Fetched(note)), _) => {
+                ui.label(RichText::new(note).small().color(Color32::GRAY));
+            }
+            (Some(PageFetch::Fetching(fetching)), Some(url)) if fetching == url => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label(
+                        RichText::new(tr("Fetching {host}…").replace("{host}", fetch::host(url)))
+                            .small()
+                            .color(Color32::GRAY),
+                    );
+                });
+            }
+            (_, Some(url)) => {
+                let label = tr("🔗 Attach the page of {host}").replace("{host}", fetch::host(url));
+                if ui
+                    .small_button(label)
+                    .on_hover_text(tr("Fetch the page and send its text with the prompt"))
+                    .clicked()
+                {
+                    self.fetch_page(url.to_string());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Remember a paste that is larger than the paste limit, to warn about it once it is in the
+    /// prompt
+    fn check_paste(&mut self, ctx: &egui::Context) {
+        let limit = self.settings.paste_limit.unwrap_or(DEFAULT_PASTE_LIMIT);
+        let large = ctx.input(|inp| {
+            inp.events.iter().find_map(|event| match event {
+                Event::Paste(text) if TextSize::of(text).tokens > limit => Some(text.clone()),
+                _ => None,
+            })
+        });
+        if let Some(text) = large {
+            let size = TextSize::of(&text);
+            self.large_paste = Some(LargePaste { text, size });
+        }
+    }
+
+    /// The warning about a large paste with buttons to trim it to the paste limit. Trims it right
+    /// away if the settings choose a mode.
+    fn show_large_paste(&mut self, ui: &mut egui::Ui) {
+        let Some(paste) = &self.large_paste else {
+            return;
+        };
+        // The paste went somewhere else or was deleted again
+        if !self.prompt.contains(&paste.text) {
+            self.large_paste = None;
+            return;
+        }
+
+        let mut trim = self.settings.paste_trim;
+        let mut dismiss = false;
+        if trim.is_none() {
+            ui.horizontal_wrapped(|ui| {
+                ui.label(
+                    RichText::new(format!(
+                        "⚠ {}",
+                        tr("The paste has {chars} characters, {words} words and ~{tokens} tokens")
+                            .replace("{chars}", &paste.size.chars.to_string())
+                            .replace("{words}", &paste.size.words.to_string())
+                            .replace("{tokens}", &format_tokens(paste.size.tokens))
+                    ))
+                    .small()
+                    .color(Color32::from_rgb(230, 160, 60)),
+                );
+                let buttons = [
+                    (TrimMode::Head, tr("Keep the start")),
+                    (TrimMode::Tail, tr("Keep the end")),
+                    (TrimMode::Middle, tr("Cut the middle")),
+                ];
+                for (mode, label) in buttons {
+                    if ui.small_button(label).clicked() {
+                        trim = Some(mode);
+                    }
+                }
+                dismiss = ui.small_button("✖").clicked();
+            });
+        }
+        if dismiss {
+            self.large_paste = None;
+        }
+
+        let Some(mode) = trim else {
+            return;
+        };
+        if let Some(paste) = self.large_paste.take() {
+            let limit = self.settings.paste_limit.unwrap_or(DEFAULT_PASTE_LIMIT);
+            let trimmed = trim_to_tokens(&paste.text, limit, mode);
+            self.prompt = self.prompt.replacen(&paste.text, &trimmed, 1);
+            self.cursor_to_end = true;
+        }
+    }
+
+    /// Fetch the page in the background, it is attached once it arrives
+    fn fetch_page(&mut self, url: String) {
+        self.page_fetch = Some(PageFetch::Fetching(url.clone()));
+
+        let sender = self.ui_sender.clone();
+        std::thread::spawn(move || {
+            let page = fetch::fetch_page(&url).map_err(|e| format!("{e:#}"));
+            let _ = sender.send(GUIMsg::PageFetched(page));
+        });
+    }
+
+    /// The estimated tokens of the next request, colored by how close they are to the context
+    /// limit or the context window of the model
+    fn token_count_label(&mut self) -> Option<RichText> {
+        let chatgpt = self.chatgpt.try_read().ok()?;
+        let limit = self
+            .settings
+            .context_limit
+            .or_else(|| context_window(chatgpt.model()));
+
+        // Estimating goes through the whole conversation, so it is only redone after changes
+        let mut hasher = DefaultHasher::new();
+        (
+            &self.prompt,
+            self.attachments.len(),
+            chatgpt.conversation().len(),
+            chatgpt.model(),
+        )
+            .hash(&mut hasher);
+        let key = hasher.finish();
+        let tokens = match self.token_estimate {
+            Some((cached_key, tokens)) if cached_key == key => tokens,
+            _ => {
+                let prompt = with_attachments(&self.prompt, &self.attachments);
+                let tokens = chatgpt.estimated_request_tokens(&prompt);
+                self.token_estimate = Some((key, tokens));
+                tokens
+            }
+        };
+        drop(chatgpt);
+
+        let text = match limit {
+            Some(limit) => format!("~{} / {}", format_tokens(tokens), format_tokens(limit)),
+            None => format!("~{}", format_tokens(tokens)),
+        };
+        let usage = limit.map_or(0.0, |limit| tokens as f32 / limit as f32);
+        let color = match usage {
+            _ if usage >= 0.9 => Color32::from_rgb(230, 90, 90),
+            _ if usage >= 0.75 => Color32::from_rgb(230, 160, 60),
+            _ => Color32::GRAY,
+        };
+
+        Some(RichText::new(text).small().color(color))
+    }
+
+    /// Editor for the system prompt of the active profile. The new prompt applies to the next
+    /// conversation.
+    fn show_system_prompt_editor(&mut self, ui: &mut egui::Ui) {
+        let Some(system_prompt) = &mut self.system_prompt_editor else {
+            return;
+        };
+
+        ui.label(RichText::new(tr("System prompt for new conversations")).color(Color32::GRAY));
+        ui.add(
+            TextEdit::multiline(system_prompt)
+                .font(IN_FONT)
+                .desired_rows(3)
+                .desired_width(f32::INFINITY),
+        );
+
+        let mut save = false;
+        let mut close = false;
+        ui.horizontal(|ui| {
+            save = ui.button(tr("Save")).clicked();
+            close = ui.button(tr("Cancel")).clicked();
+        });
+
+        if save {
+            let system_prompt = self.system_prompt_editor.take().unwrap_or_default();
+            self.settings.set_system_prompt(system_prompt);
+            self.apply_settings();
+
+            if let Err(e) = self.settings.save() {
+                self.error = Some(format!("{e:#}"));
+            }
+        } else if close {
+            self.system_prompt_editor = None;
+        }
+
+        ui.add(Separator::default());
+    }
+
+    /// The system prompt of the current conversation as a one-line header. Clicking it edits the
+    /// prompt for this conversation only, the saved default stays as it is.
+    fn show_system_prompt_header(&mut self, ui: &mut egui::Ui) {
+        let Some(system_prompt) = &mut self.conversation_prompt_editor else {
+            let Ok(chatgpt) = self.chatgpt.try_read() else {
+                return;
+            };
+            let system_prompt = chatgpt.conversation_system_message().to_string();
+            drop(chatgpt);
+
+            let first_line = system_prompt.lines().next().unwrap_or_default();
+            let mut header: String = first_line.chars().take(SYSTEM_PROMPT_HEADER_LEN).collect();
+            if header.len() < system_prompt.trim_end().len() {
+                header.push('…');
+            }
+
+            let label = egui::Label::new(RichText::new(header).small().color(Color32::GRAY))
+                .wrap(false)
+                .sense(Sense::click());
+            if ui
+                .add(label)
+                .on_hover_text(tr("Edit the system prompt of this conversation"))
+                .clicked()
+            {
+                self.conversation_prompt_editor = Some(system_prompt);
+            }
+            return;
+        };
+
+        ui.add(
+            TextEdit::multiline(system_prompt)
+                .font(egui::TextStyle::Small)
+                .desired_rows(2)
+                .desired_width(f32::INFINITY),
+        );
+
+        let mut apply = false;
+        let mut close = false;
+        ui.horizontal(|ui| {
+            apply = ui.small_button(tr("Apply")).clicked();
+            close = ui.small_button(tr("Cancel")).clicked();
+        });
+
+        if apply {
+            let system_prompt = self.conversation_prompt_editor.take().unwrap_or_default();
+            if let Ok(mut chatgpt) = self.chatgpt.try_write() {
+                chatgpt.set_conversation_system_message(system_prompt);
+            }
+            self.focus_input = true;
+        } else if close {
+            self.conversation_prompt_editor = None;
+            self.focus_input = true;
+        }
+    }
+
+    /// Apply a slash command at the start of the prompt. Returns the prompt to send and the
+    /// system prompt of the command, if any.
+    fn resolve_command(&self) -> Result<(String, Option<String>), String> {
+        let Some((name, input)) = parse_command(&self.prompt) else {
+            return Ok((self.prompt.clone(), None));
+        };
+
+        match self
+            .settings
+            .commands()
+            .into_iter()
+            .find(|cmd| cmd.name == name)
+        {
+            Some(cmd) => Ok((cmd.render(input), cmd.system_prompt)),
+            None => Err(format!("{} /{name}", tr("Unknown command"))),
+        }
+    }
+
+    /// The pipeline named by the slash command at the start of the prompt and its input
+    fn resolve_pipeline(&self) -> Option<(Pipeline, String)> {
+        let (name, input) = parse_command(&self.prompt)?;
+        let pipeline = self
+            .settings
+            .pipelines
+            .iter()
+            .find(|pipeline| pipeline.name == name)?;
+
+        Some((pipeline.clone(), input.to_string()))
+    }
+
+    /// The commands matching the partially typed slash command in the prompt
+    fn command_suggestions(&self) -> Vec<PromptTemplate> {
+        match parse_command(&self.prompt) {
+            Some((name, "")) if !self.prompt.ends_with(char::is_whitespace) => self
+                .settings
+                .commands()
+                .into_iter()
+                .filter(|cmd| cmd.name.starts_with(name))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Run the steps of a pipeline one after another, each in a fresh conversation with the
+    /// response to the previous step as input. The finished steps stay visible above the response
+    /// and cancelling stops the pipeline after the current step.
+    fn run_pipeline(&mut self, pipeline: Pipeline, input: String) {
+        let steps = match pipeline.resolve(&self.settings.commands()) {
+            Ok(steps) => steps,
+            Err(e) => {
+                self.error = Some(format!("{e:#}"));
+                return;
+            }
+        };
+
+        self.loading = true;
+        self.truncated = false;
+        self.filtered = None;
+        self.error = None;
+        self.announcer.clear();
+        self.logprobs.clear();
+        self.pipeline_steps.clear();
+        self.response.clear();
+        self.response_render_len = 0;
+        self.reasoning.clear();
+        self.stats = None;
+        self.response_view.reset();
+
+        let chatgpt = Arc::clone(&self.chatgpt);
+        let cancel = Arc::clone(&self.cancel);
+        let sender = self.ui_sender.clone();
+        let plugins = Arc::clone(&self.plugins);
+        let rules = self.settings.post_processing.clone().unwrap_or_default();
+        let disabled_plugins = self.disabled_plugins.clone();
+        let enabled = move |name: &str| !disabled_plugins.contains(name);
+
+        self.worker.run(move || {
+            let mut chatgpt = chatgpt.write().unwrap();
+            let mut input = input;
+
+            for (i, step) in steps.iter().enumerate() {
+                chatgpt.clear_conversation();
+                if let Some(system_prompt) = &step.system_prompt {
+                    chatgpt.set_conversation_system_message(system_prompt);
+                }
+
+                let prompt = match plugins.pre_process(step.render(&input), &enabled) {
+                    Ok(prompt) => prompt,
+                    Err(e) => {
+                        let _ = sender.send(GUIMsg::Error(format!("{e:#}")));
+                        break;
+                    }
+                };
+
+                let forwarder = StreamForwarder::new(sender.clone());
+                let resp = chatgpt.ask_stream(prompt, &forwarder);
+
+                let resp = match resp.and_then(|mut resp| {
+                    post_process(&mut chatgpt, &plugins, &enabled, &rules, &mut resp)?;
+                    Ok(resp)
+                }) {
+                    Ok(resp) => resp,
+                    Err(e) => {
+                        let _ = sender.send(GUIMsg::Error(format!("{e:#}")));
+                        break;
+                    }
+                };
+                if !plugins.is_empty() || !rules.is_empty() {
+                    let processed = resp.primary_response().unwrap_or_default().to_string();
+                    let _ = sender.send(GUIMsg::Processed(processed));
+                }
+
+                if cancel.load(Ordering::Relaxed) || i + 1 == steps.len() {
+                    let _ = sender.send(GUIMsg::Flush);
+                    break;
+                }
+
+                input = resp.primary_response().unwrap_or_default().to_string();
+                let _ = sender.send(GUIMsg::PipelineStep(step.name.clone()));
+            }
+        });
+    }
+
+    /// Run the pipeline, command or plain prompt in the prompt input with the attachments
+    fn submit_prompt(&mut self) {
+        self.page_fetch = None;
+        self.large_paste = None;
+        self.prompt = snippet::expand(&self.prompt, &self.settings.snippets);
+        if self.translating {
+            self.send_translation();
+            return;
+        }
+
+        let attachments = std::mem::take(&mut self.attachments);
+        if let Some((pipeline, input)) = self.resolve_pipeline() {
+            self.run_pipeline(pipeline, with_attachments(&input, &attachments));
+        } else {
+            match self.resolve_command() {
+                Ok((prompt, system_prompt)) => {
+                    let prompt = with_attachments(&prompt, &attachments);
+                    self.send_prompt(prompt, system_prompt)
+                }
+                Err(e) => {
+                    self.attachments = attachments;
+                    self.error = Some(e);
+                }
+            }
+        }
+    }
+
+    /// Append a snippet to the scratchpad, separated from the previous one by a blank line
+    fn add_to_scratchpad(&mut self, text: &str) {
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+
+        let kept = self.scratchpad.trim_end().len();
+        self.scratchpad.truncate(kept);
+        if !self.scratchpad.is_empty() {
+            self.scratchpad.push_str("\n\n");
+        }
+        self.scratchpad.push_str(text);
+        self.show_scratchpad = true;
+    }
+
+    /// The editable scratchpad with buttons to add the response, copy and clear it
+    fn show_scratchpad(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label(RichText::new(tr("Scratchpad")).color(Color32::GRAY));
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui.small_button(tr("Clear")).clicked() {
+                    self.scratchpad.clear();
+                }
+                if ui
+                    .add_enabled(
+                        !self.scratchpad.is_empty(),
+                        egui::Button::new(tr("Copy")).small(),
+                    )
+                    .clicked()
+                {
+                    ui.output_mut(|output| output.copied_text = self.scratchpad.clone());
+                }
+                if ui
+                    .add_enabled(
+                        !self.loading && !self.answer().is_empty(),
+                        egui::Button::new(tr("Add the response")).small(),
+                    )
+                    .clicked()
+                {
+                    let answer = self.answer().to_string();
+                    self.add_to_scratchpad(&answer);
+                }
+            });
+        });
+
+        ScrollArea::vertical()
+            .id_source("scratchpad_scroll")
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                ui.add(
+                    TextEdit::multiline(&mut self.scratchpad)
+                        .font(OUT_FONT)
+                        .desired_width(f32::INFINITY)
+                        .desired_rows(10),
+                );
+            });
+    }
+
+    /// The pinned response in a collapsible panel with a button to unpin it
+    fn show_pinned(&mut self, ui: &mut egui::Ui) {
+        let Some(pinned) = &self.pinned else {
+            return;
+        };
+
+        let mut unpin = false;
+        ui.horizontal_top(|ui| {
+            egui::CollapsingHeader::new(RichText::new(tr("Pinned")).color(Color32::GRAY))
+                .id_source("pinned")
+                .default_open(true)
+                .show(ui, |ui| {
+                    ScrollArea::vertical()
+                        .id_source("pinned_scroll")
+                        .max_height(150.0)
+                        .show(ui, |ui| {
+                            ui.label(RichText::new(pinned).font(OUT_FONT));
+                        });
+                });
+            unpin = ui.small_button("✖").on_hover_text(tr("Unpin")).clicked();
+        });
+        ui.add(Separator::default());
+
+        if unpin {
+            self.pinned = None;
+        }
+    }
+
+    /// The tool calls of the agent, with buttons to allow or deny the one it waits for
+    fn show_tool_calls(&mut self, ui: &mut egui::Ui) {
+        for view in &mut self.tool_calls {
+            ui.horizontal_wrapped(|ui| {
+                let function = &view.call.function;
+                ui.label(
+                    RichText::new(format!("🔧 {}({})", function.name, function.arguments))
+                        .small()
+                        .monospace()
+                        .color(Color32::GRAY),
+                );
+
+                let Some(pending) = &view.pending else {
+                    if !view.allowed {
+                        ui.label(RichText::new(tr("denied")).small().color(Color32::GRAY));
+                    }
+                    return;
+                };
+                let answer = if ui.small_button(tr("Allow")).clicked() {
+                    Some(true)
+                } else if ui.small_button(tr("Deny")).clicked() {
+                    Some(false)
+                } else {
+                    None
+                };
+                if let Some(allowed) = answer {
+                    let _ = pending.send(allowed);
+                    view.pending = None;
+                    view.allowed = allowed;
+                }
+            });
+        }
+    }
+
+    /// Answer the tool calls the agent waits for with a denial, e.g. when the response is stopped
+    fn deny_pending_tool_calls(&mut self) {
+        for view in &mut self.tool_calls {
+            if let Some(pending) = view.pending.take() {
+                let _ = pending.send(false);
+            }
+        }
+    }
+
+    /// Translate the prompt in a conversation of its own
+    fn send_translation(&mut self) {
+        let text = with_attachments(&self.prompt, &std::mem::take(&mut self.attachments));
+        if text.trim().is_empty() {
+            return;
+        }
+
+        // Only the text is sent, earlier translations would just distract the model
+        {
+            let mut chatgpt = self.chatgpt.write().unwrap();
+            chatgpt.clear_conversation();
+            // The target language is part of the prompt
+            chatgpt.skip_reply_language();
+        }
+        self.history_conversation = None;
+
+        let source = self.translate_source.as_deref();
+        let prompt = translate::prompt(&text, source, &self.translate_target);
+        self.send_prompt(prompt, Some(translate::SYSTEM_PROMPT.to_string()));
+    }
+
+    /// Translate the translation back, from the target language to the source language
+    fn swap_translation(&mut self) {
+        let (detected, translation) = translate::split_detected(self.answer_with_detection());
+        let Some(source) = self.translate_source.as_deref().or(detected) else {
+            return;
+        };
+        let (source, translation) = (source.to_string(), translation.to_string());
+
+        self.prompt = translation;
+        self.translate_source = Some(std::mem::replace(&mut self.translate_target, source));
+        self.send_translation();
+    }
+
+    /// The language pickers of the translate mode
+    fn show_translate_bar(&mut self, ui: &mut egui::Ui) {
+        if !self.translating {
+            return;
+        }
+
+        let mut swap = false;
+        ui.horizontal(|ui| {
+            let detect = tr("Detect language");
+            egui::ComboBox::from_id_source("translate_source")
+                .selected_text(self.translate_source.as_deref().unwrap_or(detect))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.translate_source, None, detect);
+                    for language in translate::LANGUAGES {
+                        let value = Some(language.to_string());
+                        ui.selectable_value(&mut self.translate_source, value, *language);
+                    }
+                });
+
+            ui.label("→");
+
+            let target = self.translate_target.clone();
+            egui::ComboBox::from_id_source("translate_target")
+                .selected_text(&target)
+                .show_ui(ui, |ui| {
+                    for language in translate::LANGUAGES {
+                        let value = language.to_string();
+                        ui.selectable_value(&mut self.translate_target, value, *language);
+                    }
+                });
+            if self.translate_target != target {
+                self.settings.translate_target = Some(self.translate_target.clone());
+                if let Err(e) = self.settings.save() {
+                    self.error = Some(format!("{e:#}"));
+                }
+            }
+
+            swap = ui
+                .add_enabled(
+                    !self.loading && !self.answer().is_empty(),
+                    egui::Button::new("⇄").small(),
+                )
+                .on_hover_text(tr("Swap the languages and translate the translation"))
+                .clicked();
+        });
+
+        if swap {
+            self.swap_translation();
+        }
+    }
+
+    /// Submit the next queued prompt once the running response is complete. The queue stops at an
+    /// error, so that the following prompts aren't sent without the answer they build on, and
+    /// while the API can't be reached.
+    fn send_queued(&mut self) {
+        if self.loading || self.error.is_some() || self.offline {
+            return;
+        }
+        let Some((prompt, attachments)) = self.queue.pop_front() else {
+            return;
+        };
+
+        // Keep what is typed meanwhile, it is restored after the queued prompt is sent
+        let draft = std::mem::replace(&mut self.prompt, prompt);
+        let draft_attachments = std::mem::replace(&mut self.attachments, attachments);
+        self.submit_prompt();
+        if !draft.is_empty() {
+            self.prompt = draft;
+            self.cursor_to_end = true;
+        }
+        self.attachments.extend(draft_attachments);
+    }
+
+    /// List the queued prompts below the prompt input, each can be removed
+    fn show_queue(&mut self, ui: &mut egui::Ui) {
+        let mut removed = None;
+        for (i, (prompt, attachments)) in self.queue.iter().enumerate() {
+            ui.horizontal(|ui| {
+                if ui
+                    .small_button("✖")
+                    .on_hover_text(tr("Remove from the queue"))
+                    .clicked()
+                {
+                    removed = Some(i);
+                }
+                let mut text = format!("⏳ {prompt}");
+                if !attachments.is_empty() {
+                    text.push_str(&format!("  📄 {}", attachments.len()));
+                }
+                ui.label(RichText::new(text).color(Color32::GRAY));
+            });
+        }
+        if let Some(i) = removed {
+            self.queue.remove(i);
+        }
+    }
+
+    /// Send a prompt in the current conversation and stream the response into the UI
+    fn send_prompt(&mut self, prompt: String, system_prompt: Option<String>) {
+        self.start_request(Some((prompt, system_prompt)));
+    }
+
+    /// Send the request that failed on the way to the API again. Its question is already part of
+    /// the conversation.
+    fn retry_request(&mut self) {
+        tracing::info!("retrying the failed request");
+        self.start_request(None);
+    }
+
+    /// Stream the response to the prompt and its system prompt into the UI, or the response to
+    /// the failed request without a prompt
+    fn start_request(&mut self, prompt: Option<(String, Option<String>)>) {
+        self.loading = true;
+        self.can_retry = false;
+        self.truncated = false;
+        self.filtered = None;
+        self.error = None;
+        self.announcer.clear();
+        self.logprobs.clear();
+        self.pipeline_steps.clear();
+        self.response.clear();
+        self.response_render_len = 0;
+        self.reasoning.clear();
+        self.stats = None;
+        self.response_view.reset();
+        self.tool_calls.clear();
+
+        let chatgpt = Arc::clone(&self.chatgpt);
+        let sender = self.ui_sender.clone();
+
+        let history = self.history.clone();
+        let history_conversation = self.history_conversation;
+        let plugins = Arc::clone(&self.plugins);
+        let rules = self.settings.post_processing.clone().unwrap_or_default();
+        let disabled_plugins = self.disabled_plugins.clone();
+        let enabled = move |name: &str| !disabled_plugins.contains(name);
+        // The images of a failed request are part of it, new ones stay for the next prompt
+        let images: Vec<String> = match prompt.is_some() {
+            true => std::mem::take(&mut self.images)
+                .into_iter()
+                .map(|(image, _)| image.data_url())
+                .collect(),
+            false => Vec::new(),
+        };
+        let agent = self.agent;
+        let host = AgentHost {
+            search: self.settings.search.clone(),
+        };
+        let confirm_tools = self.settings.confirm_tools.unwrap_or(true);
+        let max_messages = self.max_messages();
+
+        self.worker.run(move || {
+            let mut chatgpt = chatgpt.write().unwrap();
+            let asked = match prompt {
+                Some((prompt, system_prompt)) => {
+                    if let Some(system_prompt) = system_prompt {
+                        chatgpt.set_conversation_system_message(system_prompt);
+                    }
+                    plugins.pre_process(prompt, &enabled).map(|prompt| {
+                        let question = Message {
+                            images,
+                            ..Message::user(&prompt)
+                        };
+                        (prompt, Some(question))
+                    })
+                }
+                None => Ok((last_question(&chatgpt), None)),
+            };
+
+            let resp = asked.and_then(|(prompt, question)| {
+                let start = Instant::now();
+                let forwarder = StreamForwarder::new(sender.clone());
+                let resp = match (question, agent) {
+                    (None, _) => chatgpt.retry_stream(&forwarder),
+                    (Some(question), true) => {
+                        agent::run(&mut chatgpt, question, &forwarder, &host, |call| {
+                            confirm_tool_call(&sender, call, confirm_tools)
+                        })
+                    }
+                    (Some(question), false) => chatgpt.ask_stream_message(question, &forwarder),
+                };
+
+                let mut resp = resp?;
+                let first_token = forwarder.first_token();
+                let stats = ExchangeStats::new(chatgpt.model(), start, first_token, &resp);
+                let _ = sender.send(GUIMsg::Stats(stats));
+                post_process(&mut chatgpt, &plugins, &enabled, &rules, &mut resp)?;
+                Ok((prompt, resp))
+            });
+            match resp {
+                Ok((prompt, resp)) => {
+                    if !plugins.is_empty() || !rules.is_empty() {
+                        let processed = resp.primary_response().unwrap_or_default().to_string();
+                        let _ = sender.send(GUIMsg::Processed(processed));
+                    }
+
+                    if let Some(history) = history {
+                        let saved = save_exchange(
+                            &history.lock().unwrap(),
+                            history_conversation,
+                            &chatgpt,
+                            &prompt,
+                            &resp,
+                        );
+
+                        // Give new conversations a title once the first exchange is complete
+                        if let (Some(Ok(id)), None) = (&saved, history_conversation) {
+                            let answer = resp.primary_response().unwrap_or_default().to_string();
+                            spawn_title_request(
+                                chatgpt.clone(),
+                                history,
+                                *id,
+                                prompt.clone(),
+                                answer,
+                            );
+                        }
+
+                        // The saved messages can be dropped from memory
+                        if let Some(Ok(_)) = saved {
+                            chatgpt.spill_conversation(max_messages);
+                        }
+
+                        if let Some(saved) = saved {
+                            let saved = saved.map_err(|e| format!("{e:#}"));
+                            let _ = sender.send(GUIMsg::HistorySaved(saved));
+                        }
+                    }
+                    let _ = sender.send(GUIMsg::Flush);
+                }
+                Err(e) if http::is_network_error(&e) => {
+                    let _ = sender.send(GUIMsg::NetworkError {
+                        error: format!("{e:#}"),
+                        // The agent may have run tools for the failed request, it starts over
+                        can_retry: chatgpt.can_retry() && !agent,
+                    });
+                }
+                Err(e) => {
+                    let _ = sender.send(GUIMsg::Error(format!("{e:#}")));
+                }
+            }
+        });
+    }
+
+    /// Block until a hotkey is pressed and show the popup for a new conversation
+    fn wait_for_hotkey(&mut self, keep: Keep) {
+        self.waiting_for_hotkey.store(true, Ordering::SeqCst);
+        let action = loop {
+            if !self.apply_hotkey_suspension() {
+                break None;
+            }
+            let action = self.hotkey_mgr.handle_hotkey();
+            // The tray menu toggled the suspension, which is applied without showing the popup
+            if self.suspend_hotkeys.load(Ordering::SeqCst) == self.hotkeys_suspended {
+                break action;
+            }
+        };
+        self.waiting_for_hotkey.store(false, Ordering::SeqCst);
+        self.previous_window = insert::foreground_window();
+
+        self.focus_input = true;
+
+        // Quick actions and personas always start over, they don't fit into the last conversation
+        let start_over = keep == Keep::Nothing
+            || matches!(
+                action,
+                Some(HotkeyAction::QuickActions | HotkeyAction::Persona(_))
+            );
+        if start_over {
+            self.new_conversation();
+            self.quick_actions = None;
+            self.persona = match &action {
+                Some(HotkeyAction::Persona(name)) => self
+                    .settings
+                    .personas
+                    .iter()
+                    .find(|persona| persona.name == *name)
+                    .cloned(),
+                _ => None,
+            };
+        } else if keep == Keep::Conversation {
+            self.prompt.clear();
+            self.attachments.clear();
+            self.images.clear();
+        }
+        self.apply_settings();
+
+        if action == Some(HotkeyAction::QuickActions) {
+            self.open_quick_actions();
+        }
+
+        self.show_window(true);
+        self.warm_up_connection();
+    }
+
+    /// Open the connection to the API in the background while the prompt is typed, unless it was
+    /// opened recently or is in use
+    fn warm_up_connection(&mut self) {
+        if !self.settings.warm_up.unwrap_or(true)
+            || self
+                .last_warm_up
+                .is_some_and(|warm_up| warm_up.elapsed() < WARM_UP_INTERVAL)
+        {
+            return;
+        }
+        self.last_warm_up = Some(Instant::now());
+
+        let chatgpt = Arc::clone(&self.chatgpt);
+        std::thread::spawn(move || {
+            if let Ok(chatgpt) = chatgpt.try_read() {
+                chatgpt.warm_up();
+            }
+        });
+    }
+
+    /// Unregister the hotkeys or register them again if the tray menu toggled the suspension.
+    /// Returns false if they could not be registered again, the popup is shown with the error then.
+    fn apply_hotkey_suspension(&mut self) -> bool {
+        let suspend = self.suspend_hotkeys.load(Ordering::SeqCst);
+        if suspend == self.hotkeys_suspended {
+            return true;
+        }
+
+        if suspend {
+            if let Err(e) = self.hotkey_mgr.unregister_all() {
+                tracing::warn!(error = %e, "could not suspend the hotkeys");
+            }
+        } else {
+            match hotkeys::register_all(&mut self.hotkey_mgr, &self.settings) {
+                Ok(errors) if !errors.is_empty() => self.error = Some(errors.join("\n")),
+                Ok(_) => (),
+                Err(e) => {
+                    self.error = Some(e);
+                    self.suspend_hotkeys.store(true, Ordering::SeqCst);
+                    return false;
+                }
+            }
+        }
+        tracing::info!(suspend, "hotkeys toggled");
+
+        self.hotkeys_suspended = suspend;
+        if let Some(tray) = &self.tray {
+            tray.set_suspended(suspend);
+        }
+        true
+    }
+
+    /// Forget the conversation, the prompt and the last response
+    fn new_conversation(&mut self) {
+        self.prompt.clear();
+        self.queue.clear();
+        self.chatgpt.write().unwrap().clear_conversation();
+        self.history_conversation = None;
+        self.history_browser = None;
+        self.disabled_plugins.clear();
+        self.attachments.clear();
+        self.conversation_prompt_editor = None;
+        self.images.clear();
+        self.truncated = false;
+        self.filtered = None;
+        self.error = None;
+        self.can_retry = false;
+        self.logprobs.clear();
+        self.pipeline_steps.clear();
+        self.response.clear();
+        self.response_render_len = 0;
+        self.reasoning.clear();
+        self.stats = None;
+        self.response_view.reset();
+        self.tool_calls.clear();
+        self.page_fetch = None;
+        self.large_paste = None;
+        self.load_plugins();
+    }
+
+    /// Copy the complete response to the clipboard, if enabled in the settings
+    fn copy_response(&mut self) {
+        let text = match self.settings.copy_response {
+            None => return,
+            Some(CopyResponse::Response) => self.answer().to_string(),
+            Some(CopyResponse::CodeBlock) => match code_blocks(self.answer()).first() {
+                Some(block) => block.code.to_string(),
+                None => return,
+            },
+        };
+        if text.is_empty() {
+            return;
+        }
+
+        if let Err(e) = arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(text))
+        {
+            self.error = Some(format!("{e:#}"));
+        }
+    }
+
+    /// The response without the reasoning of the model
+    fn answer(&self) -> &str {
+        match self.translating {
+            true => translate::split_detected(self.answer_with_detection()).1,
+            false => self.answer_with_detection(),
+        }
+    }
+
+    /// The response without the thinking, including the detected language of a translation
+    fn answer_with_detection(&self) -> &str {
+        split_thinking(&self.response).1
+    }
+
+    /// Append the last question and its response to the notes file
+    fn append_to_notes(&mut self) {
+        let Some(pattern) = self.settings.notes_file.clone() else {
+            return;
+        };
+        let question = {
+            let Ok(chatgpt) = self.chatgpt.try_read() else {
+                return;
+            };
+            last_question(&chatgpt)
+        };
+
+        match notes::append(&pattern, &question, self.answer()) {
+            Ok(path) => tracing::info!(path = %path.display(), "appended to notes"),
+            Err(e) => self.error = Some(format!("{e:#}")),
+        }
+    }
+
+    /// Hide the popup and paste the text into the window that was active before it
+    fn insert(&mut self, text: String) {
+        self.show_window(false);
+
+        if let Err(e) = insert::paste_into(self.previous_window, &text) {
+            // Bring the popup back, so the error isn't lost
+            self.error = Some(format!("{e:#}"));
+            self.show_window(true);
+            return;
+        }
+
+        self.wait_for_hotkey(Keep::Nothing);
+    }
+
+    /// Show why the running request failed and whether it can be sent again
+    fn request_failed(&mut self, e: String, can_retry: bool) {
+        tracing::error!(error = %e, can_retry, "request failed");
+        self.announcer
+            .announce(format!("{}: {e}", tr("The request failed")));
+        self.error = Some(e);
+        self.can_retry = can_retry;
+        self.loading = false;
+    }
+
+    /// Show the offline state and check in the background until the API can be reached again
+    fn go_offline(&mut self) {
+        if self.offline {
+            return;
+        }
+        tracing::info!("offline");
+        self.offline = true;
+
+        let chatgpt = Arc::clone(&self.chatgpt);
+        let sender = self.ui_sender.clone();
+        let hidden = Arc::clone(&self.waiting_for_hotkey);
+        let notifier = self.tray.as_ref().map(Tray::notifier);
+        std::thread::spawn(move || loop {
+            std::thread::sleep(OFFLINE_CHECK_INTERVAL);
+            if !chatgpt
+                .try_read()
+                .is_ok_and(|chatgpt| chatgpt.is_reachable())
+            {
+                continue;
+            }
+
+            // The popup handles the message once it is shown again
+            if let Some(notifier) = notifier.filter(|_| hidden.load(Ordering::SeqCst)) {
+                notifier.notify("Popup-GPT", tr("The connection to the API is back"));
+            }
+            let _ = sender.send(GUIMsg::Online);
+            break;
+        });
+    }
+
+    /// Leave the offline state and send the failed request and the queued prompts again, unless
+    /// the settings turn that off
+    fn back_online(&mut self) {
+        if !self.offline {
+            return;
+        }
+        tracing::info!("back online");
+        self.offline = false;
+        self.announcer
+            .announce(tr("The connection to the API is back"));
+
+        if !self.settings.auto_retry.unwrap_or(true) || self.loading {
+            return;
+        }
+        if self.can_retry {
+            self.retry_request();
+        } else if !self.queue.is_empty() {
+            // The queue stops at the error of the failed request
+            self.error = None;
+        }
+    }
+
+    /// Apply a message from the background threads to the UI state
+    fn handle_message(&mut self, msg: GUIMsg) {
+        match msg {
+            GUIMsg::CompletionResponse(resp) if self.loading => {
+                self.response = resp.primary_response().unwrap_or_default().to_string();
+                self.truncated = resp.finish_reason() == Some(&FinishReason::Length);
+                if resp.finish_reason() == Some(&FinishReason::ContentFilter) {
+                    self.filtered = Some(resp.filtered_categories());
+                }
+                self.loading = false;
+                self.announcer.announce(tr("The response is complete"));
+            }
+            GUIMsg::PartialCompletionResponse(resp) if self.loading => {
+                let delta = resp
+                    .choices
+                    .first()
+                    .and_then(|choice| choice.delta.as_ref());
+                if let Some(reasoning) = delta.and_then(|delta| delta.reasoning_content.as_ref()) {
+                    self.reasoning.push_str(reasoning);
+                }
+                if let Some(content) = delta.and_then(|delta| delta.content.as_ref()) {
+                    self.response.push_str(content);
+                }
+                if let Some(content) = resp
+                    .choices
+                    .first()
+                    .and_then(|choice| choice.logprobs.as_ref())
+                    .and_then(|logprobs| logprobs.content.as_ref())
+                {
+                    self.logprobs.extend(content.iter().cloned());
+                }
+                if resp.finish_reason() == Some(&FinishReason::Length) {
+                    self.truncated = true;
+                }
+                if resp.finish_reason() == Some(&FinishReason::ContentFilter) {
+                    self.filtered = Some(resp.filtered_categories());
+                }
+            }
+            GUIMsg::Error(e) if self.loading => self.request_failed(e, false),
+            GUIMsg::NetworkError { error, can_retry } if self.loading => {
+                self.request_failed(error, can_retry);
+                self.go_offline();
+            }
+            GUIMsg::Online => self.back_online(),
+            GUIMsg::ScriptDone(outputs) => {
+                self.script_running = None;
+                match outputs {
+                    Ok(outputs) => self.apply_script_outputs(outputs),
+                    Err(e) => {
+                        tracing::warn!(error = %e, "script action failed");
+                        self.error = Some(e);
+                    }
+                }
+            }
+            GUIMsg::Hide => {
+                self.show_window(false);
+                self.wait_for_hotkey(Keep::Everything);
+            }
+            GUIMsg::Processed(response) if self.loading => {
+                self.response_render_len = response.len();
+                self.response = response;
+            }
+            GUIMsg::PipelineStep(name) if self.loading => {
+                self.pipeline_steps
+                    .push((name, std::mem::take(&mut self.response)));
+                self.response_render_len = 0;
+                self.logprobs.clear();
+            }
+            GUIMsg::KeyChecked(check) => {
+                tracing::info!(?check, "API key checked");
+                if let Some(editor) = &mut self.key_editor {
+                    editor.checked(check);
+                }
+            }
+            GUIMsg::UpdateAvailable(release) => {
+                self.update = Some(release);
+            }
+            GUIMsg::UpdateInstalled(installed) => {
+                self.updating = false;
+                match installed {
+                    Ok(version) => {
+                        tracing::info!(version, "update installed");
+                        self.update = None;
+                        self.error = Some(
+                            tr("Version {version} is installed, restart Popup-GPT to use it")
+                                .replace("{version}", &version),
+                        );
+                    }
+                    Err(e) => {
+                        tracing::warn!(error = %e, "update failed");
+                        self.error = Some(e);
+                    }
+                }
+            }
+            GUIMsg::HistorySaved(Ok(id)) => {
+                self.history_conversation = Some(id);
+            }
+            GUIMsg::HistorySaved(Err(e)) => {
+                tracing::warn!(error = %e, "could not save the conversation");
+                self.error = Some(format!("{}: {e}", tr("Could not save the conversation")));
+            }
+            GUIMsg::PageFetched(page) => match page {
+                // The prompt was sent or the popup cleared in the meantime
+                _ if !matches!(self.page_fetch, Some(PageFetch::Fetching(_))) => (),
+                Ok(page) => {
+                    tracing::info!(url = %page.url, truncated = page.truncated, "page fetched");
+                    let mut note = tr("Fetched {tokens} tokens from {host}")
+                        .replace("{tokens}", &page.estimated_tokens().to_string())
+                        .replace("{host}", fetch::host(&page.url));
+                    if page.truncated {
+                        note = format!("{note} ({})", tr("cut off"));
+                    }
+                    self.attachments.push(page.attachment());
+                    self.page_fetch = Some(PageFetch::Fetched(note));
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "could not fetch the page");
+                    self.error = Some(e);
+                    self.page_fetch = None;
+                }
+            },
+            GUIMsg::ToolCall(call, pending) if self.loading => {
+                self.tool_calls.push(ToolCallView {
+                    allowed: pending.is_none(),
+                    call,
+                    pending,
+                });
+            }
+            GUIMsg::Stats(stats) if self.loading => {
+                self.stats = Some(stats);
+            }
+            GUIMsg::Flush if self.loading => {
+                self.loading = false;
+                // A retry got through before the check noticed the API is back
+                self.offline = false;
+                self.announcer.announce(tr("The response is complete"));
+                self.copy_response();
+            }
+            GUIMsg::SettingsChanged(settings) => {
+                tracing::info!("settings reloaded");
+                i18n::set_language(settings.language.unwrap_or_else(Language::system));
+                self.settings = *settings;
+                self.error = None;
+                if let Err(e) = autostart::apply(&self.settings) {
+                    self.error = Some(format!("{e:#}"));
+                }
+                self.apply_settings();
+                self.apply_backdrop();
+            }
+            GUIMsg::SystemTheme(dark) => {
+                tracing::info!(dark, "system theme changed");
+                self.system_dark = dark;
+            }
+            GUIMsg::SettingsError(e) => {
+                tracing::warn!(error = %e, "could not reload the settings");
+                self.error = Some(e);
+            }
+            _ => (),
+        }
+    }
+
+    /// Reveal the response like a typewriter. New deltas wake the UI up through the channel,
+    /// otherwise frames are only requested at the ticks of the typewriter while it is behind.
+    fn reveal_response(&mut self, ctx: &egui::Context) {
+        let Some(remaining) = self.response.get(self.response_render_len..) else {
+            self.response_render_len = self.response.len();
+            return;
+        };
+        if remaining.is_empty() {
+            self.typewriter_tick = None;
+            return;
+        }
+
+        let now = Instant::now();
+        let last = *self.typewriter_tick.get_or_insert(now);
+        // The typewriter never falls behind the stream by more than a second
+        let speed = TYPEWRITER_SPEED.max(remaining.len() as f32);
+        let chars = ((now - last).as_secs_f32() * speed) as usize;
+        if chars > 0 {
+            self.response_render_len += remaining
+                .char_indices()
+                .nth(chars)
+                .map_or(remaining.len(), |(i, _)| i);
+            self.typewriter_tick = Some(now);
+        }
+        ctx.request_repaint_after(TYPEWRITER_TICK);
+    }
+
+    /// Zoom the user interface with Ctrl+mouse wheel, Ctrl+0 resets it. The zoom is kept in the
+    /// settings.
+    fn apply_zoom(&mut self, ctx: &egui::Context, frame: &eframe::Frame) {
+        let zoom = self.settings.zoom.unwrap_or(1.0);
+        let (zoom_delta, reset) = ctx.input(|inp| {
+            (
+                inp.zoom_delta(),
+                inp.modifiers.ctrl && inp.key_pressed(Key::Num0),
+            )
+        });
+
+        let new_zoom = match reset {
+            true => 1.0,
+            false => (zoom * zoom_delta).clamp(MIN_ZOOM, MAX_ZOOM),
+        };
+        if new_zoom != zoom {
+            self.settings.zoom = Some(new_zoom);
+            if let Err(e) = self.settings.save() {
+                self.error = Some(format!("{e:#}"));
+            }
+        }
 
-use popup_gpt::{chatgpt::ChatGPT, model::CompletionResponse};
+        let native = frame.info().native_pixels_per_point.unwrap_or(1.0);
+        let pixels_per_point = native * new_zoom;
+        if ctx.pixels_per_point() != pixels_per_point {
+            ctx.set_pixels_per_point(pixels_per_point);
+        }
+    }
 
-const IN_FONT: FontId = FontId {
-    size: 16.0,
-    family: FontFamily::Monospace,
-};
+    /// Advance the fade in that `show_window` started
+    fn animate_fade_in(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        let Some((start, target)) = &mut self.fade_in else {
+            return;
+        };
 
-const OUT_FONT: FontId = FontId {
-    size: 16.0,
-    family: FontFamily::Monospace,
-};
+        let t = start.elapsed().as_secs_f32() / self.settings.fade_duration().as_secs_f32();
+        let eased = fade::ease(t);
+        fade::set_opacity(self.window_handle, eased);
 
-// Todo: Either remove the dead code or actually use the full response mode
-#[allow(dead_code)]
-enum GUIMsg {
-    CompletionResponse(CompletionResponse),
-    PartialCompletionResponse(CompletionResponse),
-    Flush,
-}
-unsafe impl Send for GUIMsg {}
+        if self.settings.slide {
+            if target.is_none() {
+                *target = frame.info().window_info.position;
+            }
+            if let Some(target) = *target {
+                let offset = fade::SLIDE_DISTANCE * (1.0 - eased);
+                frame.set_window_pos(target + Vec2::new(0.0, offset));
+            }
+        }
 
-struct App {
-    settings: Settings,
+        if t >= 1.0 {
+            self.fade_in = None;
+        } else {
+            ctx.request_repaint();
+        }
+    }
 
-    // UI State
-    prompt: String,
-    response: String,
-    response_render_len: usize,
-    loading: bool,
-    focus_input: bool,
+    /// The native window of the popup, 0 if it isn't known yet
+    fn window_handle(&mut self) -> u64 {
+        use winapi::um::winuser::GetActiveWindow;
 
-    com: (Sender<GUIMsg>, Receiver<GUIMsg>),
-    hotkey_mgr: HotkeyManager<()>,
-    chatgpt: Arc<RwLock<ChatGPT>>,
+        if self.window_handle == 0 {
+            self.window_handle = unsafe { GetActiveWindow() as u64 };
+        }
+        self.window_handle
+    }
 
-    window_handle: u64,
+    /// Switch between the dark and light visuals as the settings or the system ask for
+    fn apply_theme(&self, ctx: &egui::Context) {
+        let dark = match self.settings.theme.unwrap_or(Theme::Dark) {
+            Theme::Dark => true,
+            Theme::Light => false,
+            Theme::System => self.system_dark,
+        };
+        if ctx.style().visuals.dark_mode != dark {
+            ctx.set_visuals(match dark {
+                true => egui::Visuals::dark(),
+                false => egui::Visuals::light(),
+            });
+        }
+    }
 
-    // Window moving / scaling helpers
-    window_scale_direction: Vec2,
-    window_pointer_offset: Vec2,
-}
+    /// Apply the backdrop from the settings to the window
+    fn apply_backdrop(&mut self) {
+        let window = self.window_handle();
+        if window == 0 {
+            return;
+        }
 
-impl App {
-    fn new(settings: Settings) -> Self {
-        let mut hkm = HotkeyManager::new();
-        hkm.register(VKey::K, &[ModKey::Ctrl, ModKey::Alt], || {})
-            .unwrap();
+        let backdrop = self.settings.backdrop.unwrap_or(Backdrop::None);
+        self.backdrop_active = backdrop::apply(window, backdrop) && backdrop != Backdrop::None;
+        if backdrop != Backdrop::None && !self.backdrop_active {
+            tracing::warn!(?backdrop, "the backdrop is not supported on this system");
+        }
+    }
 
-        let chatgpt = ChatGPT::new(settings.openai_token.clone());
-        let chatgpt = Arc::new(RwLock::new(chatgpt));
+    /// Switch the overlay when its hotkey is pressed. The keys are polled, since the popup has no
+    /// focus while it is an overlay and global hotkeys only arrive while it is hidden.
+    fn poll_overlay_hotkey(&mut self, ctx: &egui::Context) {
+        let Ok(hotkey) = self.settings.overlay_hotkey() else {
+            return;
+        };
 
-        let com = channel();
+        let down = overlay::is_down(&hotkey);
+        if down && !self.overlay_hotkey_down {
+            self.set_overlay(!self.overlay);
+        }
+        self.overlay_hotkey_down = down;
 
-        Self {
-            settings,
-            chatgpt,
-            hotkey_mgr: hkm,
-            com,
-            focus_input: true,
-            loading: false,
-            prompt: String::new(),
-            response: String::new(),
-            response_render_len: 0,
-            window_handle: 0,
-            window_scale_direction: Vec2::ZERO,
-            window_pointer_offset: Vec2::ZERO,
+        if self.overlay || down {
+            ctx.request_repaint_after(OVERLAY_POLL_INTERVAL);
         }
     }
 
+    /// Make the popup a faded overlay that clicks pass through, or a normal window again
+    fn set_overlay(&mut self, overlay: bool) {
+        let window = self.window_handle();
+        if window == 0 {
+            return;
+        }
+
+        tracing::debug!(overlay, "overlay toggled");
+        self.overlay = overlay;
+        let opacity = self.settings.overlay_opacity.unwrap_or(0.6);
+        overlay::set_click_through(window, overlay, opacity);
+    }
+
     fn show_window(&mut self, shown: bool) {
-        use winapi::um::winuser::GetActiveWindow;
         use winapi::um::winuser::{ShowWindow, SW_HIDE, SW_SHOW};
 
-        if self.window_handle == 0 {
-            self.window_handle = unsafe { GetActiveWindow() as u64 };
+        self.window_handle();
+        if !shown && self.overlay {
+            self.set_overlay(false);
         }
 
-        if self.window_handle != 0 {
+        let fade_duration = self.settings.fade_duration();
+        if self.window_handle != 0 && !fade_duration.is_zero() {
+            self.fade_in = None;
+            match shown {
+                false => fade::fade_out(self.window_handle, fade_duration, self.settings.slide),
+                true => {
+                    // The fade continues frame by frame in `update`
+                    fade::set_opacity(self.window_handle, 0.0);
+                    unsafe { ShowWindow(self.window_handle as _, SW_SHOW) };
+                    self.fade_in = Some((Instant::now(), None));
+                }
+            }
+        } else if self.window_handle != 0 {
             let cmd_show = match shown {
                 false => SW_HIDE,
                 true => SW_SHOW,
@@ -114,68 +2994,148 @@ impl eframe::App for App {
     }
 
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        match self.com.1.try_recv() {
-            Ok(GUIMsg::CompletionResponse(resp)) if self.loading => {
-                self.response = resp.primary_response().unwrap().to_string();
-                self.loading = false;
-            }
-            Ok(GUIMsg::PartialCompletionResponse(resp)) if self.loading => {
-                if let Some(delta) = resp
-                    .choices
-                    .first()
-                    .unwrap()
-                    .delta
-                    .as_ref()
-                    .map(|delta| delta.content.as_ref())
-                    .flatten()
-                {
-                    self.response.push_str(delta);
-                    ctx.request_repaint();
-                }
-            }
-            Ok(GUIMsg::Flush) if self.loading => {
-                self.loading = false;
-            }
-            _ => (),
+        if self.start_hidden {
+            // The first frame is painted into the hidden window, which sets up the renderer and
+            // the fonts, so that the popup shows up right away when the hotkey is pressed
+            self.start_hidden = false;
+            self.wait_after_frame = true;
+            frame.set_visible(false);
+            ctx.request_repaint();
+        } else if self.wait_after_frame {
+            self.wait_after_frame = false;
+            self.wait_for_hotkey(Keep::Nothing);
         }
 
-        if self.response_render_len + 1 < self.response.len() {
-            self.response_render_len += 1;
-            while !self.response.is_char_boundary(self.response_render_len) {
-                self.response_render_len += 1;
-            }
-            ctx.request_repaint();
+        self.animate_fade_in(ctx, frame);
+
+        if !self.backdrop_applied {
+            self.backdrop_applied = true;
+            self.apply_backdrop();
+        }
+
+        self.convert_html_paste(ctx);
+        self.check_paste(ctx);
+        self.apply_zoom(ctx, frame);
+        self.poll_overlay_hotkey(ctx);
+
+        while let Ok(msg) = self.messages.try_recv() {
+            self.handle_message(msg);
+        }
+        self.announcer.show(ctx);
+
+        if self.settings_dirty && !self.loading {
+            self.apply_settings();
         }
 
+        self.send_queued();
+
+        self.reveal_response(ctx);
+
+        self.apply_theme(ctx);
+
+        let alpha = self.settings.background_alpha.unwrap_or(230);
+        let [r, g, b] = theme::palette(&ctx.style().visuals).background;
+        // A backdrop blurs the whole window, so the frame covers it and the system rounds the
+        // corners
+        let (margin, rounding, shadow) = match self.backdrop_active {
+            true => (0.0, 0.0, Shadow::NONE),
+            false => {
+                let margin = self.settings.margin.unwrap_or(20.0).max(0.0);
+                let shadow = Shadow {
+                    extrusion: margin.min(16.0),
+                    color: Color32::from_black_alpha(self.settings.shadow.unwrap_or(20)),
+                };
+                (margin, self.settings.corner_radius.unwrap_or(5.0), shadow)
+            }
+        };
+        let border = Stroke::new(
+            self.settings.border_width.unwrap_or(0.0),
+            ctx.style().visuals.widgets.noninteractive.bg_stroke.color,
+        );
+
         egui::CentralPanel::default()
             .frame(Frame {
-                inner_margin: Margin::same(10.0),
-                outer_margin: Margin::same(20.0),
-                fill: Color32::from_rgba_unmultiplied(50, 54, 62, 230),
-                rounding: egui::Rounding::same(5.0),
-                shadow: Shadow::small_light(),
-                ..Default::default()
+                inner_margin: Margin::same(self.settings.padding.unwrap_or(10.0)),
+                outer_margin: Margin::same(margin),
+                fill: Color32::from_rgba_unmultiplied(r, g, b, alpha),
+                rounding: egui::Rounding::same(rounding),
+                shadow,
+                stroke: border,
             })
             .show(ctx, |ui| {
+                egui::TopBottomPanel::bottom("status_bar")
+                    .frame(Frame::none())
+                    .show_inside(ui, |ui| self.show_status_bar(ui));
+
+                if let Some(stats) = self.stats.as_ref().filter(|_| !self.loading) {
+                    egui::TopBottomPanel::bottom("stats")
+                        .frame(Frame::none())
+                        .show_inside(ui, |ui| {
+                            ui.label(RichText::new(stats.summary()).small().color(Color32::GRAY));
+                        });
+                }
+
+                if self.show_scratchpad {
+                    egui::SidePanel::right("scratchpad")
+                        .frame(Frame::none())
+                        .default_width(250.0)
+                        .show_inside(ui, |ui| self.show_scratchpad(ui));
+                }
+
+                if self.history_browser.is_some() {
+                    self.show_history_browser(ui);
+                    return;
+                }
+                if self.keymap_editor.is_some() {
+                    self.show_keymap_editor(ui);
+                    return;
+                }
+
+                self.show_system_prompt_header(ui);
+                self.show_translate_bar(ui);
+                self.show_attachments(ui);
+                self.show_page_fetch(ui);
+                self.show_large_paste(ui);
+
+                let token_count = self.token_count_label();
+                let prompt_size = TextSize::of(&self.prompt);
+
                 let prompt_input = TextEdit::singleline(&mut self.prompt)
                     .font(IN_FONT)
                     .margin(Vec2::new(0.0, 0.0))
-                    .text_color(Color32::from_gray(255))
+                    .text_color(theme::palette(ui.visuals()).input)
                     .lock_focus(true)
                     .frame(false);
 
-                let prompt_input = ui.add_sized(
-                    Vec2 {
-                        y: 20.0,
-                        ..ui.available_size()
-                    },
-                    prompt_input,
-                );
+                let prompt_input = ui
+                    .with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                        if let Some(token_count) = token_count {
+                            ui.label(token_count).on_hover_ui(|ui| {
+                                ui.label(tr("Estimated tokens of the prompt and the conversation"));
+                                ui.label(
+                                    tr("The prompt has {chars} characters, {words} words and \
+                                        ~{tokens} tokens")
+                                    .replace("{chars}", &prompt_size.chars.to_string())
+                                    .replace("{words}", &prompt_size.words.to_string())
+                                    .replace("{tokens}", &format_tokens(prompt_size.tokens)),
+                                );
+                            });
+                        }
+                        ui.add_sized(
+                            Vec2 {
+                                y: 20.0,
+                                ..ui.available_size()
+                            },
+                            prompt_input,
+                        )
+                    })
+                    .inner;
+                accessibility::set_name(&prompt_input, tr("Prompt"));
 
                 if self.focus_input {
                     self.focus_input = false;
 
-                    let mut state = TextEdit::load_state(ctx, prompt_input.id).unwrap();
+                    let mut state = TextEdit::load_state(ctx, prompt_input.id).unwrap_or_default();
                     state.set_ccursor_range(Some(CCursorRange::two(
                         CCursor::new(0),
                         CCursor::new(self.prompt.chars().count()),
@@ -185,88 +3145,251 @@ impl eframe::App for App {
                     prompt_input.request_focus();
                 }
 
+                if self.cursor_to_end {
+                    self.cursor_to_end = false;
+
+                    if let Some(mut state) = TextEdit::load_state(ctx, prompt_input.id) {
+                        let end = CCursor::new(self.prompt.chars().count());
+                        state.set_ccursor_range(Some(CCursorRange::one(end)));
+                        TextEdit::store_state(ctx, prompt_input.id, state);
+                    }
+                }
+
+                self.show_command_popup(ui, &prompt_input);
+                self.show_snippet_popup(ui, &prompt_input);
+                self.show_queue(ui);
+
                 ui.add(Separator::default());
 
-                let mut response = &self.response[..self.response_render_len];
-                let out = TextEdit::multiline(&mut response)
-                    .font(OUT_FONT)
-                    .margin(Vec2::new(0.0, 0.0))
-                    .text_color(Color32::from_rgb(180, 180, 190))
-                    .frame(false);
+                self.show_system_prompt_editor(ui);
+                self.show_key_editor(ui);
+                self.show_quick_actions(ui);
 
-                ScrollArea::new([false, true])
-                    .auto_shrink([false, false])
-                    .stick_to_bottom(true)
-                    .always_show_scroll(true)
-                    .show(ui, |ui| {
-                        ui.add_sized(
-                            Vec2 {
-                                ..ui.available_size()
-                            },
-                            out,
+                if let Some(description) = &self.script_running {
+                    ui.horizontal(|ui| {
+                        ui.spinner();
+                        ui.label(
+                            RichText::new(tr("Running {name}…").replace("{name}", description))
+                                .color(Color32::GRAY),
                         );
                     });
-            });
+                }
+                if self.offline {
+                    ui.colored_label(
+                        Color32::from_rgb(230, 160, 60),
+                        format!(
+                            "📴 {}",
+                            tr("Offline. Prompts are queued until the API can be reached again.")
+                        ),
+                    );
+                }
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::from_rgb(230, 90, 90), format!("⚠ {error}"));
+                }
+                if self.can_retry
+                    && !self.loading
+                    && ui
+                        .small_button(tr("Retry"))
+                        .on_hover_text(tr("Send the failed request again"))
+                        .clicked()
+                {
+                    self.retry_request();
+                }
 
-        ctx.input(|inp| {
-            if inp.key_down(Key::Enter) {
-                if !self.loading {
-                    self.loading = true;
-                    self.response.clear();
-                    self.response_render_len = 0;
-
-                    let prompt = self.prompt.clone();
-                    let chatgpt = Arc::clone(&self.chatgpt);
-                    let (tx_stream, rx_stream) = channel();
-                    let sender = self.com.0.clone();
-                    let ctx = ctx.clone();
-
-                    std::thread::spawn(move || {
-                        let _resp = chatgpt
-                            .write()
-                            .unwrap()
-                            .ask_stream(prompt, tx_stream)
-                            .unwrap();
-                        sender.send(GUIMsg::Flush).unwrap();
+                if self.truncated {
+                    ui.colored_label(
+                        Color32::from_rgb(230, 160, 60),
+                        format!(
+                            "⚠ {}",
+                            tr(
+                                "The response was cut off because it reached the token limit. \
+                                Raise max_tokens or ask the model to continue."
+                            )
+                        ),
+                    );
+                }
+
+                if let Some(categories) = &self.filtered {
+                    let mut warning = format!(
+                        "⚠ {}",
+                        tr("The content filter of the API stopped the response.")
+                    );
+                    if !categories.is_empty() {
+                        warning.push_str(&format!(" ({})", categories.join(", ")));
+                    }
+                    ui.colored_label(Color32::from_rgb(230, 160, 60), warning);
+                }
+
+                for (i, (name, response)) in self.pipeline_steps.iter().enumerate() {
+                    egui::CollapsingHeader::new(
+                        RichText::new(format!("/{name}")).color(Color32::GRAY),
+                    )
+                    .id_source(("pipeline_step", i))
+                    .show(ui, |ui| {
+                        ui.label(RichText::new(response).font(OUT_FONT));
                     });
+                }
 
-                    let sender = self.com.0.clone();
-                    std::thread::spawn(move || {
-                        while let Ok(resp) = rx_stream.recv() {
-                            sender
-                                .send(GUIMsg::PartialCompletionResponse(resp))
-                                .unwrap();
-                            ctx.request_repaint();
+                self.show_tool_calls(ui);
+
+                if self.transcript.is_some() {
+                    self.show_transcript(ui);
+                    return;
+                }
+
+                self.show_pinned(ui);
+
+                if self.settings.top_logprobs.is_some() && !self.logprobs.is_empty() {
+                    show_logprobs(ui, &self.logprobs);
+                }
+
+                let (thinking, response) =
+                    split_thinking(&self.response[..self.response_render_len]);
+                let reasoning = match thinking {
+                    _ if !self.reasoning.is_empty() => Some(self.reasoning.trim()),
+                    thinking => thinking,
+                };
+                if let Some(reasoning) = reasoning {
+                    let title = if self.loading && response.is_empty() {
+                        tr("Thinking…")
+                    } else {
+                        tr("Reasoning")
+                    };
+                    egui::CollapsingHeader::new(RichText::new(title).color(Color32::GRAY))
+                        .id_source("thinking")
+                        .show(ui, |ui| {
+                            ScrollArea::vertical()
+                                .id_source("thinking_scroll")
+                                .max_height(ui.available_height() / 2.0)
+                                .show(ui, |ui| {
+                                    ui.label(
+                                        RichText::new(reasoning)
+                                            .font(OUT_FONT)
+                                            .color(Color32::GRAY),
+                                    );
+                                });
+                        });
+                }
+
+                let response = match self.translating {
+                    true => {
+                        let (detected, translation) = translate::split_detected(response);
+                        if let Some(language) = detected {
+                            ui.label(
+                                RichText::new(
+                                    tr("Translated from {language}")
+                                        .replace("{language}", language),
+                                )
+                                .small()
+                                .color(Color32::GRAY),
+                            );
                         }
-                    });
+                        translation
+                    }
+                    false => response,
+                };
+
+                let wrap_code = self.settings.wrap_code.unwrap_or(true);
+                accessibility::document(ui, tr("Response"), response, |ui| {
+                    self.response_view.show(ui, response, wrap_code)
+                });
+                if let Some(text) = self.response_view.take_scratchpad_text() {
+                    self.add_to_scratchpad(&text);
+                }
+                if let Some((code, extension)) = self.response_view.take_code_to_save() {
+                    if let Err(e) = dialogs::save_code_block(&code, extension) {
+                        self.error = Some(format!("{e:#}"));
+                    }
+                }
+            });
+
+        let dropped_files = ctx.input(|inp| inp.raw.dropped_files.clone());
+        if !dropped_files.is_empty() {
+            self.attach_files(ctx, dropped_files);
+        }
+        if ctx.input(|inp| inp.modifiers.ctrl && inp.key_pressed(Key::V)) {
+            self.paste_image(ctx);
+        }
+
+        ctx.input(|inp| {
+            if inp.key_pressed(Key::Enter)
+                && self.system_prompt_editor.is_none()
+                && self.conversation_prompt_editor.is_none()
+                && self.quick_actions.is_none()
+                && self.history_browser.is_none()
+                && self.keymap_editor.is_none()
+                && self.key_editor.is_none()
+                && !self.transcript.as_ref().is_some_and(Transcript::is_editing)
+            {
+                if !self.loading && !self.offline {
+                    self.submit_prompt();
+                } else if !self.prompt.trim().is_empty() {
+                    let attachments = std::mem::take(&mut self.attachments);
+                    self.queue
+                        .push_back((std::mem::take(&mut self.prompt), attachments));
                 }
             }
 
-            if inp.key_pressed(Key::Escape) {
-                self.show_window(false);
+            let shortcuts: Vec<Shortcut> = Shortcut::ALL
+                .into_iter()
+                .filter(|shortcut| keymap::pressed(inp, &self.settings.shortcut(*shortcut)))
+                .collect();
+            let pressed = |shortcut| shortcuts.contains(&shortcut);
+
+            if pressed(Shortcut::NextProfile) {
+                self.next_profile();
+            }
+
+            if pressed(Shortcut::History) {
+                self.toggle_history_browser();
+            }
 
-                // Wait for hotkey
-                self.hotkey_mgr.handle_hotkey();
+            if pressed(Shortcut::Transcript) {
+                self.toggle_transcript();
+            }
 
+            if pressed(Shortcut::AppendToNotes) && !self.loading && !self.response.is_empty() {
+                self.append_to_notes();
+            }
+            if pressed(Shortcut::NewConversation) && !self.loading {
+                self.new_conversation();
                 self.focus_input = true;
+            }
 
-                // Start a new conversation
-                self.prompt.clear();
-                self.chatgpt.write().unwrap().clear_conversation();
+            if inp.key_pressed(Key::Escape) && self.loading {
+                // Stop the running response and the queued prompts first, the next Esc hides the
+                // window
+                self.cancel.store(true, Ordering::Relaxed);
+                self.queue.clear();
+                self.deny_pending_tool_calls();
+            } else if inp.key_pressed(Key::Escape) {
+                let keep = match self.settings.escape.unwrap_or_default() {
+                    EscapeAction::Quit => {
+                        frame.close();
+                        return;
+                    }
+                    EscapeAction::Hide => Keep::Everything,
+                    EscapeAction::HideKeep => Keep::Conversation,
+                    EscapeAction::HideClear => Keep::Nothing,
+                };
+                self.show_window(false);
+                self.wait_for_hotkey(keep);
+            }
 
-                self.show_window(true);
+            if pressed(Shortcut::InsertResponse) && !self.loading && !self.response.is_empty() {
+                self.pending_insert = Some(self.answer().to_string());
             }
 
-            if inp.modifiers.alt {
+            let window_pos = frame.info().window_info.position;
+            if let Some(pos) = window_pos.filter(|_| inp.modifiers.alt) {
                 let size = frame.info().window_info.size;
-                let pos = frame.info().window_info.position.unwrap();
 
                 // Move Window
                 frame.drag_window();
 
                 // Scale Window
-                if inp.pointer.secondary_pressed() {
-                    let point = inp.pointer.press_origin().unwrap();
+                let press_origin = inp.pointer.press_origin();
+                if let Some(point) = press_origin.filter(|_| inp.pointer.secondary_pressed()) {
                     self.window_scale_direction.x = if point.x > size.x / 2.0 { 1.0 } else { -1.0 };
                     self.window_scale_direction.y = if point.y > size.y / 2.0 { 1.0 } else { -1.0 };
                 }
@@ -304,39 +3427,325 @@ impl eframe::App for App {
                     self.settings.window_size_x = Some(size.x);
                     self.settings.window_size_y = Some(size.y);
 
-                    std::fs::write(
-                        &self.settings.file_location,
-                        serde_json::to_string_pretty(&self.settings).unwrap(),
-                    )
-                    .unwrap();
+                    if let Err(e) = self.settings.save() {
+                        self.error = Some(format!("{e:#}"));
+                    }
                 }
             }
         });
+
+        if let Some(text) = self.pending_insert.take() {
+            self.insert(text);
+        }
+    }
+}
+
+/// A token count in thousands above 1000, e.g. `12.5k`
+fn format_tokens(tokens: usize) -> String {
+    match tokens {
+        0..=999 => tokens.to_string(),
+        _ => format!("{:.1}k", tokens as f32 / 1000.0),
+    }
+}
+
+/// The content of the last user message of the conversation
+fn last_question(chatgpt: &ChatGPT) -> String {
+    chatgpt
+        .conversation()
+        .iter()
+        .rev()
+        .find(|message| matches!(message.role, Role::User))
+        .map(|message| message.content.clone())
+        .unwrap_or_default()
+}
+
+/// Save a question and its response in the history, starting a new history conversation if there
+/// is none yet. Returns the ID of the history conversation, or `None` if nothing was generated.
+fn save_exchange(
+    history: &History,
+    conversation: Option<i64>,
+    chatgpt: &ChatGPT,
+    question: &str,
+    resp: &CompletionResponse,
+) -> Option<anyhow::Result<i64>> {
+    let answer = resp.primary_response()?;
+    let model = chatgpt.model();
+
+    let save = || {
+        let conversation = match conversation {
+            Some(conversation) => conversation,
+            None => history.start_conversation(chatgpt.conversation_system_message(), model)?,
+        };
+        history.add_message(conversation, &Message::user(question), model, None)?;
+        history.add_message(
+            conversation,
+            &Message::assistant(answer),
+            model,
+            resp.usage.as_ref(),
+        )?;
+
+        Ok(conversation)
+    };
+
+    Some(save())
+}
+
+/// Run a complete response through the enabled plugins. The processed response also replaces the
+/// generated one in the conversation.
+fn post_process(
+    chatgpt: &mut ChatGPT,
+    plugins: &Plugins,
+    enabled: impl Fn(&str) -> bool,
+    rules: &[Rule],
+    resp: &mut CompletionResponse,
+) -> anyhow::Result<()> {
+    let Some(message) = resp
+        .choices
+        .first_mut()
+        .and_then(|choice| choice.message.as_mut())
+    else {
+        return Ok(());
+    };
+
+    let content = plugins.post_process(message.content.clone(), enabled)?;
+    message.content = postprocess::apply(rules, content)?;
+    let last = chatgpt.conversation().len().saturating_sub(1);
+    chatgpt.set_message_content(last, &message.content);
+
+    Ok(())
+}
+
+/// Gives the script actions the clipboard and clients with the settings of the active profile
+struct ScriptHost {
+    settings: Settings,
+}
+
+impl script::Host for ScriptHost {
+    fn clipboard_text(&self) -> anyhow::Result<String> {
+        Ok(arboard::Clipboard::new()?.get_text()?)
+    }
+
+    fn ask(&self, prompt: &str, system_prompt: Option<&str>) -> anyhow::Result<String> {
+        let mut chatgpt = ChatGPT::new(String::new());
+        self.settings.configure(&mut chatgpt, None)?;
+        if let Some(system_prompt) = system_prompt {
+            chatgpt.set_system_message(system_prompt);
+        }
+
+        let resp = chatgpt.ask(prompt)?;
+        Ok(resp.primary_response().unwrap_or_default().to_string())
+    }
+}
+
+/// Gives the agent tools access to the clipboard, the local time and the configured web search
+struct AgentHost {
+    search: Option<SearchEngine>,
+}
+
+impl agent::Host for AgentHost {
+    fn clipboard_text(&self) -> anyhow::Result<String> {
+        Ok(arboard::Clipboard::new()?.get_text()?)
+    }
+
+    fn local_time(&self) -> String {
+        chrono::Local::now()
+            .format("%A, %Y-%m-%d %H:%M %:z")
+            .to_string()
+    }
+
+    fn search_engine(&self) -> Option<&SearchEngine> {
+        self.search.as_ref()
+    }
+}
+
+/// Show a tool call of the agent in the popup. If calls must be confirmed, block until the user
+/// allows or denies it, a closed popup denies it.
+fn confirm_tool_call(sender: &UiSender, call: &ToolCall, confirm: bool) -> bool {
+    if !confirm {
+        let _ = sender.send(GUIMsg::ToolCall(call.clone(), None));
+        return true;
     }
+
+    let (answer, answered) = channel();
+    let _ = sender.send(GUIMsg::ToolCall(call.clone(), Some(answer)));
+    answered.recv().unwrap_or(false)
+}
+
+/// Generate a title for a history conversation in the background. Failures are ignored, the
+/// history browser falls back to the first question.
+fn spawn_title_request(
+    chatgpt: ChatGPT,
+    history: Arc<Mutex<History>>,
+    id: i64,
+    question: String,
+    answer: String,
+) {
+    std::thread::spawn(move || {
+        if let Ok(title) = chatgpt.suggest_title(&question, &answer) {
+            if !title.is_empty() {
+                let _ = history.lock().unwrap().set_title(id, &title);
+            }
+        }
+    });
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Settings {
-    #[serde(skip)]
-    file_location: PathBuf,
-    openai_token: String,
-    window_pos_x: Option<f32>,
-    window_pos_y: Option<f32>,
-    window_size_x: Option<f32>,
-    window_size_y: Option<f32>,
+/// Debug view listing every generated token colored by its probability. Hovering a token shows the
+/// most likely alternatives at that position.
+fn show_logprobs(ui: &mut egui::Ui, logprobs: &[TokenLogprob]) {
+    egui::CollapsingHeader::new(tr("Token probabilities")).show(ui, |ui| {
+        ScrollArea::vertical()
+            .id_source("logprobs")
+            .max_height(100.0)
+            .show(ui, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    ui.spacing_mut().item_spacing.x = 0.0;
+
+                    for token in logprobs {
+                        let probability = token.probability();
+                        let color = Color32::from_rgb(
+                            (255.0 * (1.0 - probability)) as u8,
+                            (255.0 * probability) as u8,
+                            120,
+                        );
+
+                        let alternatives = token
+                            .top_logprobs
+                            .iter()
+                            .map(|alt| {
+                                format!("{:?}: {:.2}%", alt.token, alt.probability() * 100.0)
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n");
+
+                        ui.label(
+                            egui::RichText::new(&token.token)
+                                .font(OUT_FONT)
+                                .color(color),
+                        )
+                        .on_hover_text(format!(
+                            "{:?}: {:.2}%\n\n{}",
+                            token.token,
+                            probability * 100.0,
+                            alternatives
+                        ));
+                    }
+                });
+            });
+    });
 }
 
 fn main() {
-    let settings_dir = dirs::config_dir().unwrap().join("popup-gpt");
-    if !settings_dir.exists() {
-        std::fs::create_dir(&settings_dir).unwrap();
+    // Until the settings are loaded, e.g. for usage errors
+    i18n::set_language(Language::system());
+
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            dialogs::show_usage_error(&e);
+            return;
+        }
+    };
+
+    let terminal = cli.command.is_some();
+    if terminal {
+        cli::attach_console();
+    }
+
+    let settings_path = match cli.settings_path() {
+        Ok(path) => path,
+        Err(e) if terminal => {
+            eprintln!("Error: {e:#}");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            dialogs::show_config_path_error(&e);
+            return;
+        }
+    };
+    logging::install_panic_hook(settings_path.with_file_name("logs"), terminal);
+
+    let mut settings = match Settings::load(&settings_path) {
+        Ok(settings) => settings,
+        Err(e) if terminal => {
+            eprintln!("Error: {e:#}");
+            std::process::exit(1);
+        }
+        Err(e) => {
+            dialogs::show_config_error(settings_path, &e);
+            return;
+        }
+    };
+    i18n::set_language(settings.language.unwrap_or_else(Language::system));
+
+    let log_level = settings
+        .log_level
+        .as_deref()
+        .unwrap_or(logging::DEFAULT_LOG_LEVEL);
+    // Without a log file the popup still works, so a failure is only shown in the popup
+    let (_log_guard, log_error) = match logging::init(&settings.log_dir(), log_level) {
+        Ok(guard) => (Some(guard), None),
+        Err(e) => (None, Some(format!("{}: {e:#}", tr("Logging is disabled")))),
+    };
+    tracing::info!(version = env!("CARGO_PKG_VERSION"), "starting");
+    update::clean_up();
+
+    if let Some(profile) = cli.profile {
+        if !settings.profile_names().contains(&profile) {
+            let e = anyhow::anyhow!("There is no profile named {profile:?}");
+            if terminal {
+                eprintln!("Error: {e:#}");
+                std::process::exit(1);
+            }
+            dialogs::show_config_error(settings_path, &e);
+            return;
+        }
+        settings.active_profile = Some(profile);
+    }
+
+    if let Some(Command::Ask {
+        question,
+        no_history,
+        audio,
+        save_audio,
+    }) = cli.command
+    {
+        let asked = match audio {
+            Some(wav) => cli::ask_audio(&settings, question, wav, save_audio, no_history),
+            None => cli::ask(&settings, question, no_history),
+        };
+        if let Err(e) = asked {
+            tracing::error!(error = %e, "ask failed");
+            eprintln!("Error: {e:#}");
+            std::process::exit(1);
+        }
+        return;
     }
-    let settings_path = settings_dir.join("popup-gpt.json");
 
-    let settings = std::fs::read_to_string(&settings_path).unwrap();
-    let mut settings: Settings = serde_json::from_str(&settings).unwrap();
-    settings.file_location = settings_path;
+    // Without the popup hotkey there is no way to show the popup, so ask for another one
+    let hotkeys = loop {
+        match hotkeys::register(&settings) {
+            Ok(hotkeys) => break hotkeys,
+            Err(e) => {
+                let current = settings
+                    .hotkey
+                    .as_deref()
+                    .unwrap_or(hotkeys::DEFAULT_HOTKEY);
+                let Some(hotkey) = dialogs::pick_hotkey(&e, current) else {
+                    return;
+                };
+                settings.hotkey = Some(hotkey);
+                if let Err(e) = settings.save() {
+                    tracing::warn!(error = %e, "could not save the hotkey");
+                }
+            }
+        }
+    };
 
+    let daemon = cli.daemon;
+    let hidden = cli.hidden;
+    if let Err(e) = autostart::apply(&settings) {
+        tracing::warn!(error = %e, "could not apply the start at login");
+    }
     let mut opts = NativeOptions {
         always_on_top: true,
         decorated: false,
@@ -348,12 +3757,9 @@ fn main() {
         ..Default::default()
     };
 
-    match (settings.window_pos_x, settings.window_pos_y) {
-        (Some(x), Some(y)) => {
-            opts.initial_window_pos = Some(Pos2::new(x, y));
-            opts.centered = false;
-        }
-        _ => (),
+    if let (Some(x), Some(y)) = (settings.window_pos_x, settings.window_pos_y) {
+        opts.initial_window_pos = Some(Pos2::new(x, y));
+        opts.centered = false;
     }
     match (settings.window_size_x, settings.window_size_y) {
         (Some(x), Some(y)) => opts.initial_window_size = Some(Vec2::new(x, y)),
@@ -363,7 +3769,14 @@ fn main() {
     eframe::run_native(
         "Popup-GPT",
         opts,
-        Box::new(|_cc| Box::new(App::new(settings))),
+        Box::new(move |cc| {
+            fonts::install_fallbacks(&cc.egui_ctx);
+            let mut app = App::new(settings, hotkeys, daemon, hidden, &cc.egui_ctx);
+            if log_error.is_some() {
+                app.error = log_error;
+            }
+            Box::new(app)
+        }),
     )
     .unwrap();
 }
@@ -0,0 +1,552 @@
+/// A fenced code block in a response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock<'a> {
+    /// The language given after the opening fence, if any
+    pub lang: Option<&'a str>,
+    pub code: &'a str,
+}
+
+/// A part of a response, either regular text or a fenced code block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment<'a> {
+    Text(&'a str),
+    Code(CodeBlock<'a>),
+}
+
+/// Split the text into regular text and fenced code blocks. A block that is not closed yet, e.g.
+/// while the response is still streaming, extends to the end of the text.
+pub fn segments(text: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    // The opening fence, language and start of the code of the open block
+    let mut open: Option<(&str, Option<&str>, usize)> = None;
+    let mut text_start = 0;
+    let mut pos = 0;
+
+    for line in text.split_inclusive('\n') {
+        let trimmed = line.trim();
+        let line_end = pos + line.len();
+
+        match open {
+            None => {
+                if let Some(fence) = fence(trimmed) {
+                    if text_start < pos {
+                        segments.push(Segment::Text(&text[text_start..pos]));
+                    }
+                    let lang = trimmed[fence.len()..].trim();
+                    open = Some((fence, (!lang.is_empty()).then_some(lang), line_end));
+                }
+            }
+            Some((opening, lang, start)) => {
+                if trimmed.starts_with(opening) && trimmed.trim_start_matches(opening).is_empty() {
+                    segments.push(Segment::Code(CodeBlock {
+                        lang,
+                        code: &text[start..pos],
+                    }));
+                    open = None;
+                    text_start = line_end;
+                }
+            }
+        }
+
+        pos = line_end;
+    }
+
+    match open {
+        Some((_, lang, start)) => segments.push(Segment::Code(CodeBlock {
+            lang,
+            code: &text[start..],
+        })),
+        None if text_start < text.len() => segments.push(Segment::Text(&text[text_start..])),
+        None => (),
+    }
+
+    segments
+}
+
+/// The paragraphs of the text, each with the first line break of the empty line after it. Shown
+/// one below the other they look like the whole text.
+pub fn paragraphs(text: &str) -> Vec<&str> {
+    let mut paragraphs = Vec::new();
+    let mut rest = text;
+    while let Some(pos) = rest.find("\n\n") {
+        paragraphs.push(&rest[..pos + 1]);
+        rest = &rest[pos + 2..];
+    }
+    paragraphs.push(rest);
+    paragraphs
+}
+
+/// How the cells of a table column are aligned, set by the colons of the delimiter row
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColumnAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// A pipe table like `| a | b |` with a delimiter row like `|---|--:|` below the header. Every
+/// row has as many cells as the header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Table<'a> {
+    pub header: Vec<&'a str>,
+    pub align: Vec<ColumnAlign>,
+    pub rows: Vec<Vec<&'a str>>,
+}
+
+/// A part of a paragraph, either regular text or a table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextPart<'a> {
+    Text(&'a str),
+    Table(Table<'a>),
+}
+
+/// Split the text of a paragraph into regular text and tables. A table ends at the first line
+/// without a pipe. While a response streams in, the last row may be incomplete and is padded
+/// with empty cells, and a header without its delimiter row stays text.
+pub fn split_tables(text: &str) -> Vec<TextPart<'_>> {
+    let mut parts = Vec::new();
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut text_start = 0;
+    let mut pos = 0;
+    let mut i = 0;
+
+    while i < lines.len() {
+        let header = table_row(lines[i]);
+        let align = lines.get(i + 1).and_then(|line| delimiter_row(line));
+        let (Some(header), Some(mut align)) = (header, align) else {
+            pos += lines[i].len();
+            i += 1;
+            continue;
+        };
+        if align.len() > header.len() {
+            pos += lines[i].len();
+            i += 1;
+            continue;
+        }
+
+        if text_start < pos {
+            parts.push(TextPart::Text(&text[text_start..pos]));
+        }
+        pos += lines[i].len() + lines[i + 1].len();
+        i += 2;
+
+        let mut rows = Vec::new();
+        while let Some(mut row) = lines.get(i).and_then(|line| table_row(line)) {
+            row.resize(header.len(), "");
+            rows.push(row);
+            pos += lines[i].len();
+            i += 1;
+        }
+
+        align.resize(header.len(), ColumnAlign::Left);
+        parts.push(TextPart::Table(Table {
+            header,
+            align,
+            rows,
+        }));
+        text_start = pos;
+    }
+
+    if text_start < text.len() {
+        parts.push(TextPart::Text(&text[text_start..]));
+    }
+
+    parts
+}
+
+/// The trimmed cells of a table row, `None` if the line has no pipe. Escaped pipes `\|` are part
+/// of the cell.
+fn table_row(line: &str) -> Option<Vec<&str>> {
+    let line = line.trim();
+    if !line.contains('|') {
+        return None;
+    }
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = match line.strip_suffix('|') {
+        Some(inner) if !inner.ends_with('\\') => inner,
+        _ => line,
+    };
+
+    let mut cells = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '|' if !escaped => {
+                cells.push(line[start..i].trim());
+                start = i + 1;
+            }
+            _ => escaped = c == '\\' && !escaped,
+        }
+    }
+    cells.push(line[start..].trim());
+
+    Some(cells)
+}
+
+/// The alignments of the columns if the line is the delimiter row of a table, e.g. `|:--|:-:|`
+fn delimiter_row(line: &str) -> Option<Vec<ColumnAlign>> {
+    let line = line.trim();
+    if !line.contains(['|', '-']) {
+        return None;
+    }
+
+    table_row(line)?
+        .into_iter()
+        .map(|cell| {
+            let left = cell.starts_with(':');
+            let right = cell.ends_with(':') && cell.len() > 1;
+            let dashes = cell.trim_matches(':');
+            if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+                return None;
+            }
+            Some(match (left, right) {
+                (true, true) => ColumnAlign::Center,
+                (false, true) => ColumnAlign::Right,
+                _ => ColumnAlign::Left,
+            })
+        })
+        .collect()
+}
+
+impl CodeBlock<'_> {
+    /// The number of lines of code, not counting the newline before the closing fence
+    pub fn line_count(&self) -> usize {
+        self.code.trim_end_matches('\n').lines().count()
+    }
+
+    /// The file extension for the language of the block, `txt` for unknown languages
+    pub fn file_extension(&self) -> &'static str {
+        let lang = self
+            .lang
+            .and_then(|lang| lang.split_whitespace().next())
+            .unwrap_or_default()
+            .to_lowercase();
+
+        match lang.as_str() {
+            "rust" | "rs" => "rs",
+            "python" | "py" => "py",
+            "javascript" | "js" => "js",
+            "typescript" | "ts" => "ts",
+            "jsx" => "jsx",
+            "tsx" => "tsx",
+            "c" => "c",
+            "cpp" | "c++" => "cpp",
+            "csharp" | "c#" | "cs" => "cs",
+            "java" => "java",
+            "kotlin" | "kt" => "kt",
+            "go" | "golang" => "go",
+            "ruby" | "rb" => "rb",
+            "php" => "php",
+            "swift" => "swift",
+            "bash" | "sh" | "shell" | "zsh" => "sh",
+            "powershell" | "ps1" | "pwsh" => "ps1",
+            "batch" | "bat" | "cmd" => "bat",
+            "sql" => "sql",
+            "html" => "html",
+            "css" => "css",
+            "json" => "json",
+            "yaml" | "yml" => "yml",
+            "toml" => "toml",
+            "xml" => "xml",
+            "markdown" | "md" => "md",
+            "lua" => "lua",
+            "haskell" | "hs" => "hs",
+            _ => "txt",
+        }
+    }
+}
+
+/// All fenced code blocks in the text
+pub fn code_blocks(text: &str) -> Vec<CodeBlock<'_>> {
+    segments(text)
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Code(block) => Some(block),
+            Segment::Text(_) => None,
+        })
+        .collect()
+}
+
+/// A code fence for the text, longer than any backtick run in it so the block doesn't end early
+pub fn fence_for(text: &str) -> String {
+    let longest_run = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+
+    "`".repeat(longest_run.max(2) + 1)
+}
+
+/// Quote the text as a Markdown block quote
+pub fn quote(text: &str) -> String {
+    text.trim()
+        .lines()
+        .map(|line| format!("> {line}").trim_end().to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The text without Markdown syntax, for pasting into places that don't render it. Code blocks
+/// keep their content, headings, emphasis and inline code lose their markers and links become
+/// `text (url)`.
+pub fn to_plain_text(text: &str) -> String {
+    let mut out = String::new();
+
+    for segment in segments(text) {
+        match segment {
+            Segment::Code(block) => out.push_str(block.code),
+            Segment::Text(text) => {
+                for line in text.split_inclusive('\n') {
+                    let trimmed = line.trim_start();
+                    let line = match trimmed.trim_start_matches('#') {
+                        rest if rest.len() < trimmed.len() && rest.starts_with(' ') => &rest[1..],
+                        _ => line,
+                    };
+                    out.push_str(&plain_inline(line));
+                }
+            }
+        }
+    }
+
+    out
+}
+
+/// Remove the emphasis and inline code markers and resolve links in a line
+fn plain_inline(line: &str) -> String {
+    let mut line = line.replace("**", "").replace("__", "").replace('`', "");
+
+    // [text](url) becomes text (url)
+    while let Some(start) = line.find('[') {
+        let Some(middle) = line[start..].find("](").map(|i| start + i) else {
+            break;
+        };
+        let Some(end) = line[middle..].find(')').map(|i| middle + i) else {
+            break;
+        };
+        let link = format!("{} ({})", &line[start + 1..middle], &line[middle + 2..end]);
+        line.replace_range(start..=end, &link);
+    }
+
+    line
+}
+
+/// A link in Markdown text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link<'a> {
+    pub text: &'a str,
+    pub url: &'a str,
+}
+
+/// The `[text](url)` links to web pages outside of code blocks, each URL once
+pub fn links(text: &str) -> Vec<Link<'_>> {
+    let mut links: Vec<Link> = Vec::new();
+
+    for segment in segments(text) {
+        let Segment::Text(mut rest) = segment else {
+            continue;
+        };
+        while let Some(start) = rest.find('[') {
+            rest = &rest[start + 1..];
+            let Some((text, after)) = rest.split_once("](") else {
+                break;
+            };
+            let Some((url, after)) = after.split_once(')') else {
+                break;
+            };
+            // Brackets that aren't a link, e.g. `[1] [the docs](url)`, end before the `](`
+            if text.contains(['[', ']', '\n']) {
+                continue;
+            }
+
+            let is_web = url.starts_with("https://") || url.starts_with("http://");
+            if is_web && !links.iter().any(|link| link.url == url) {
+                links.push(Link { text, url });
+            }
+            rest = after;
+        }
+    }
+
+    links
+}
+
+/// Split a response into the reasoning in a leading `<think>` block and the answer. A block that
+/// is not closed yet, e.g. while the model is still thinking, contains the whole rest.
+pub fn split_thinking(text: &str) -> (Option<&str>, &str) {
+    let Some(rest) = text.trim_start().strip_prefix("<think>") else {
+        return (None, text);
+    };
+
+    match rest.split_once("</think>") {
+        Some((reasoning, answer)) => (Some(reasoning.trim()), answer.trim_start()),
+        None => (Some(rest.trim()), ""),
+    }
+}
+
+/// The opening fence at the start of the line, three or more backticks or tildes
+fn fence(line: &str) -> Option<&str> {
+    let fence_char = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = line.len() - line.trim_start_matches(fence_char).len();
+
+    (len >= 3).then(|| &line[..len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fenced_blocks() {
+        let text = "Try this:\n```rust\nfn main() {}\n```\nor\n~~~\necho hi\n~~~\n";
+
+        assert_eq!(
+            code_blocks(text),
+            [
+                CodeBlock {
+                    lang: Some("rust"),
+                    code: "fn main() {}\n",
+                },
+                CodeBlock {
+                    lang: None,
+                    code: "echo hi\n",
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn text_and_code_segments() {
+        let text = "Run\n```sh\nls\n```\ndone";
+
+        assert_eq!(
+            segments(text),
+            [
+                Segment::Text("Run\n"),
+                Segment::Code(CodeBlock {
+                    lang: Some("sh"),
+                    code: "ls\n",
+                }),
+                Segment::Text("done"),
+            ]
+        );
+    }
+
+    #[test]
+    fn paragraphs_keep_the_empty_lines() {
+        assert_eq!(
+            paragraphs("One\ntwo\n\nThree\n\n\nFour"),
+            ["One\ntwo\n", "Three\n", "\nFour"]
+        );
+        assert_eq!(paragraphs("Streaming\n\n"), ["Streaming\n", ""]);
+        assert_eq!(paragraphs(""), [""]);
+    }
+
+    #[test]
+    fn tables_between_text() {
+        let text = "Compare:\n| Name | Size |\n|:-----|-----:|\n| a \\| b | 1 |\n| c |\nDone\n";
+
+        assert_eq!(
+            split_tables(text),
+            [
+                TextPart::Text("Compare:\n"),
+                TextPart::Table(Table {
+                    header: vec!["Name", "Size"],
+                    align: vec![ColumnAlign::Left, ColumnAlign::Right],
+                    rows: vec![vec!["a \\| b", "1"], vec!["c", ""]],
+                }),
+                TextPart::Text("Done\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn streaming_tables() {
+        // The delimiter row hasn't arrived yet
+        assert_eq!(split_tables("| a | b |\n"), [TextPart::Text("| a | b |\n")]);
+        let TextPart::Table(table) = &split_tables("| a | b |\n|-")[0] else {
+            panic!("the partial delimiter row starts a table");
+        };
+        assert!(table.rows.is_empty());
+
+        let parts = split_tables("| a | b |\n|---|:-:|\n| 1 | 2 |\n| 3");
+        let TextPart::Table(table) = &parts[0] else {
+            panic!("no table in {parts:?}");
+        };
+        assert_eq!(table.align, [ColumnAlign::Left, ColumnAlign::Center]);
+        assert_eq!(table.rows, [vec!["1", "2"], vec!["3", ""]]);
+        assert_eq!(parts.len(), 1);
+
+        assert_eq!(split_tables("a | b\n"), [TextPart::Text("a | b\n")]);
+    }
+
+    #[test]
+    fn code_block_extension_and_lines() {
+        let block = |lang, code| CodeBlock { lang, code };
+
+        assert_eq!(block(Some("Rust"), "fn main() {}\n").file_extension(), "rs");
+        assert_eq!(block(Some("python title=x"), "").file_extension(), "py");
+        assert_eq!(block(Some("brainfuck"), "").file_extension(), "txt");
+        assert_eq!(block(None, "").file_extension(), "txt");
+
+        assert_eq!(block(None, "a\nb\n").line_count(), 2);
+        assert_eq!(block(None, "a\n\nb").line_count(), 3);
+        assert_eq!(block(None, "").line_count(), 0);
+    }
+
+    #[test]
+    fn longer_fences_contain_shorter_ones() {
+        let text = "````md\n```\nnested\n```\n````\n";
+        let blocks = code_blocks(text);
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].code, "```\nnested\n```\n");
+    }
+
+    #[test]
+    fn unclosed_block_extends_to_the_end() {
+        let blocks = code_blocks("```py\nprint(1)\npri");
+        assert_eq!(blocks[0].code, "print(1)\npri");
+    }
+
+    #[test]
+    fn plain_text() {
+        let text = "## Setup\n\nRun **`cargo build`**, see [the docs](https://docs.rs).\n```sh\ncargo build\n```\n";
+
+        assert_eq!(
+            to_plain_text(text),
+            "Setup\n\nRun cargo build, see the docs (https://docs.rs).\ncargo build\n"
+        );
+        assert_eq!(quote("a\n\nb\n"), "> a\n>\n> b");
+    }
+
+    #[test]
+    fn web_links() {
+        let text = "See [Rust](https://www.rust-lang.org/) and [1] [the docs](http://a.b), \
+            [Rust again](https://www.rust-lang.org/) or [local](./file).\n```\n[x](https://c.d)\n```";
+
+        assert_eq!(
+            links(text),
+            [
+                Link {
+                    text: "Rust",
+                    url: "https://www.rust-lang.org/"
+                },
+                Link {
+                    text: "the docs",
+                    url: "http://a.b"
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn thinking_is_split_from_the_answer() {
+        assert_eq!(
+            split_thinking("<think>\nThe user greets me.\n</think>\n\nHello!"),
+            (Some("The user greets me."), "Hello!")
+        );
+        assert_eq!(
+            split_thinking("<think>\nStill thinking"),
+            (Some("Still thinking"), "")
+        );
+        assert_eq!(split_thinking("No <think> here"), (None, "No <think> here"));
+    }
+}
@@ -0,0 +1,515 @@
+use std::borrow::Cow;
+
+/// The TeX commands that stand for a single symbol
+const SYMBOLS: &[(&str, &str)] = &[
+    ("alpha", "α"),
+    ("beta", "β"),
+    ("gamma", "γ"),
+    ("delta", "δ"),
+    ("epsilon", "ε"),
+    ("varepsilon", "ε"),
+    ("zeta", "ζ"),
+    ("eta", "η"),
+    ("theta", "θ"),
+    ("vartheta", "ϑ"),
+    ("iota", "ι"),
+    ("kappa", "κ"),
+    ("lambda", "λ"),
+    ("mu", "μ"),
+    ("nu", "ν"),
+    ("xi", "ξ"),
+    ("pi", "π"),
+    ("rho", "ρ"),
+    ("sigma", "σ"),
+    ("tau", "τ"),
+    ("upsilon", "υ"),
+    ("phi", "ϕ"),
+    ("varphi", "φ"),
+    ("chi", "χ"),
+    ("psi", "ψ"),
+    ("omega", "ω"),
+    ("Gamma", "Γ"),
+    ("Delta", "Δ"),
+    ("Theta", "Θ"),
+    ("Lambda", "Λ"),
+    ("Xi", "Ξ"),
+    ("Pi", "Π"),
+    ("Sigma", "Σ"),
+    ("Phi", "Φ"),
+    ("Psi", "Ψ"),
+    ("Omega", "Ω"),
+    ("sum", "∑"),
+    ("prod", "∏"),
+    ("int", "∫"),
+    ("iint", "∬"),
+    ("oint", "∮"),
+    ("partial", "∂"),
+    ("nabla", "∇"),
+    ("infty", "∞"),
+    ("pm", "±"),
+    ("mp", "∓"),
+    ("times", "×"),
+    ("cdot", "·"),
+    ("div", "÷"),
+    ("ast", "∗"),
+    ("circ", "∘"),
+    ("le", "≤"),
+    ("leq", "≤"),
+    ("ge", "≥"),
+    ("geq", "≥"),
+    ("ne", "≠"),
+    ("neq", "≠"),
+    ("approx", "≈"),
+    ("equiv", "≡"),
+    ("sim", "∼"),
+    ("propto", "∝"),
+    ("ll", "≪"),
+    ("gg", "≫"),
+    ("in", "∈"),
+    ("notin", "∉"),
+    ("subset", "⊂"),
+    ("subseteq", "⊆"),
+    ("supset", "⊃"),
+    ("supseteq", "⊇"),
+    ("cup", "∪"),
+    ("cap", "∩"),
+    ("emptyset", "∅"),
+    ("forall", "∀"),
+    ("exists", "∃"),
+    ("neg", "¬"),
+    ("land", "∧"),
+    ("lor", "∨"),
+    ("to", "→"),
+    ("rightarrow", "→"),
+    ("leftarrow", "←"),
+    ("Rightarrow", "⇒"),
+    ("Leftarrow", "⇐"),
+    ("iff", "⟺"),
+    ("implies", "⟹"),
+    ("mapsto", "↦"),
+    ("ldots", "…"),
+    ("cdots", "⋯"),
+    ("dots", "…"),
+    ("langle", "⟨"),
+    ("rangle", "⟩"),
+    ("lfloor", "⌊"),
+    ("rfloor", "⌋"),
+    ("lceil", "⌈"),
+    ("rceil", "⌉"),
+    ("hbar", "ℏ"),
+    ("ell", "ℓ"),
+    ("degree", "°"),
+    ("prime", "′"),
+    ("sin", "sin"),
+    ("cos", "cos"),
+    ("tan", "tan"),
+    ("log", "log"),
+    ("ln", "ln"),
+    ("exp", "exp"),
+    ("lim", "lim"),
+    ("max", "max"),
+    ("min", "min"),
+    ("det", "det"),
+    (",", " "),
+    (";", " "),
+    (":", " "),
+    ("!", ""),
+    (" ", " "),
+    ("quad", "  "),
+    ("qquad", "    "),
+    ("{", "{"),
+    ("}", "}"),
+    ("%", "%"),
+    ("$", "$"),
+    ("&", "&"),
+    ("#", "#"),
+    ("_", "_"),
+    ("|", "‖"),
+];
+
+/// The commands whose argument is shown as it is
+const TEXT_COMMANDS: &[&str] = &[
+    "text",
+    "textrm",
+    "textbf",
+    "textit",
+    "mathrm",
+    "mathbf",
+    "mathit",
+    "mathsf",
+    "mathtt",
+    "operatorname",
+    "boldsymbol",
+];
+
+/// The commands that only change the size of the delimiter after them
+const SIZE_COMMANDS: &[&str] = &[
+    "left", "right", "big", "Big", "bigg", "Bigg", "bigl", "bigr", "Bigl", "Bigr",
+];
+
+const SUPERSCRIPTS: &[(char, char)] = &[
+    ('0', '⁰'),
+    ('1', '¹'),
+    ('2', '²'),
+    ('3', '³'),
+    ('4', '⁴'),
+    ('5', '⁵'),
+    ('6', '⁶'),
+    ('7', '⁷'),
+    ('8', '⁸'),
+    ('9', '⁹'),
+    ('+', '⁺'),
+    ('-', '⁻'),
+    ('=', '⁼'),
+    ('(', '⁽'),
+    (')', '⁾'),
+    ('n', 'ⁿ'),
+    ('i', 'ⁱ'),
+    ('T', 'ᵀ'),
+    ('′', '′'),
+];
+
+const SUBSCRIPTS: &[(char, char)] = &[
+    ('0', '₀'),
+    ('1', '₁'),
+    ('2', '₂'),
+    ('3', '₃'),
+    ('4', '₄'),
+    ('5', '₅'),
+    ('6', '₆'),
+    ('7', '₇'),
+    ('8', '₈'),
+    ('9', '₉'),
+    ('+', '₊'),
+    ('-', '₋'),
+    ('=', '₌'),
+    ('(', '₍'),
+    (')', '₎'),
+    ('a', 'ₐ'),
+    ('e', 'ₑ'),
+    ('i', 'ᵢ'),
+    ('j', 'ⱼ'),
+    ('k', 'ₖ'),
+    ('n', 'ₙ'),
+    ('o', 'ₒ'),
+    ('t', 'ₜ'),
+    ('x', 'ₓ'),
+];
+
+/// Replace the math in Markdown text with a Unicode rendering of it. Inline math in `$...$` or
+/// `\(...\)` stays in the line, display math in `$$...$$` or `\[...\]` gets lines of its own.
+/// Math that is not closed yet, e.g. while the response streams in, stays as it is.
+pub fn render_math(text: &str) -> Cow<'_, str> {
+    if !text.contains(['$', '\\']) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut out = String::new();
+    let mut rest = text;
+    let mut rendered = false;
+
+    while let Some((start, open)) = next_opening(rest) {
+        let body_start = start + open.len();
+        let Some(end) = closing(&rest[body_start..], open).map(|end| body_start + end) else {
+            // A single dollar may be an amount, there can be more math after it
+            if open == "$" {
+                out.push_str(&rest[..body_start]);
+                rest = &rest[body_start..];
+                continue;
+            }
+            break;
+        };
+        let tex = &rest[body_start..end];
+        out.push_str(&rest[..start]);
+        rest = &rest[end + open.len()..];
+        rendered = true;
+
+        match open {
+            "$$" | "\\[" => {
+                if !out.is_empty() && !out.ends_with('\n') {
+                    out.push('\n');
+                }
+                out.push_str("    ");
+                out.push_str(&tex_to_unicode(tex.trim()));
+                out.push('\n');
+                rest = rest.strip_prefix('\n').unwrap_or(rest);
+            }
+            _ => out.push_str(&tex_to_unicode(tex)),
+        }
+    }
+
+    if !rendered {
+        return Cow::Borrowed(text);
+    }
+    out.push_str(rest);
+    Cow::Owned(out)
+}
+
+/// The position and delimiter of the next opening of math
+fn next_opening(text: &str) -> Option<(usize, &'static str)> {
+    let mut search = 0;
+    loop {
+        let i = search + text[search..].find(['$', '\\'])?;
+        let rest = &text[i..];
+        let open = if rest.starts_with("$$") {
+            "$$"
+        } else if rest.starts_with("\\[") {
+            "\\["
+        } else if rest.starts_with("\\(") {
+            "\\("
+        } else if rest.starts_with('$') && !rest[1..].starts_with(char::is_whitespace) {
+            "$"
+        } else {
+            // An escaped character or a dollar followed by a space
+            search = i + rest.chars().next().map_or(1, char::len_utf8);
+            if rest.starts_with('\\') && rest.len() > 1 {
+                search += rest[1..].chars().next().map_or(0, char::len_utf8);
+            }
+            continue;
+        };
+        return Some((i, open));
+    }
+}
+
+/// The end of the math opened with the delimiter. Inline `$` math doesn't span lines and its
+/// closing `$` follows no space and no digit, so amounts like `$5 and $10` stay text.
+fn closing(text: &str, open: &str) -> Option<usize> {
+    match open {
+        "$$" => text.find("$$"),
+        "\\[" => text.find("\\]"),
+        "\\(" => text.find("\\)"),
+        _ => {
+            let line = text.split('\n').next().unwrap_or_default();
+            line.char_indices()
+                .filter(|(_, c)| *c == '$')
+                .map(|(i, _)| i)
+                .find(|&i| {
+                    i > 0
+                        && !line[..i].ends_with([' ', '\\'])
+                        && !line[i + 1..].starts_with(|c: char| c.is_ascii_digit())
+                })
+        }
+    }
+}
+
+/// Render TeX math as Unicode text, e.g. `\frac{a}{b^2}` as `a/b²`. Unknown commands are kept.
+pub fn tex_to_unicode(tex: &str) -> String {
+    let mut parser = Parser {
+        chars: tex.chars().collect(),
+        pos: 0,
+    };
+    parser.render_until(None)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    /// Render up to the closing character, which is consumed, or to the end
+    fn render_until(&mut self, close: Option<char>) -> String {
+        let mut out = String::new();
+
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                c if Some(c) == close => break,
+                '{' => out.push_str(&self.render_until(Some('}'))),
+                '\\' => out.push_str(&self.command()),
+                '^' => {
+                    let arg = self.argument();
+                    out.push_str(&script(&arg, SUPERSCRIPTS, '^'));
+                }
+                '_' => {
+                    let arg = self.argument();
+                    out.push_str(&script(&arg, SUBSCRIPTS, '_'));
+                }
+                '~' => out.push(' '),
+                '&' => out.push(' '),
+                c if c.is_whitespace() => {
+                    if !out.ends_with(' ') && !out.is_empty() {
+                        out.push(' ');
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+
+        out
+    }
+
+    /// The rendered argument of a command or script, a group or a single character or command
+    fn argument(&mut self) -> String {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                self.render_until(Some('}'))
+            }
+            Some('\\') => {
+                self.pos += 1;
+                self.command()
+            }
+            Some(c) => {
+                self.pos += 1;
+                c.to_string()
+            }
+            None => String::new(),
+        }
+    }
+
+    /// Render the command after a backslash
+    fn command(&mut self) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        // A command is a word or a single other character
+        if self.pos == start && self.peek().is_some() {
+            self.pos += 1;
+        }
+        let name: String = self.chars[start..self.pos].iter().collect();
+
+        match name.as_str() {
+            "frac" | "dfrac" | "tfrac" => {
+                let numerator = self.argument();
+                let denominator = self.argument();
+                format!(
+                    "{}/{}",
+                    parenthesize(&numerator),
+                    parenthesize(&denominator)
+                )
+            }
+            "sqrt" => {
+                let root = match self.peek() {
+                    Some('[') => {
+                        self.pos += 1;
+                        self.render_until(Some(']'))
+                    }
+                    _ => String::new(),
+                };
+                let radicand = self.argument();
+                let sign = match root.as_str() {
+                    "" | "2" => "√",
+                    "3" => "∛",
+                    "4" => "∜",
+                    root => {
+                        return format!(
+                            "{}√{}",
+                            script(root, SUPERSCRIPTS, '^'),
+                            parenthesize(&radicand)
+                        )
+                    }
+                };
+                format!("{sign}{}", parenthesize(&radicand))
+            }
+            "mathbb" => self.argument().chars().map(double_struck).collect(),
+            "\\" => "\n".to_string(),
+            name if TEXT_COMMANDS.contains(&name) => self.argument(),
+            name if SIZE_COMMANDS.contains(&name) => match self.argument().as_str() {
+                "." => String::new(),
+                delimiter => delimiter.to_string(),
+            },
+            name => match SYMBOLS.iter().find(|(command, _)| *command == name) {
+                Some((_, symbol)) => symbol.to_string(),
+                None => format!("\\{name}"),
+            },
+        }
+    }
+}
+
+/// The text in super- or subscript characters, or after the marker in parentheses if some of
+/// them have none
+fn script(text: &str, table: &[(char, char)], marker: char) -> String {
+    let mapped: Option<String> = text
+        .chars()
+        .map(|c| table.iter().find(|(from, _)| *from == c).map(|(_, to)| *to))
+        .collect();
+
+    match mapped {
+        Some(mapped) if !mapped.is_empty() => mapped,
+        _ if text.chars().count() == 1 => format!("{marker}{text}"),
+        _ => format!("{marker}({text})"),
+    }
+}
+
+/// Parentheses around the part of a fraction unless it is a single number or symbol
+fn parenthesize(text: &str) -> String {
+    match text.chars().all(char::is_alphanumeric) || text.chars().count() == 1 {
+        true => text.to_string(),
+        false => format!("({text})"),
+    }
+}
+
+/// The double-struck letter for the sets like ℝ and ℕ
+fn double_struck(c: char) -> char {
+    match c {
+        'C' => 'ℂ',
+        'H' => 'ℍ',
+        'N' => 'ℕ',
+        'P' => 'ℙ',
+        'Q' => 'ℚ',
+        'R' => 'ℝ',
+        'Z' => 'ℤ',
+        c => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tex_as_unicode() {
+        assert_eq!(tex_to_unicode(r"x^2 + y_1 = r^{2n}"), "x² + y₁ = r²ⁿ");
+        assert_eq!(tex_to_unicode(r"\frac{a+b}{2}"), "(a+b)/2");
+        assert_eq!(tex_to_unicode(r"\sqrt{x} \le \sqrt[3]{y}"), "√x ≤ ∛y");
+        assert_eq!(
+            tex_to_unicode(r"\sum_{i=1}^{n} i = \frac{n(n+1)}{2}"),
+            "∑ᵢ₌₁ⁿ i = (n(n+1))/2"
+        );
+        assert_eq!(
+            tex_to_unicode(r"\forall x \in \mathbb{R}: e^{i\pi} \ne \text{big}"),
+            "∀ x ∈ ℝ: e^(iπ) ≠ big"
+        );
+        assert_eq!(
+            tex_to_unicode(r"\left( \alpha \right) \unknown"),
+            "( α ) \\unknown"
+        );
+    }
+
+    #[test]
+    fn inline_and_display_math() {
+        assert_eq!(
+            render_math(r"The area is $\pi r^2$ and \(a \cdot b\)."),
+            "The area is π r² and a · b."
+        );
+        assert_eq!(
+            render_math("Solve\n$$x = \\frac{1}{2}$$\nfor x."),
+            "Solve\n    x = 1/2\nfor x."
+        );
+        assert_eq!(
+            render_math("Solve \\[ E = mc^2 \\] now"),
+            "Solve \n    E = mc²\n now"
+        );
+    }
+
+    #[test]
+    fn text_that_is_not_math() {
+        assert_eq!(
+            render_math("It costs $5 or $10 today"),
+            "It costs $5 or $10 today"
+        );
+        assert_eq!(render_math("Use \\n and $ 3"), "Use \\n and $ 3");
+        // Still streaming
+        assert_eq!(render_math("Then $x^"), "Then $x^");
+        assert!(matches!(render_math("no math"), Cow::Borrowed(_)));
+    }
+}
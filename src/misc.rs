@@ -1,62 +1,530 @@
-pub struct SSEStream<T: std::io::Read> {
+use std::{
+    io::{ErrorKind, Read},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, Receiver, RecvTimeoutError},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// A single event received from a server-sent events stream
+///
+/// - https://html.spec.whatwg.org/multipage/server-sent-events.html#event-stream-interpretation
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    /// The event type, `None` for the default `message` type
+    pub event: Option<String>,
+    /// The event payload. Multiple `data:` lines are joined with `\n`.
+    pub data: String,
+    /// The last event ID that was set on the stream
+    pub id: Option<String>,
+    /// The reconnection time in milliseconds, if the server sent one with this event
+    pub retry: Option<u64>,
+}
+
+/// Errors that can end a server-sent events stream
+#[derive(Debug)]
+pub enum SseError {
+    /// Reading from the underlying source failed
+    Io(std::io::Error),
+    /// The source ended in the middle of an event
+    UnexpectedEof,
+    /// A line of the stream was not valid UTF-8
+    InvalidUtf8(std::string::FromUtf8Error),
+    /// The stream was cancelled through its cancel flag
+    Cancelled,
+    /// No data was received for longer than the configured idle timeout
+    IdleTimeout,
+}
+
+impl std::fmt::Display for SseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SseError::Io(e) => write!(f, "Reading the event stream failed: {e}"),
+            SseError::UnexpectedEof => write!(f, "The event stream ended unexpectedly"),
+            SseError::InvalidUtf8(e) => write!(f, "The event stream is not valid UTF-8: {e}"),
+            SseError::Cancelled => write!(f, "The event stream was cancelled"),
+            SseError::IdleTimeout => write!(f, "The event stream stalled and timed out"),
+        }
+    }
+}
+
+impl std::error::Error for SseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SseError::Io(e) => Some(e),
+            SseError::InvalidUtf8(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Parses a byte stream into server-sent events. The parser supports `\r\n`, `\n` and `\r` line
+/// endings, multi-line `data:` fields, the `event:`, `id:` and `retry:` fields as well as comment
+/// lines.
+///
+/// If the source returns `TimedOut` or `WouldBlock` errors, for example a `PollingReader`, the
+/// stream keeps waiting but can be stopped in between through a cancel flag or an idle timeout.
+pub struct SSEStream<T: Read> {
     source: T,
     buf: Vec<u8>,
-    filled: usize,
+    /// The event that is currently being assembled from the received fields
+    pending: SseEvent,
+    /// The data buffer of the pending event, including a trailing `\n` per data line
+    data: String,
+    last_event_id: Option<String>,
+    /// The previous line ended with `\r` at the end of the buffer, so a following `\n` belongs to
+    /// the same line ending
+    skip_lf: bool,
+    started: bool,
+    /// The source is exhausted or failed, no more events will be produced
+    done: bool,
+    cancel: Option<Arc<AtomicBool>>,
+    idle_timeout: Option<Duration>,
+    last_activity: Instant,
 }
 
-impl<T: std::io::Read> SSEStream<T> {
+impl<T: Read> SSEStream<T> {
     pub fn new(source: T) -> Self {
         Self {
             source,
-            buf: vec![0; 1024 * 4],
-            filled: 0,
+            buf: Vec::with_capacity(1024 * 4),
+            pending: SseEvent::default(),
+            data: String::new(),
+            last_event_id: None,
+            skip_lf: false,
+            started: false,
+            done: false,
+            cancel: None,
+            idle_timeout: None,
+            last_activity: Instant::now(),
+        }
+    }
+
+    /// Stop the stream with `SseError::Cancelled` as soon as the flag is set
+    pub fn cancel_flag(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Stop the stream with `SseError::IdleTimeout` if no data arrives for the given duration
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .map(|cancel| cancel.load(Ordering::Relaxed))
+            .unwrap_or(false)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.idle_timeout
+            .map(|timeout| self.last_activity.elapsed() > timeout)
+            .unwrap_or(false)
+    }
+
+    fn fail(&mut self, error: SseError) -> Option<Result<SseEvent, SseError>> {
+        self.done = true;
+        Some(Err(error))
+    }
+
+    /// Take the next complete line from the buffer, without its line ending
+    fn next_line(&mut self) -> Option<Vec<u8>> {
+        if self.skip_lf && !self.buf.is_empty() {
+            if self.buf[0] == b'\n' {
+                self.buf.remove(0);
+            }
+            self.skip_lf = false;
+        }
+
+        let end = self.buf.iter().position(|&b| b == b'\r' || b == b'\n')?;
+
+        let consumed = match (self.buf[end], self.buf.get(end + 1)) {
+            (b'\r', Some(b'\n')) => end + 2,
+            (b'\r', None) => {
+                self.skip_lf = true;
+                end + 1
+            }
+            _ => end + 1,
+        };
+
+        let mut line: Vec<u8> = self.buf.drain(..consumed).collect();
+        line.truncate(end);
+
+        Some(line)
+    }
+
+    /// Interpret a single line, returning an event if the line completed one. Lines are only split
+    /// at ASCII line breaks, so a complete line never ends in the middle of a UTF-8 sequence.
+    fn process_line(&mut self, line: Vec<u8>) -> Result<Option<SseEvent>, SseError> {
+        let line = String::from_utf8(line).map_err(SseError::InvalidUtf8)?;
+        let mut line = line.as_str();
+
+        if !self.started {
+            self.started = true;
+            line = line.strip_prefix('\u{feff}').unwrap_or(line);
+        }
+
+        if line.is_empty() {
+            return Ok(self.dispatch());
         }
+
+        if line.starts_with(':') {
+            // Comment line
+            return Ok(None);
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+
+        match field {
+            "data" => {
+                self.data.push_str(value);
+                self.data.push('\n');
+            }
+            "event" => self.pending.event = Some(value.to_string()),
+            "id" if !value.contains('\0') => self.last_event_id = Some(value.to_string()),
+            "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                self.pending.retry = value.parse().ok();
+            }
+            _ => (),
+        }
+
+        Ok(None)
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let mut event = std::mem::take(&mut self.pending);
+
+        if self.data.is_empty() {
+            return None;
+        }
+
+        self.data.pop();
+        event.data = std::mem::take(&mut self.data);
+        event.id = self.last_event_id.clone();
+
+        Some(event)
     }
 }
 
-impl<T: std::io::Read> Iterator for SSEStream<T> {
-    type Item = String;
+impl<T: Read> Iterator for SSEStream<T> {
+    type Item = Result<SseEvent, SseError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.buf.len() - self.filled < 128 {
-            self.buf.resize_with(self.buf.len() * 2, || 0);
-        }
+        let mut chunk = [0; 1024 * 4];
 
         loop {
-            let bytes_read = self.source.read(&mut self.buf[self.filled..]);
+            while let Some(line) = self.next_line() {
+                match self.process_line(line) {
+                    Ok(Some(event)) => return Some(Ok(event)),
+                    Ok(None) => (),
+                    Err(e) => return self.fail(e),
+                }
+            }
+
+            if self.done {
+                return None;
+            }
+            if self.is_cancelled() {
+                return self.fail(SseError::Cancelled);
+            }
 
-            match bytes_read {
+            match self.source.read(&mut chunk) {
+                Ok(0) => {
+                    self.done = true;
+                    // An incomplete event at the end of the stream is discarded, but reported
+                    if !self.buf.is_empty() || !self.data.is_empty() {
+                        return Some(Err(SseError::UnexpectedEof));
+                    }
+                }
                 Ok(bytes_read) => {
-                    self.filled += bytes_read;
+                    self.last_activity = Instant::now();
+                    self.buf.extend_from_slice(&chunk[..bytes_read]);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock) => {
+                    if self.is_idle() {
+                        return self.fail(SseError::IdleTimeout);
+                    }
+                }
+                Err(e) => return self.fail(SseError::Io(e)),
+            }
+        }
+    }
+}
 
-                    let splitpos = String::from_utf8_lossy(&self.buf).find("\n\n");
+/// Turns a blocking reader into one that can be polled. The source is read on a background thread
+/// and `read` returns a `TimedOut` error if no data arrived within the poll interval, which gives
+/// the caller a chance to give up on a stalled source.
+///
+/// If the reader is dropped while the background thread is blocked, the thread exits as soon as
+/// the source returns. Give sockets a read timeout, e.g. [`crate::http::READ_TIMEOUT`], so that
+/// this also happens when the connection stalls.
+pub struct PollingReader {
+    chunks: Receiver<std::io::Result<Vec<u8>>>,
+    current: Vec<u8>,
+    pos: usize,
+    poll_interval: Duration,
+    done: bool,
+}
 
-                    if let Some(splitpos) = splitpos {
-                        // skip 6 chars for "data: "
-                        let data = &self.buf[6..splitpos];
-                        let data = String::from_utf8_lossy(data).to_string();
+impl PollingReader {
+    pub fn new(mut source: impl Read + Send + 'static, poll_interval: Duration) -> Self {
+        let (tx, rx) = sync_channel(16);
 
-                        if data == "[DONE]" {
-                            return None;
-                        }
+        std::thread::spawn(move || {
+            let mut buf = vec![0; 1024 * 4];
+            loop {
+                let chunk = match source.read(&mut buf) {
+                    Ok(bytes_read) => Ok(buf[..bytes_read].to_vec()),
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => Err(e),
+                };
+                let last = !matches!(&chunk, Ok(chunk) if !chunk.is_empty());
 
-                        // +2 because of "\n\n"
-                        if self.filled > splitpos + 2 {
-                            let filled = self.filled;
-                            self.buf.copy_within(splitpos + 2..filled, 0);
-                        }
-                        self.filled -= splitpos + 2;
+                if tx.send(chunk).is_err() || last {
+                    break;
+                }
+            }
+        });
 
-                        return Some(data);
-                    }
+        Self {
+            chunks: rx,
+            current: Vec::new(),
+            pos: 0,
+            poll_interval,
+            done: false,
+        }
+    }
+}
+
+impl Read for PollingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.current.len() {
+            if self.done {
+                return Ok(0);
+            }
+
+            match self.chunks.recv_timeout(self.poll_interval) {
+                Ok(Ok(chunk)) => {
+                    self.done = chunk.is_empty();
+                    self.current = chunk;
+                    self.pos = 0;
                 }
-                Err(e) => {
-                    eprintln!("{e}");
-                    break;
+                Ok(Err(e)) => {
+                    self.done = true;
+                    return Err(e);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(std::io::Error::new(ErrorKind::TimedOut, "no data received"))
                 }
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
             }
         }
 
-        None
+        let len = buf.len().min(self.current.len() - self.pos);
+        buf[..len].copy_from_slice(&self.current[self.pos..self.pos + len]);
+        self.pos += len;
+
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Vec<SseEvent> {
+        SSEStream::new(input.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn line_endings() {
+        let events = parse("data: a\r\n\r\ndata: b\n\ndata: c\r\rdata: d\n\n");
+        let data: Vec<_> = events.iter().map(|e| e.data.as_str()).collect();
+        assert_eq!(data, ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn multi_line_data_and_fields() {
+        let events = parse(
+            ": keep-alive\n\
+             event: update\n\
+             id: 42\n\
+             retry: 1000\n\
+             data: first\n\
+             data:second\n\
+             data\n\
+             \n\
+             data: next\n\n",
+        );
+
+        assert_eq!(
+            events,
+            [
+                SseEvent {
+                    event: Some("update".to_string()),
+                    data: "first\nsecond\n".to_string(),
+                    id: Some("42".to_string()),
+                    retry: Some(1000),
+                },
+                SseEvent {
+                    event: None,
+                    data: "next".to_string(),
+                    id: Some("42".to_string()),
+                    retry: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn events_without_data_are_dropped() {
+        let events = parse("event: ping\n\ndata: done\n\n");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data, "done");
+    }
+
+    #[test]
+    fn incomplete_event_is_an_error() {
+        let mut stream = SSEStream::new("data: done\n\ndata: incomplete".as_bytes());
+        assert_eq!(stream.next().unwrap().unwrap().data, "done");
+        assert!(matches!(stream.next(), Some(Err(SseError::UnexpectedEof))));
+        assert!(stream.next().is_none());
+    }
+
+    /// A source that never produces any data, like a stalled connection
+    struct StalledReader;
+
+    impl Read for StalledReader {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            std::thread::sleep(Duration::from_millis(5));
+            Err(std::io::Error::new(ErrorKind::TimedOut, "stalled"))
+        }
+    }
+
+    #[test]
+    fn cancel_stalled_stream() {
+        let cancel = Arc::new(AtomicBool::new(false));
+        let mut stream = SSEStream::new(StalledReader).cancel_flag(Arc::clone(&cancel));
+
+        let canceller = {
+            let cancel = Arc::clone(&cancel);
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(20));
+                cancel.store(true, Ordering::Relaxed);
+            })
+        };
+
+        assert!(matches!(stream.next(), Some(Err(SseError::Cancelled))));
+        assert!(stream.next().is_none());
+        canceller.join().unwrap();
+    }
+
+    #[test]
+    fn idle_timeout_stalled_stream() {
+        let mut stream = SSEStream::new(StalledReader).idle_timeout(Duration::from_millis(20));
+        assert!(matches!(stream.next(), Some(Err(SseError::IdleTimeout))));
+    }
+
+    #[test]
+    fn polling_reader_passes_through_data() {
+        let reader = PollingReader::new(
+            "data: a\n\ndata: b\n\n".as_bytes(),
+            Duration::from_millis(10),
+        );
+        let events = SSEStream::new(reader)
+            .map(|event| event.unwrap().data)
+            .collect::<Vec<_>>();
+        assert_eq!(events, ["a", "b"]);
+    }
+
+    /// Tells when it is dropped, i.e. when the thread that reads it has exited
+    struct DropSignal<R> {
+        source: R,
+        dropped: std::sync::mpsc::Sender<()>,
+    }
+
+    impl<R: Read> Read for DropSignal<R> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.source.read(buf)
+        }
+    }
+
+    impl<R> Drop for DropSignal<R> {
+        fn drop(&mut self) {
+            let _ = self.dropped.send(());
+        }
+    }
+
+    #[test]
+    fn polling_reader_thread_exits_on_a_stalled_socket() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let socket = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        // The server accepts but never sends anything
+        let _server = listener.accept().unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+
+        let (dropped, thread_exited) = std::sync::mpsc::channel();
+        let source = DropSignal {
+            source: socket,
+            dropped,
+        };
+        let mut stream = SSEStream::new(PollingReader::new(source, Duration::from_millis(10)))
+            .idle_timeout(Duration::from_millis(20));
+        assert!(stream.next().is_some_and(|event| event.is_err()));
+        drop(stream);
+
+        assert!(thread_exited.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+
+    /// Yields the source in chunks of the given size, regardless of character boundaries
+    struct ChunkedReader<'a> {
+        source: &'a [u8],
+        chunk_size: usize,
+    }
+
+    impl Read for ChunkedReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let len = self.chunk_size.min(buf.len()).min(self.source.len());
+            buf[..len].copy_from_slice(&self.source[..len]);
+            self.source = &self.source[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn multi_byte_characters_split_across_reads() {
+        let input = "data: 🦀 Rust ist großartig\n\ndata: 你好，世界 😀👍🏽\r\n\r\n";
+
+        for chunk_size in 1..=8 {
+            let reader = ChunkedReader {
+                source: input.as_bytes(),
+                chunk_size,
+            };
+            let events = SSEStream::new(reader)
+                .map(|event| event.unwrap().data)
+                .collect::<Vec<_>>();
+
+            assert_eq!(events, ["🦀 Rust ist großartig", "你好，世界 😀👍🏽"]);
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_is_an_error() {
+        let mut stream = SSEStream::new(&b"data: \xf0\x9f\n\n"[..]);
+        assert!(matches!(stream.next(), Some(Err(SseError::InvalidUtf8(_)))));
+        assert!(stream.next().is_none());
     }
 }
@@ -1,22 +1,285 @@
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
 use serde::{Deserialize, Serialize};
 
 pub const DEFAULT_MODEL: &str = "gpt-3.5-turbo";
 
+/// The model that answers spoken questions if the settings don't choose one
+pub const DEFAULT_AUDIO_MODEL: &str = "gpt-4o-audio-preview";
+
+/// The voice of spoken answers if the settings don't choose one
+pub const DEFAULT_VOICE: &str = "alloy";
+
+/// The context windows of the OpenAI models in tokens, by model name prefix
+const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-3.5-turbo", 16_385),
+    ("gpt-3.5-turbo-instruct", 4_096),
+    ("gpt-4", 8_192),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-1106", 128_000),
+    ("gpt-4-0125", 128_000),
+    ("gpt-4o", 128_000),
+    ("gpt-4.1", 1_047_576),
+    ("o1", 200_000),
+    ("o1-mini", 128_000),
+    ("o3", 200_000),
+    ("o4-mini", 200_000),
+];
+
+/// The prices of the OpenAI models in US dollars per million input and output tokens, by model
+/// name prefix
+const PRICES: &[(&str, (f64, f64))] = &[
+    ("gpt-3.5-turbo", (0.5, 1.5)),
+    ("gpt-4", (30.0, 60.0)),
+    ("gpt-4-32k", (60.0, 120.0)),
+    ("gpt-4-turbo", (10.0, 30.0)),
+    ("gpt-4-1106", (10.0, 30.0)),
+    ("gpt-4-0125", (10.0, 30.0)),
+    ("gpt-4o", (2.5, 10.0)),
+    ("gpt-4o-mini", (0.15, 0.6)),
+    ("gpt-4.1", (2.0, 8.0)),
+    ("gpt-4.1-mini", (0.4, 1.6)),
+    ("gpt-4.1-nano", (0.1, 0.4)),
+    ("o1", (15.0, 60.0)),
+    ("o1-mini", (1.1, 4.4)),
+    ("o3", (2.0, 8.0)),
+    ("o3-mini", (1.1, 4.4)),
+    ("o4-mini", (1.1, 4.4)),
+];
+
+/// The context window of a known model in tokens. Deployments with custom names, e.g. on Azure,
+/// are unknown.
+pub fn context_window(model: &str) -> Option<usize> {
+    by_prefix(CONTEXT_WINDOWS, model)
+}
+
+/// What the tokens cost with a known model in US dollars
+pub fn cost(model: &str, prompt_tokens: u64, completion_tokens: u64) -> Option<f64> {
+    let (input, output) = by_prefix(PRICES, model)?;
+    Some((prompt_tokens as f64 * input + completion_tokens as f64 * output) / 1_000_000.0)
+}
+
+fn by_prefix<T: Copy>(table: &[(&str, T)], model: &str) -> Option<T> {
+    // The longest prefix wins, so `gpt-4-32k` isn't taken for `gpt-4`
+    table
+        .iter()
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, value)| *value)
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Role {
     System,
     Assistant,
     User,
+    Tool,
+}
+
+impl std::str::FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "system" => Ok(Role::System),
+            "assistant" => Ok(Role::Assistant),
+            "user" => Ok(Role::User),
+            "tool" => Ok(Role::Tool),
+            _ => bail!("Unknown role {s:?}"),
+        }
+    }
+}
+
+impl Role {
+    /// The name of the role as used by the API
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::Assistant => "assistant",
+            Role::User => "user",
+            Role::Tool => "tool",
+        }
+    }
 }
 
 /// A chat single message than can occur in CompletionRequest or CompletionResponse
 ///
 /// - https://platform.openai.com/docs/guides/chat/response-format
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct Message {
     pub role: Role,
+    /// The message text. Assistant messages that only contain tool calls have `null` content,
+    /// which is represented as an empty string.
+    #[serde(default, deserialize_with = "deserialize_null_string")]
     pub content: String,
+
+    /// The tool calls generated by the model, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_calls: Option<Vec<ToolCall>>,
+
+    /// For messages with the `tool` role, the ID of the tool call this message is the result of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+
+    /// Muted messages stay in the conversation but are not sent to the API
+    #[serde(skip)]
+    pub muted: bool,
+
+    /// Pinned messages are never dropped when the context is trimmed
+    #[serde(skip)]
+    pub pinned: bool,
+
+    /// Images sent with the message as data URLs, for multimodal models
+    #[serde(skip)]
+    pub images: Vec<String>,
+
+    /// Audio sent with the message, for models that understand speech
+    #[serde(skip)]
+    pub input_audio: Option<InputAudio>,
+
+    /// The spoken answer of an audio model. Later requests refer to it by its ID.
+    #[serde(default)]
+    pub audio: Option<MessageAudio>,
+}
+
+/// Audio that is part of a question
+///
+/// - https://platform.openai.com/docs/guides/audio
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct InputAudio {
+    /// The base64 encoded audio
+    pub data: String,
+    /// `wav` or `mp3`
+    pub format: String,
+}
+
+impl InputAudio {
+    pub fn wav(wav: &[u8]) -> Self {
+        Self {
+            data: STANDARD.encode(wav),
+            format: "wav".to_string(),
+        }
+    }
+}
+
+/// The spoken answer of an audio model, or a part of it in streamed responses
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct MessageAudio {
+    #[serde(default)]
+    pub id: String,
+    /// The base64 encoded audio in the requested format
+    #[serde(default)]
+    pub data: String,
+    /// What the model said
+    #[serde(default)]
+    pub transcript: String,
+    /// The Unix time after which the API forgets the audio and later requests can't refer to it
+    pub expires_at: Option<u64>,
+}
+
+impl MessageAudio {
+    /// The audio in the requested format, e.g. a WAV file
+    pub fn decode(&self) -> Result<Vec<u8>> {
+        STANDARD
+            .decode(&self.data)
+            .context("The audio of the response is not valid base64")
+    }
+
+    fn expired(&self) -> bool {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// A part of a message content that mixes text and images
+///
+/// - https://platform.openai.com/docs/guides/vision
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentPart<'a> {
+    Text { text: &'a str },
+    ImageUrl { image_url: ImageUrl<'a> },
+    InputAudio { input_audio: &'a InputAudio },
+}
+
+#[derive(Debug, Serialize)]
+struct ImageUrl<'a> {
+    url: &'a str,
+}
+
+/// An earlier spoken answer, by its ID
+#[derive(Debug, Serialize)]
+struct AudioReference<'a> {
+    id: &'a str,
+}
+
+/// Messages with images or audio send their content as a list of parts, all others as a string
+impl Serialize for Message {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("role", &self.role)?;
+        if self.images.is_empty() && self.input_audio.is_none() {
+            map.serialize_entry("content", &self.content)?;
+        } else {
+            // A spoken question doesn't need any text
+            let text = (!self.content.is_empty() || self.input_audio.is_none()).then_some(
+                ContentPart::Text {
+                    text: &self.content,
+                },
+            );
+            let parts = text
+                .into_iter()
+                .chain(self.images.iter().map(|url| ContentPart::ImageUrl {
+                    image_url: ImageUrl { url },
+                }))
+                .chain(
+                    self.input_audio
+                        .iter()
+                        .map(|input_audio| ContentPart::InputAudio { input_audio }),
+                )
+                .collect::<Vec<_>>();
+            map.serialize_entry("content", &parts)?;
+        }
+        if let Some(audio) = self.audio.as_ref().filter(|audio| !audio.expired()) {
+            map.serialize_entry("audio", &AudioReference { id: &audio.id })?;
+        }
+        if let Some(tool_calls) = &self.tool_calls {
+            map.serialize_entry("tool_calls", tool_calls)?;
+        }
+        if let Some(tool_call_id) = &self.tool_call_id {
+            map.serialize_entry("tool_call_id", tool_call_id)?;
+        }
+        map.end()
+    }
+}
+
+/// A call of a tool that was requested by the model
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionCall,
+}
+
+/// The function name and JSON encoded arguments the model wants to call a function with
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct FunctionCall {
+    pub name: String,
+    pub arguments: String,
+}
+
+fn deserialize_null_string<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<String>::deserialize(deserializer)?.unwrap_or_default())
 }
 
 /// A Chat Completion Request
@@ -56,6 +319,10 @@ pub struct CompletionRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
 
+    /// Options for streamed responses, only allowed together with `stream`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_options: Option<StreamOptions>,
+
     // stop: Option<String>
     /// The maximum number of tokens allowed for the generated answer. By default, the number of
     /// tokens the model can return will be (4096 - prompt tokens).
@@ -77,6 +344,64 @@ pub struct CompletionRequest {
     /// abuse.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub user: Option<String>,
+
+    /// A list of tools the model may call. Currently, only functions are supported as a tool.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<Tool>>,
+
+    /// Whether to return log probabilities of the output tokens or not.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logprobs: Option<bool>,
+
+    /// An integer between 0 and 20 specifying the number of most likely tokens to return at each
+    /// token position. `logprobs` must be set to `true` if this parameter is used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_logprobs: Option<u8>,
+
+    /// The kinds of output to generate, `["text", "audio"]` for a spoken answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modalities: Option<Vec<String>>,
+
+    /// The voice and format of a spoken answer, required with the audio modality
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioOutput>,
+}
+
+/// How a spoken answer sounds and is encoded
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AudioOutput {
+    /// e.g. `alloy`, `echo` or `shimmer`
+    pub voice: String,
+    /// `wav`, `mp3`, `flac`, `opus` or `pcm16`, streamed answers only support `pcm16`
+    pub format: String,
+}
+
+/// Options for streamed responses
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StreamOptions {
+    /// Send the usage of the request in an additional event without choices before `[DONE]`
+    pub include_usage: bool,
+}
+
+/// A tool that the model may call during a completion
+///
+/// - https://platform.openai.com/docs/guides/function-calling
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Tool {
+    /// The type of the tool. Currently, only `function` is supported.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub function: FunctionDefinition,
+}
+
+/// The description of a function that the model can call
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FunctionDefinition {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// The parameters the function accepts, described as a JSON Schema object
+    pub parameters: serde_json::Value,
 }
 
 /// The API Response to a completion Request. This contains the completed chat messages.
@@ -89,6 +414,108 @@ pub struct CompletionResponse {
     pub created: u64,
     pub choices: Vec<Choice>,
     pub usage: Option<Usage>,
+    /// How the content filter of Azure OpenAI rated the prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_filter_results: Option<Vec<PromptFilterResult>>,
+}
+
+/// The content filter results of one prompt of a request to Azure OpenAI
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct PromptFilterResult {
+    #[serde(default)]
+    pub prompt_index: u64,
+    pub content_filter_results: Option<ContentFilterResults>,
+}
+
+/// How the content filter of Azure OpenAI rated a prompt or a response in each category. Only
+/// Azure sends these, a category is missing if it was not checked.
+///
+/// - https://learn.microsoft.com/en-us/azure/ai-services/openai/concepts/content-filter
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ContentFilterResults {
+    pub hate: Option<ContentFilterSeverity>,
+    pub self_harm: Option<ContentFilterSeverity>,
+    pub sexual: Option<ContentFilterSeverity>,
+    pub violence: Option<ContentFilterSeverity>,
+    pub profanity: Option<ContentFilterDetection>,
+    pub jailbreak: Option<ContentFilterDetection>,
+    pub protected_material_text: Option<ContentFilterDetection>,
+    pub protected_material_code: Option<ContentFilterDetection>,
+}
+
+/// A category of the content filter that is rated by severity
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ContentFilterSeverity {
+    #[serde(default)]
+    pub filtered: bool,
+    /// `safe`, `low`, `medium` or `high`
+    pub severity: Option<String>,
+}
+
+/// A category of the content filter that is either detected or not
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ContentFilterDetection {
+    #[serde(default)]
+    pub filtered: bool,
+    #[serde(default)]
+    pub detected: bool,
+}
+
+impl ContentFilterResults {
+    /// The names of the categories that caused the content to be filtered
+    pub fn filtered(&self) -> Vec<&'static str> {
+        let severities = [
+            ("hate", &self.hate),
+            ("self harm", &self.self_harm),
+            ("sexual", &self.sexual),
+            ("violence", &self.violence),
+        ];
+        let detections = [
+            ("profanity", &self.profanity),
+            ("jailbreak", &self.jailbreak),
+            ("protected text", &self.protected_material_text),
+            ("protected code", &self.protected_material_code),
+        ];
+
+        let severities = severities
+            .into_iter()
+            .filter(|(_, result)| result.as_ref().is_some_and(|result| result.filtered));
+        let detections = detections
+            .into_iter()
+            .filter(|(_, result)| result.as_ref().is_some_and(|result| result.filtered));
+        severities
+            .map(|(name, _)| name)
+            .chain(detections.map(|(name, _)| name))
+            .collect()
+    }
+}
+
+/// The payload returned by the API when a request fails
+///
+/// - https://platform.openai.com/docs/guides/error-codes/api-errors
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApiErrorResponse {
+    pub error: ApiError,
+}
+
+/// Details about why a request failed
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApiError {
+    pub message: String,
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub param: Option<String>,
+    pub code: Option<String>,
+    /// Details of Azure OpenAI, e.g. why the content filter rejected the prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub innererror: Option<InnerError>,
+}
+
+/// The details Azure OpenAI adds to an error
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct InnerError {
+    pub code: Option<String>,
+    pub content_filter_result: Option<ContentFilterResults>,
 }
 
 /// A single variant of possible completions. A CompletionResponse can contain multiple different
@@ -98,13 +525,80 @@ pub struct Choice {
     pub index: u64,
     pub message: Option<Message>,
     pub delta: Option<MessageDelta>,
-    pub finish_reason: Option<String>,
+    pub finish_reason: Option<FinishReason>,
+    pub logprobs: Option<ChoiceLogprobs>,
+    /// How the content filter of Azure OpenAI rated the response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_filter_results: Option<ContentFilterResults>,
+}
+
+/// Log probability information for the tokens of a Choice
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ChoiceLogprobs {
+    pub content: Option<Vec<TokenLogprob>>,
+}
+
+/// The log probability of a single output token, together with the most likely alternatives at
+/// that position
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TokenLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub bytes: Option<Vec<u8>>,
+    #[serde(default)]
+    pub top_logprobs: Vec<TopLogprob>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TopLogprob {
+    pub token: String,
+    pub logprob: f64,
+    pub bytes: Option<Vec<u8>>,
+}
+
+/// The reason why the model stopped generating tokens for a Choice
+///
+/// - https://platform.openai.com/docs/api-reference/chat/object
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum FinishReason {
+    /// The model hit a natural stop point or a provided stop sequence
+    Stop,
+    /// The maximum number of tokens specified in the request was reached
+    Length,
+    /// The model called a tool
+    ToolCalls,
+    /// Content was omitted due to a flag from the content filters
+    ContentFilter,
+    /// Any finish reason that is not (yet) known to this client
+    Other(String),
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct MessageDelta {
     pub role: Option<Role>,
     pub content: Option<String>,
+    /// The reasoning of the model, sent separately from the content by some APIs
+    pub reasoning_content: Option<String>,
+    pub tool_calls: Option<Vec<ToolCallDelta>>,
+    pub audio: Option<MessageAudio>,
+}
+
+/// A partial tool call as sent in streamed responses. The `id`, `type` and function name are only
+/// sent with the first delta of a call, the arguments are streamed in fragments.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ToolCallDelta {
+    pub index: u64,
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    pub function: Option<FunctionCallDelta>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FunctionCallDelta {
+    pub name: Option<String>,
+    pub arguments: Option<String>,
 }
 
 /// Token Usage of the associated Request & Response
@@ -115,23 +609,134 @@ pub struct Usage {
     pub total_tokens: u32,
 }
 
-impl Message {
-    pub fn system(msg: impl AsRef<str>) -> Self {
+impl Tool {
+    pub fn function(
+        name: impl AsRef<str>,
+        description: impl AsRef<str>,
+        parameters: serde_json::Value,
+    ) -> Self {
         Self {
-            role: Role::System,
-            content: msg.as_ref().to_string(),
+            kind: "function".to_string(),
+            function: FunctionDefinition {
+                name: name.as_ref().to_string(),
+                description: Some(description.as_ref().to_string()),
+                parameters,
+            },
         }
     }
-    pub fn user(msg: impl AsRef<str>) -> Self {
+}
+
+impl Message {
+    pub fn new(role: Role, msg: impl AsRef<str>) -> Self {
         Self {
-            role: Role::User,
+            role,
             content: msg.as_ref().to_string(),
+            tool_calls: None,
+            tool_call_id: None,
+            muted: false,
+            pinned: false,
+            images: Vec::new(),
+            input_audio: None,
+            audio: None,
+        }
+    }
+
+    /// A rough estimate of the tokens the message uses, about four characters per token plus the
+    /// overhead of the message itself
+    pub fn estimated_tokens(&self) -> usize {
+        self.content.chars().count() / 4 + 4
+    }
+
+    /// The tokens the message uses with the tokenizer of the model, plus the overhead of the
+    /// message itself. Models without a known tokenizer get [`Self::estimated_tokens`].
+    pub fn tokens(&self, model: &str) -> usize {
+        // Models of other providers are often named like `openai/gpt-4o`
+        let model = model.rsplit('/').next().unwrap_or(model);
+        match tiktoken_rs::bpe_for_model(model) {
+            Ok(bpe) => bpe.encode_with_special_tokens(&self.content).len() + 4,
+            Err(_) => self.estimated_tokens(),
         }
     }
+
+    pub fn system(msg: impl AsRef<str>) -> Self {
+        Self::new(Role::System, msg)
+    }
+    pub fn user(msg: impl AsRef<str>) -> Self {
+        Self::new(Role::User, msg)
+    }
     pub fn assistant(msg: impl AsRef<str>) -> Self {
+        Self::new(Role::Assistant, msg)
+    }
+    pub fn tool(tool_call_id: impl AsRef<str>, msg: impl AsRef<str>) -> Self {
         Self {
-            role: Role::Assistant,
-            content: msg.as_ref().to_string(),
+            tool_call_id: Some(tool_call_id.as_ref().to_string()),
+            ..Self::new(Role::Tool, msg)
+        }
+    }
+
+    /// A tool call of the model or the result of one. They are steps on the way to an answer and
+    /// are not saved in the history.
+    pub fn is_tool_exchange(&self) -> bool {
+        matches!(self.role, Role::Tool) || self.tool_calls.is_some()
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.code.as_ref().or(self.kind.as_ref()) {
+            Some(code) => write!(f, "{}: {}", code, self.message)?,
+            None => write!(f, "{}", self.message)?,
+        }
+
+        let filtered = self
+            .innererror
+            .as_ref()
+            .and_then(|inner| inner.content_filter_result.as_ref())
+            .map(|results| results.filtered())
+            .unwrap_or_default();
+        match filtered.is_empty() {
+            true => Ok(()),
+            false => write!(f, " (filtered: {})", filtered.join(", ")),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl TokenLogprob {
+    /// The linear probability of the token between 0 and 1
+    pub fn probability(&self) -> f64 {
+        self.logprob.exp()
+    }
+}
+
+impl TopLogprob {
+    /// The linear probability of the token between 0 and 1
+    pub fn probability(&self) -> f64 {
+        self.logprob.exp()
+    }
+}
+
+impl From<String> for FinishReason {
+    fn from(reason: String) -> Self {
+        match reason.as_str() {
+            "stop" => Self::Stop,
+            "length" => Self::Length,
+            "tool_calls" => Self::ToolCalls,
+            "content_filter" => Self::ContentFilter,
+            _ => Self::Other(reason),
+        }
+    }
+}
+
+impl From<FinishReason> for String {
+    fn from(reason: FinishReason) -> Self {
+        match reason {
+            FinishReason::Stop => "stop".to_string(),
+            FinishReason::Length => "length".to_string(),
+            FinishReason::ToolCalls => "tool_calls".to_string(),
+            FinishReason::ContentFilter => "content_filter".to_string(),
+            FinishReason::Other(reason) => reason,
         }
     }
 }
@@ -140,38 +745,533 @@ impl CompletionResponse {
     pub fn primary_response(&self) -> Option<&str> {
         self.choices
             .first()
-            .map(|it| it.message.as_ref().map(|msg| msg.content.as_str()))
-            .flatten()
+            .and_then(|it| it.message.as_ref().map(|msg| msg.content.as_str()))
+    }
+
+    pub fn finish_reason(&self) -> Option<&FinishReason> {
+        self.choices
+            .first()
+            .and_then(|it| it.finish_reason.as_ref())
+    }
+
+    /// The content filter categories that caused the prompt or the primary response to be
+    /// filtered by Azure OpenAI
+    pub fn filtered_categories(&self) -> Vec<&'static str> {
+        let prompts = self.prompt_filter_results.iter().flatten();
+        let prompts = prompts.filter_map(|prompt| prompt.content_filter_results.as_ref());
+        let response = self
+            .choices
+            .first()
+            .and_then(|choice| choice.content_filter_results.as_ref());
+
+        let mut categories = Vec::new();
+        for results in prompts.chain(response) {
+            for category in results.filtered() {
+                if !categories.contains(&category) {
+                    categories.push(category);
+                }
+            }
+        }
+        categories
     }
 
     pub fn used_tokens(&self) -> Option<u32> {
         self.usage.as_ref().map(|usage| usage.total_tokens)
     }
 
+    /// Merge a partial response from a stream into this response. Merging all stream events in
+    /// order reconstructs the complete response including tool calls and finish reasons.
     pub fn merge_delta(&mut self, other: Self) {
+        if self.id.is_empty() {
+            self.id = other.id;
+            self.object = other.object;
+            self.created = other.created;
+        }
+        if other.usage.is_some() {
+            self.usage = other.usage;
+        }
+        if other.prompt_filter_results.is_some() {
+            self.prompt_filter_results = other.prompt_filter_results;
+        }
+
         for choice in other.choices {
             while self.choices.len() <= choice.index as usize {
-                self.choices.push(Choice::default());
+                let index = self.choices.len() as u64;
+                self.choices.push(Choice {
+                    index,
+                    ..Default::default()
+                });
             }
 
             let own_choice = &mut self.choices[choice.index as usize];
 
             if let Some(delta) = choice.delta {
+                let message = own_choice
+                    .message
+                    .get_or_insert_with(|| Message::assistant(""));
+
                 if let Some(role) = delta.role {
-                    own_choice.message = Some(Message {
-                        role,
-                        content: String::new(),
-                    });
+                    message.role = role;
                 }
                 if let Some(content) = delta.content {
-                    own_choice
-                        .message
-                        .as_mut()
-                        .unwrap()
-                        .content
-                        .push_str(&content);
+                    message.content.push_str(&content);
+                }
+                if let Some(delta) = delta.audio {
+                    let audio = message.audio.get_or_insert_with(Default::default);
+                    if !delta.id.is_empty() {
+                        audio.id = delta.id;
+                    }
+                    audio.data.push_str(&delta.data);
+                    audio.transcript.push_str(&delta.transcript);
+                    audio.expires_at = delta.expires_at.or(audio.expires_at);
+                }
+                for call_delta in delta.tool_calls.into_iter().flatten() {
+                    let calls = message.tool_calls.get_or_insert_with(Vec::new);
+                    while calls.len() <= call_delta.index as usize {
+                        calls.push(ToolCall::default());
+                    }
+
+                    let call = &mut calls[call_delta.index as usize];
+                    if let Some(id) = call_delta.id {
+                        call.id = id;
+                    }
+                    if let Some(kind) = call_delta.kind {
+                        call.kind = kind;
+                    }
+                    if let Some(function) = call_delta.function {
+                        if let Some(name) = function.name {
+                            call.function.name.push_str(&name);
+                        }
+                        if let Some(arguments) = function.arguments {
+                            call.function.arguments.push_str(&arguments);
+                        }
+                    }
+                }
+            }
+
+            if let Some(content) = choice.logprobs.and_then(|logprobs| logprobs.content) {
+                own_choice
+                    .logprobs
+                    .get_or_insert_with(Default::default)
+                    .content
+                    .get_or_insert_with(Vec::new)
+                    .extend(content);
+            }
+
+            // Each chunk rates the text so far, an empty rating must not replace a filtered one
+            if let Some(results) = choice.content_filter_results {
+                let own_filtered = own_choice
+                    .content_filter_results
+                    .as_ref()
+                    .is_some_and(|own| !own.filtered().is_empty());
+                if !own_filtered && results != ContentFilterResults::default() {
+                    own_choice.content_filter_results = Some(results);
                 }
             }
+
+            if choice.finish_reason.is_some() {
+                own_choice.finish_reason = choice.finish_reason;
+            }
+        }
+    }
+}
+
+impl CompletionRequest {
+    pub fn builder(model: impl AsRef<str>) -> CompletionRequestBuilder {
+        CompletionRequestBuilder::new(model)
+    }
+}
+
+/// A fluent builder for CompletionRequest that validates the parameter ranges documented by the
+/// API before a request is sent
+#[derive(Debug, Clone, Default)]
+pub struct CompletionRequestBuilder {
+    req: CompletionRequest,
+}
+
+impl CompletionRequestBuilder {
+    pub fn new(model: impl AsRef<str>) -> Self {
+        Self {
+            req: CompletionRequest {
+                model: model.as_ref().to_string(),
+                ..Default::default()
+            },
         }
     }
+
+    pub fn model(mut self, model: impl AsRef<str>) -> Self {
+        self.req.model = model.as_ref().to_string();
+        self
+    }
+
+    pub fn message(mut self, message: Message) -> Self {
+        self.req.messages.push(message);
+        self
+    }
+
+    pub fn messages(mut self, messages: impl IntoIterator<Item = Message>) -> Self {
+        self.req.messages.extend(messages);
+        self
+    }
+
+    pub fn temperature(mut self, temperature: f32) -> Self {
+        self.req.temperature = Some(temperature);
+        self
+    }
+
+    pub fn top_p(mut self, top_p: f32) -> Self {
+        self.req.top_p = Some(top_p);
+        self
+    }
+
+    pub fn n(mut self, n: u32) -> Self {
+        self.req.n = Some(n);
+        self
+    }
+
+    pub fn stream(mut self, stream: bool) -> Self {
+        self.req.stream = Some(stream);
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: u64) -> Self {
+        self.req.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn presence_penalty(mut self, presence_penalty: f32) -> Self {
+        self.req.presence_penalty = Some(presence_penalty);
+        self
+    }
+
+    pub fn frequency_penalty(mut self, frequency_penalty: f32) -> Self {
+        self.req.frequency_penalty = Some(frequency_penalty);
+        self
+    }
+
+    pub fn user(mut self, user: impl AsRef<str>) -> Self {
+        self.req.user = Some(user.as_ref().to_string());
+        self
+    }
+
+    pub fn tool(mut self, tool: Tool) -> Self {
+        self.req.tools.get_or_insert_with(Vec::new).push(tool);
+        self
+    }
+
+    pub fn tools(mut self, tools: impl IntoIterator<Item = Tool>) -> Self {
+        self.req.tools.get_or_insert_with(Vec::new).extend(tools);
+        self
+    }
+
+    pub fn logprobs(mut self, logprobs: bool) -> Self {
+        self.req.logprobs = Some(logprobs);
+        self
+    }
+
+    pub fn top_logprobs(mut self, top_logprobs: u8) -> Self {
+        self.req.top_logprobs = Some(top_logprobs);
+        self
+    }
+
+    /// Validate the configured parameters and produce the CompletionRequest
+    pub fn build(self) -> Result<CompletionRequest> {
+        let req = self.req;
+
+        if req.model.is_empty() {
+            bail!("model must not be empty");
+        }
+        if req.messages.is_empty() {
+            bail!("at least one message is required");
+        }
+        if let Some(temperature) = req.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                bail!("temperature must be between 0 and 2, got {temperature}");
+            }
+        }
+        if let Some(top_p) = req.top_p {
+            if !(0.0..=1.0).contains(&top_p) {
+                bail!("top_p must be between 0 and 1, got {top_p}");
+            }
+        }
+        if req.n == Some(0) {
+            bail!("n must be at least 1");
+        }
+        if let Some(top_logprobs) = req.top_logprobs {
+            if top_logprobs > 20 {
+                bail!("top_logprobs must be between 0 and 20, got {top_logprobs}");
+            }
+            if req.logprobs != Some(true) {
+                bail!("top_logprobs requires logprobs to be enabled");
+            }
+        }
+        if req.max_tokens == Some(0) {
+            bail!("max_tokens must be at least 1");
+        }
+        if let Some(presence_penalty) = req.presence_penalty {
+            if !(-2.0..=2.0).contains(&presence_penalty) {
+                bail!("presence_penalty must be between -2 and 2, got {presence_penalty}");
+            }
+        }
+        if let Some(frequency_penalty) = req.frequency_penalty {
+            if !(-2.0..=2.0).contains(&frequency_penalty) {
+                bail!("frequency_penalty must be between -2 and 2, got {frequency_penalty}");
+            }
+        }
+
+        Ok(req)
+    }
+}
+
+/// A saved conversation with all its messages
+#[derive(Debug, Clone)]
+pub struct SavedConversation {
+    pub system_prompt: String,
+    pub model: String,
+    pub messages: Vec<Message>,
+}
+
+impl SavedConversation {
+    /// A title made from the start of the first question
+    pub fn fallback_title(&self) -> String {
+        let question = self
+            .messages
+            .iter()
+            .find(|message| matches!(message.role, Role::User))
+            .map(|message| message.content.trim())
+            .unwrap_or_default();
+
+        match question.char_indices().nth(60) {
+            Some((end, _)) => format!("{}…", &question[..end]),
+            None => question.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Merge all events of a recorded stream the same way `request_stream` does
+    fn merge_recorded(stream: &str) -> CompletionResponse {
+        let mut response = CompletionResponse::default();
+
+        stream
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .take_while(|data| *data != "[DONE]")
+            .for_each(|data| response.merge_delta(serde_json::from_str(data).unwrap()));
+
+        response
+    }
+
+    #[test]
+    fn merge_text_stream() {
+        let resp = merge_recorded(include_str!("../tests/fixtures/text_stream.txt"));
+
+        assert_eq!(resp.id, "chatcmpl-7Ab1");
+        assert_eq!(
+            resp.primary_response(),
+            Some("Hello! How can I help you today?")
+        );
+        assert_eq!(resp.finish_reason(), Some(&FinishReason::Stop));
+        assert!(resp.choices[0]
+            .message
+            .as_ref()
+            .unwrap()
+            .tool_calls
+            .is_none());
+    }
+
+    #[test]
+    fn merge_tool_call_stream() {
+        let resp = merge_recorded(include_str!("../tests/fixtures/tool_call_stream.txt"));
+
+        assert_eq!(resp.finish_reason(), Some(&FinishReason::ToolCalls));
+
+        let message = resp.choices[0].message.as_ref().unwrap();
+        assert_eq!(message.content, "");
+
+        let calls = message.tool_calls.as_ref().unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id, "call_abc123");
+        assert_eq!(calls[0].kind, "function");
+        assert_eq!(calls[0].function.name, "get_current_weather");
+        assert_eq!(calls[0].function.arguments, r#"{"location":"Berlin"}"#);
+        assert_eq!(calls[1].id, "call_def456");
+        assert_eq!(calls[1].function.name, "get_time");
+        assert_eq!(calls[1].function.arguments, r#"{"tz":"CET"}"#);
+    }
+
+    #[test]
+    fn merge_length_stream() {
+        let resp = merge_recorded(include_str!("../tests/fixtures/length_stream.txt"));
+
+        assert_eq!(resp.primary_response(), Some("Once upon a time"));
+        assert_eq!(resp.finish_reason(), Some(&FinishReason::Length));
+    }
+
+    #[test]
+    fn merge_azure_filtered_stream() {
+        let resp = merge_recorded(include_str!("../tests/fixtures/azure_filtered_stream.txt"));
+
+        assert_eq!(resp.primary_response(), Some("The battle"));
+        assert_eq!(resp.finish_reason(), Some(&FinishReason::ContentFilter));
+        assert_eq!(resp.filtered_categories(), ["violence"]);
+
+        let prompt = &resp.prompt_filter_results.as_ref().unwrap()[0];
+        let prompt = prompt.content_filter_results.as_ref().unwrap();
+        assert!(prompt.filtered().is_empty());
+        assert_eq!(prompt.jailbreak.as_ref().map(|it| it.detected), Some(false));
+
+        let error: ApiErrorResponse = serde_json::from_str(
+            r#"{"error": {"message": "The prompt was filtered.", "code": "content_filter",
+                "innererror": {"code": "ResponsibleAIPolicyViolation", "content_filter_result":
+                {"hate": {"filtered": true, "severity": "medium"}}}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            error.error.to_string(),
+            "content_filter: The prompt was filtered. (filtered: hate)"
+        );
+    }
+
+    #[test]
+    fn finish_reason_roundtrip() {
+        for (raw, reason) in [
+            ("\"stop\"", FinishReason::Stop),
+            ("\"length\"", FinishReason::Length),
+            ("\"tool_calls\"", FinishReason::ToolCalls),
+            ("\"content_filter\"", FinishReason::ContentFilter),
+            (
+                "\"function_call\"",
+                FinishReason::Other("function_call".to_string()),
+            ),
+        ] {
+            assert_eq!(serde_json::from_str::<FinishReason>(raw).unwrap(), reason);
+            assert_eq!(serde_json::to_string(&reason).unwrap(), raw);
+        }
+    }
+
+    #[test]
+    fn context_windows_by_prefix() {
+        assert_eq!(context_window("gpt-4"), Some(8_192));
+        assert_eq!(context_window("gpt-4-32k-0613"), Some(32_768));
+        assert_eq!(context_window("gpt-4o-mini"), Some(128_000));
+        assert_eq!(context_window("my-azure-deployment"), None);
+        assert_eq!(cost("gpt-4o-mini-2024-07-18", 1_000_000, 0), Some(0.15));
+        assert_eq!(cost("my-azure-deployment", 10, 10), None);
+    }
+
+    #[test]
+    fn images_are_content_parts() {
+        let text = serde_json::to_value(Message::user("Hi")).unwrap();
+        assert_eq!(text, serde_json::json!({"role": "user", "content": "Hi"}));
+
+        let message = Message {
+            images: vec!["data:image/png;base64,AAAA".to_string()],
+            ..Message::user("What is this?")
+        };
+        assert_eq!(
+            serde_json::to_value(message).unwrap(),
+            serde_json::json!({
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "What is this?"},
+                    {"type": "image_url", "image_url": {"url": "data:image/png;base64,AAAA"}},
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn audio_questions_and_answers() {
+        let question = Message {
+            input_audio: Some(InputAudio::wav(b"RIFF")),
+            ..Message::user("")
+        };
+        assert_eq!(
+            serde_json::to_value(question).unwrap(),
+            serde_json::json!({
+                "role": "user",
+                "content": [{"type": "input_audio", "input_audio": {"data": "UklGRg==", "format": "wav"}}],
+            })
+        );
+
+        let mut resp = CompletionResponse::default();
+        for data in ["UklG", "Rg=="] {
+            let event = serde_json::json!({"id": "1", "object": "", "created": 0, "choices": [
+                {"index": 0, "delta": {"audio": {"id": "audio_1", "data": data, "transcript": "Hi"}}}
+            ]});
+            resp.merge_delta(serde_json::from_value(event).unwrap());
+        }
+        let answer = resp.choices[0].message.clone().unwrap();
+        let audio = answer.audio.as_ref().unwrap();
+        assert_eq!(audio.decode().unwrap(), b"RIFF");
+        assert_eq!(audio.transcript, "HiHi");
+        assert_eq!(
+            serde_json::to_value(answer).unwrap()["audio"],
+            serde_json::json!({"id": "audio_1"})
+        );
+    }
+
+    #[test]
+    fn tokens_of_known_and_unknown_models() {
+        let message = Message::user("Hello, world!");
+        assert_eq!(message.tokens("gpt-4o"), 4 + 4);
+        assert_eq!(message.tokens("openai/gpt-4"), 4 + 4);
+        assert_eq!(message.tokens("llama3"), message.estimated_tokens());
+
+        // Far from four characters per token
+        assert!(
+            Message::user("你好，世界").tokens("gpt-4o")
+                > Message::user("你好，世界").estimated_tokens()
+        );
+    }
+
+    #[test]
+    fn builder_validates_ranges() {
+        let builder = || CompletionRequestBuilder::new("gpt-4o").message(Message::user("Hi"));
+        let valid = |build: fn(CompletionRequestBuilder) -> CompletionRequestBuilder| {
+            build(builder()).build().is_ok()
+        };
+
+        assert!(builder().build().is_ok());
+        assert!(CompletionRequestBuilder::new("")
+            .message(Message::user("Hi"))
+            .build()
+            .is_err());
+        assert!(CompletionRequestBuilder::new("gpt-4o").build().is_err());
+
+        assert!(valid(|b| b.temperature(0.0)));
+        assert!(valid(|b| b.temperature(2.0)));
+        assert!(!valid(|b| b.temperature(-0.1)));
+        assert!(!valid(|b| b.temperature(2.1)));
+
+        assert!(valid(|b| b.top_p(0.0)));
+        assert!(valid(|b| b.top_p(1.0)));
+        assert!(!valid(|b| b.top_p(-0.1)));
+        assert!(!valid(|b| b.top_p(1.1)));
+
+        assert!(valid(|b| b.presence_penalty(-2.0)));
+        assert!(valid(|b| b.presence_penalty(2.0)));
+        assert!(!valid(|b| b.presence_penalty(-2.1)));
+        assert!(!valid(|b| b.presence_penalty(2.1)));
+
+        assert!(valid(|b| b.frequency_penalty(-2.0)));
+        assert!(valid(|b| b.frequency_penalty(2.0)));
+        assert!(!valid(|b| b.frequency_penalty(-2.1)));
+        assert!(!valid(|b| b.frequency_penalty(2.1)));
+
+        assert!(valid(|b| b.n(1)));
+        assert!(!valid(|b| b.n(0)));
+        assert!(valid(|b| b.max_tokens(1)));
+        assert!(!valid(|b| b.max_tokens(0)));
+
+        assert!(valid(|b| b.logprobs(true).top_logprobs(0)));
+        assert!(valid(|b| b.logprobs(true).top_logprobs(20)));
+        assert!(!valid(|b| b.logprobs(true).top_logprobs(21)));
+        assert!(!valid(|b| b.top_logprobs(5)));
+        assert!(!valid(|b| b.logprobs(false).top_logprobs(5)));
+    }
 }
@@ -0,0 +1,41 @@
+use std::{io::Write, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+use chrono::{
+    format::{Item, StrftimeItems},
+    Local,
+};
+use popup_gpt::export::note_entry;
+
+/// Append a question and its answer to the notes file. Date placeholders like `%Y-%m-%d` in the
+/// path are replaced with the current date, so there can be a file per day. Returns the file.
+pub fn append(pattern: &str, question: &str, answer: &str) -> Result<PathBuf> {
+    // Formatting an invalid placeholder panics, so the pattern is checked first
+    if StrftimeItems::new(pattern).any(|item| item == Item::Error) {
+        bail!("The notes file {pattern:?} contains an invalid date placeholder");
+    }
+
+    let now = Local::now();
+    let path = PathBuf::from(now.format(pattern).to_string());
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Could not create {}", dir.display()))?;
+    }
+
+    let entry = note_entry(&now.format("%Y-%m-%d %H:%M").to_string(), question, answer);
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Could not open {}", path.display()))?;
+
+    // Keep a blank line between the entry and whatever the file ended with
+    let separator = match file.metadata().map(|meta| meta.len()) {
+        Ok(0) | Err(_) => "",
+        Ok(_) => "\n",
+    };
+    file.write_all(format!("{separator}{entry}").as_bytes())
+        .with_context(|| format!("Could not write {}", path.display()))?;
+
+    Ok(path)
+}
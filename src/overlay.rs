@@ -0,0 +1,46 @@
+use popup_gpt::hotkey::Hotkey;
+use winapi::{
+    shared::windef::HWND,
+    um::winuser::{
+        GetAsyncKeyState, GetWindowLongW, SetForegroundWindow, SetWindowLongW, GWL_EXSTYLE,
+        VK_CONTROL, VK_LWIN, VK_MENU, VK_RWIN, VK_SHIFT, WS_EX_TRANSPARENT,
+    },
+};
+
+use crate::fade;
+
+/// Let clicks pass through the window to the one below and fade it to the opacity, or make it a
+/// normal window again and focus it
+pub fn set_click_through(window: u64, click_through: bool, opacity: f32) {
+    let hwnd = window as HWND;
+    unsafe {
+        let style = GetWindowLongW(hwnd, GWL_EXSTYLE);
+        let style = match click_through {
+            true => style | WS_EX_TRANSPARENT as i32,
+            false => style & !(WS_EX_TRANSPARENT as i32),
+        };
+        SetWindowLongW(hwnd, GWL_EXSTYLE, style);
+    }
+
+    if click_through {
+        fade::set_opacity(window, opacity);
+    } else {
+        fade::set_opacity(window, 1.0);
+        unsafe { SetForegroundWindow(hwnd) };
+    }
+}
+
+fn key_down(key: i32) -> bool {
+    unsafe { GetAsyncKeyState(key) as u16 & 0x8000 != 0 }
+}
+
+/// Whether the keys of the hotkey are held down right now, no matter which window has the focus.
+/// A global hotkey can't be used, since it is only received while the popup waits for it.
+pub fn is_down(hotkey: &Hotkey) -> bool {
+    let modifiers = hotkey.modifiers;
+    (!modifiers.ctrl || key_down(VK_CONTROL))
+        && (!modifiers.alt || key_down(VK_MENU))
+        && (!modifiers.shift || key_down(VK_SHIFT))
+        && (!modifiers.win || key_down(VK_LWIN) || key_down(VK_RWIN))
+        && key_down(hotkey.key as i32)
+}
@@ -0,0 +1,175 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use rhai::{Engine, Scope, AST};
+
+/// The function a plugin defines to transform the outgoing prompt
+const PRE_PROCESS: &str = "pre_process";
+/// The function a plugin defines to transform the incoming response
+const POST_PROCESS: &str = "post_process";
+
+/// The file extension of plugin scripts
+const PLUGIN_EXTENSION: &str = "rhai";
+
+/// Stops scripts that are stuck in a loop instead of hanging the request
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// A Rhai script that transforms prompts and responses. It defines `pre_process(prompt)`,
+/// `post_process(response)` or both, each returning the transformed text, e.g.
+///
+/// ```rhai
+/// fn pre_process(prompt) {
+///     prompt + "\n\nAnswer in German."
+/// }
+/// ```
+pub struct Plugin {
+    /// The file name of the script without the extension
+    pub name: String,
+    ast: AST,
+}
+
+impl Plugin {
+    fn defines(&self, function: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == function && f.params.len() == 1)
+    }
+}
+
+/// All loaded plugins, run in the order of their names
+pub struct Plugins {
+    engine: Engine,
+    plugins: Vec<Plugin>,
+}
+
+impl Default for Plugins {
+    fn default() -> Self {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+
+        Self {
+            engine,
+            plugins: Vec::new(),
+        }
+    }
+}
+
+impl Plugins {
+    /// Load all `.rhai` scripts in the directory. A missing directory means there are no plugins.
+    pub fn load_dir(dir: &Path) -> Result<Self> {
+        let mut plugins = Self::default();
+        if !dir.exists() {
+            return Ok(plugins);
+        }
+
+        let entries =
+            std::fs::read_dir(dir).with_context(|| format!("Could not read {}", dir.display()))?;
+        let mut paths = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == PLUGIN_EXTENSION))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        for path in paths {
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let source = std::fs::read_to_string(&path)
+                .with_context(|| format!("Could not read {}", path.display()))?;
+            plugins.add(name, &source)?;
+        }
+
+        Ok(plugins)
+    }
+
+    /// Compile a plugin from its source
+    pub fn add(&mut self, name: impl Into<String>, source: &str) -> Result<()> {
+        let name = name.into();
+        let ast = self
+            .engine
+            .compile(source)
+            .map_err(|e| anyhow!("The plugin {name} is invalid: {e}"))?;
+
+        self.plugins.push(Plugin { name, ast });
+        Ok(())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.plugins.iter().map(|plugin| plugin.name.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    /// Run the prompt through the `pre_process` function of every plugin for which `enabled`
+    /// returns true
+    pub fn pre_process(&self, prompt: String, enabled: impl Fn(&str) -> bool) -> Result<String> {
+        self.run(PRE_PROCESS, prompt, enabled)
+    }
+
+    /// Run the response through the `post_process` function of every plugin for which `enabled`
+    /// returns true
+    pub fn post_process(&self, response: String, enabled: impl Fn(&str) -> bool) -> Result<String> {
+        self.run(POST_PROCESS, response, enabled)
+    }
+
+    fn run(&self, function: &str, text: String, enabled: impl Fn(&str) -> bool) -> Result<String> {
+        let mut text = text;
+
+        for plugin in &self.plugins {
+            if !enabled(&plugin.name) || !plugin.defines(function) {
+                continue;
+            }
+
+            text = self
+                .engine
+                .call_fn::<String>(&mut Scope::new(), &plugin.ast, function, (text,))
+                .map_err(|e| anyhow!("The plugin {} failed in {function}: {e}", plugin.name))?;
+        }
+
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn processing() {
+        let mut plugins = Plugins::default();
+        plugins
+            .add(
+                "german",
+                r#"fn pre_process(prompt) { prompt + "\nAnswer in German." }"#,
+            )
+            .unwrap();
+        plugins
+            .add(
+                "redact",
+                r#"
+                fn post_process(response) {
+                    response.replace("sk-secret", "[redacted]");
+                    response
+                }
+                "#,
+            )
+            .unwrap();
+
+        let prompt = plugins.pre_process("Hi".into(), |_| true).unwrap();
+        assert_eq!(prompt, "Hi\nAnswer in German.");
+        let prompt = plugins.pre_process("Hi".into(), |name| name != "german");
+        assert_eq!(prompt.unwrap(), "Hi");
+
+        let response = plugins.post_process("Use sk-secret".into(), |_| true);
+        assert_eq!(response.unwrap(), "Use [redacted]");
+
+        assert!(plugins.add("broken", "fn pre_process(").is_err());
+        plugins
+            .add("looping", "fn pre_process(p) { loop {} }")
+            .unwrap();
+        assert!(plugins.pre_process("Hi".into(), |_| true).is_err());
+    }
+}
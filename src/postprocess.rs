@@ -0,0 +1,107 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::markdown::to_plain_text;
+
+/// How a preamble starts, e.g. `Sure! Here's the translation:`
+const PREAMBLE_STARTS: &[&str] = &[
+    "sure",
+    "certainly",
+    "of course",
+    "absolutely",
+    "here's",
+    "here is",
+    "here are",
+];
+
+/// The longest first line that is still taken for a preamble
+const MAX_PREAMBLE_LEN: usize = 120;
+
+/// A change made to every completed response, in the order of the settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    /// Replace every match of the regular expression, `$1` in `with` inserts the first group
+    Replace { pattern: String, with: String },
+    /// Remove the Markdown syntax, see [`to_plain_text`]
+    StripMarkdown,
+    /// Remove a first line like `Sure! Here's the email:` or `Certainly!`
+    StripPreamble,
+}
+
+/// Apply the rules to a response one after the other
+pub fn apply(rules: &[Rule], response: String) -> Result<String> {
+    let mut response = response;
+
+    for rule in rules {
+        response = match rule {
+            Rule::Replace { pattern, with } => {
+                let regex = Regex::new(pattern)
+                    .with_context(|| format!("Invalid post-processing pattern {pattern:?}"))?;
+                regex.replace_all(&response, with.as_str()).into_owned()
+            }
+            Rule::StripMarkdown => to_plain_text(&response),
+            Rule::StripPreamble => strip_preamble(&response).to_string(),
+        };
+    }
+
+    Ok(response)
+}
+
+/// The response without a short first line that only introduces the rest
+fn strip_preamble(response: &str) -> &str {
+    let response = response.trim_start();
+    let Some((first, rest)) = response.split_once('\n') else {
+        return response;
+    };
+
+    let first = first.trim();
+    let lower = first.to_lowercase();
+    let is_preamble = first.len() <= MAX_PREAMBLE_LEN
+        && PREAMBLE_STARTS.iter().any(|start| lower.starts_with(start))
+        && (first.ends_with(':') || PREAMBLE_STARTS.contains(&lower.trim_end_matches(['!', '.'])));
+    match is_preamble {
+        true => rest.trim_start(),
+        false => response,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_in_order() {
+        let rules = [
+            Rule::StripPreamble,
+            Rule::Replace {
+                pattern: r"(\d+) EUR".into(),
+                with: "€$1".into(),
+            },
+            Rule::StripMarkdown,
+        ];
+        assert_eq!(
+            apply(
+                &rules,
+                "Sure! Here's the price:\n\nIt costs **20 EUR**.".into()
+            )
+            .unwrap(),
+            "It costs €20."
+        );
+        assert_eq!(
+            apply(&rules, "Sure, Python is great.\nNext line".into()).unwrap(),
+            "Sure, Python is great.\nNext line"
+        );
+        assert_eq!(
+            apply(&[Rule::StripPreamble], "Certainly!\nHallo".into()).unwrap(),
+            "Hallo"
+        );
+
+        let invalid = [Rule::Replace {
+            pattern: "(".into(),
+            with: String::new(),
+        }];
+        assert!(apply(&invalid, "text".into()).is_err());
+    }
+}
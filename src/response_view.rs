@@ -0,0 +1,417 @@
+use std::{collections::HashSet, ops::Range};
+
+use egui::{
+    text::LayoutJob, text_edit::TextEditOutput, Align, Color32, Frame, Layout, RichText,
+    ScrollArea, TextEdit, Vec2,
+};
+use popup_gpt::{
+    markdown::{
+        links, paragraphs, segments, split_tables, CodeBlock, ColumnAlign, Segment, Table, TextPart,
+    },
+    math::render_math,
+};
+
+use crate::{accessibility, i18n::tr, theme::palette, OUT_FONT};
+
+/// The response with its code blocks set apart and its tables and math rendered. Long code lines wrap by default and each block
+/// can be switched to scroll horizontally instead.
+///
+/// Every part keeps its ID while the response streams in, so a selection survives the updates.
+/// The view stops following the end of the response while text is selected, otherwise the text
+/// would move away under the selection.
+///
+/// The response is shown in paragraphs, so only the last one is laid out again when a delta
+/// arrives. Complete paragraphs are split only once and skipped while they are out of view.
+#[derive(Default)]
+pub struct ResponseView {
+    /// The indices of the code blocks whose wrapping is switched from the default
+    toggled: HashSet<usize>,
+    /// Whether text was being selected in the last frame
+    selecting: bool,
+    /// Text that was sent to the scratchpad from the context menu
+    to_scratchpad: Option<String>,
+    /// A code block the user wants to save to a file, with the file extension of its language
+    to_save: Option<(String, &'static str)>,
+    /// The index of the code block under the pointer in the last frame, which shows its buttons
+    hovered_code: Option<usize>,
+    blocks: Blocks,
+}
+
+/// The complete paragraphs and code blocks at the start of the response
+#[derive(Default)]
+struct Blocks {
+    /// The start of the response the blocks were split from
+    source: String,
+    done: Vec<Block>,
+    /// The heights of the shown blocks including the spacing, at the width of the last frame
+    heights: Vec<f32>,
+    width: f32,
+}
+
+/// A complete paragraph or code block in the source of the blocks
+enum Block {
+    Text(Range<usize>),
+    Code {
+        lang: Option<String>,
+        code: Range<usize>,
+    },
+}
+
+impl Block {
+    fn segment<'a>(&'a self, source: &'a str) -> Segment<'a> {
+        match self {
+            Block::Text(range) => Segment::Text(&source[range.clone()]),
+            Block::Code { lang, code } => Segment::Code(CodeBlock {
+                lang: lang.as_deref(),
+                code: &source[code.clone()],
+            }),
+        }
+    }
+}
+
+impl Blocks {
+    /// Split the part of the response after the complete blocks. Everything but the last
+    /// paragraph or code block is complete, the last one may still grow.
+    fn update(&mut self, response: &str) {
+        // The response was replaced, e.g. by the plugins
+        if !response.starts_with(&self.source) {
+            *self = Self::default();
+        }
+
+        let start = self.source.len();
+        let rest = &response[start..];
+        let segments = segments(rest);
+        let Some((last, complete)) = segments.split_last() else {
+            return;
+        };
+        let range = |part: &str| {
+            let from = start + offset(rest, part);
+            from..from + part.len()
+        };
+
+        for segment in complete {
+            match segment {
+                Segment::Text(text) => self
+                    .done
+                    .extend(paragraphs(text).into_iter().map(|p| Block::Text(range(p)))),
+                Segment::Code(block) => self.done.push(Block::Code {
+                    lang: block.lang.map(str::to_string),
+                    code: range(block.code),
+                }),
+            }
+        }
+
+        let end = match last {
+            Segment::Text(text) => {
+                let paragraphs = paragraphs(text);
+                let (last, complete) = paragraphs.split_last().expect("at least one paragraph");
+                self.done
+                    .extend(complete.iter().map(|p| Block::Text(range(p))));
+                offset(rest, last)
+            }
+            // The start of the line with the opening fence
+            Segment::Code(block) => {
+                let code = offset(rest, block.code);
+                rest[..code.saturating_sub(1)]
+                    .rfind('\n')
+                    .map_or(0, |i| i + 1)
+            }
+        };
+        self.source.push_str(&rest[..end]);
+    }
+}
+
+/// The byte offset of a slice of the text
+fn offset(text: &str, part: &str) -> usize {
+    part.as_ptr() as usize - text.as_ptr() as usize
+}
+
+impl ResponseView {
+    /// Forget the per-block settings, e.g. for a new response
+    pub fn reset(&mut self) {
+        self.toggled.clear();
+        self.blocks = Blocks::default();
+    }
+
+    /// The text the user sent to the scratchpad since the last call
+    pub fn take_scratchpad_text(&mut self) -> Option<String> {
+        self.to_scratchpad.take()
+    }
+
+    /// The code block and its file extension the user wants to save since the last call
+    pub fn take_code_to_save(&mut self) -> Option<(String, &'static str)> {
+        self.to_save.take()
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, response: &str, wrap_code: bool) {
+        self.blocks.update(response);
+
+        ScrollArea::new([false, true])
+            .auto_shrink([false, false])
+            .stick_to_bottom(!self.selecting)
+            .always_show_scroll(true)
+            .show_viewport(ui, |ui, viewport| {
+                let origin = ui.max_rect().top();
+                if ui.available_width() != self.blocks.width {
+                    self.blocks.width = ui.available_width();
+                    self.blocks.heights.clear();
+                }
+
+                let text_color = palette(ui.visuals()).text;
+                let mut selecting = false;
+                let mut code_index = 0;
+                let mut hovered_code = None;
+                let blocks = &mut self.blocks;
+                let done = blocks
+                    .done
+                    .iter()
+                    .map(|block| block.segment(&blocks.source));
+                let last = segments(&response[blocks.source.len()..]);
+                for (i, segment) in done.chain(last).enumerate() {
+                    let top = ui.cursor().top();
+                    let is_code = matches!(segment, Segment::Code(_));
+
+                    if let Some(&height) = blocks.heights.get(i) {
+                        let y = top - origin;
+                        if y + height < viewport.top() || y > viewport.bottom() {
+                            ui.add_space(height);
+                            code_index += is_code as usize;
+                            continue;
+                        }
+                    }
+
+                    match segment {
+                        Segment::Text(text) => {
+                            for (j, part) in split_tables(text).into_iter().enumerate() {
+                                match part {
+                                    TextPart::Text(text) => {
+                                        let text = render_math(text);
+                                        let mut text = text.as_ref();
+                                        let output = TextEdit::multiline(&mut text)
+                                            .id_source(("response", i, j))
+                                            .font(OUT_FONT)
+                                            .margin(Vec2::ZERO)
+                                            .text_color(text_color)
+                                            .desired_rows(1)
+                                            .desired_width(f32::INFINITY)
+                                            .frame(false)
+                                            .show(ui);
+                                        selecting |= is_selecting(&output);
+                                        scratchpad_menu(&mut self.to_scratchpad, &output, text);
+                                    }
+                                    TextPart::Table(table) => show_table(ui, (i, j), &table),
+                                }
+                            }
+                        }
+                        Segment::Code(block) => {
+                            let wrap = wrap_code != self.toggled.contains(&code_index);
+                            let hovered = self.hovered_code == Some(code_index);
+                            let shown = show_code_block(ui, i, &block, wrap, hovered);
+                            if shown.toggled && !self.toggled.remove(&code_index) {
+                                self.toggled.insert(code_index);
+                            }
+                            if shown.save {
+                                let code = block.code.trim_end_matches('\n').to_string();
+                                self.to_save = Some((code, block.file_extension()));
+                            }
+                            if shown.hovered {
+                                hovered_code = Some(code_index);
+                            }
+                            selecting |= is_selecting(&shown.output);
+                            scratchpad_menu(&mut self.to_scratchpad, &shown.output, block.code);
+                            code_index += 1;
+                        }
+                    }
+
+                    // Only the complete blocks keep their height
+                    if i < blocks.done.len() {
+                        let height = ui.cursor().top() - top;
+                        match blocks.heights.get_mut(i) {
+                            Some(known) => *known = height,
+                            None => blocks.heights.push(height),
+                        }
+                    }
+                }
+                self.selecting = selecting;
+                self.hovered_code = hovered_code;
+
+                show_links(ui, response);
+            });
+    }
+}
+
+/// The web pages the response links to, e.g. the sources of a web search, as clickable links
+fn show_links(ui: &mut egui::Ui, response: &str) {
+    let links = links(response);
+    if links.is_empty() {
+        return;
+    }
+
+    ui.add_space(6.0);
+    ui.label(RichText::new(tr("Links")).small().color(Color32::GRAY));
+    for link in links {
+        ui.hyperlink_to(link.text, link.url).on_hover_text(link.url);
+    }
+}
+
+/// A table with its columns aligned as the delimiter row says. Wide tables scroll horizontally.
+fn show_table(ui: &mut egui::Ui, id: (usize, usize), table: &Table) {
+    let text_color = palette(ui.visuals()).text;
+    let cell = |ui: &mut egui::Ui, text: RichText, align: ColumnAlign| {
+        let layout = match align {
+            ColumnAlign::Left => Layout::left_to_right(Align::Center),
+            ColumnAlign::Center => Layout::top_down(Align::Center),
+            ColumnAlign::Right => Layout::right_to_left(Align::Center),
+        };
+        ui.with_layout(layout, |ui| ui.label(text.font(OUT_FONT).color(text_color)));
+    };
+
+    ScrollArea::horizontal()
+        .id_source(("table_scroll", id))
+        .show(ui, |ui| {
+            egui::Grid::new(("table", id))
+                .striped(true)
+                .spacing(Vec2::new(16.0, 4.0))
+                .show(ui, |ui| {
+                    for (text, align) in table.header.iter().zip(&table.align) {
+                        cell(ui, RichText::new(render_math(text)).strong(), *align);
+                    }
+                    ui.end_row();
+
+                    for row in &table.rows {
+                        for (text, align) in row.iter().zip(&table.align) {
+                            cell(ui, RichText::new(render_math(text)), *align);
+                        }
+                        ui.end_row();
+                    }
+                });
+        });
+    ui.add_space(6.0);
+}
+
+/// A context menu that sends the selected text, or all of it, to the scratchpad
+fn scratchpad_menu(to_scratchpad: &mut Option<String>, output: &TextEditOutput, text: &str) {
+    output.response.clone().context_menu(|ui| {
+        if ui.button(tr("Send to scratchpad")).clicked() {
+            let selected = output
+                .state
+                .ccursor_range()
+                .filter(|range| range.primary.index != range.secondary.index)
+                .map(|range| {
+                    let [start, end] = range.sorted();
+                    text.chars()
+                        .skip(start.index)
+                        .take(end.index - start.index)
+                        .collect()
+                });
+            *to_scratchpad = Some(selected.unwrap_or_else(|| text.to_string()));
+            ui.close_menu();
+        }
+    });
+}
+
+/// Whether the text is being selected or has a selection
+fn is_selecting(output: &TextEditOutput) -> bool {
+    output.response.is_pointer_button_down_on()
+        || (output.response.has_focus()
+            && output.cursor_range.is_some_and(|range| !range.is_empty()))
+}
+
+/// What happened to a code block in this frame
+struct ShownCodeBlock {
+    output: TextEditOutput,
+    /// The wrap toggle was clicked
+    toggled: bool,
+    /// The save button was clicked
+    save: bool,
+    /// The pointer is over the block
+    hovered: bool,
+}
+
+/// A code block in a darker frame, with its language and length in the header. The copy and save
+/// buttons are only shown while the block is hovered, so they don't cover the code.
+fn show_code_block(
+    ui: &mut egui::Ui,
+    id: usize,
+    block: &CodeBlock,
+    wrap: bool,
+    hovered: bool,
+) -> ShownCodeBlock {
+    let mut toggled = false;
+    let mut save = false;
+    let palette = palette(ui.visuals());
+    let code = block.code.trim_end_matches('\n');
+
+    let frame = Frame::none()
+        .fill(palette.code_background)
+        .rounding(3.0)
+        .inner_margin(6.0)
+        .show(ui, |ui| {
+            ui.horizontal(|ui| {
+                let lines = match block.line_count() {
+                    1 => tr("1 line").to_string(),
+                    count => tr("{count} lines").replace("{count}", &count.to_string()),
+                };
+                let header = match block.lang {
+                    Some(lang) => format!("{lang} · {lines}"),
+                    None => lines,
+                };
+                ui.label(RichText::new(header).small().color(Color32::GRAY));
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    let hint = match wrap {
+                        true => tr("Scroll long lines instead of wrapping them"),
+                        false => tr("Wrap long lines"),
+                    };
+                    let toggle = ui
+                        .selectable_label(wrap, RichText::new("⮨").small())
+                        .on_hover_text(hint);
+                    accessibility::set_name(&toggle, hint);
+                    toggled = toggle.clicked();
+
+                    if hovered {
+                        let hint = tr("Save the code to a file");
+                        let button = ui.small_button("💾").on_hover_text(hint);
+                        accessibility::set_name(&button, hint);
+                        save = button.clicked();
+
+                        let hint = tr("Copy the code");
+                        let button = ui.small_button("📋").on_hover_text(hint);
+                        accessibility::set_name(&button, hint);
+                        if button.clicked() {
+                            ui.output_mut(|o| o.copied_text = code.to_string());
+                        }
+                    }
+                });
+            });
+
+            let mut code = code;
+            let mut layouter = |ui: &egui::Ui, text: &str, wrap_width: f32| {
+                let wrap_width = if wrap { wrap_width } else { f32::INFINITY };
+                let job = LayoutJob::simple(text.to_string(), OUT_FONT, palette.text, wrap_width);
+                ui.fonts(|fonts| fonts.layout_job(job))
+            };
+            let code_edit = TextEdit::multiline(&mut code)
+                .id_source(("response", id))
+                .margin(Vec2::ZERO)
+                .desired_rows(1)
+                .desired_width(f32::INFINITY)
+                .frame(false)
+                .layouter(&mut layouter);
+
+            if wrap {
+                code_edit.show(ui)
+            } else {
+                ScrollArea::horizontal()
+                    .id_source(("code_scroll", id))
+                    .show(ui, |ui| code_edit.show(ui))
+                    .inner
+            }
+        });
+
+    ShownCodeBlock {
+        hovered: ui.rect_contains_pointer(frame.response.rect),
+        output: frame.inner,
+        toggled,
+        save,
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Context, Result};
+use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST};
+
+/// The function a script action defines, called with the text the action runs on
+const RUN: &str = "run";
+
+/// The file extension of script actions
+const SCRIPT_EXTENSION: &str = "rhai";
+
+/// Stops scripts that are stuck in a loop. Waiting for `ask` doesn't count.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// What scripts can use from the application
+pub trait Host: Send + Sync {
+    fn clipboard_text(&self) -> Result<String>;
+    /// Answer the prompt in a conversation of its own with the active profile, with the system
+    /// prompt of the profile if none is given
+    fn ask(&self, prompt: &str, system_prompt: Option<&str>) -> Result<String>;
+}
+
+/// What a script wants done with its results, in the order it asked for them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    /// Copy the text to the clipboard
+    Copy(String),
+    /// Paste the text into the window that was active before the popup
+    Insert(String),
+    /// Show the text as the response in the popup
+    Show(String),
+}
+
+/// A Rhai script that is a quick action. It defines `run(text)`, which gets the clipboard text,
+/// and uses `ask`, `copy`, `insert` and `show`. The first line is the description in the palette
+/// if it is a comment, e.g.
+///
+/// ```rhai
+/// // Explain the clipboard in simple words
+/// fn run(text) {
+///     let answer = ask("Explain this in simple words:\n\n" + text);
+///     copy(answer);
+///     show(answer);
+/// }
+/// ```
+///
+/// `ask(prompt, system_prompt)` sets the system prompt and `clipboard()` reads the clipboard
+/// again.
+pub struct ScriptAction {
+    /// The file name of the script without the extension
+    pub name: String,
+    pub description: String,
+    ast: AST,
+}
+
+/// All script actions, in the order of their names
+pub struct ScriptActions {
+    engine: Engine,
+    actions: Vec<ScriptAction>,
+}
+
+impl Default for ScriptActions {
+    fn default() -> Self {
+        Self {
+            engine: engine(),
+            actions: Vec::new(),
+        }
+    }
+}
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(MAX_OPERATIONS);
+    engine
+}
+
+impl ScriptActions {
+    /// Load all `.rhai` scripts in the directory. A missing directory means there are no
+    /// actions.
+    pub fn load_dir(dir: &Path) -> Result<Self> {
+        let mut actions = Self::default();
+        if !dir.exists() {
+            return Ok(actions);
+        }
+
+        let entries =
+            std::fs::read_dir(dir).with_context(|| format!("Could not read {}", dir.display()))?;
+        let mut paths = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == SCRIPT_EXTENSION))
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        for path in paths {
+            let name = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let source = std::fs::read_to_string(&path)
+                .with_context(|| format!("Could not read {}", path.display()))?;
+            actions.add(name, &source)?;
+        }
+
+        Ok(actions)
+    }
+
+    /// Compile an action from its source
+    pub fn add(&mut self, name: impl Into<String>, source: &str) -> Result<()> {
+        let name = name.into();
+        let ast = self
+            .engine
+            .compile(source)
+            .map_err(|e| anyhow!("The script {name} is invalid: {e}"))?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == RUN && f.params.len() == 1)
+        {
+            return Err(anyhow!("The script {name} doesn't define {RUN}(text)"));
+        }
+
+        let description = source
+            .lines()
+            .next()
+            .and_then(|line| line.trim().strip_prefix("//"))
+            .map(|comment| comment.trim_start_matches('/').trim().to_string())
+            .filter(|description| !description.is_empty())
+            .unwrap_or_else(|| name.clone());
+
+        self.actions.push(ScriptAction {
+            name,
+            description,
+            ast,
+        });
+        Ok(())
+    }
+
+    pub fn actions(&self) -> &[ScriptAction] {
+        &self.actions
+    }
+
+    /// Run the action with the given name on the text. Blocks while the script waits for `ask`.
+    pub fn run(&self, name: &str, text: String, host: Arc<dyn Host>) -> Result<Vec<Output>> {
+        let action = self
+            .actions
+            .iter()
+            .find(|action| action.name == name)
+            .ok_or_else(|| anyhow!("There is no script {name}"))?;
+
+        let outputs = Arc::new(Mutex::new(Vec::new()));
+        let mut engine = engine();
+        register_host(&mut engine, host, &outputs);
+
+        // The outputs are what counts, not the value `run` returns
+        let _ = engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &action.ast, RUN, (text,))
+            .map_err(|e| anyhow!("The script {name} failed: {e}"))?;
+
+        let outputs = std::mem::take(&mut *outputs.lock().unwrap());
+        Ok(outputs)
+    }
+}
+
+/// Give the scripts the functions of the host and collect what they want done with the results
+fn register_host(engine: &mut Engine, host: Arc<dyn Host>, outputs: &Arc<Mutex<Vec<Output>>>) {
+    let error = |e: anyhow::Error| -> Box<EvalAltResult> { format!("{e:#}").into() };
+
+    let ask_host = Arc::clone(&host);
+    engine.register_fn("ask", move |prompt: &str| {
+        ask_host.ask(prompt, None).map_err(error)
+    });
+    let ask_host = Arc::clone(&host);
+    engine.register_fn("ask", move |prompt: &str, system_prompt: &str| {
+        ask_host.ask(prompt, Some(system_prompt)).map_err(error)
+    });
+    engine.register_fn("clipboard", move || host.clipboard_text().map_err(error));
+
+    let outputs_for = |output: fn(String) -> Output| {
+        let outputs = Arc::clone(outputs);
+        move |text: &str| outputs.lock().unwrap().push(output(text.to_string()))
+    };
+    engine.register_fn("copy", outputs_for(Output::Copy));
+    engine.register_fn("insert", outputs_for(Output::Insert));
+    engine.register_fn("show", outputs_for(Output::Show));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers every prompt with the prompt in upper case
+    struct Shouting;
+
+    impl Host for Shouting {
+        fn clipboard_text(&self) -> Result<String> {
+            Ok("clipboard".to_string())
+        }
+
+        fn ask(&self, prompt: &str, system_prompt: Option<&str>) -> Result<String> {
+            match system_prompt {
+                Some("fail") => Err(anyhow!("No network")),
+                Some(system_prompt) => Ok(format!("{system_prompt}: {}", prompt.to_uppercase())),
+                None => Ok(prompt.to_uppercase()),
+            }
+        }
+    }
+
+    #[test]
+    fn scripts_ask_and_collect_outputs() {
+        let mut actions = ScriptActions::default();
+        actions
+            .add(
+                "shout",
+                r#"// Shout the clipboard
+                fn run(text) {
+                    let answer = ask("say " + text);
+                    copy(answer);
+                    show(ask(clipboard(), "sys"));
+                }"#,
+            )
+            .unwrap();
+        actions
+            .add("failing", r#"fn run(text) { insert(ask(text, "fail")) }"#)
+            .unwrap();
+
+        let descriptions: Vec<_> = actions
+            .actions()
+            .iter()
+            .map(|action| action.description.as_str())
+            .collect();
+        assert_eq!(descriptions, ["Shout the clipboard", "failing"]);
+
+        let outputs = actions
+            .run("shout", "hi".into(), Arc::new(Shouting))
+            .unwrap();
+        assert_eq!(
+            outputs,
+            [
+                Output::Copy("SAY HI".into()),
+                Output::Show("sys: CLIPBOARD".into())
+            ]
+        );
+
+        let e = actions.run("failing", "hi".into(), Arc::new(Shouting));
+        assert!(e.unwrap_err().to_string().contains("No network"));
+        assert!(actions
+            .run("missing", "hi".into(), Arc::new(Shouting))
+            .is_err());
+    }
+
+    #[test]
+    fn scripts_need_a_run_function() {
+        let mut actions = ScriptActions::default();
+        assert!(actions.add("empty", "let x = 1;").is_err());
+        assert!(actions.add("broken", "fn run(").is_err());
+
+        actions.add("looping", "fn run(text) { loop {} }").unwrap();
+        assert!(actions
+            .run("looping", "".into(), Arc::new(Shouting))
+            .is_err());
+    }
+}
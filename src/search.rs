@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::http;
+
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(15);
+
+const BING_ENDPOINT: &str = "https://api.bing.microsoft.com/v7.0/search";
+const BRAVE_ENDPOINT: &str = "https://api.search.brave.com/res/v1/web/search";
+
+/// The number of results returned to the model if the settings don't choose one
+const DEFAULT_RESULTS: usize = 5;
+
+/// The web search API the search tool uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchBackend {
+    Bing,
+    Brave,
+    /// A SearXNG instance with the JSON format enabled
+    Searxng,
+}
+
+/// The search backend and its access, from the settings
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchEngine {
+    pub backend: SearchBackend,
+    /// The subscription key of Bing or Brave, SearXNG instances usually don't need one
+    pub api_key: Option<String>,
+    /// The address of the SearXNG instance, e.g. `https://searx.example.com`. Replaces the API
+    /// endpoint of the other backends.
+    pub url: Option<String>,
+    /// The number of results the model gets, 5 if not set
+    pub results: Option<usize>,
+}
+
+/// A web page found by a search
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub title: String,
+    pub url: String,
+    pub snippet: String,
+}
+
+impl SearchEngine {
+    pub fn search(&self, query: &str) -> Result<Vec<SearchResult>> {
+        let count = self.results.unwrap_or(DEFAULT_RESULTS);
+        let endpoint = match (self.backend, &self.url) {
+            (SearchBackend::Searxng, Some(url)) => format!("{}/search", url.trim_end_matches('/')),
+            (SearchBackend::Searxng, None) => {
+                bail!("The search settings need the url of the SearXNG instance")
+            }
+            (_, Some(url)) => url.clone(),
+            (SearchBackend::Bing, None) => BING_ENDPOINT.to_string(),
+            (SearchBackend::Brave, None) => BRAVE_ENDPOINT.to_string(),
+        };
+        let api_key = self.api_key.as_deref().unwrap_or_default();
+
+        let request = http::get(&endpoint)
+            .timeout(SEARCH_TIMEOUT)
+            .query("q", query);
+        let request = match self.backend {
+            SearchBackend::Bing => request
+                .query("count", &count.to_string())
+                .set("Ocp-Apim-Subscription-Key", api_key),
+            SearchBackend::Brave => request
+                .query("count", &count.to_string())
+                .set("Accept", "application/json")
+                .set("X-Subscription-Token", api_key),
+            SearchBackend::Searxng => request.query("format", "json"),
+        };
+
+        let body: Value = request
+            .call()
+            .context("The search failed")?
+            .into_json()
+            .context("The search returned an invalid response")?;
+
+        let mut results = parse_results(self.backend, &body);
+        results.truncate(count);
+        Ok(results)
+    }
+}
+
+/// The results of the response of a backend, each backend names the fields differently
+fn parse_results(backend: SearchBackend, body: &Value) -> Vec<SearchResult> {
+    let (results, title, snippet) = match backend {
+        SearchBackend::Bing => (&body["webPages"]["value"], "name", "snippet"),
+        SearchBackend::Brave => (&body["web"]["results"], "title", "description"),
+        SearchBackend::Searxng => (&body["results"], "title", "content"),
+    };
+    let text = |result: &Value, key: &str| result[key].as_str().unwrap_or_default().to_string();
+
+    results
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|result| SearchResult {
+            title: text(result, title),
+            url: text(result, "url"),
+            // Brave marks the query terms with HTML
+            snippet: text(result, snippet)
+                .replace("<strong>", "")
+                .replace("</strong>", ""),
+        })
+        .filter(|result| !result.url.is_empty())
+        .collect()
+}
+
+/// The results as a numbered Markdown list for the model
+pub fn format_results(results: &[SearchResult]) -> String {
+    if results.is_empty() {
+        return "No results found.".to_string();
+    }
+
+    results
+        .iter()
+        .enumerate()
+        .map(|(i, result)| {
+            format!(
+                "{}. [{}]({})\n   {}",
+                i + 1,
+                result.title,
+                result.url,
+                result.snippet
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn results_of_each_backend() {
+        let brave = json!({ "web": { "results": [
+            { "title": "Rust", "url": "https://www.rust-lang.org/", "description": "A <strong>language</strong>" },
+            { "title": "No URL" }
+        ]}});
+        let results = parse_results(SearchBackend::Brave, &brave);
+        assert_eq!(
+            results,
+            [SearchResult {
+                title: "Rust".into(),
+                url: "https://www.rust-lang.org/".into(),
+                snippet: "A language".into(),
+            }]
+        );
+        assert_eq!(
+            format_results(&results),
+            "1. [Rust](https://www.rust-lang.org/)\n   A language"
+        );
+
+        let bing = json!({ "webPages": { "value": [{ "name": "Rust", "url": "https://a", "snippet": "b" }]}});
+        assert_eq!(parse_results(SearchBackend::Bing, &bing)[0].title, "Rust");
+
+        let searxng =
+            json!({ "results": [{ "title": "Rust", "url": "https://a", "content": "b" }]});
+        assert_eq!(
+            parse_results(SearchBackend::Searxng, &searxng)[0].snippet,
+            "b"
+        );
+        assert!(parse_results(SearchBackend::Searxng, &json!({})).is_empty());
+    }
+}
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+
+/// The characters that start a snippet in the prompt, e.g. `;;review`
+pub const SNIPPET_TRIGGER: &str = ";;";
+
+/// A piece of text that is inserted into the prompt by its name, e.g. a code prefix or an
+/// instruction that is used often
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snippet {
+    /// The name that is typed after the trigger, e.g. `review` for `;;review`
+    pub name: String,
+    /// The text the snippet expands to
+    pub text: String,
+}
+
+/// The snippet name that is being typed at the end of the prompt, with the byte offset of its
+/// trigger. `None` if the prompt doesn't end in a trigger followed by a partial name.
+pub fn snippet_query(prompt: &str) -> Option<(usize, &str)> {
+    let start = prompt.rfind(SNIPPET_TRIGGER)?;
+    let query = &prompt[start + SNIPPET_TRIGGER.len()..];
+
+    match query.contains(char::is_whitespace) {
+        true => None,
+        false => Some((start, query)),
+    }
+}
+
+/// How well the query matches the name, `None` if the characters of the query don't appear in
+/// the name in order. Matches at the start of the name and consecutive characters score higher.
+pub fn fuzzy_score(query: &str, name: &str) -> Option<u32> {
+    let mut score = 0;
+    let mut name_chars = name.chars().flat_map(char::to_lowercase).enumerate();
+    let mut last = None;
+
+    for q in query.chars().flat_map(char::to_lowercase) {
+        let (i, _) = name_chars.find(|(_, c)| *c == q)?;
+        score += match (i, last) {
+            (0, _) => 3,
+            (i, Some(last)) if i == last + 1 => 2,
+            _ => 1,
+        };
+        last = Some(i);
+    }
+
+    Some(score)
+}
+
+/// The snippets matching the query, the best matches first. Shorter names win between equally
+/// good matches.
+pub fn suggest<'a>(snippets: &'a [Snippet], query: &str) -> Vec<&'a Snippet> {
+    let mut matches: Vec<_> = snippets
+        .iter()
+        .filter_map(|snippet| Some((fuzzy_score(query, &snippet.name)?, snippet)))
+        .collect();
+    matches.sort_by(|(a, a_snippet), (b, b_snippet)| {
+        b.cmp(a)
+            .then(a_snippet.name.len().cmp(&b_snippet.name.len()))
+    });
+
+    matches.into_iter().map(|(_, snippet)| snippet).collect()
+}
+
+/// Replace every trigger followed by the exact name of a snippet with its text. Unknown names are
+/// kept as they are.
+pub fn expand(prompt: &str, snippets: &[Snippet]) -> String {
+    let mut parts = prompt.split(SNIPPET_TRIGGER);
+    let mut expanded = parts.next().unwrap_or_default().to_string();
+
+    for part in parts {
+        let name_len = part.find(char::is_whitespace).unwrap_or(part.len());
+        let (name, rest) = part.split_at(name_len);
+
+        match snippets.iter().find(|snippet| snippet.name == name) {
+            Some(snippet) if !name.is_empty() => {
+                expanded.push_str(&snippet.text);
+                expanded.push_str(rest);
+            }
+            _ => {
+                expanded.push_str(SNIPPET_TRIGGER);
+                expanded.push_str(part);
+            }
+        }
+    }
+
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snippet(name: &str, text: &str) -> Snippet {
+        Snippet {
+            name: name.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn query_at_the_end_of_the_prompt() {
+        assert_eq!(snippet_query("Please ;;rev"), Some((7, "rev")));
+        assert_eq!(snippet_query(";;"), Some((0, "")));
+        assert_eq!(snippet_query(";;review the code"), None);
+        assert_eq!(snippet_query("no snippet"), None);
+    }
+
+    #[test]
+    fn fuzzy_matches_rank_prefixes_first() {
+        let snippets = [
+            snippet("code-review", ""),
+            snippet("review", ""),
+            snippet("rust", ""),
+        ];
+
+        let names: Vec<_> = suggest(&snippets, "rev")
+            .iter()
+            .map(|snippet| snippet.name.as_str())
+            .collect();
+        assert_eq!(names, ["review", "code-review"]);
+
+        assert_eq!(suggest(&snippets, "RS").len(), 1);
+        assert_eq!(suggest(&snippets, "").len(), 3);
+        assert!(fuzzy_score("xyz", "review").is_none());
+    }
+
+    #[test]
+    fn expand_known_snippets() {
+        let snippets = [snippet("rs", "Answer with idiomatic Rust.")];
+
+        assert_eq!(
+            expand(";;rs How do I read a file?", &snippets),
+            "Answer with idiomatic Rust. How do I read a file?"
+        );
+        assert_eq!(
+            expand("a;;b ;; ;;rs", &snippets),
+            "a;;b ;; Answer with idiomatic Rust."
+        );
+    }
+}
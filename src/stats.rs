@@ -0,0 +1,80 @@
+use std::time::{Duration, Instant};
+
+use popup_gpt::model::{CompletionResponse, Message};
+
+use crate::i18n::tr;
+
+/// Timings and token counts of the last exchange
+#[derive(Debug, Clone)]
+pub struct ExchangeStats {
+    pub model: String,
+    /// The time from sending the request until the first part of the response arrived
+    pub time_to_first_token: Option<Duration>,
+    /// The time until the response was complete
+    pub total: Duration,
+    pub prompt_tokens: Option<u32>,
+    pub completion_tokens: u32,
+    /// Whether the completion tokens are estimated since the API didn't report its usage
+    pub estimated: bool,
+}
+
+impl ExchangeStats {
+    /// Measure a complete response to a request that was sent at `start`
+    pub fn new(
+        model: &str,
+        start: Instant,
+        first_token: Option<Instant>,
+        resp: &CompletionResponse,
+    ) -> Self {
+        let (prompt_tokens, completion_tokens, estimated) = match &resp.usage {
+            Some(usage) => (Some(usage.prompt_tokens), usage.completion_tokens, false),
+            None => {
+                let response = resp.primary_response().unwrap_or_default();
+                let tokens = Message::assistant(response).estimated_tokens() as u32;
+                (None, tokens, true)
+            }
+        };
+
+        Self {
+            model: model.to_string(),
+            time_to_first_token: first_token.map(|first| first.duration_since(start)),
+            total: start.elapsed(),
+            prompt_tokens,
+            completion_tokens,
+            estimated,
+        }
+    }
+
+    /// The generation speed after the first token
+    pub fn tokens_per_second(&self) -> Option<f32> {
+        let generating = self
+            .total
+            .saturating_sub(self.time_to_first_token.unwrap_or_default());
+        (generating.as_secs_f32() > 0.0)
+            .then(|| self.completion_tokens as f32 / generating.as_secs_f32())
+    }
+
+    /// The stats as one line, e.g. `gpt-4o · first token 0.4 s · 3.1 s · 52 tokens/s · 9 tokens`
+    pub fn summary(&self) -> String {
+        let mut parts = vec![self.model.clone()];
+
+        if let Some(ttft) = self.time_to_first_token {
+            parts.push(
+                tr("first token {time}").replace("{time}", &format!("{:.1} s", ttft.as_secs_f32())),
+            );
+        }
+        parts.push(format!("{:.1} s", self.total.as_secs_f32()));
+        if let Some(speed) = self.tokens_per_second() {
+            parts.push(tr("{speed} tokens/s").replace("{speed}", &format!("{speed:.0}")));
+        }
+
+        let approx = if self.estimated { "~" } else { "" };
+        let tokens = match self.prompt_tokens {
+            Some(prompt) => format!("{prompt} + {approx}{}", self.completion_tokens),
+            None => format!("{approx}{}", self.completion_tokens),
+        };
+        parts.push(tr("{tokens} tokens").replace("{tokens}", &tokens));
+
+        parts.join(" · ")
+    }
+}
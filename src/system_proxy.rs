@@ -0,0 +1,84 @@
+use popup_gpt::http::{parse_connection_settings, parse_proxy_server, ProxyConfig};
+use winapi::{
+    shared::{minwindef::HKEY, winerror::ERROR_SUCCESS},
+    um::winreg::{
+        RegGetValueW, HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE, RRF_RT_REG_BINARY, RRF_RT_REG_DWORD,
+        RRF_RT_REG_SZ,
+    },
+};
+
+/// The registry key of the Internet Options, which browsers use too
+const INTERNET_SETTINGS_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Internet Settings";
+
+/// The registry key of the WinHTTP proxy, which `netsh winhttp set proxy` sets
+const WINHTTP_KEY: &str =
+    r"SOFTWARE\Microsoft\Windows\CurrentVersion\Internet Settings\Connections";
+
+/// The proxy that is set in the Internet Options of the user or for WinHTTP. Proxy auto-config
+/// scripts can't be evaluated, so only manually set proxies are found.
+pub fn system_proxy() -> Option<ProxyConfig> {
+    let key = INTERNET_SETTINGS_KEY;
+    if read_dword(HKEY_CURRENT_USER, key, "ProxyEnable").unwrap_or(0) != 0 {
+        let server = read_string(HKEY_CURRENT_USER, key, "ProxyServer").unwrap_or_default();
+        let bypass = read_string(HKEY_CURRENT_USER, key, "ProxyOverride").unwrap_or_default();
+        if let Some(proxy) = parse_proxy_server(&server, &bypass) {
+            return Some(proxy);
+        }
+    }
+
+    let winhttp = read_binary(HKEY_LOCAL_MACHINE, WINHTTP_KEY, "WinHttpSettings")
+        .and_then(|settings| parse_connection_settings(&settings));
+    if winhttp.is_none() && read_string(HKEY_CURRENT_USER, key, "AutoConfigURL").is_some() {
+        tracing::warn!("the system proxy uses an auto-config script, set the proxy manually");
+    }
+    winhttp
+}
+
+fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain([0]).collect()
+}
+
+/// Read a registry value into the buffer, returning the number of bytes read
+fn read_value(root: HKEY, key: &str, name: &str, kind: u32, buffer: &mut [u8]) -> Option<usize> {
+    let key = wide(key);
+    let name = wide(name);
+    let mut size = buffer.len() as u32;
+
+    let status = unsafe {
+        RegGetValueW(
+            root,
+            key.as_ptr(),
+            name.as_ptr(),
+            kind,
+            std::ptr::null_mut(),
+            buffer.as_mut_ptr() as _,
+            &mut size,
+        )
+    };
+    (status == ERROR_SUCCESS as i32).then_some(size as usize)
+}
+
+fn read_dword(root: HKEY, key: &str, name: &str) -> Option<u32> {
+    let mut value = [0; 4];
+    read_value(root, key, name, RRF_RT_REG_DWORD, &mut value)?;
+    Some(u32::from_le_bytes(value))
+}
+
+fn read_string(root: HKEY, key: &str, name: &str) -> Option<String> {
+    let mut buffer = vec![0; 4096];
+    let len = read_value(root, key, name, RRF_RT_REG_SZ, &mut buffer)?;
+
+    let wide: Vec<u16> = buffer[..len]
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|&c| c != 0)
+        .collect();
+    Some(String::from_utf16_lossy(&wide)).filter(|value| !value.is_empty())
+}
+
+fn read_binary(root: HKEY, key: &str, name: &str) -> Option<Vec<u8>> {
+    let mut buffer = vec![0; 4096];
+    let len = read_value(root, key, name, RRF_RT_REG_BINARY, &mut buffer)?;
+    buffer.truncate(len);
+    Some(buffer)
+}
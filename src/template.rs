@@ -0,0 +1,159 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// The placeholder in a template that is replaced with the user input
+pub const INPUT_PLACEHOLDER: &str = "{input}";
+
+/// A reusable prompt, consisting of an optional system prompt and a template that wraps the user
+/// input
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PromptTemplate {
+    /// The name used to refer to the template, e.g. `translate` for `/translate`
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Replaces the system prompt of the conversation the template is used in
+    pub system_prompt: Option<String>,
+    /// The prompt that is sent. `{input}` is replaced with the user input, if the template doesn't
+    /// contain the placeholder, the input is appended.
+    #[serde(default)]
+    pub template: String,
+}
+
+impl PromptTemplate {
+    pub fn new(
+        name: impl AsRef<str>,
+        description: impl AsRef<str>,
+        template: impl AsRef<str>,
+    ) -> Self {
+        Self {
+            name: name.as_ref().to_string(),
+            description: description.as_ref().to_string(),
+            system_prompt: None,
+            template: template.as_ref().to_string(),
+        }
+    }
+
+    /// Fill the template with the given input
+    pub fn render(&self, input: impl AsRef<str>) -> String {
+        let input = input.as_ref();
+
+        if self.template.is_empty() {
+            input.to_string()
+        } else if self.template.contains(INPUT_PLACEHOLDER) {
+            self.template.replace(INPUT_PLACEHOLDER, input)
+        } else {
+            format!("{}\n\n{}", self.template, input)
+        }
+    }
+}
+
+/// A chain of templates that are run one after another, each one on the response to the previous
+/// one, e.g. `extract key points` followed by `rewrite as email`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Pipeline {
+    /// The name used to run the pipeline, e.g. `email` for `/email`
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// The names of the templates that make up the steps
+    pub steps: Vec<String>,
+}
+
+impl Pipeline {
+    /// Look up the templates of all steps
+    pub fn resolve(&self, templates: &[PromptTemplate]) -> Result<Vec<PromptTemplate>> {
+        self.steps
+            .iter()
+            .map(|step| {
+                templates
+                    .iter()
+                    .find(|template| template.name == *step)
+                    .cloned()
+                    .with_context(|| format!("Unknown step {step:?} in pipeline /{}", self.name))
+            })
+            .collect()
+    }
+}
+
+/// The templates that are available without any configuration
+pub fn builtin_templates() -> Vec<PromptTemplate> {
+    vec![
+        PromptTemplate::new(
+            "translate",
+            "Translate the text to English",
+            "Translate the following text to English. Only reply with the translation.\n\n{input}",
+        ),
+        PromptTemplate::new(
+            "summarize",
+            "Summarize the text",
+            "Summarize the following text in a few concise bullet points.\n\n{input}",
+        ),
+        PromptTemplate::new(
+            "fix",
+            "Fix spelling and grammar",
+            "Fix the spelling and grammar of the following text without changing its meaning. \
+             Only reply with the corrected text.\n\n{input}",
+        ),
+        PromptTemplate::new(
+            "explain",
+            "Explain code or a concept",
+            "Explain the following in simple terms. If it is code, explain what it does step by \
+             step.\n\n{input}",
+        ),
+    ]
+}
+
+/// Split a prompt like `/translate Hallo Welt` into the command name and the remaining input
+pub fn parse_command(prompt: &str) -> Option<(&str, &str)> {
+    let command = prompt.strip_prefix('/')?;
+
+    match command.split_once(char::is_whitespace) {
+        Some((name, input)) => Some((name, input.trim_start())),
+        None => Some((command, "")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(parse_command("/fix teh text"), Some(("fix", "teh text")));
+        assert_eq!(
+            parse_command("/translate\n  Hallo"),
+            Some(("translate", "Hallo"))
+        );
+        assert_eq!(parse_command("/sum"), Some(("sum", "")));
+        assert_eq!(parse_command("no command"), None);
+    }
+
+    #[test]
+    fn render_templates() {
+        let mut template = PromptTemplate::new("t", "", "Translate: {input}!");
+        assert_eq!(template.render("Hallo"), "Translate: Hallo!");
+
+        template.template = "Translate".to_string();
+        assert_eq!(template.render("Hallo"), "Translate\n\nHallo");
+
+        template.template.clear();
+        assert_eq!(template.render("Hallo"), "Hallo");
+    }
+
+    #[test]
+    fn resolve_pipeline() {
+        let mut pipeline = Pipeline {
+            name: "p".to_string(),
+            description: String::new(),
+            steps: vec!["summarize".to_string(), "translate".to_string()],
+        };
+
+        let steps = pipeline.resolve(&builtin_templates()).unwrap();
+        let names: Vec<_> = steps.iter().map(|step| step.name.as_str()).collect();
+        assert_eq!(names, ["summarize", "translate"]);
+
+        pipeline.steps.push("missing".to_string());
+        assert!(pipeline.resolve(&builtin_templates()).is_err());
+    }
+}
@@ -0,0 +1,112 @@
+use egui::{Color32, Visuals};
+use winapi::{
+    shared::{minwindef::HKEY, winerror::ERROR_SUCCESS},
+    um::{
+        winnt::{KEY_NOTIFY, KEY_READ, REG_NOTIFY_CHANGE_LAST_SET},
+        winreg::{
+            RegGetValueW, RegNotifyChangeKeyValue, RegOpenKeyExW, HKEY_CURRENT_USER,
+            RRF_RT_REG_DWORD,
+        },
+    },
+};
+
+/// The registry key of the Windows color settings
+const PERSONALIZE_KEY: &str = r"Software\Microsoft\Windows\CurrentVersion\Themes\Personalize";
+
+/// The colors of the popup that egui's visuals don't cover
+pub struct Palette {
+    /// The window background without its alpha, which comes from the settings
+    pub background: [u8; 3],
+    /// The prompt and the user messages
+    pub input: Color32,
+    /// The responses and the assistant messages
+    pub text: Color32,
+    /// Messages that are left out of the context
+    pub muted: Color32,
+    pub code_background: Color32,
+}
+
+const DARK: Palette = Palette {
+    background: [50, 54, 62],
+    input: Color32::from_gray(255),
+    text: Color32::from_rgb(180, 180, 190),
+    muted: Color32::from_gray(100),
+    code_background: Color32::from_black_alpha(60),
+};
+
+const LIGHT: Palette = Palette {
+    background: [246, 247, 249],
+    input: Color32::from_gray(10),
+    text: Color32::from_rgb(50, 52, 60),
+    muted: Color32::from_gray(170),
+    code_background: Color32::from_black_alpha(15),
+};
+
+/// The palette that goes with the dark or light visuals
+pub fn palette(visuals: &Visuals) -> &'static Palette {
+    match visuals.dark_mode {
+        true => &DARK,
+        false => &LIGHT,
+    }
+}
+
+fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain([0]).collect()
+}
+
+/// Whether Windows is set to dark mode for apps, dark if the setting can't be read
+pub fn system_is_dark() -> bool {
+    let key = wide(PERSONALIZE_KEY);
+    let value = wide("AppsUseLightTheme");
+    let mut light: u32 = 0;
+    let mut size = std::mem::size_of::<u32>() as u32;
+
+    let status = unsafe {
+        RegGetValueW(
+            HKEY_CURRENT_USER,
+            key.as_ptr(),
+            value.as_ptr(),
+            RRF_RT_REG_DWORD,
+            std::ptr::null_mut(),
+            &mut light as *mut u32 as _,
+            &mut size,
+        )
+    };
+
+    status != ERROR_SUCCESS as i32 || light == 0
+}
+
+/// Call `changed` with the dark mode of Windows on a background thread whenever the color settings
+/// change
+pub fn watch_system(changed: impl Fn(bool) + Send + 'static) {
+    std::thread::spawn(move || {
+        let path = wide(PERSONALIZE_KEY);
+        let mut key: HKEY = std::ptr::null_mut();
+        let opened = unsafe {
+            RegOpenKeyExW(
+                HKEY_CURRENT_USER,
+                path.as_ptr(),
+                0,
+                KEY_READ | KEY_NOTIFY,
+                &mut key,
+            )
+        };
+        if opened != ERROR_SUCCESS as i32 {
+            tracing::warn!(status = opened, "could not watch the system theme");
+            return;
+        }
+
+        let mut dark = system_is_dark();
+        // Blocks until a value of the key is set
+        while unsafe {
+            RegNotifyChangeKeyValue(key, 0, REG_NOTIFY_CHANGE_LAST_SET, std::ptr::null_mut(), 0)
+        } == ERROR_SUCCESS as i32
+        {
+            // Other color settings are in the same key, only a switch is reported
+            if system_is_dark() != dark {
+                dark = !dark;
+                changed(dark);
+            }
+        }
+    });
+}
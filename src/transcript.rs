@@ -0,0 +1,257 @@
+use egui::{Align, Color32, Key, RichText, ScrollArea, Sense, Stroke, TextEdit, Vec2};
+use popup_gpt::{
+    markdown::{quote, to_plain_text},
+    model::{Message, Role},
+};
+
+use crate::{i18n::tr, theme::palette};
+
+/// What the user chose to do with a message in the transcript
+pub enum TranscriptAction {
+    /// Start a new branch of the conversation that ends with the message at this index
+    Branch(usize),
+    /// Replace the user message at this index, drop everything after it and request a new response
+    Edit(usize, String),
+    /// Exclude the message at this index from the context or include it again
+    Mute(usize, bool),
+    /// Remove the message at this index from the conversation
+    Delete(usize),
+    /// Keep the message at this index when the context is trimmed, or stop doing so
+    Pin(usize, bool),
+    /// Add the text to the prompt
+    Quote(String),
+}
+
+/// All messages of the current conversation with actions for the individual messages. PgUp and
+/// PgDn scroll it, Ctrl+Up and Ctrl+Down select the previous or next message and Ctrl+C copies
+/// the selected one, so it can be read without a mouse.
+#[derive(Default)]
+pub struct Transcript {
+    /// The index and new text of the user message that is being edited
+    editing: Option<(usize, String)>,
+    /// The index of the message that was selected with the keyboard or clicked
+    selected: Option<usize>,
+    /// Scroll the selected message into view in this frame
+    scroll_to_selected: bool,
+}
+
+impl Transcript {
+    pub fn is_editing(&self) -> bool {
+        self.editing.is_some()
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, messages: &[Message]) -> Option<TranscriptAction> {
+        let mut action = None;
+        let mut cancel_edit = false;
+
+        if messages.is_empty() {
+            ui.label(RichText::new(tr("The conversation is empty")).color(Color32::GRAY));
+        }
+
+        // The keys would also move the cursor of the message that is being edited
+        let pages = match self.editing {
+            Some(_) => 0.0,
+            None => self.navigate(ui, messages),
+        };
+
+        ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                ui.scroll_with_delta(Vec2::new(0.0, -pages * ui.clip_rect().height()));
+
+                for (i, message) in messages.iter().enumerate() {
+                    let top = ui.cursor().top();
+                    ui.horizontal(|ui| {
+                        ui.label(
+                            RichText::new(message.role.as_str())
+                                .small()
+                                .color(Color32::GRAY),
+                        );
+                        let pin = egui::SelectableLabel::new(message.pinned, "📌");
+                        if ui
+                            .add(pin)
+                            .on_hover_text(tr("Never drop the message when the context is trimmed"))
+                            .clicked()
+                        {
+                            action = Some(TranscriptAction::Pin(i, !message.pinned));
+                        }
+                        let (mute_icon, mute_hint) = match message.muted {
+                            true => ("🔈", tr("Include the message in the context again")),
+                            false => ("🔇", tr("Exclude the message from the context")),
+                        };
+                        if ui
+                            .small_button(mute_icon)
+                            .on_hover_text(mute_hint)
+                            .clicked()
+                        {
+                            action = Some(TranscriptAction::Mute(i, !message.muted));
+                        }
+                        if ui
+                            .small_button("🗑")
+                            .on_hover_text(tr("Delete the message"))
+                            .clicked()
+                        {
+                            action = Some(TranscriptAction::Delete(i));
+                        }
+                        if matches!(message.role, Role::User)
+                            && self.editing.is_none()
+                            && ui.small_button(tr("✏ Edit")).clicked()
+                        {
+                            self.editing = Some((i, message.content.clone()));
+                        }
+                        if i + 1 < messages.len()
+                            && ui
+                                .small_button(tr("⑂ Branch"))
+                                .on_hover_text(tr("Continue from here in a new branch"))
+                                .clicked()
+                        {
+                            action = Some(TranscriptAction::Branch(i + 1));
+                        }
+                    });
+
+                    if let Some((_, text)) = self.editing.as_mut().filter(|(j, _)| *j == i) {
+                        ui.add(TextEdit::multiline(text).desired_width(f32::INFINITY));
+
+                        ui.horizontal(|ui| {
+                            if ui.button(tr("Save & regenerate")).clicked() {
+                                action = Some(TranscriptAction::Edit(i, text.clone()));
+                            }
+                            cancel_edit = ui.button(tr("Cancel")).clicked();
+                        });
+                    } else {
+                        let palette = palette(ui.visuals());
+                        let color = match message.role {
+                            _ if message.muted => palette.muted,
+                            Role::User => palette.input,
+                            _ => palette.text,
+                        };
+                        let label = egui::Label::new(RichText::new(&message.content).color(color))
+                            .sense(Sense::click());
+                        let label = ui.add(label);
+                        if label.clicked() || label.secondary_clicked() {
+                            self.selected = Some(i);
+                        }
+                        label.context_menu(|ui| {
+                            if let Some(chosen) = self.context_menu(ui, messages, i) {
+                                action = Some(chosen);
+                            }
+                        });
+                    }
+
+                    if self.selected == Some(i) {
+                        let mut rect = ui.min_rect();
+                        rect.set_top(top);
+                        rect = rect.expand(2.0);
+                        ui.painter().rect_stroke(
+                            rect,
+                            3.0,
+                            Stroke::new(1.0, palette(ui.visuals()).input),
+                        );
+                        if std::mem::take(&mut self.scroll_to_selected) {
+                            ui.scroll_to_rect(rect, Some(Align::Center));
+                        }
+                    }
+                    ui.add_space(6.0);
+                }
+            });
+
+        if action.is_some() || cancel_edit {
+            self.editing = None;
+        }
+
+        action
+    }
+
+    /// Handle the keys that move through the transcript. Returns how many pages to scroll down,
+    /// negative to scroll up.
+    fn navigate(&mut self, ui: &egui::Ui, messages: &[Message]) -> f32 {
+        let (pages, step, copy) = ui.input(|inp| {
+            let pages = inp.key_pressed(Key::PageDown) as i32 - inp.key_pressed(Key::PageUp) as i32;
+            let step = match inp.modifiers.ctrl {
+                true => {
+                    inp.key_pressed(Key::ArrowDown) as i32 - inp.key_pressed(Key::ArrowUp) as i32
+                }
+                false => 0,
+            };
+            (pages, step, inp.modifiers.ctrl && inp.key_pressed(Key::C))
+        });
+
+        let last = messages.len().checked_sub(1);
+        // Messages may have been deleted since the last frame
+        self.selected = self.selected.zip(last).map(|(i, last)| i.min(last));
+
+        if step != 0 {
+            if let Some(last) = last {
+                self.selected = Some(match self.selected {
+                    Some(i) => i.saturating_add_signed(step as isize).min(last),
+                    // The first step starts at the end that it moves away from
+                    None if step < 0 => last,
+                    None => 0,
+                });
+                self.scroll_to_selected = true;
+            }
+        }
+
+        if copy {
+            if let Some(message) = self.selected.map(|i| &messages[i]) {
+                ui.output_mut(|output| output.copied_text = to_plain_text(&message.content));
+            }
+        }
+
+        pages as f32
+    }
+
+    /// The actions for the message at the index, shown when it is right-clicked
+    fn context_menu(
+        &mut self,
+        ui: &mut egui::Ui,
+        messages: &[Message],
+        i: usize,
+    ) -> Option<TranscriptAction> {
+        let message = &messages[i];
+        let mut action = None;
+
+        if ui.button(tr("Copy")).clicked() {
+            ui.output_mut(|output| output.copied_text = to_plain_text(&message.content));
+            ui.close_menu();
+        }
+        if ui.button(tr("Copy as Markdown")).clicked() {
+            ui.output_mut(|output| output.copied_text = message.content.clone());
+            ui.close_menu();
+        }
+        if ui.button(tr("Quote into prompt")).clicked() {
+            action = Some(TranscriptAction::Quote(format!(
+                "{}\n\n",
+                quote(&message.content)
+            )));
+        }
+        ui.separator();
+        if matches!(message.role, Role::User)
+            && self.editing.is_none()
+            && ui.button(tr("✏ Edit")).clicked()
+        {
+            self.editing = Some((i, message.content.clone()));
+            ui.close_menu();
+        }
+        // The response is regenerated by sending the question that led to it again
+        let question = messages[..=i]
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, message)| matches!(message.role, Role::User));
+        if let Some((j, question)) = question {
+            if ui.button(tr("Regenerate from here")).clicked() {
+                action = Some(TranscriptAction::Edit(j, question.content.clone()));
+            }
+        }
+        if ui.button(tr("Delete")).clicked() {
+            action = Some(TranscriptAction::Delete(i));
+        }
+
+        if action.is_some() {
+            ui.close_menu();
+        }
+        action
+    }
+}
@@ -0,0 +1,301 @@
+use std::{
+    cell::RefCell,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::sync_channel,
+        Arc,
+    },
+};
+
+use anyhow::{bail, Context, Result};
+use winapi::{
+    shared::{
+        minwindef::{LPARAM, LRESULT, UINT, WPARAM},
+        windef::{HWND, POINT},
+    },
+    um::{
+        libloaderapi::GetModuleHandleW,
+        shellapi::{
+            Shell_NotifyIconW, NIF_ICON, NIF_INFO, NIF_MESSAGE, NIF_TIP, NIIF_INFO, NIM_ADD,
+            NIM_DELETE, NIM_MODIFY, NOTIFYICONDATAW,
+        },
+        winuser::{
+            AppendMenuW, CreatePopupMenu, CreateWindowExW, DefWindowProcW, DestroyMenu,
+            DispatchMessageW, GetCursorPos, GetMessageW, LoadIconW, RegisterClassW,
+            SetForegroundWindow, TrackPopupMenu, TranslateMessage, IDI_APPLICATION, IDI_WARNING,
+            MF_CHECKED, MF_SEPARATOR, MF_STRING, MSG, TPM_RETURNCMD, TPM_RIGHTBUTTON, WM_APP,
+            WM_LBUTTONUP, WM_RBUTTONUP, WNDCLASSW,
+        },
+    },
+};
+
+use crate::i18n::tr;
+
+/// The message the tray icon sends to its window on mouse events
+const WM_TRAY: UINT = WM_APP + 1;
+const TRAY_ID: UINT = 1;
+
+const MENU_SHOW: i32 = 1;
+const MENU_SUSPEND: i32 = 2;
+const MENU_QUIT: i32 = 3;
+
+/// What the menu of the tray icon acts on, owned by the tray thread
+struct Handler {
+    /// The hotkey should be suspended, applied by the popup the next time it waits for the hotkey
+    suspend: Arc<AtomicBool>,
+    /// Wake up the popup thread if it waits for the hotkey. It shows the popup, unless the
+    /// suspension was toggled, which it applies instead.
+    wake: Box<dyn Fn()>,
+}
+
+thread_local! {
+    static HANDLER: RefCell<Option<Handler>> = const { RefCell::new(None) };
+}
+
+/// The icon in the notification area with a menu to show the popup, suspend the hotkey and quit
+pub struct Tray {
+    window: u64,
+}
+
+impl Tray {
+    /// Add the icon, its window runs on a thread of its own since the popup thread blocks while it
+    /// waits for the hotkey
+    pub fn spawn(suspend: Arc<AtomicBool>, wake: impl Fn() + Send + 'static) -> Result<Self> {
+        let (sender, receiver) = sync_channel(1);
+
+        std::thread::spawn(move || {
+            let window = match create_window() {
+                Ok(window) => window,
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    return;
+                }
+            };
+            HANDLER.with(|handler| {
+                *handler.borrow_mut() = Some(Handler {
+                    suspend,
+                    wake: Box::new(wake),
+                })
+            });
+
+            if !notify_icon(window, NIM_ADD, false) {
+                let _ = sender.send(Err(anyhow::anyhow!("Could not add the tray icon")));
+                return;
+            }
+            let _ = sender.send(Ok(window as u64));
+
+            let mut msg: MSG = unsafe { std::mem::zeroed() };
+            while unsafe { GetMessageW(&mut msg, std::ptr::null_mut(), 0, 0) } > 0 {
+                unsafe {
+                    TranslateMessage(&msg);
+                    DispatchMessageW(&msg);
+                }
+            }
+        });
+
+        let window = receiver.recv().context("The tray icon thread stopped")??;
+        Ok(Self { window })
+    }
+
+    /// Show whether the hotkey is suspended with the icon and its tooltip
+    pub fn set_suspended(&self, suspended: bool) {
+        notify_icon(self.window as HWND, NIM_MODIFY, suspended);
+    }
+
+    /// A handle that shows notifications next to the icon from other threads
+    pub fn notifier(&self) -> Notifier {
+        Notifier {
+            window: self.window,
+        }
+    }
+}
+
+/// Shows notifications next to the tray icon, e.g. while the popup is hidden
+#[derive(Clone, Copy)]
+pub struct Notifier {
+    window: u64,
+}
+
+impl Notifier {
+    pub fn notify(&self, title: &str, text: &str) {
+        unsafe {
+            let mut data: NOTIFYICONDATAW = std::mem::zeroed();
+            data.cbSize = std::mem::size_of::<NOTIFYICONDATAW>() as u32;
+            data.hWnd = self.window as HWND;
+            data.uID = TRAY_ID;
+            data.uFlags = NIF_INFO;
+            data.dwInfoFlags = NIIF_INFO;
+            for (dst, src) in data
+                .szInfoTitle
+                .iter_mut()
+                .zip(title.encode_utf16().take(63))
+            {
+                *dst = src;
+            }
+            for (dst, src) in data.szInfo.iter_mut().zip(text.encode_utf16().take(255)) {
+                *dst = src;
+            }
+
+            Shell_NotifyIconW(NIM_MODIFY, &mut data);
+        }
+    }
+}
+
+impl Drop for Tray {
+    fn drop(&mut self) {
+        remove_icon(self.window as HWND);
+    }
+}
+
+fn wide(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain([0]).collect()
+}
+
+/// A hidden window that receives the messages of the tray icon
+fn create_window() -> Result<HWND> {
+    let class_name = wide("popup-gpt-tray");
+
+    unsafe {
+        let instance = GetModuleHandleW(std::ptr::null());
+        let class = WNDCLASSW {
+            lpfnWndProc: Some(window_proc),
+            hInstance: instance,
+            lpszClassName: class_name.as_ptr(),
+            ..std::mem::zeroed()
+        };
+        RegisterClassW(&class);
+
+        let window = CreateWindowExW(
+            0,
+            class_name.as_ptr(),
+            class_name.as_ptr(),
+            0,
+            0,
+            0,
+            0,
+            0,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            instance,
+            std::ptr::null_mut(),
+        );
+        if window.is_null() {
+            bail!("Could not create the window of the tray icon");
+        }
+        Ok(window)
+    }
+}
+
+/// Add or update the icon, the warning icon marks a suspended hotkey
+fn notify_icon(window: HWND, message: u32, suspended: bool) -> bool {
+    let tip = match suspended {
+        true => format!("Popup-GPT ({})", tr("hotkey suspended")),
+        false => "Popup-GPT".to_string(),
+    };
+
+    unsafe {
+        let mut data: NOTIFYICONDATAW = std::mem::zeroed();
+        data.cbSize = std::mem::size_of::<NOTIFYICONDATAW>() as u32;
+        data.hWnd = window;
+        data.uID = TRAY_ID;
+        data.uFlags = NIF_ICON | NIF_MESSAGE | NIF_TIP;
+        data.uCallbackMessage = WM_TRAY;
+        data.hIcon = LoadIconW(
+            std::ptr::null_mut(),
+            if suspended {
+                IDI_WARNING
+            } else {
+                IDI_APPLICATION
+            },
+        );
+        for (dst, src) in data.szTip.iter_mut().zip(tip.encode_utf16().take(127)) {
+            *dst = src;
+        }
+
+        Shell_NotifyIconW(message, &mut data) != 0
+    }
+}
+
+fn remove_icon(window: HWND) {
+    unsafe {
+        let mut data: NOTIFYICONDATAW = std::mem::zeroed();
+        data.cbSize = std::mem::size_of::<NOTIFYICONDATAW>() as u32;
+        data.hWnd = window;
+        data.uID = TRAY_ID;
+        Shell_NotifyIconW(NIM_DELETE, &mut data);
+    }
+}
+
+unsafe extern "system" fn window_proc(
+    window: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    if msg != WM_TRAY {
+        return DefWindowProcW(window, msg, wparam, lparam);
+    }
+
+    HANDLER.with(|handler| {
+        let handler = handler.borrow();
+        let Some(handler) = handler.as_ref() else {
+            return;
+        };
+        match lparam as u32 {
+            WM_LBUTTONUP => (handler.wake)(),
+            WM_RBUTTONUP => show_menu(window, handler),
+            _ => (),
+        }
+    });
+    0
+}
+
+fn show_menu(window: HWND, handler: &Handler) {
+    let suspended = handler.suspend.load(Ordering::SeqCst);
+    let show = wide(tr("Show"));
+    let suspend = wide(tr("Suspend the hotkey"));
+    let quit = wide(tr("Quit"));
+
+    let chosen = unsafe {
+        let menu = CreatePopupMenu();
+        AppendMenuW(menu, MF_STRING, MENU_SHOW as usize, show.as_ptr());
+        let checked = if suspended { MF_CHECKED } else { 0 };
+        AppendMenuW(
+            menu,
+            MF_STRING | checked,
+            MENU_SUSPEND as usize,
+            suspend.as_ptr(),
+        );
+        AppendMenuW(menu, MF_SEPARATOR, 0, std::ptr::null());
+        AppendMenuW(menu, MF_STRING, MENU_QUIT as usize, quit.as_ptr());
+
+        // Without the focus the menu doesn't close when clicking elsewhere
+        let mut cursor = POINT { x: 0, y: 0 };
+        GetCursorPos(&mut cursor);
+        SetForegroundWindow(window);
+        let chosen = TrackPopupMenu(
+            menu,
+            TPM_RETURNCMD | TPM_RIGHTBUTTON,
+            cursor.x,
+            cursor.y,
+            0,
+            window,
+            std::ptr::null(),
+        );
+        DestroyMenu(menu);
+        chosen
+    };
+
+    match chosen {
+        MENU_SHOW => (handler.wake)(),
+        MENU_SUSPEND => {
+            handler.suspend.store(!suspended, Ordering::SeqCst);
+            (handler.wake)();
+        }
+        MENU_QUIT => {
+            remove_icon(window);
+            std::process::exit(0);
+        }
+        _ => (),
+    }
+}
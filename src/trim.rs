@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+
+use crate::model::Message;
+
+/// The characters per token of the estimates, see [`Message::estimated_tokens`]
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Which part of a long text is kept when it is trimmed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TrimMode {
+    /// The start, e.g. for the header of a log
+    Head,
+    /// The end, e.g. for the latest lines of a log
+    Tail,
+    /// The start and the end, without the middle
+    Middle,
+}
+
+/// The size of a text as shown to the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextSize {
+    pub chars: usize,
+    pub words: usize,
+    pub tokens: usize,
+}
+
+impl TextSize {
+    pub fn of(text: &str) -> Self {
+        Self {
+            chars: text.chars().count(),
+            words: text.split_whitespace().count(),
+            tokens: Message::user(text).estimated_tokens(),
+        }
+    }
+}
+
+/// Shorten the text to about `max_tokens`, marking where characters were left out. Cuts are moved
+/// to a line break if there is one nearby.
+pub fn trim_to_tokens(text: &str, max_tokens: usize, mode: TrimMode) -> String {
+    let budget = max_tokens * CHARS_PER_TOKEN;
+    let total = text.chars().count();
+    if total <= budget {
+        return text.to_string();
+    }
+
+    let (head, tail) = match mode {
+        TrimMode::Head => (budget, 0),
+        TrimMode::Tail => (0, budget),
+        TrimMode::Middle => (budget / 2, budget - budget / 2),
+    };
+    let start = prefix(text, head);
+    let end = suffix(text, total - tail);
+    let omitted = text[start.len()..text.len() - end.len()].chars().count();
+    let marker = format!("[… {omitted} characters omitted …]");
+
+    [start, &marker, end]
+        .into_iter()
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The first `chars` characters, without a partial last line if the line is not too long
+fn prefix(text: &str, chars: usize) -> &str {
+    let end = text
+        .char_indices()
+        .nth(chars)
+        .map_or(text.len(), |(i, _)| i);
+    let start = &text[..end];
+    match start.rfind('\n') {
+        Some(line_end) if line_end >= end / 2 => &start[..line_end],
+        _ => start,
+    }
+}
+
+/// The text from the character `from` on, without a partial first line if the line is not too
+/// long
+fn suffix(text: &str, from: usize) -> &str {
+    let begin = text.char_indices().nth(from).map_or(text.len(), |(i, _)| i);
+    let end = &text[begin..];
+    if begin == 0 || text[..begin].ends_with('\n') {
+        return end;
+    }
+    match end.find('\n') {
+        Some(line_start) if line_start < end.len() / 2 => &end[line_start + 1..],
+        _ => end,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trimming() {
+        let log: String = (0..100).map(|i| format!("line {i:02}\n")).collect();
+
+        let head = trim_to_tokens(&log, 10, TrimMode::Head);
+        assert!(head.starts_with("line 00\nline 01\nline 02\nline 03\nline 04\n[…"));
+        assert!(head.ends_with("characters omitted …]"));
+
+        let tail = trim_to_tokens(&log, 10, TrimMode::Tail);
+        assert!(tail.starts_with("[… "));
+        assert!(tail.ends_with("omitted …]\nline 95\nline 96\nline 97\nline 98\nline 99\n"));
+
+        let middle = trim_to_tokens(&log, 10, TrimMode::Middle);
+        assert!(middle.starts_with("line 00\nline 01\n["));
+        assert!(middle.ends_with("line 99\n"));
+
+        assert_eq!(trim_to_tokens("short", 10, TrimMode::Middle), "short");
+        assert_eq!(
+            trim_to_tokens(&"a".repeat(100), 5, TrimMode::Head),
+            format!("{}\n[… 80 characters omitted …]", "a".repeat(20))
+        );
+
+        let size = TextSize::of("two words");
+        assert_eq!((size.chars, size.words), (9, 2));
+    }
+}
@@ -0,0 +1,184 @@
+use std::{io::Read, path::PathBuf};
+
+use anyhow::{bail, Context, Result};
+use popup_gpt::http;
+use serde::Deserialize;
+
+const LATEST_RELEASE_URL: &str = "https://api.github.com/repos/dnlmlr/popup-gpt/releases/latest";
+
+/// Releases larger than this are not a popup-gpt executable
+const MAX_DOWNLOAD_SIZE: u64 = 100 * 1024 * 1024;
+
+/// A release on GitHub
+#[derive(Debug, Clone, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    pub html_url: String,
+    #[serde(default)]
+    assets: Vec<Asset>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+impl Release {
+    /// The version without the `v` prefix of the tag
+    pub fn version(&self) -> &str {
+        self.tag_name.trim_start_matches('v')
+    }
+
+    fn executable(&self) -> Option<&Asset> {
+        self.assets
+            .iter()
+            .find(|asset| asset.name.to_lowercase().ends_with(".exe"))
+    }
+
+    /// The published SHA-256 checksums, either `popup-gpt.exe.sha256` or a `SHA256SUMS` file
+    fn checksums(&self, executable: &Asset) -> Option<&Asset> {
+        let own = format!("{}.sha256", executable.name).to_lowercase();
+        self.assets.iter().find(|asset| {
+            let name = asset.name.to_lowercase();
+            name == own || name.starts_with("sha256sums")
+        })
+    }
+}
+
+/// Look up the latest release. Returns it if it is newer than the running version.
+pub fn check() -> Result<Option<Release>> {
+    let release: Release = http::get(LATEST_RELEASE_URL)
+        .set(
+            "User-Agent",
+            concat!("popup-gpt/", env!("CARGO_PKG_VERSION")),
+        )
+        .call()
+        .context("Could not check for updates")?
+        .into_json()
+        .context("Could not read the latest release")?;
+
+    if !is_newer(release.version(), env!("CARGO_PKG_VERSION")) {
+        return Ok(None);
+    }
+
+    Ok(Some(release))
+}
+
+/// Compare two `major.minor.patch` versions. Pre-release suffixes are ignored.
+fn is_newer(version: &str, current: &str) -> bool {
+    let parse = |version: &str| {
+        version
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .map(|part| part.parse::<u64>().unwrap_or(0))
+            .collect::<Vec<_>>()
+    };
+
+    parse(version) > parse(current)
+}
+
+/// The previous executable, kept until the next start since a running executable can only be
+/// renamed, not deleted
+fn old_executable() -> Result<PathBuf> {
+    let exe = std::env::current_exe().context("Could not locate the executable")?;
+    Ok(exe.with_extension("old.exe"))
+}
+
+/// Download the executable of the release and put it in place of the running one. The update is
+/// used from the next start, the running instance holds the hotkeys until then.
+pub fn install(release: &Release) -> Result<()> {
+    let Some(asset) = release.executable() else {
+        bail!("The release {} has no executable", release.tag_name);
+    };
+
+    tracing::info!(version = release.version(), url = %asset.browser_download_url, "downloading update");
+    let bytes = download(&asset.browser_download_url).context("Could not download the update")?;
+    if let Some(checksums) = release.checksums(asset) {
+        let checksums = download(&checksums.browser_download_url)
+            .context("Could not download the checksums of the update")?;
+        verify_checksum(&bytes, &String::from_utf8_lossy(&checksums), &asset.name)?;
+    }
+
+    // The complete update is written first, so a failed write leaves the executable as it is
+    let exe = std::env::current_exe().context("Could not locate the executable")?;
+    let new = exe.with_extension("new.exe");
+    std::fs::write(&new, &bytes).context("Could not write the update")?;
+
+    let old = old_executable()?;
+    let _ = std::fs::remove_file(&old);
+    if let Err(e) = std::fs::rename(&exe, &old) {
+        let _ = std::fs::remove_file(&new);
+        return Err(e).context("Could not move the running executable aside");
+    }
+    if let Err(e) = std::fs::rename(&new, &exe) {
+        // Put the running version back, so there still is an executable to start
+        let _ = std::fs::rename(&old, &exe);
+        let _ = std::fs::remove_file(&new);
+        return Err(e).context("Could not put the update in place");
+    }
+
+    Ok(())
+}
+
+/// Download a release asset completely. Fails if it is larger than [`MAX_DOWNLOAD_SIZE`] or
+/// shorter than announced, instead of returning a part of it.
+fn download(url: &str) -> Result<Vec<u8>> {
+    let resp = http::get(url).call()?;
+    let announced = resp
+        .header("Content-Length")
+        .and_then(|length| length.parse::<u64>().ok());
+    if announced.is_some_and(|length| length > MAX_DOWNLOAD_SIZE) {
+        bail!("The download is larger than {MAX_DOWNLOAD_SIZE} bytes");
+    }
+
+    let mut bytes = Vec::new();
+    resp.into_reader()
+        .take(MAX_DOWNLOAD_SIZE + 1)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_DOWNLOAD_SIZE {
+        bail!("The download is larger than {MAX_DOWNLOAD_SIZE} bytes");
+    }
+    if let Some(announced) = announced.filter(|&length| length != bytes.len() as u64) {
+        bail!(
+            "The download ended after {} of {announced} bytes",
+            bytes.len()
+        );
+    }
+
+    Ok(bytes)
+}
+
+/// Check the download against the checksum of the file in a checksum file, which either only has
+/// the checksum or a line `<checksum>  <file name>` per file
+fn verify_checksum(bytes: &[u8], checksums: &str, name: &str) -> Result<()> {
+    let expected = checksums
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            Some((parts.next()?, parts.next()))
+        })
+        .find(|(_, file)| file.is_none_or(|file| file.trim_start_matches('*') == name))
+        .map(|(checksum, _)| checksum.to_lowercase())
+        .with_context(|| format!("The checksums of the release don't include {name}"))?;
+
+    let actual: String = ring::digest::digest(&ring::digest::SHA256, bytes)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    if actual != expected {
+        bail!("The update doesn't match its published checksum");
+    }
+
+    Ok(())
+}
+
+/// Remove the executable that an update left behind
+pub fn clean_up() {
+    if let Ok(old) = old_executable() {
+        let _ = std::fs::remove_file(old);
+    }
+}
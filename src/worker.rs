@@ -0,0 +1,93 @@
+use std::{
+    cell::Cell,
+    sync::mpsc::{channel, Receiver, SendError, Sender},
+    time::Instant,
+};
+
+use popup_gpt::{chatgpt::StreamSink, model::CompletionResponse};
+
+use crate::GUIMsg;
+
+/// The sending side of the channel to the UI. Every message wakes the UI up, so it is handled in
+/// the next frame without polling.
+#[derive(Clone)]
+pub struct UiSender {
+    sender: Sender<GUIMsg>,
+    ctx: egui::Context,
+}
+
+impl UiSender {
+    pub fn send(&self, msg: GUIMsg) -> Result<(), SendError<GUIMsg>> {
+        let sent = self.sender.send(msg);
+        self.ctx.request_repaint();
+        sent
+    }
+}
+
+/// A channel to the UI, the UI handles all pending messages in each frame
+pub fn ui_channel(ctx: &egui::Context) -> (UiSender, Receiver<GUIMsg>) {
+    let (sender, receiver) = channel();
+    let ctx = ctx.clone();
+    (UiSender { sender, ctx }, receiver)
+}
+
+/// Sends the partial responses of a stream to the UI and notes when the first token arrived
+pub struct StreamForwarder {
+    ui: UiSender,
+    first_token: Cell<Option<Instant>>,
+}
+
+impl StreamForwarder {
+    pub fn new(ui: UiSender) -> Self {
+        Self {
+            ui,
+            first_token: Cell::new(None),
+        }
+    }
+
+    pub fn first_token(&self) -> Option<Instant> {
+        self.first_token.get()
+    }
+}
+
+impl StreamSink for StreamForwarder {
+    fn send_partial(&self, partial: CompletionResponse) {
+        if self.first_token.get().is_none() {
+            let text = partial
+                .choices
+                .first()
+                .and_then(|choice| choice.delta.as_ref())
+                .and_then(|delta| delta.content.as_ref().or(delta.reasoning_content.as_ref()));
+            if text.is_some_and(|text| !text.is_empty()) {
+                self.first_token.set(Some(Instant::now()));
+            }
+        }
+        let _ = self
+            .ui
+            .send(GUIMsg::PartialCompletionResponse(Box::new(partial)));
+    }
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A thread that runs the requests one after the other for the whole runtime. Requests hold the
+/// client while they run, so they couldn't run at the same time anyway.
+pub struct Worker {
+    jobs: Sender<Job>,
+}
+
+impl Worker {
+    pub fn spawn() -> Self {
+        let (jobs, queue) = channel::<Job>();
+        std::thread::spawn(move || {
+            for job in queue {
+                job();
+            }
+        });
+        Self { jobs }
+    }
+
+    pub fn run(&self, job: impl FnOnce() + Send + 'static) {
+        let _ = self.jobs.send(Box::new(job));
+    }
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use std::sync::mpsc::channel;
+
+use common::{
+    fixture,
+    server::{MockResponse, MockServer},
+};
+use popup_gpt::{
+    chatgpt::{ChatGPT, KeyCheck, Provider, AZURE_API_VERSION},
+    model::FinishReason,
+};
+
+fn client(server: &MockServer) -> ChatGPT {
+    let mut chatgpt = ChatGPT::new("sk-test".to_string());
+    chatgpt.set_base_url(server.base_url());
+    chatgpt
+}
+
+fn completion() -> MockResponse {
+    MockResponse::Json(200, String::from_utf8(fixture("completion.json")).unwrap())
+}
+
+#[test]
+fn ask_continues_the_conversation() {
+    let server = MockServer::start(vec![completion(), completion()]);
+    let mut chatgpt = client(&server);
+
+    let resp = chatgpt.ask("Hi").unwrap();
+    assert_eq!(
+        resp.primary_response(),
+        Some("Hello! How can I help you today?")
+    );
+    assert_eq!(resp.usage.unwrap().completion_tokens, 9);
+    chatgpt.ask("Tell me a joke").unwrap();
+
+    let requests = server.finish();
+    assert_eq!(requests[0].path, "/v1/chat/completions");
+    assert_eq!(requests[0].header("authorization"), Some("Bearer sk-test"));
+
+    // The system message, both questions and the first answer
+    let messages = requests[1].body["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 4);
+    assert_eq!(messages[2]["content"], "Hello! How can I help you today?");
+    assert_eq!(messages[3]["content"], "Tell me a joke");
+    assert_eq!(chatgpt.conversation().len(), 4);
+}
+
+#[test]
+fn ask_stream_forwards_the_partial_responses() {
+    let server = MockServer::start(vec![MockResponse::Stream(fixture("text_stream.txt"))]);
+    let mut chatgpt = client(&server);
+
+    let (tx, rx) = channel();
+    let resp = chatgpt.ask_stream("Hi", tx).unwrap();
+
+    let streamed: String = rx
+        .iter()
+        .filter_map(|partial| partial.choices[0].delta.as_ref()?.content.clone())
+        .collect();
+    assert_eq!(streamed, "Hello! How can I help you today?");
+    assert_eq!(resp.primary_response(), Some(streamed.as_str()));
+    assert_eq!(resp.finish_reason(), Some(&FinishReason::Stop));
+
+    let requests = server.finish();
+    assert_eq!(requests[0].body["stream"], true);
+    assert_eq!(chatgpt.conversation().len(), 2);
+}
+
+#[test]
+fn incomplete_stream_is_an_error() {
+    let stream = fixture("text_stream.txt");
+    let text = String::from_utf8(stream).unwrap();
+    let truncated = text.replace("data: [DONE]", "");
+
+    let server = MockServer::start(vec![MockResponse::Stream(truncated.into_bytes())]);
+    let mut chatgpt = client(&server);
+
+    let (tx, _rx) = channel();
+    let e = chatgpt.ask_stream("Hi", tx).unwrap_err();
+    assert!(e.to_string().contains("ended before"));
+    server.finish();
+}
+
+#[test]
+fn rejected_stream_drops_the_question() {
+    let body = r#"{"error":{"message":"Rate limit reached","type":"requests","code":"rate_limit_exceeded"}}"#;
+    let server = MockServer::start(vec![MockResponse::Json(429, body.to_string())]);
+    let mut chatgpt = client(&server);
+
+    let (tx, _rx) = channel();
+    assert!(chatgpt.ask_stream("Hi", tx).is_err());
+    assert!(!chatgpt.can_retry());
+    server.finish();
+    assert!(chatgpt.conversation().is_empty());
+}
+
+#[test]
+fn failed_stream_is_retried_without_a_new_question() {
+    let server = MockServer::start(vec![MockResponse::Stream(fixture("text_stream.txt"))]);
+    let mut chatgpt = client(&server);
+
+    // Nothing listens on this port, as if the network was down
+    chatgpt.set_base_url("http://127.0.0.1:1/v1");
+    let (tx, _rx) = channel();
+    assert!(chatgpt.ask_stream("Hi", tx).is_err());
+    assert!(chatgpt.can_retry());
+
+    chatgpt.set_base_url(server.base_url());
+    let (tx, _rx) = channel();
+    let resp = chatgpt.retry_stream(tx).unwrap();
+    assert_eq!(
+        resp.primary_response(),
+        Some("Hello! How can I help you today?")
+    );
+    assert!(!chatgpt.can_retry());
+
+    let requests = server.finish();
+    let messages = requests[0].body["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[1]["content"], "Hi");
+    assert_eq!(chatgpt.conversation().len(), 2);
+}
+
+#[test]
+fn api_errors_are_reported() {
+    let body = r#"{"error":{"message":"Incorrect API key provided","type":"invalid_request_error","code":"invalid_api_key"}}"#;
+    let server = MockServer::start(vec![
+        MockResponse::Json(401, body.to_string()),
+        MockResponse::Json(502, "Bad gateway".to_string()),
+    ]);
+    let mut chatgpt = client(&server);
+
+    let e = chatgpt.ask("Hi").unwrap_err();
+    assert_eq!(e.to_string(), "invalid_api_key: Incorrect API key provided");
+    assert!(chatgpt.conversation().is_empty());
+
+    let e = chatgpt.ask("Hi").unwrap_err();
+    assert!(e.to_string().contains("502"));
+    assert!(!chatgpt.can_retry());
+    let requests = server.finish();
+
+    // Failed questions are not sent again with the next one
+    assert_eq!(requests[1].body["messages"].as_array().unwrap().len(), 2);
+    assert!(chatgpt.conversation().is_empty());
+}
+
+#[test]
+fn check_key_reports_why_the_key_fails() {
+    let body = r#"{"error":{"message":"Incorrect API key provided","type":"invalid_request_error","code":"invalid_api_key"}}"#;
+    let server = MockServer::start(vec![
+        completion(),
+        MockResponse::Json(401, body.to_string()),
+        MockResponse::Json(404, "Not found".to_string()),
+    ]);
+    let chatgpt = client(&server);
+
+    assert_eq!(chatgpt.check_key(), KeyCheck::Valid);
+    assert_eq!(
+        chatgpt.check_key(),
+        KeyCheck::InvalidKey("invalid_api_key: Incorrect API key provided".to_string())
+    );
+    assert!(matches!(chatgpt.check_key(), KeyCheck::Failed(reason) if reason.contains("404")));
+
+    let requests = server.finish();
+    assert_eq!(requests[0].body["max_tokens"], 1);
+    assert!(chatgpt.conversation().is_empty());
+
+    let mut unreachable = ChatGPT::new("sk-test".to_string());
+    unreachable.set_base_url("http://127.0.0.1:1/v1");
+    assert!(matches!(unreachable.check_key(), KeyCheck::Unreachable(_)));
+}
+
+#[test]
+fn azure_uses_the_api_key_header() {
+    let server = MockServer::start(vec![completion()]);
+    let mut chatgpt = client(&server);
+    chatgpt.set_provider(Provider::Azure, server.base_url());
+
+    chatgpt.ask("Hi").unwrap();
+
+    let requests = server.finish();
+    assert_eq!(
+        requests[0].path,
+        format!("/v1/chat/completions?api-version={AZURE_API_VERSION}")
+    );
+    assert_eq!(requests[0].header("api-key"), Some("sk-test"));
+    assert_eq!(requests[0].header("authorization"), None);
+}
@@ -0,0 +1,90 @@
+#![allow(dead_code)]
+
+pub mod server;
+
+use std::io::Read;
+
+use popup_gpt::{misc::SSEStream, model::CompletionResponse};
+
+/// Load a recorded stream from `tests/fixtures`
+pub fn fixture(name: &str) -> Vec<u8> {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/");
+    std::fs::read(format!("{path}{name}")).unwrap()
+}
+
+/// A reader that hands out its source in chunks of the given sizes, cycling through the pattern.
+/// This simulates the arbitrary packet boundaries of a network stream.
+pub struct ChunkedReader {
+    source: Vec<u8>,
+    pos: usize,
+    pattern: Vec<usize>,
+    reads: usize,
+}
+
+impl ChunkedReader {
+    pub fn new(source: impl Into<Vec<u8>>, pattern: Vec<usize>) -> Self {
+        assert!(pattern.iter().all(|&size| size > 0));
+
+        Self {
+            source: source.into(),
+            pos: 0,
+            pattern,
+            reads: 0,
+        }
+    }
+
+    /// Split the source into chunks of random sizes between 1 and `max_chunk`
+    pub fn random(source: impl Into<Vec<u8>>, rng: &mut Rng, max_chunk: usize) -> Self {
+        let pattern = (0..32).map(|_| rng.range(1, max_chunk)).collect();
+        Self::new(source, pattern)
+    }
+}
+
+impl Read for ChunkedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let chunk = self.pattern[self.reads % self.pattern.len()];
+        let len = chunk.min(buf.len()).min(self.source.len() - self.pos);
+
+        buf[..len].copy_from_slice(&self.source[self.pos..self.pos + len]);
+        self.pos += len;
+        self.reads += 1;
+
+        Ok(len)
+    }
+}
+
+/// A small deterministic xorshift generator, so failing split patterns are reproducible
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A random number in `min..=max`
+    pub fn range(&mut self, min: usize, max: usize) -> usize {
+        min + (self.next() % (max - min + 1) as u64) as usize
+    }
+}
+
+/// Parse a stream and merge all events until `[DONE]`, the same way the client does
+pub fn merge_stream(source: impl Read) -> CompletionResponse {
+    let mut response = CompletionResponse::default();
+
+    for event in SSEStream::new(source) {
+        let event = event.unwrap();
+        if event.data == "[DONE]" {
+            return response;
+        }
+        response.merge_delta(serde_json::from_str(&event.data).unwrap());
+    }
+
+    panic!("stream ended without [DONE]");
+}
@@ -0,0 +1,132 @@
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread::JoinHandle,
+};
+
+/// A canned response of the mock server
+pub enum MockResponse {
+    /// A JSON body with the given status code
+    Json(u16, String),
+    /// A server-sent event stream, the connection is closed after the body
+    Stream(Vec<u8>),
+}
+
+/// A request as the mock server received it
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    /// The path including the query, e.g. `/v1/chat/completions`
+    pub path: String,
+    /// The headers with lowercase names
+    pub headers: Vec<(String, String)>,
+    pub body: serde_json::Value,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A minimal HTTP server on a random local port that answers the requests with the given responses
+/// in order, one per connection. It stops once all responses are served.
+pub struct MockServer {
+    base_url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl MockServer {
+    pub fn start(responses: Vec<MockResponse>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let requests = Arc::<Mutex<Vec<RecordedRequest>>>::default();
+
+        let recorded = Arc::clone(&requests);
+        let handle = std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let request = read_request(&mut stream);
+                recorded.lock().unwrap().push(request);
+                write_response(&mut stream, response);
+            }
+        });
+
+        Self {
+            base_url,
+            requests,
+            handle: Some(handle),
+        }
+    }
+
+    /// The API base URL to configure the client with
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Wait until all responses are served and return the received requests
+    pub fn finish(mut self) -> Vec<RecordedRequest> {
+        if let Some(handle) = self.handle.take() {
+            handle.join().unwrap();
+        }
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn read_request(stream: &mut TcpStream) -> RecordedRequest {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once(':') {
+            headers.push((key.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    let length = headers
+        .iter()
+        .find(|(key, _)| key == "content-length")
+        .map(|(_, value)| value.parse().unwrap())
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+
+    RecordedRequest {
+        path,
+        headers,
+        body: serde_json::from_slice(&body).unwrap_or_default(),
+    }
+}
+
+fn write_response(stream: &mut TcpStream, response: MockResponse) {
+    let (status, content_type, body) = match response {
+        MockResponse::Json(status, body) => (status, "application/json", body.into_bytes()),
+        MockResponse::Stream(body) => (200, "text/event-stream", body),
+    };
+
+    let head = format!(
+        "HTTP/1.1 {status} Mock\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).unwrap();
+    stream.write_all(&body).unwrap();
+    stream.flush().unwrap();
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use common::{fixture, merge_stream, ChunkedReader, Rng};
+use popup_gpt::{
+    misc::{SSEStream, SseError},
+    model::{CompletionResponse, FinishReason},
+};
+
+const FIXTURES: &[&str] = &[
+    "text_stream.txt",
+    "tool_call_stream.txt",
+    "length_stream.txt",
+    "unicode_crlf_stream.txt",
+];
+
+/// Compare the parts of two responses that are reconstructed from the stream
+fn assert_same_response(a: &CompletionResponse, b: &CompletionResponse) {
+    assert_eq!(
+        serde_json::to_value(a).unwrap(),
+        serde_json::to_value(b).unwrap()
+    );
+}
+
+#[test]
+fn recorded_streams_reconstruct_the_response() {
+    let text = merge_stream(&fixture("text_stream.txt")[..]);
+    assert_eq!(
+        text.primary_response(),
+        Some("Hello! How can I help you today?")
+    );
+
+    let unicode = merge_stream(&fixture("unicode_crlf_stream.txt")[..]);
+    assert_eq!(unicode.primary_response(), Some("🦀 Krabbe, 螃蟹, краб 👍🏽"));
+    assert_eq!(unicode.finish_reason(), Some(&FinishReason::Stop));
+
+    let tools = merge_stream(&fixture("tool_call_stream.txt")[..]);
+    let calls = tools.choices[0]
+        .message
+        .as_ref()
+        .unwrap()
+        .tool_calls
+        .as_ref()
+        .unwrap();
+    assert_eq!(calls[0].function.arguments, r#"{"location":"Berlin"}"#);
+}
+
+#[test]
+fn single_byte_reads() {
+    for name in FIXTURES {
+        let source = fixture(name);
+        let expected = merge_stream(&source[..]);
+        let chunked = merge_stream(ChunkedReader::new(source, vec![1]));
+
+        assert_same_response(&expected, &chunked);
+    }
+}
+
+#[test]
+fn random_chunk_boundaries() {
+    let mut rng = Rng::new(0x5eed);
+
+    for name in FIXTURES {
+        let source = fixture(name);
+        let expected = merge_stream(&source[..]);
+
+        for _ in 0..200 {
+            let max_chunk = rng.range(1, 64);
+            let reader = ChunkedReader::random(source.clone(), &mut rng, max_chunk);
+
+            assert_same_response(&expected, &merge_stream(reader));
+        }
+    }
+}
+
+#[test]
+fn events_are_identical_for_every_split_point() {
+    for name in FIXTURES {
+        let source = fixture(name);
+        let expected = SSEStream::new(&source[..])
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        for split in 1..source.len() {
+            let reader = ChunkedReader::new(source.clone(), vec![split, source.len()]);
+            let events = SSEStream::new(reader)
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+
+            assert_eq!(expected, events, "{name} split at byte {split}");
+        }
+    }
+}
+
+#[test]
+fn truncated_stream_reports_unexpected_eof() {
+    let source = fixture("text_stream.txt");
+    let mut rng = Rng::new(42);
+
+    for _ in 0..50 {
+        // Cut the stream somewhere inside an event
+        let cut = rng.range(1, source.len() - 20);
+        if source[..cut].ends_with(b"\n\n") {
+            continue;
+        }
+
+        let reader = ChunkedReader::random(source[..cut].to_vec(), &mut rng, 16);
+        let last = SSEStream::new(reader).last().unwrap();
+
+        assert!(matches!(last, Err(SseError::UnexpectedEof)), "cut at {cut}");
+    }
+}